@@ -1,15 +1,18 @@
 import axios from "axios";
 
+// Quotes come from the Rust price service (`pricer/`), which holds the provider keys
+const PRICE_SERVICE_URL = process.env.PRICE_SERVICE_URL ?? "http://localhost:5001";
+
 export const POST = async (request) => {
     const { sell_token_price, buy_token_price } = await request.json();
     try {
-        const response = await axios.get(`https://pro-api.coinmarketcap.com/v1/tools/price-conversion?symbol=${sell_token_price}&amount=1&convert=${buy_token_price}`,
-            { headers: { "X-CMC_PRO_API_KEY": "99e8b7ac-34a8-4b56-9ac3-a00ce4165050" } },
+        const response = await axios.post(`${PRICE_SERVICE_URL}/api/price-conversion`,
+            { sell_token_price, buy_token_price },
         );
 
-
         return Response.json({ data: response.data?.data }, { status: 200 })
     } catch (error) {
-        return Response.error({ error }, { status: 500 })
+        return Response.json({ error: error.response?.data?.error ?? error.message },
+            { status: error.response?.status ?? 500 })
     }
-}
\ No newline at end of file
+}
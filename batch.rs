@@ -0,0 +1,284 @@
+use cosmwasm_std::{Decimal, Uint128, Uint256};
+
+/// Revealed order in a batch, normalized to one side of a base/quote pair
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchOrder {
+    pub order_id: u64,
+    /// `true` when the order sells base for quote, `false` when it buys base with quote
+    pub sells_base: bool,
+    /// Base sold for sell orders, quote offered for buy orders
+    pub amount_in: Uint128,
+    /// Minimum (sell) or maximum (buy) quote per base the order accepts
+    pub limit_price: Decimal,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchFill {
+    pub order_id: u64,
+    /// Part of `amount_in` that was traded, the rest is refunded
+    pub spent: Uint128,
+    pub received: Uint128,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchClearing {
+    /// Quote per base every fill in the batch settles at
+    pub clearing_price: Decimal,
+    pub base_volume: Uint128,
+    pub fills: Vec<BatchFill>,
+}
+
+/**
+ * @dev Clears a batch of orders at a single uniform price.
+ *
+ * Every limit price is a candidate clearing price. The candidate that matches the most
+ * base volume wins, ties go to the smaller supply/demand imbalance and then to the lower
+ * price. Orders whose limit is not met at the clearing price are left unfilled, the long
+ * side of the book is filled pro-rata.
+ *
+ * Rounding: both sides share exactly the matched base volume, split pro-rata with the
+ * largest remainders rounded up. Quote received by sellers is rounded down and quote
+ * spent by buyers is rounded up, so no order gets more than the clearing price allows,
+ * base is conserved and the quote taken in covers the quote paid out. Any quote dust
+ * stays with the contract.
+ *
+ * Returns `None` when nothing can be matched.
+ */
+pub fn clear_batch(orders: &[BatchOrder]) -> Option<BatchClearing> {
+    let mut candidates: Vec<Decimal> = orders
+        .iter()
+        .filter(|order| !order.limit_price.is_zero())
+        .map(|order| order.limit_price)
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+
+    let mut best: Option<(Decimal, Uint128, Uint128, Uint128)> = None;
+    for price in candidates {
+        let (supply, demand) = supply_and_demand(orders, price);
+        let volume = supply.min(demand);
+        if volume.is_zero() {
+            continue;
+        }
+        let imbalance = supply.abs_diff(demand);
+        let better = match &best {
+            None => true,
+            Some((_, best_supply, best_demand, best_volume)) => {
+                volume.gt(best_volume)
+                    || (volume.eq(best_volume) && imbalance.lt(&best_supply.abs_diff(*best_demand)))
+            }
+        };
+        if better {
+            best = Some((price, supply, demand, volume));
+        }
+    }
+
+    let (clearing_price, _, _, base_volume) = best?;
+
+    // Base each eligible order offers or wants at the clearing price, per side
+    let side = |sells_base: bool| -> Vec<Uint128> {
+        orders
+            .iter()
+            .map(|order| {
+                if order.sells_base != sells_base || !is_eligible(order, clearing_price) {
+                    Uint128::zero()
+                } else if sells_base {
+                    order.amount_in
+                } else {
+                    order.amount_in.div_floor(clearing_price)
+                }
+            })
+            .collect()
+    };
+    let sold = pro_rata(&side(true), base_volume);
+    let bought = pro_rata(&side(false), base_volume);
+
+    // A buyer never pays more than `amount_in`: `bought * price <= wanted * price <= amount_in`
+    let fills = orders
+        .iter()
+        .zip(sold.into_iter().zip(bought))
+        .map(|(order, (sold, bought))| {
+            if order.sells_base {
+                BatchFill {
+                    order_id: order.order_id,
+                    spent: sold,
+                    received: sold.mul_floor(clearing_price),
+                }
+            } else {
+                BatchFill {
+                    order_id: order.order_id,
+                    spent: bought.mul_ceil(clearing_price),
+                    received: bought,
+                }
+            }
+        })
+        .collect();
+
+    Some(BatchClearing {
+        clearing_price,
+        base_volume,
+        fills,
+    })
+}
+
+/// Splits `volume`, at most the sum of `amounts`, in proportion to `amounts`. Shares are
+/// rounded down and the units left over go to the largest remainders, earlier entries
+/// first, so the shares add up to `volume` and none exceeds its amount.
+fn pro_rata(amounts: &[Uint128], volume: Uint128) -> Vec<Uint128> {
+    let total = amounts.iter().fold(Uint256::zero(), |total, amount| {
+        total + Uint256::from(*amount)
+    });
+    if total.is_zero() {
+        return vec![Uint128::zero(); amounts.len()];
+    }
+
+    let mut shares: Vec<Uint128> = vec![];
+    let mut remainders: Vec<(Uint256, usize)> = vec![];
+    for (index, amount) in amounts.iter().enumerate() {
+        let scaled = amount.full_mul(volume);
+        // Below `amount` since `volume <= total`
+        shares.push(Uint128::try_from(scaled / total).unwrap_or(*amount));
+        remainders.push((scaled % total, index));
+    }
+
+    let allotted = shares
+        .iter()
+        .fold(Uint128::zero(), |allotted, share| allotted + share);
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, index) in remainders
+        .into_iter()
+        .take((volume - allotted).u128() as usize)
+    {
+        shares[index] += Uint128::one();
+    }
+    shares
+}
+
+fn is_eligible(order: &BatchOrder, price: Decimal) -> bool {
+    if order.sells_base {
+        order.limit_price.le(&price)
+    } else {
+        order.limit_price.ge(&price)
+    }
+}
+
+/// Base offered and base wanted at `price`
+fn supply_and_demand(orders: &[BatchOrder], price: Decimal) -> (Uint128, Uint128) {
+    orders
+        .iter()
+        .filter(|order| is_eligible(order, price))
+        .fold((Uint128::zero(), Uint128::zero()), |(supply, demand), order| {
+            if order.sells_base {
+                (supply.saturating_add(order.amount_in), demand)
+            } else {
+                (supply, demand.saturating_add(order.amount_in.div_floor(price)))
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn order(order_id: u64, sells_base: bool, amount_in: u128, limit_price: &str) -> BatchOrder {
+        BatchOrder {
+            order_id,
+            sells_base,
+            amount_in: Uint128::from(amount_in),
+            limit_price: limit_price.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn clears_at_uniform_price() {
+        let orders = vec![
+            order(1, true, 100, "1.8"),
+            order(2, true, 100, "2.2"),
+            order(3, false, 400, "2.5"),
+            order(4, false, 100, "1.5"),
+        ];
+
+        let clearing = clear_batch(&orders).unwrap();
+        assert_eq!(clearing.clearing_price, "2.2".parse::<Decimal>().unwrap());
+        assert_eq!(clearing.base_volume, Uint128::from(181u128));
+
+        // Both sellers fill pro-rata against the only buyer above 2.2, the odd unit goes
+        // to the first one
+        assert_eq!(clearing.fills[0].spent, Uint128::from(91u128));
+        assert_eq!(clearing.fills[0].received, Uint128::from(200u128));
+        assert_eq!(clearing.fills[1].spent, Uint128::from(90u128));
+        assert_eq!(clearing.fills[2].received, Uint128::from(181u128));
+        assert_eq!(clearing.fills[2].spent, Uint128::from(399u128));
+        assert_eq!(clearing.fills[3].spent, Uint128::zero());
+        assert_conserved(&orders, &clearing);
+    }
+
+    /// Base in and out balance exactly, quote taken in covers quote paid out
+    fn assert_conserved(orders: &[BatchOrder], clearing: &BatchClearing) {
+        let (mut base_in, mut base_out, mut quote_in, mut quote_out) =
+            (Uint128::zero(), Uint128::zero(), Uint128::zero(), Uint128::zero());
+        for (order, fill) in orders.iter().zip(clearing.fills.iter()) {
+            assert!(fill.spent <= order.amount_in, "order {} overspent", order.order_id);
+            if order.sells_base {
+                base_in += fill.spent;
+                quote_out += fill.received;
+            } else {
+                quote_in += fill.spent;
+                base_out += fill.received;
+            }
+        }
+        assert_eq!(base_in, clearing.base_volume);
+        assert_eq!(base_out, clearing.base_volume);
+        assert!(quote_in >= quote_out, "quote in {} < quote out {}", quote_in, quote_out);
+    }
+
+    #[test]
+    fn rounding_conserves_both_sides() {
+        // Each buyer wants a third of the base, the units lost to rounding down go to the
+        // first buyer instead of leaving the seller paid for base nobody bought
+        let orders = vec![
+            order(1, true, 100, "1"),
+            order(2, false, 50, "1"),
+            order(3, false, 50, "1"),
+            order(4, false, 50, "1"),
+        ];
+
+        let clearing = clear_batch(&orders).unwrap();
+        assert_eq!(clearing.base_volume, Uint128::from(100u128));
+        assert_eq!(clearing.fills[0].received, Uint128::from(100u128));
+        let bought: Vec<u128> = clearing.fills[1..]
+            .iter()
+            .map(|fill| fill.received.u128())
+            .collect();
+        assert_eq!(bought, vec![34, 33, 33]);
+        assert_conserved(&orders, &clearing);
+    }
+
+    proptest! {
+        #[test]
+        fn clearing_conserves_base_and_quote(
+            books in prop::collection::vec((any::<bool>(), 1u128..1_000_000, 1u64..5_000), 1..12)
+        ) {
+            let orders: Vec<BatchOrder> = books
+                .iter()
+                .enumerate()
+                .map(|(index, (sells_base, amount_in, limit_bps))| BatchOrder {
+                    order_id: index as u64,
+                    sells_base: *sells_base,
+                    amount_in: Uint128::from(*amount_in),
+                    limit_price: Decimal::from_ratio(*limit_bps, 1_000u64),
+                })
+                .collect();
+            if let Some(clearing) = clear_batch(&orders) {
+                assert_conserved(&orders, &clearing);
+            }
+        }
+    }
+
+    #[test]
+    fn no_overlap_clears_nothing() {
+        let orders = vec![order(1, true, 100, "3"), order(2, false, 100, "2")];
+        assert_eq!(clear_batch(&orders), None);
+    }
+}
@@ -0,0 +1,25 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid command: {reason}")]
+    InvalidCommand { reason: String },
+
+    #[error("Node error: {error}")]
+    Node { error: String },
+
+    #[error("Contract error: {error}")]
+    Contract { error: String },
+
+    #[error("{command} is not supported by this target")]
+    Unsupported { command: String },
+
+    #[error("Line {line}: {error}")]
+    Script { line: usize, error: Box<CliError> },
+}
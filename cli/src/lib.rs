@@ -0,0 +1,244 @@
+//! Command-line client for the leverage contract.
+//!
+//! Messages are built from the variant name in snake case followed by its fields:
+//! `key=value` sets a string field and `key:=json` a raw JSON one, so
+//! `borrow token_address=usdc token_amount=100` builds `ExecuteMsg::Borrow` and
+//! `cancel_conditional_order order_id:=3` passes a number. A single JSON object may be
+//! given instead of fields. Every message is checked against `ExecuteMsg` and
+//! `QueryMsg` before it is sent.
+//!
+//! Commands run against a node through its CLI binary, which signs with a key from its
+//! keyring, or against an in-process `cw-multi-test` app for local scripting, see
+//! [`script`].
+
+mod error;
+pub mod script;
+pub mod target;
+
+pub use crate::error::CliError;
+
+use cosmwasm_std::{Coin, Uint128};
+use leverage_contract::msg::{ExecuteMsg, QueryMsg};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::target::Target;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Execute {
+        /// Signer, the target's default when `None`
+        sender: Option<String>,
+        funds: Vec<Coin>,
+        /// Simulate without committing
+        dry_run: bool,
+        msg: Box<ExecuteMsg>,
+    },
+    Query(QueryMsg),
+    /// Advances the chain by `count` blocks, local targets only
+    Block {
+        count: u64,
+    },
+    /// Mints native `funds` to `address`, local targets only
+    Fund {
+        address: String,
+        funds: Vec<Coin>,
+    },
+}
+
+impl Command {
+    /**
+     * @dev Parses `execute [--as <sender>] [--amount <coins>] [--dry-run] <variant> <fields>`,
+     * `query <variant> <fields>`, `block [count]` or `fund <address> <coins>`.
+     */
+    pub fn parse(args: &[String]) -> Result<Self, CliError> {
+        let (command, args) = match args.split_first() {
+            Some((command, args)) => (command.as_str(), args),
+            None => return Err(invalid("missing command")),
+        };
+
+        match command {
+            "execute" => {
+                let mut sender = None;
+                let mut funds = vec![];
+                let mut dry_run = false;
+                let mut args = args;
+                while let Some((flag, rest)) = args.split_first() {
+                    match flag.as_str() {
+                        "--dry-run" => dry_run = true,
+                        "--as" | "--amount" => {
+                            let (value, rest) = rest
+                                .split_first()
+                                .ok_or_else(|| invalid(&format!("{} needs a value", flag)))?;
+                            if flag == "--as" {
+                                sender = Some(value.clone());
+                            } else {
+                                funds = parse_coins(value)?;
+                            }
+                            args = rest;
+                            continue;
+                        }
+                        _ => break,
+                    }
+                    args = rest;
+                }
+                Ok(Command::Execute {
+                    sender,
+                    funds,
+                    dry_run,
+                    msg: Box::new(build_msg(args)?),
+                })
+            }
+            "query" => Ok(Command::Query(build_msg(args)?)),
+            "block" => {
+                let count = match args.first() {
+                    Some(count) => count.parse().map_err(|_| invalid("block count"))?,
+                    None => 1,
+                };
+                Ok(Command::Block { count })
+            }
+            "fund" => match args {
+                [address, coins] => Ok(Command::Fund {
+                    address: address.clone(),
+                    funds: parse_coins(coins)?,
+                }),
+                _ => Err(invalid("fund takes an address and coins")),
+            },
+            _ => Err(invalid(&format!("unknown command {}", command))),
+        }
+    }
+
+    pub fn run(self, target: &mut dyn Target) -> Result<Value, CliError> {
+        match self {
+            Command::Execute {
+                sender,
+                funds,
+                dry_run,
+                msg,
+            } => target.execute(sender.as_deref(), &msg, &funds, dry_run),
+            Command::Query(msg) => target.query(&msg),
+            Command::Block { count } => target.advance_blocks(count).map(|_| Value::Null),
+            Command::Fund { address, funds } => target.fund(&address, &funds).map(|_| Value::Null),
+        }
+    }
+}
+
+fn invalid(reason: &str) -> CliError {
+    CliError::InvalidCommand {
+        reason: reason.to_string(),
+    }
+}
+
+/// Builds `{ "<variant>": { <fields> } }` and checks it against `T`
+pub fn build_msg<T: DeserializeOwned>(args: &[String]) -> Result<T, CliError> {
+    let (variant, fields) = match args.split_first() {
+        Some((variant, fields)) => (variant.replace('-', "_"), fields),
+        None => return Err(invalid("missing message variant")),
+    };
+
+    let body = match fields {
+        [json] if json.trim_start().starts_with('{') => serde_json::from_str(json)?,
+        _ => {
+            let mut body = Map::new();
+            for field in fields {
+                let (key, value) = field
+                    .split_once('=')
+                    .ok_or_else(|| invalid(&format!("expected key=value, got {}", field)))?;
+                let value = match key.strip_suffix(':') {
+                    Some(_) => serde_json::from_str(value)?,
+                    None => Value::String(value.to_string()),
+                };
+                body.insert(key.trim_end_matches(':').to_string(), value);
+            }
+            Value::Object(body)
+        }
+    };
+
+    let mut msg = Map::new();
+    msg.insert(variant, body);
+    serde_json::from_value(Value::Object(msg)).map_err(|error| invalid(&error.to_string()))
+}
+
+/// Parses `100uosmo,5uatom`
+pub fn parse_coins(coins: &str) -> Result<Vec<Coin>, CliError> {
+    coins
+        .split(',')
+        .filter(|coin| !coin.is_empty())
+        .map(|coin| {
+            let split = coin
+                .find(|c: char| !c.is_ascii_digit())
+                .ok_or_else(|| invalid(&format!("coin {} has no denom", coin)))?;
+            let (amount, denom) = coin.split_at(split);
+            let amount: u128 = amount
+                .parse()
+                .map_err(|_| invalid(&format!("coin {} has no amount", coin)))?;
+            Ok(Coin {
+                denom: denom.to_string(),
+                amount: Uint128::from(amount),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::Addr;
+    use leverage_contract::msg::TokenData;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn builds_messages_from_fields() {
+        let command = Command::parse(&args(
+            "execute --as user_one --dry-run borrow token_address=usdc token_amount=100 \
+             on_behalf_of:=null sub_account:=1",
+        ))
+        .unwrap();
+        assert_eq!(
+            command,
+            Command::Execute {
+                sender: Some("user_one".to_string()),
+                funds: vec![],
+                dry_run: true,
+                msg: Box::new(ExecuteMsg::Borrow(TokenData {
+                    token_address: Addr::unchecked("usdc"),
+                    token_amount: Uint128::from(100u128),
+                    on_behalf_of: None,
+                    sub_account: Some(1),
+                })),
+            }
+        );
+
+        let command =
+            Command::parse(&args("query account-health {\"user_address\":\"a\"}")).unwrap();
+        assert_eq!(
+            command,
+            Command::Query(QueryMsg::AccountHealth {
+                user_address: Addr::unchecked("a"),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_variants_and_fields() {
+        assert!(Command::parse(&args("execute borow token_address=usdc")).is_err());
+        assert!(Command::parse(&args("query risk_config extra=1")).is_err());
+        assert!(Command::parse(&args("execute --amount 10 deposit_native")).is_err());
+        assert_eq!(
+            parse_coins("100uosmo,5uatom").unwrap(),
+            vec![
+                Coin {
+                    denom: "uosmo".to_string(),
+                    amount: Uint128::from(100u128),
+                },
+                Coin {
+                    denom: "uatom".to_string(),
+                    amount: Uint128::from(5u128),
+                },
+            ]
+        );
+    }
+}
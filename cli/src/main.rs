@@ -0,0 +1,105 @@
+use std::fs;
+use std::process::exit;
+
+use leverage_cli::script;
+use leverage_cli::target::{MultiTestTarget, NodeTarget};
+use leverage_cli::{CliError, Command};
+use serde_json::Value;
+
+const USAGE: &str = "usage:
+  leverage --contract <address> --from <key> --chain-id <id> [--binary osmosisd]
+           [--node http://localhost:26657] [--gas-prices 0.025uosmo] <command>
+  leverage script <file> [--usdc usdc_contract]
+
+commands:
+  execute [--as <key>] [--amount <coins>] [--dry-run] <variant> [key=value | key:=json]...
+  query <variant> [key=value | key:=json]...";
+
+fn print(value: &Value) {
+    match serde_json::to_string_pretty(value) {
+        Ok(pretty) => println!("{}", pretty),
+        Err(_) => println!("{}", value),
+    }
+}
+
+fn run_script(args: &[String]) -> Result<(), CliError> {
+    let (path, usdc) = match args {
+        [path] => (path, "usdc_contract"),
+        [path, flag, usdc] if flag == "--usdc" => (path, usdc.as_str()),
+        _ => {
+            return Err(CliError::InvalidCommand {
+                reason: "script takes a file and an optional --usdc".to_string(),
+            })
+        }
+    };
+    let mut target = MultiTestTarget::new(usdc)?;
+    for (line, output) in script::run(&mut target, &fs::read_to_string(path)?)? {
+        println!("> {}", line);
+        if !output.is_null() {
+            print(&output);
+        }
+    }
+    Ok(())
+}
+
+fn run_node(args: &[String]) -> Result<(), CliError> {
+    let mut target = NodeTarget {
+        binary: "osmosisd".to_string(),
+        node: "http://localhost:26657".to_string(),
+        chain_id: String::new(),
+        contract: String::new(),
+        from: String::new(),
+        gas_prices: "0.025uosmo".to_string(),
+    };
+
+    let mut args = args;
+    while let [flag, value, rest @ ..] = args {
+        match flag.as_str() {
+            "--binary" => target.binary = value.clone(),
+            "--node" => target.node = value.clone(),
+            "--chain-id" => target.chain_id = value.clone(),
+            "--contract" => target.contract = value.clone(),
+            "--from" => target.from = value.clone(),
+            "--gas-prices" => target.gas_prices = value.clone(),
+            _ => break,
+        }
+        args = rest;
+    }
+    if target.contract.is_empty() {
+        return Err(CliError::InvalidCommand {
+            reason: "missing --contract".to_string(),
+        });
+    }
+
+    let command = Command::parse(args)?;
+    if matches!(command, Command::Execute { .. })
+        && (target.from.is_empty() || target.chain_id.is_empty())
+    {
+        return Err(CliError::InvalidCommand {
+            reason: "execute needs --from and --chain-id".to_string(),
+        });
+    }
+    print(&command.run(&mut target)?);
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, rest)) if command == "script" => run_script(rest),
+        Some(_) => run_node(&args),
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        if matches!(error, CliError::InvalidCommand { .. }) {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+        exit(1);
+    }
+}
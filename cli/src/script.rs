@@ -0,0 +1,90 @@
+//! Scenario scripts: one command per line as on the command line, without the
+//! `leverage` prefix, `#` starts a comment.
+//!
+//! ```text
+//! fund user_one 1000uosmo
+//! execute update_asset_price asset=osmo price=2
+//! execute --as usdc_contract receive sender=user_one amount=100 msg=e30=
+//! execute --as user_one --dry-run borrow token_address=usdc_contract token_amount=500
+//! query account_health user_address=user_one
+//! block 10
+//! ```
+
+use serde_json::Value;
+
+use crate::target::Target;
+use crate::{CliError, Command};
+
+/// Runs `source` line by line, stopping at the first failing command
+pub fn run(target: &mut dyn Target, source: &str) -> Result<Vec<(String, Value)>, CliError> {
+    let mut outputs = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let script_error = |error| CliError::Script {
+            line: index + 1,
+            error: Box::new(error),
+        };
+        let args = split_line(line).map_err(script_error)?;
+        if args.is_empty() {
+            continue;
+        }
+        let output = Command::parse(&args)
+            .and_then(|command| command.run(target))
+            .map_err(script_error)?;
+        outputs.push((line.trim().to_string(), output));
+    }
+    Ok(outputs)
+}
+
+/// Splits on whitespace outside single or double quotes, up to a `#` comment
+pub fn split_line(line: &str) -> Result<Vec<String>, CliError> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, '#') => break,
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(CliError::InvalidCommand {
+            reason: "unterminated quote".to_string(),
+        });
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_quoted_arguments() {
+        assert_eq!(
+            split_line(r#"execute --as a 'x y' data:='{"k": "v w"}' # note"#).unwrap(),
+            vec!["execute", "--as", "a", "x y", r#"data:={"k": "v w"}"#]
+        );
+        assert!(split_line("  # only a comment").unwrap().is_empty());
+        assert!(split_line("query 'open").is_err());
+    }
+}
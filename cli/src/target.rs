@@ -0,0 +1,315 @@
+use std::process::Command;
+
+use cosmwasm_std::{Addr, Coin, Event};
+use cw_multi_test::{App, AppResponse, BankSudo, ContractWrapper, Executor, SudoMsg};
+use leverage_contract::contract::{execute, instantiate, query, reply};
+use leverage_contract::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use serde_json::{json, Map, Value};
+
+use crate::CliError;
+
+/// Where commands are sent
+pub trait Target {
+    fn execute(
+        &mut self,
+        sender: Option<&str>,
+        msg: &ExecuteMsg,
+        funds: &[Coin],
+        dry_run: bool,
+    ) -> Result<Value, CliError>;
+
+    fn query(&self, msg: &QueryMsg) -> Result<Value, CliError>;
+
+    fn advance_blocks(&mut self, _count: u64) -> Result<(), CliError> {
+        Err(CliError::Unsupported {
+            command: "block".to_string(),
+        })
+    }
+
+    fn fund(&mut self, _address: &str, _funds: &[Coin]) -> Result<(), CliError> {
+        Err(CliError::Unsupported {
+            command: "fund".to_string(),
+        })
+    }
+}
+
+/**
+ * @dev Talks to a node through its CLI binary (`osmosisd`, `wasmd`, ...), which does the
+ * signing with a key from its keyring. Dry runs simulate the transaction on the node.
+ */
+pub struct NodeTarget {
+    pub binary: String,
+    pub node: String,
+    pub chain_id: String,
+    pub contract: String,
+    /// Keyring name of the default signer
+    pub from: String,
+    pub gas_prices: String,
+}
+
+impl NodeTarget {
+    fn run(&self, args: &[&str]) -> Result<(bool, String, String), CliError> {
+        let output = Command::new(&self.binary)
+            .args(args)
+            .args(["--node", &self.node])
+            .output()?;
+        Ok((
+            output.status.success(),
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+
+    fn run_json(&self, args: &[&str]) -> Result<Value, CliError> {
+        let mut args = args.to_vec();
+        args.extend(["--output", "json"]);
+        match self.run(&args)? {
+            (true, stdout, _) => Ok(serde_json::from_str(&stdout)?),
+            (false, _, stderr) => Err(CliError::Node { error: stderr }),
+        }
+    }
+}
+
+impl Target for NodeTarget {
+    fn execute(
+        &mut self,
+        sender: Option<&str>,
+        msg: &ExecuteMsg,
+        funds: &[Coin],
+        dry_run: bool,
+    ) -> Result<Value, CliError> {
+        let msg = serde_json::to_string(msg)?;
+        let amount = funds
+            .iter()
+            .map(|coin| coin.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut args: Vec<&str> = vec![
+            "tx",
+            "wasm",
+            "execute",
+            &self.contract,
+            &msg,
+            "--from",
+            sender.unwrap_or(self.from.as_str()),
+            "--chain-id",
+            &self.chain_id,
+            "--gas",
+            "auto",
+            "--gas-adjustment",
+            "1.3",
+            "--gas-prices",
+            &self.gas_prices,
+        ];
+        if !amount.is_empty() {
+            args.extend(["--amount", amount.as_str()]);
+        }
+
+        if dry_run {
+            // The simulation result is printed as text, e.g. "gas estimate: 182344"
+            args.push("--dry-run");
+            return match self.run(&args)? {
+                (true, stdout, stderr) => {
+                    let simulation = [stdout, stderr].join("\n");
+                    Ok(json!({ "simulation": simulation.trim() }))
+                }
+                (false, _, stderr) => Err(CliError::Contract { error: stderr }),
+            };
+        }
+
+        args.push("--yes");
+        let response = self.run_json(&args)?;
+        // CheckTx failures come back with a non-zero code and a zero exit status
+        if response["code"].as_u64().unwrap_or(0) != 0 {
+            return Err(CliError::Contract {
+                error: response["raw_log"].as_str().unwrap_or_default().to_string(),
+            });
+        }
+        Ok(response)
+    }
+
+    fn query(&self, msg: &QueryMsg) -> Result<Value, CliError> {
+        let msg = serde_json::to_string(msg)?;
+        let response = self.run_json(&[
+            "query",
+            "wasm",
+            "contract-state",
+            "smart",
+            &self.contract,
+            &msg,
+        ])?;
+        Ok(response["data"].clone())
+    }
+}
+
+/// Steps replayed to rebuild the app for dry runs
+#[derive(Clone)]
+enum Step {
+    Execute {
+        sender: Addr,
+        msg: Box<ExecuteMsg>,
+        funds: Vec<Coin>,
+    },
+    Block {
+        count: u64,
+    },
+    Fund {
+        address: String,
+        funds: Vec<Coin>,
+    },
+}
+
+/**
+ * @dev The contract in an in-process `cw-multi-test` app, instantiated by `creator`,
+ * which is also the default sender.
+ *
+ * `App` cannot be snapshotted, so a dry run rebuilds the app from the committed steps,
+ * executes the message there and throws it away.
+ */
+pub struct MultiTestTarget {
+    app: App,
+    contract: Addr,
+    usdc: String,
+    history: Vec<Step>,
+}
+
+impl MultiTestTarget {
+    pub fn new(usdc: &str) -> Result<Self, CliError> {
+        let (app, contract) = instantiate_app(usdc)?;
+        Ok(MultiTestTarget {
+            app,
+            contract,
+            usdc: usdc.to_string(),
+            history: vec![],
+        })
+    }
+
+    pub fn contract(&self) -> &Addr {
+        &self.contract
+    }
+
+    pub fn app(&self) -> &App {
+        &self.app
+    }
+
+    fn apply(app: &mut App, contract: &Addr, step: &Step) -> Result<Value, CliError> {
+        match step {
+            Step::Execute { sender, msg, funds } => app
+                .execute_contract(sender.clone(), contract.clone(), msg.as_ref(), funds)
+                .map(|response| response_json(&response))
+                .map_err(|error| CliError::Contract {
+                    error: error.root_cause().to_string(),
+                }),
+            Step::Block { count } => {
+                app.update_block(|block| {
+                    block.height += count;
+                    block.time = block.time.plus_seconds(5 * count);
+                });
+                Ok(Value::Null)
+            }
+            Step::Fund { address, funds } => app
+                .sudo(SudoMsg::Bank(BankSudo::Mint {
+                    to_address: address.clone(),
+                    amount: funds.clone(),
+                }))
+                .map(|_| Value::Null)
+                .map_err(|error| CliError::Contract {
+                    error: error.root_cause().to_string(),
+                }),
+        }
+    }
+
+    fn commit(&mut self, step: Step) -> Result<Value, CliError> {
+        let result = Self::apply(&mut self.app, &self.contract, &step)?;
+        self.history.push(step);
+        Ok(result)
+    }
+}
+
+impl Target for MultiTestTarget {
+    fn execute(
+        &mut self,
+        sender: Option<&str>,
+        msg: &ExecuteMsg,
+        funds: &[Coin],
+        dry_run: bool,
+    ) -> Result<Value, CliError> {
+        let step = Step::Execute {
+            sender: Addr::unchecked(sender.unwrap_or("creator")),
+            msg: Box::new(msg.clone()),
+            funds: funds.to_vec(),
+        };
+        if !dry_run {
+            return self.commit(step);
+        }
+
+        let (mut app, contract) = instantiate_app(&self.usdc)?;
+        for committed in &self.history {
+            Self::apply(&mut app, &contract, committed)?;
+        }
+        Self::apply(&mut app, &contract, &step)
+    }
+
+    fn query(&self, msg: &QueryMsg) -> Result<Value, CliError> {
+        self.app
+            .wrap()
+            .query_wasm_smart(self.contract.clone(), msg)
+            .map_err(|error| CliError::Contract {
+                error: error.to_string(),
+            })
+    }
+
+    fn advance_blocks(&mut self, count: u64) -> Result<(), CliError> {
+        self.commit(Step::Block { count }).map(|_| ())
+    }
+
+    fn fund(&mut self, address: &str, funds: &[Coin]) -> Result<(), CliError> {
+        self.commit(Step::Fund {
+            address: address.to_string(),
+            funds: funds.to_vec(),
+        })
+        .map(|_| ())
+    }
+}
+
+fn instantiate_app(usdc: &str) -> Result<(App, Addr), CliError> {
+    let mut app = App::default();
+    let code_id = app.store_code(Box::new(
+        ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+    ));
+    let contract = app
+        .instantiate_contract(
+            code_id,
+            Addr::unchecked("creator"),
+            &InstantiateMsg {
+                token_contract_address: usdc.to_string(),
+            },
+            &[],
+            "leverage_contract",
+            None,
+        )
+        .map_err(|error| CliError::Contract {
+            error: error.root_cause().to_string(),
+        })?;
+    Ok((app, contract))
+}
+
+/// Events of the response as `{ "type": ..., "attributes": { key: value } }`
+fn response_json(response: &AppResponse) -> Value {
+    let events: Vec<Value> = response.events.iter().map(event_json).collect();
+    json!({ "events": events })
+}
+
+fn event_json(event: &Event) -> Value {
+    let attributes: Map<String, Value> = event
+        .attributes
+        .iter()
+        .map(|attribute| {
+            (
+                attribute.key.clone(),
+                Value::String(attribute.value.clone()),
+            )
+        })
+        .collect();
+    json!({ "type": event.ty, "attributes": attributes })
+}
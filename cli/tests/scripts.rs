@@ -0,0 +1,70 @@
+use cosmwasm_std::Addr;
+use leverage_cli::script;
+use leverage_cli::target::{MultiTestTarget, Target};
+use leverage_cli::CliError;
+use leverage_contract::msg::{QueryMsg, QueryTokenData};
+use serde_json::{json, Value};
+
+const SETUP: &str = "
+# usdc is priced at 1, user_one deposits 100 usdc and borrows 500
+execute update_asset_price asset=usdc_contract price=1
+execute --as usdc_contract receive sender=user_one amount=100 msg=e30=
+execute --as user_one borrow token_address=usdc_contract token_amount=500
+";
+
+fn borrowed(target: &MultiTestTarget) -> Value {
+    target
+        .query(&QueryMsg::UserBorrowTokenBalance(QueryTokenData {
+            token_address: Addr::unchecked("usdc_contract"),
+            user_address: Addr::unchecked("user_one"),
+        }))
+        .unwrap()
+}
+
+#[test]
+fn dry_runs_leave_the_app_untouched() {
+    let mut target = MultiTestTarget::new("usdc_contract").unwrap();
+    script::run(&mut target, SETUP).unwrap();
+    assert_eq!(borrowed(&target), json!("500"));
+
+    let outputs = script::run(
+        &mut target,
+        "execute --as user_one --dry-run repay token_address=usdc_contract token_amount=200",
+    )
+    .unwrap();
+    let events = outputs[0].1["events"].as_array().unwrap();
+    assert!(events.iter().any(|event| event["type"] == "wasm-repay"));
+    assert_eq!(borrowed(&target), json!("500"));
+
+    let outputs = script::run(
+        &mut target,
+        "execute --as user_one repay token_address=usdc_contract token_amount=200
+         query user_borrow_token_balance token_address=usdc_contract user_address=user_one",
+    )
+    .unwrap();
+    assert_eq!(outputs[1].1, json!("300"));
+    assert_eq!(borrowed(&target), json!("300"));
+}
+
+#[test]
+fn failing_lines_are_reported() {
+    let mut target = MultiTestTarget::new("usdc_contract").unwrap();
+    let error = script::run(
+        &mut target,
+        &format!(
+            "{}\nexecute --as user_one borrow token_address=usdc_contract token_amount=5000",
+            SETUP
+        ),
+    )
+    .unwrap_err();
+    match error {
+        CliError::Script { line, error } => {
+            assert_eq!(line, 7);
+            assert!(matches!(*error, CliError::Contract { .. }));
+        }
+        error => panic!("unexpected error {}", error),
+    }
+
+    let error = script::run(&mut target, "execute borrow token_address=usdc_contract").unwrap_err();
+    assert!(matches!(error, CliError::Script { line: 1, .. }));
+}
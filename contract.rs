@@ -1,25 +1,63 @@
+use crate::batch::{clear_batch, BatchOrder};
 use crate::error::ContractError;
+use crate::events::{AccountBalances, LeverageEvent};
+use crate::health::{health_factor, is_liquidatable, liquidation_split};
 use crate::msg::{
-    Cw20ReceiveMsg, ExecuteMsg, GetListedTokenResponse, GetProfitTokenResponse,
-    GetUserBorrowTokenResponse, GetUserTokenBalanceResponse, GetUserUnmintedTokenResponse,
-    InstantiateMsg, MigrateMsg, QueryMsg,
+    AccountHealth, AccountLedgersResponse, AssetAmount, BorrowResponse, ClosePositionData,
+    ConditionalOrderData, Cw20ReceiveMsg, DepositReceiveMsg, ExecuteMsg,
+    FlashLoanReceiverExecuteMsg, InstantiateMsg, InsuranceFundResponse, LeveragedPositionData,
+    MigrateMsg, OperatorGrantResponse, OrderExecute, PriceInfoResponse, PriceOracleQueryMsg,
+    PriceOracleResponse, PriceSourceState, PriceSourcesResponse, PriceUpdate, QueryMsg,
+    QueryTokenData, RevealedOrderData, SubAccountResponse, SwapRouterExecuteMsg,
+    TokenCapacityResponse, TokenData, WithdrawData,
 };
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_json_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdResult,
-    Uint128, WasmMsg,
+    coins, from_json, to_json_binary, to_json_string, to_json_vec, Addr, BankMsg, Binary,
+    CosmosMsg, Decimal, Decimal256, Deps, DepsMut, Env, Event, MessageInfo, Order, Reply, Response,
+    StdError, StdResult, Storage, SubMsg, Timestamp, Uint128, Uint512, WasmMsg,
 };
 use cw2::set_contract_version;
+use cw20::Expiration;
+use cw_storage_plus::{Bound, Map};
+use leverage_math::vtoken;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 
+use crate::oracle::{self, Aggregate, Pricing};
+use crate::paillier::PublicKey;
 use crate::state::{
-    LEVERAGE_CONTRACT_OWNER, LISTED_TOKEN, USER_BORROW_BALANCE, USER_PROFIT_TOKEN,
-    USER_TOKEN_BALANCE, USER_UNMINTED_TOKEN,
+    ConditionalOrder, ConditionalOrderKind, FeeConfig, InsuranceConfig, KeeperBounty, LossIndex,
+    MarginMode, OperatorGrant, OperatorScope, OraclePrice, OrderCommitment, PendingClose,
+    PendingFlashLoan, PendingSwap, PriceFeedConfig, PriceObservation, PriceSource, PriceSources,
+    RevealedOrder, RiskConfig, SwapConfig, TokenCaps, TwapConfig, ASSET_PRICE, ASSET_PRICE_TIME,
+    BAD_DEBT, CONDITIONAL_ORDERS, CONDITIONAL_ORDER_SEQ, ENCRYPTED_ORDERS, ENCRYPTED_ORDER_FILLED,
+    FEE_CONFIG, INSURANCE_CONFIG, INSURANCE_FUND, LEVERAGE_CONTRACT_OWNER, LISTED_TOKEN,
+    NATIVE_TOKEN_DENOM, OPERATOR_GRANTS, ORACLE_PRICES, ORDER_COMMITMENTS, ORDER_COMMITMENT_SEQ,
+    ORDER_REVEAL_WINDOW, PAILLIER_PUBLIC_KEY, PENDING_FLASH_LOAN, PENDING_SWAP, PRICE_FEED_CONFIG,
+    PRICE_OBSERVATIONS, PRICE_SOURCES, REVEALED_ORDERS, RISK_CONFIG, SOCIALIZED_LOSS_INDEX,
+    SUB_ACCOUNTS, SUB_ACCOUNT_SEQ, SWAP_CONFIG, TOKEN_CAPS, TOTAL_BORROWS, TOTAL_DEPOSITS,
+    TREASURY, TWAP_CONFIG, USER_BORROW_BALANCE, USER_LOSS_CHECKPOINT, USER_POSITION_BALANCE,
+    USER_PROFIT_TOKEN, USER_TOKEN_BALANCE, USER_UNMINTED_TOKEN,
 };
 
 const CONTRACT_NAME: &str = "crates.io:leverage-contract";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const DEFAULT_ORDER_REVEAL_WINDOW: u64 = 10;
+
+const SWAP_REPLY_ID: u64 = 1;
+const FLASH_LOAN_REPLY_ID: u64 = 2;
+
+const DEFAULT_LIQUIDATION_THRESHOLD: &str = "1.05";
+const DEFAULT_LIQUIDATION_PENALTY_BPS: u64 = 500;
+
+const DEFAULT_TWAP_MAX_WINDOW: u64 = 3_600;
+
+const DEFAULT_PAGE_LIMIT: u32 = 10;
+const MAX_PAGE_LIMIT: u32 = 30;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
@@ -30,8 +68,7 @@ pub fn instantiate(
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
     println!("inst: {}", _msg.token_contract_address);
 
-    let mut token_list: Vec<String> = Vec::new();
-    token_list.push(_msg.token_contract_address);
+    let token_list: Vec<String> = vec![_msg.token_contract_address];
     LISTED_TOKEN.save(deps.storage, &token_list)?;
 
     LEVERAGE_CONTRACT_OWNER.save(deps.storage, &info.sender)?;
@@ -53,47 +90,220 @@ pub fn execute(
     _info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    execute::ensure_no_flash_loan(_deps.storage)?;
+
     match msg {
         ExecuteMsg::Receive(cw20_receive_msg) => {
             execute::token_deposit(_deps, _env, _info, cw20_receive_msg)
         }
-        ExecuteMsg::ListTokenOnLeverage { token_address } => {
-            execute::list_token_on_leverage(_deps, _env, _info, token_address)
+        ExecuteMsg::ListTokenOnLeverage {
+            token_address,
+            native_denom,
+        } => execute::list_token_on_leverage(_deps, _env, _info, token_address, native_denom),
+        ExecuteMsg::DepositNative {
+            token_address,
+            sub_account,
+        } => execute::deposit_native(_deps, _env, _info, token_address, sub_account),
+        ExecuteMsg::WithdrawToken(withdraw_data) => {
+            execute::token_withdraw(_deps, _env, _info, withdraw_data)
+        }
+        ExecuteMsg::Borrow(token_data) => execute::borrow(_deps, _env, _info, token_data),
+        ExecuteMsg::ExecuteOrder(order) => execute::execute_order(_deps, _env, _info, order),
+        ExecuteMsg::Repay(token_data) => execute::repay(_deps, _env, _info, token_data),
+        ExecuteMsg::Burn(token_data) => execute::burn(_deps, _env, _info, token_data),
+        ExecuteMsg::UpdateAssetPrice { asset, price } => {
+            execute::update_asset_price(_deps, _env, _info, asset, price)
+        }
+        ExecuteMsg::PlaceConditionalOrder(order_data) => {
+            execute::place_conditional_order(_deps, _env, _info, order_data)
+        }
+        ExecuteMsg::CancelConditionalOrder {
+            order_id,
+            on_behalf_of,
+            sub_account,
+        } => execute::cancel_conditional_order(
+            _deps,
+            _env,
+            _info,
+            order_id,
+            on_behalf_of,
+            sub_account,
+        ),
+        ExecuteMsg::ExecuteConditionalOrder { owner, order_id } => {
+            execute::execute_conditional_order(_deps, _env, _info, owner, order_id)
+        }
+        ExecuteMsg::UpdatePaillierPublicKey { n } => {
+            execute::update_paillier_public_key(_deps, _env, _info, n)
+        }
+        ExecuteMsg::CommitEncryptedOrder {
+            order_id,
+            encrypted_order_value,
+            on_behalf_of,
+            sub_account,
+        } => execute::commit_encrypted_order(
+            _deps,
+            _env,
+            _info,
+            order_id,
+            encrypted_order_value,
+            on_behalf_of,
+            sub_account,
+        ),
+        ExecuteMsg::CommitOrder {
+            commitment,
+            margin_token,
+            margin_amount,
+            on_behalf_of,
+            sub_account,
+        } => execute::commit_order(
+            _deps,
+            _env,
+            _info,
+            commitment,
+            margin_token,
+            margin_amount,
+            on_behalf_of,
+            sub_account,
+        ),
+        ExecuteMsg::RevealOrder {
+            commitment_id,
+            order,
+            on_behalf_of,
+            sub_account,
+        } => execute::reveal_order(
+            _deps,
+            _env,
+            _info,
+            commitment_id,
+            order,
+            on_behalf_of,
+            sub_account,
+        ),
+        ExecuteMsg::ExpireOrderCommitment { commitment_id } => {
+            execute::expire_order_commitment(_deps, _env, _info, commitment_id)
+        }
+        ExecuteMsg::SettleOrderBatch { height } => {
+            execute::settle_order_batch(_deps, _env, _info, height)
+        }
+        ExecuteMsg::UpdateRevealWindow { blocks } => {
+            execute::update_reveal_window(_deps, _env, _info, blocks)
+        }
+        ExecuteMsg::UpdateSwapConfig {
+            router,
+            max_slippage_bps,
+        } => execute::update_swap_config(_deps, _env, _info, router, max_slippage_bps),
+        ExecuteMsg::UpdateFeeConfig(fee_config) => {
+            execute::update_fee_config(_deps, _env, _info, fee_config)
+        }
+        ExecuteMsg::ClaimFees { asset, amount } => {
+            execute::claim_fees(_deps, _env, _info, asset, amount)
+        }
+        ExecuteMsg::UpdateRiskConfig(risk_config) => {
+            execute::update_risk_config(_deps, _env, _info, risk_config)
+        }
+        ExecuteMsg::UpdateInsuranceConfig(insurance_config) => {
+            execute::update_insurance_config(_deps, _env, _info, insurance_config)
+        }
+        ExecuteMsg::UpdateTokenCaps {
+            token_address,
+            caps,
+        } => execute::update_token_caps(_deps, _env, _info, token_address, caps),
+        ExecuteMsg::Liquidate {
+            user_address,
+            debt_token,
+        } => execute::liquidate(_deps, _env, _info, user_address, debt_token),
+        ExecuteMsg::GrantOperator {
+            operator,
+            scope,
+            expires,
+        } => execute::grant_operator(_deps, _env, _info, operator, scope, expires),
+        ExecuteMsg::RevokeOperator { operator } => {
+            execute::revoke_operator(_deps, _env, _info, operator)
         }
-        ExecuteMsg::WithdrawToken {
+        ExecuteMsg::OpenSubAccount { margin_mode } => {
+            execute::open_sub_account(_deps, _env, _info, margin_mode)
+        }
+        ExecuteMsg::SetMarginMode {
+            sub_account,
+            margin_mode,
+        } => execute::set_margin_mode(_deps, _env, _info, sub_account, margin_mode),
+        ExecuteMsg::TransferCollateral {
             token_address,
             amount,
-        } => execute::token_withdraw(_deps, _env, _info, token_address, amount),
-        ExecuteMsg::Borrow { borrow_amount } => execute::borrow(_deps, _env, _info, borrow_amount),
-        ExecuteMsg::Repay { repay_amount } => execute::repay(_deps, _env, _info, repay_amount),
-        ExecuteMsg::Burn {
+            from_sub_account,
+            to_sub_account,
+        } => execute::transfer_collateral(
+            _deps,
+            _env,
+            _info,
             token_address,
-            v_token_amount,
-        } => execute::burn(_deps, _env, _info, token_address, v_token_amount),
+            amount,
+            from_sub_account,
+            to_sub_account,
+        ),
+        ExecuteMsg::OpenLeveragedPosition {
+            collateral,
+            target_leverage,
+            asset_to_buy,
+            min_out,
+            sub_account,
+        } => execute::open_leveraged_position(
+            _deps,
+            _env,
+            _info,
+            collateral,
+            LeveragedPositionData {
+                target_leverage,
+                asset_to_buy,
+                min_out,
+            },
+            sub_account,
+        ),
+        ExecuteMsg::ClosePosition(close_data) => {
+            execute::close_position(_deps, _env, _info, close_data)
+        }
+        ExecuteMsg::FlashLoan {
+            asset,
+            amount,
+            callback,
+        } => execute::flash_loan(_deps, _env, _info, asset, amount, callback),
+        ExecuteMsg::UpdatePriceFeedConfig(price_feed_config) => {
+            execute::update_price_feed_config(_deps, _env, _info, price_feed_config)
+        }
+        ExecuteMsg::FeedPrices { prices } => execute::feed_prices(_deps, _env, _info, prices),
+        ExecuteMsg::UpdateTwapConfig(twap_config) => {
+            execute::update_twap_config(_deps, _env, _info, twap_config)
+        }
+        ExecuteMsg::UpdatePriceSources {
+            asset,
+            price_sources,
+        } => execute::update_price_sources(_deps, _env, _info, asset, price_sources),
+        ExecuteMsg::SyncOraclePrices { asset } => {
+            execute::sync_oracle_prices(_deps, _env, _info, asset)
+        }
     }
 }
 
 pub mod execute {
     use super::*;
 
+    /**
+     * @dev Lists `token_address` as collateral. A native token is listed with the bank denom
+     * it is deposited and withdrawn in, coins of any other denom are rejected.
+     */
     pub fn list_token_on_leverage(
         deps: DepsMut,
         _env: Env,
         info: MessageInfo,
         token_address: String,
+        native_denom: Option<String>,
     ) -> Result<Response, ContractError> {
-        match LEVERAGE_CONTRACT_OWNER.load(deps.storage) {
-            Ok(owner) => {
-                if owner != info.sender {
-                    return Err(ContractError::Unauthorized {});
-                }
-            }
-            Err(err) => {
-                return Err(ContractError::GenericError {
-                    error: err.to_string(),
-                })
-            }
-        };
+        ensure_owner(deps.storage, &info.sender)?;
+
+        let asset = Addr::unchecked(&token_address);
+        if let Some(denom) = &native_denom {
+            NATIVE_TOKEN_DENOM.save(deps.storage, &asset, denom)?;
+        }
 
         match LISTED_TOKEN.update(
             deps.storage,
@@ -102,7 +312,9 @@ pub mod execute {
                 Ok(listed_token)
             },
         ) {
-            Ok(_) => Ok(Response::new().add_attribute("method", "list_token_on_leverage")),
+            Ok(_) => Ok(Response::new()
+                .add_attribute("method", "list_token_on_leverage")
+                .add_event(config_event("token_listing", Some(&asset), &native_denom)?)),
             Err(_) => Err(ContractError::UpdateTokenListFailed {}),
         }
     }
@@ -131,55 +343,214 @@ pub mod execute {
         _cw20_receive_msg: Cw20ReceiveMsg,
     ) -> Result<Response, ContractError> {
         // Load the listed tokens from storage
-        let token = match LISTED_TOKEN.load(_deps.storage) {
-            Ok(tokens) => tokens,
-            Err(_) => vec![],
-        };
+        let token = LISTED_TOKEN.load(_deps.storage).unwrap_or_default();
 
         // Check if the sender's token is listed
-        if token.contains(&_info.sender.to_string()) {
-            // Update the user's token balance
-            USER_TOKEN_BALANCE.update(
-                _deps.storage,
-                (&_info.sender, &Addr::unchecked(&_cw20_receive_msg.sender)),
-                |opt_balance| -> Result<Uint128, ContractError> {
-                    match opt_balance {
-                        Some(balance) => match balance.checked_add(_cw20_receive_msg.amount) {
-                            Ok(data) => Ok(data),
-                            Err(_) => Err(ContractError::OverflowBalance {}),
-                        },
-                        None => Ok(_cw20_receive_msg.amount),
-                    }
+        let deposit_event = if token.contains(&_info.sender.to_string()) {
+            // The hook message optionally selects a sub-account, anything else deposits
+            // into the main account
+            let deposit_msg = match from_json::<DepositReceiveMsg>(&_cw20_receive_msg.msg) {
+                Ok(deposit_msg) => deposit_msg,
+                Err(_) => DepositReceiveMsg {
+                    sub_account: None,
+                    open_position: None,
                 },
-            )?;
-
-            // Calculate the unminted token amount and update the user's unminted token balance
-            let unminted_token = match _cw20_receive_msg.amount.checked_mul(Uint128::from(10u128)) {
-                Ok(data) => data,
-                Err(_) => Uint128::zero(),
             };
-
-            USER_UNMINTED_TOKEN.update(
+            let account = select_sub_account(
                 _deps.storage,
-                &Addr::unchecked(&_cw20_receive_msg.sender),
-                |opt_balance| -> Result<Uint128, ContractError> {
-                    match opt_balance {
-                        Some(balance) => match balance.checked_add(unminted_token) {
-                            Ok(data) => Ok(data),
-                            Err(_) => Err(ContractError::OverflowBalance {}),
-                        },
-                        None => Ok(unminted_token),
-                    }
-                },
+                Addr::unchecked(&_cw20_receive_msg.sender),
+                deposit_msg.sub_account,
             )?;
+            if let Some(position) = deposit_msg.open_position {
+                return open_position(
+                    _deps,
+                    _env,
+                    account,
+                    _info.sender,
+                    _cw20_receive_msg.amount,
+                    position,
+                );
+            }
+            check_supply_cap(_deps.storage, &_info.sender, &account, _cw20_receive_msg.amount)?;
+            credit_collateral(_deps.storage, &_info.sender, &account, _cw20_receive_msg.amount)?;
+            LeverageEvent::Deposit {
+                balances: account_balances(_deps.storage, &account, &_info.sender)?,
+                user: account,
+                asset: _info.sender.clone(),
+                amount: _cw20_receive_msg.amount,
+            }
         } else {
             return Err(ContractError::UnauthorizedToken {});
-        }
+        };
 
         Ok(Response::new()
             .add_attribute("method", "token_deposit")
             .add_attribute("token_owner", _cw20_receive_msg.sender)
-            .add_attribute("token_address", _info.sender))
+            .add_attribute("token_address", _info.sender)
+            .add_event(Event::from(deposit_event)))
+    }
+
+    /**
+     * @dev Function to handle native token deposit.
+     *
+     * The listed `token_address` (e.g. "osmo") is credited with the single coin sent
+     * along with the message, which must be in the denom the token was listed with.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender and attached funds.
+     * @param _token_address Listed name of the native token.
+     * @param _sub_account Sub-account credited, the main account when `None`.
+     * @return A response object indicating success or failure.
+     */
+    pub fn deposit_native(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _token_address: String,
+        _sub_account: Option<u32>,
+    ) -> Result<Response, ContractError> {
+        let (token_address, amount) = native_deposit(_deps.storage, &_token_address, &_info)?;
+
+        let account = select_sub_account(_deps.storage, _info.sender.clone(), _sub_account)?;
+        check_supply_cap(_deps.storage, &token_address, &account, amount)?;
+        credit_collateral(_deps.storage, &token_address, &account, amount)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "deposit_native")
+            .add_attribute("token_owner", _info.sender)
+            .add_attribute("token_address", token_address.clone())
+            .add_event(Event::from(LeverageEvent::Deposit {
+                balances: account_balances(_deps.storage, &account, &token_address)?,
+                user: account,
+                asset: token_address,
+                amount,
+            })))
+    }
+
+    /// Validates the single coin attached to a native deposit of a listed token
+    fn native_deposit(
+        storage: &dyn Storage,
+        token_address: &str,
+        info: &MessageInfo,
+    ) -> Result<(Addr, Uint128), ContractError> {
+        let token = LISTED_TOKEN.load(storage).unwrap_or_default();
+
+        if !token.iter().any(|listed| listed == token_address) {
+            return Err(ContractError::UnauthorizedToken {});
+        }
+
+        // Exactly one non-zero coin must be attached
+        let coin = match info.funds.as_slice() {
+            [coin] if !coin.amount.is_zero() => coin.clone(),
+            _ => return Err(ContractError::InvalidNativeDeposit {}),
+        };
+
+        let token_address = Addr::unchecked(token_address);
+        match NATIVE_TOKEN_DENOM.may_load(storage, &token_address)? {
+            Some(denom) if denom == coin.denom => {}
+            _ => return Err(ContractError::InvalidNativeDeposit {}),
+        }
+
+        Ok((token_address, coin.amount))
+    }
+
+    /**
+     * Function to open a leveraged position in one message.
+     *
+     * The attached native collateral is deposited, `target_leverage` times the deposit
+     * is borrowed in the collateral token and the borrowed amount (less the origination
+     * fee) is swapped into `asset_to_buy` through the swap router. CW20 collateral opens
+     * a position through the `open_position` field of the `Send` hook instead.
+     *
+     * The swap settles in `reply`, which reverts the whole transaction when the output is
+     * below `min_out` (or the router's slippage bound), or when the account's health
+     * factor ends up below the liquidation threshold.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender and attached collateral.
+     * @param _collateral Listed name of the native collateral token.
+     * @param _position Leverage, asset to buy and minimum output.
+     * @param _sub_account Sub-account the position is opened in, the main account when `None`.
+     * @return A response object indicating success or failure.
+     */
+    pub fn open_leveraged_position(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _collateral: String,
+        _position: LeveragedPositionData,
+        _sub_account: Option<u32>,
+    ) -> Result<Response, ContractError> {
+        let (token_address, amount) = native_deposit(_deps.storage, &_collateral, &_info)?;
+        let account = select_sub_account(_deps.storage, _info.sender.clone(), _sub_account)?;
+        open_position(_deps, _env, account, token_address, amount, _position)
+    }
+
+    /// Deposits `amount` of collateral, borrows against it and swaps the loan in one go
+    fn open_position(
+        deps: DepsMut,
+        env: Env,
+        account: Addr,
+        collateral: Addr,
+        amount: Uint128,
+        position: LeveragedPositionData,
+    ) -> Result<Response, ContractError> {
+        if position.target_leverage.is_zero() || position.asset_to_buy == collateral {
+            return Err(ContractError::InvalidLeverage {});
+        }
+        let swap_config = match SWAP_CONFIG.may_load(deps.storage)? {
+            Some(swap_config) => swap_config,
+            None => return Err(ContractError::SwapRouterNotConfigured {}),
+        };
+
+        check_supply_cap(deps.storage, &collateral, &account, amount)?;
+        credit_collateral(deps.storage, &collateral, &account, amount)?;
+        let deposit_event = LeverageEvent::Deposit {
+            user: account.clone(),
+            asset: collateral.clone(),
+            amount,
+            balances: account_balances(deps.storage, &account, &collateral)?,
+        };
+
+        let borrow_amount = amount.mul_floor(position.target_leverage);
+        if borrow_amount.is_zero() {
+            return Err(ContractError::InvalidLeverage {});
+        }
+        let fee = borrow_to_position(deps.storage, &collateral, &account, borrow_amount)?;
+        let borrow_event = LeverageEvent::Borrow {
+            user: account.clone(),
+            asset: collateral.clone(),
+            amount: borrow_amount,
+            fee,
+            balances: account_balances(deps.storage, &account, &collateral)?,
+        };
+
+        let order = OrderExecute {
+            user_address: account.clone(),
+            token_in: collateral.clone(),
+            token_out: position.asset_to_buy.clone(),
+            amount_in: borrow_amount - fee,
+            amount_out: position.min_out,
+            encrypted_order: None,
+        };
+        let min_health_factor = load_risk_config(deps.storage)?.liquidation_threshold;
+        let (swap_msg, min_out) =
+            dispatch_swap(deps, &env, &order, swap_config, Some(min_health_factor), None, None)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "open_leveraged_position")
+            .add_attribute("user", account)
+            .add_attribute("collateral", collateral.clone())
+            .add_attribute("deposit", amount)
+            .add_attribute("borrow", borrow_amount)
+            .add_attribute("asset_to_buy", position.asset_to_buy)
+            .add_attribute("min_out", min_out)
+            .add_submessage(swap_msg)
+            .add_event(Event::from(deposit_event))
+            .add_event(Event::from(borrow_event))
+            .add_events(fee_events("borrow", &collateral, fee)))
     }
 
     /**
@@ -194,28 +565,82 @@ pub mod execute {
      * 5. Update the user's token balance by subtracting the withdrawal amount.
      * 6. Calculate the amount of unminted tokens to remove.
      * 7. Update the user's unminted token balance.
-     * 8. Create a CW20 transfer message to transfer tokens back to the user.
+     * 8. Create a CW20 transfer or bank send message depending on `withdraw_type`.
      * 9. Return a response with attributes indicating the method and token details.
      *
      * @param _deps Storage access for contract state.
      * @param _env Contract environment information.
      * @param _info Information about the message sender.
-     * @param _token_address Address of the token to be withdrawn.
-     * @param _amount Amount of tokens to be withdrawn.
+     * @param _withdraw_data Token address, amount and withdraw type ("fungible" or "native").
+     *        An operator with a `Full` grant may withdraw `on_behalf_of` the owner, the
+     *        tokens are always sent to the owner.
      * @return A response object indicating success or failure.
      */
     pub fn token_withdraw(
         _deps: DepsMut,
         _env: Env,
         _info: MessageInfo,
-        _token_address: Addr,
-        _amount: Uint128,
+        _withdraw_data: WithdrawData,
     ) -> Result<Response, ContractError> {
+        let _token_address = _withdraw_data.token_address;
+        let _amount = _withdraw_data.token_amount;
+        let _user_address = resolve_account(
+            _deps.storage,
+            &_env,
+            &_info.sender,
+            _withdraw_data.on_behalf_of,
+            _withdraw_data.sub_account,
+            OperatorScope::Full,
+        )?;
+
+        let (transfer_msg, fee) = withdraw_collateral(
+            _deps.storage,
+            &_token_address,
+            &_user_address,
+            _amount,
+            &_withdraw_data.withdraw_type,
+            _withdraw_data.native,
+        )?;
+        let min_health_factor = load_risk_config(_deps.storage)?.liquidation_threshold;
+        ensure_healthy(_deps.storage, &_user_address, _env.block.time, min_health_factor)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "token_withdraw")
+            .add_attribute("token_address", _token_address.clone())
+            .add_attribute("user", _user_address.clone())
+            .add_message(transfer_msg)
+            .add_event(Event::from(withdraw_event(
+                _deps.storage,
+                &_token_address,
+                &_user_address,
+                _amount,
+                fee,
+            )?))
+            .add_events(fee_events("withdraw", &_token_address, fee)))
+    }
+
+    /**
+     * @dev Takes `amount` of collateral out of the account, less the withdraw fee, and
+     * builds the transfer to the owner of the account.
+     *
+     * @return The transfer message and the withdraw fee.
+     */
+    fn withdraw_collateral(
+        storage: &mut dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+        amount: Uint128,
+        withdraw_type: &str,
+        native: Option<String>,
+    ) -> Result<(CosmosMsg, Uint128), ContractError> {
+        apply_socialized_loss(storage, token_address, user_address)?;
+
         // Load user's borrow balance
-        let user_borrow_balance = match USER_BORROW_BALANCE.load(_deps.storage, &_info.sender) {
-            Ok(data) => data,
-            Err(_) => Uint128::zero(),
-        };
+        let user_borrow_balance =
+            match USER_BORROW_BALANCE.load(storage, (token_address, user_address)) {
+                Ok(data) => data,
+                Err(_) => Uint128::zero(),
+            };
 
         // Check if the user has any borrow balance
         if user_borrow_balance.gt(&Uint128::zero()) {
@@ -224,7 +649,7 @@ pub mod execute {
 
         // Load user's token balance
         let user_balance =
-            match USER_TOKEN_BALANCE.may_load(_deps.storage, (&_token_address, &_info.sender)) {
+            match USER_TOKEN_BALANCE.may_load(storage, (token_address, user_address)) {
                 Ok(opt_balance) => match opt_balance {
                     Some(balance) => balance,
                     None => Uint128::zero(),
@@ -233,18 +658,17 @@ pub mod execute {
             };
 
         // Check if user has sufficient balance for withdrawal
-        if user_balance.lt(&_amount) {
+        if user_balance.lt(&amount) {
             return Err(ContractError::InsufficientBalance {});
         }
 
-        
         // Update user's token balance
         USER_TOKEN_BALANCE.update(
-            _deps.storage,
-            (&_token_address, &_info.sender),
+            storage,
+            (token_address, user_address),
             |opt_balance| -> Result<Uint128, ContractError> {
                 match opt_balance {
-                    Some(balance) => match balance.checked_sub(_amount) {
+                    Some(balance) => match balance.checked_sub(amount) {
                         Ok(data) => Ok(data),
                         Err(_) => Err(ContractError::Overflow {})
                     },
@@ -253,16 +677,18 @@ pub mod execute {
             },
         )?;
 
+        sub_total_deposits(storage, token_address, amount)?;
+
         // Calculate the amount of unminted tokens to remove
-        let remove_unminted_token = match _amount.checked_mul(Uint128::from(10u128)) {
-            Ok(data) => data,
-            Err(_) => Uint128::zero(),
+        let remove_unminted_token = match vtoken::to_v_tokens(amount.u128()) {
+            Some(data) => Uint128::from(data),
+            None => return Err(ContractError::Overflow {}),
         };
 
         // Update user's unminted token balance
         USER_UNMINTED_TOKEN.update(
-            _deps.storage,
-            &_info.sender,
+            storage,
+            (token_address, user_address),
             |opt_balance| -> Result<Uint128, ContractError> {
                 match opt_balance {
                     Some(balance) => match balance.checked_sub(remove_unminted_token) {
@@ -274,66 +700,136 @@ pub mod execute {
             },
         )?;
 
-        // Create CW20 transfer message to transfer tokens back to the user
-        let execute_cw20_token_transfer = WasmMsg::Execute {
-            contract_addr: _token_address.to_string(),
-            msg: to_json_binary(&cw20::Cw20ExecuteMsg::Transfer {
-                recipient: _info.sender.to_string(),
-                amount: _amount,
-            })?,
-            funds: vec![],
+        // Keep the withdrawal fee in the treasury
+        let fee_config = load_fee_config(storage)?;
+        let fee = take_fee(storage, token_address, amount, fee_config.withdraw_fee_bps)?;
+        let transfer_amount = amount - fee;
+
+        // Transfer tokens back to the owner of the (sub-)account
+        let (recipient, _) = split_sub_account(user_address);
+        let transfer_msg: CosmosMsg = match withdraw_type {
+            "fungible" => WasmMsg::Execute {
+                contract_addr: token_address.to_string(),
+                msg: to_json_binary(&cw20::Cw20ExecuteMsg::Transfer {
+                    recipient: recipient.to_string(),
+                    amount: transfer_amount,
+                })?,
+                funds: vec![],
+            }
+            .into(),
+            "native" => {
+                let denom = match NATIVE_TOKEN_DENOM.may_load(storage, token_address)? {
+                    Some(denom) => denom,
+                    None => return Err(ContractError::UnauthorizedToken {}),
+                };
+                if let Some(native) = &native {
+                    if *native != denom {
+                        return Err(ContractError::UnauthorizedToken {});
+                    }
+                }
+                BankMsg::Send {
+                    to_address: recipient.to_string(),
+                    amount: coins(transfer_amount.u128(), denom),
+                }
+                .into()
+            }
+            other => {
+                return Err(ContractError::InvalidWithdrawType {
+                    withdraw_type: other.to_string(),
+                })
+            }
         };
 
-        Ok(Response::new()
-            .add_attribute("method", "token_withdraw")
-            .add_attribute("token_address", _token_address)
-            .add_attribute("user", _info.sender)
-            .add_message(execute_cw20_token_transfer))
+        Ok((transfer_msg, fee))
     }
 
     /**
      * Function to borrow tokens.
      *
      * This function allows users to borrow tokens by locking their unminted tokens and
-     * increasing their borrow balance.
+     * increasing their borrow balance. The borrowed amount is credited to the user's
+     * position in the same token, ready to be traded through `ExecuteOrder`.
      *
      * @param _deps Storage access for contract state.
      * @param _env Contract environment information.
      * @param _info Information about the message sender.
-     * @param _borrow_amount Amount of tokens to borrow.
+     * @param _token_data Token to borrow against and amount of tokens to borrow.
      * @return A response object indicating success or failure.
      */
     pub fn borrow(
         _deps: DepsMut,
         _env: Env,
         _info: MessageInfo,
-        _borrow_amount: Uint128,
+        _token_data: TokenData,
     ) -> Result<Response, ContractError> {
+        let _token_address = _token_data.token_address;
+        let _borrow_amount = _token_data.token_amount;
+        let _user_address = resolve_account(
+            _deps.storage,
+            &_env,
+            &_info.sender,
+            _token_data.on_behalf_of,
+            _token_data.sub_account,
+            OperatorScope::Full,
+        )?;
+
+        let fee = borrow_to_position(_deps.storage, &_token_address, &_user_address, _borrow_amount)?;
+        let min_health_factor = load_risk_config(_deps.storage)?.liquidation_threshold;
+        ensure_healthy(_deps.storage, &_user_address, _env.block.time, min_health_factor)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "borrow_leverage")
+            .add_event(Event::from(LeverageEvent::Borrow {
+                balances: account_balances(_deps.storage, &_user_address, &_token_address)?,
+                user: _user_address,
+                asset: _token_address.clone(),
+                amount: _borrow_amount,
+                fee,
+            }))
+            .add_events(fee_events("borrow", &_token_address, fee)))
+    }
+
+    /**
+     * @dev Borrows `borrow_amount` against the account's unminted vToken and credits it,
+     * less the origination fee, to the account's position in the same token.
+     *
+     * @return The origination fee.
+     */
+    fn borrow_to_position(
+        storage: &mut dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+        borrow_amount: Uint128,
+    ) -> Result<Uint128, ContractError> {
         // Load user's unminted token balance
-        let user_unminted_token = match USER_UNMINTED_TOKEN.may_load(_deps.storage, &_info.sender) {
-            Ok(opt_data) => match opt_data {
-                Some(data) => data,
-                None => Uint128::zero(),
-            },
-            Err(_) => {
-                return Err(ContractError::UnmintedBalanceLoadError {});
-            }
-        };
+        let user_unminted_token =
+            match USER_UNMINTED_TOKEN.may_load(storage, (token_address, user_address)) {
+                Ok(opt_data) => match opt_data {
+                    Some(data) => data,
+                    None => Uint128::zero(),
+                },
+                Err(_) => {
+                    return Err(ContractError::UnmintedBalanceLoadError {});
+                }
+            };
 
         // Check if user's unminted token balance is sufficient
-        if user_unminted_token.lt(&_borrow_amount) {
+        if user_unminted_token.lt(&borrow_amount) {
             return Err(ContractError::InsufficientUnmintedToken {});
         }
 
+        check_borrow_cap(storage, token_address, user_address, borrow_amount)?;
+        add_to_ledger(storage, &TOTAL_BORROWS, token_address, borrow_amount)?;
+
         // Update user's unminted token balance by subtracting borrowed amount
         USER_UNMINTED_TOKEN.update(
-            _deps.storage,
-            &_info.sender,
+            storage,
+            (token_address, user_address),
             |opt_unminted_balance| -> Result<Uint128, ContractError> {
                 match opt_unminted_balance {
-                    Some(data) => match data.checked_sub(_borrow_amount) {
+                    Some(data) => match data.checked_sub(borrow_amount) {
                         Ok(unminted_balance) => Ok(unminted_balance),
-                        Err(_) => return Err(ContractError::Overflow {}),
+                        Err(_) => Err(ContractError::Overflow {}),
                     },
                     None => Ok(Uint128::zero()),
                 }
@@ -342,85 +838,220 @@ pub mod execute {
 
         // Update user's borrow balance by adding borrowed amount
         USER_BORROW_BALANCE.update(
-            _deps.storage,
-            &_info.sender,
+            storage,
+            (token_address, user_address),
             |opt_borrow_balance| -> Result<Uint128, ContractError> {
                 match opt_borrow_balance {
-                    Some(data) => match data.checked_add(_borrow_amount) {
+                    Some(data) => match data.checked_add(borrow_amount) {
                         Ok(borror_balance) => Ok(borror_balance),
-                        Err(_) => return Err(ContractError::Overflow {}),
+                        Err(_) => Err(ContractError::Overflow {}),
                     },
-                    None => Ok(_borrow_amount),
+                    None => Ok(borrow_amount),
                 }
             },
         )?;
 
-        Ok(Response::new().add_attribute("method", "borrow_leverage"))
+        // Credit the borrowed amount, less the origination fee, to the user's position
+        let fee_config = load_fee_config(storage)?;
+        let fee = take_fee(storage, token_address, borrow_amount, fee_config.borrow_fee_bps)?;
+        credit_position(storage, user_address, token_address, borrow_amount - fee)?;
+        Ok(fee)
+    }
+
+    /**
+     * Function to settle a matched order.
+     *
+     * Only the contract owner (the order matching service) can settle orders. The
+     * user's position in `token_in` is debited by `amount_in` and their position in
+     * `token_out` is credited with `amount_out`.
+     *
+     * Once a Paillier public key is configured, every order must reveal the encrypted
+     * order the user committed: the randomness has to prove that the committed
+     * ciphertext decrypts to the order amount, `amount_in` unless the reveal names a
+     * larger one. Fills add up against the order amount and the commitment is consumed
+     * once it is filled completely.
+     *
+     * Once a swap router is configured, `amount_out` is only the minimum output: the
+     * contract swaps `amount_in` through the router itself and credits what it actually
+     * received in `reply`.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _order Matched order to settle.
+     * @return A response object indicating success or failure.
+     */
+    pub fn execute_order(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _order: OrderExecute,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        if let Some(public_key) = PAILLIER_PUBLIC_KEY.may_load(_deps.storage)? {
+            let reveal = match &_order.encrypted_order {
+                Some(reveal) => reveal,
+                None => return Err(ContractError::EncryptedOrderNotFound {}),
+            };
+
+            let ciphertext = match ENCRYPTED_ORDERS.may_load(
+                _deps.storage,
+                (&_order.user_address, reveal.order_id.as_str()),
+            )? {
+                Some(ciphertext) => ciphertext,
+                None => return Err(ContractError::EncryptedOrderNotFound {}),
+            };
+
+            let order_amount = reveal.order_amount.unwrap_or(_order.amount_in);
+            if !public_key.verify_decryption(
+                ciphertext,
+                Uint512::from(order_amount.u128()),
+                reveal.randomness,
+            )? {
+                return Err(ContractError::EncryptedOrderMismatch {});
+            }
+
+            let key = (&_order.user_address, reveal.order_id.as_str());
+            let filled = ENCRYPTED_ORDER_FILLED
+                .may_load(_deps.storage, key)?
+                .unwrap_or_default()
+                .checked_add(_order.amount_in)
+                .map_err(|_| ContractError::Overflow {})?;
+            if filled.gt(&order_amount) {
+                return Err(ContractError::EncryptedOrderOverfilled {});
+            }
+            if filled == order_amount {
+                ENCRYPTED_ORDERS.remove(_deps.storage, key);
+                ENCRYPTED_ORDER_FILLED.remove(_deps.storage, key);
+            } else {
+                ENCRYPTED_ORDER_FILLED.save(_deps.storage, key, &filled)?;
+            }
+        }
+
+        if let Some(swap_config) = SWAP_CONFIG.may_load(_deps.storage)? {
+            return swap_order(_deps, _env, _order, swap_config);
+        }
+
+        let settlement = settle_order(_deps.storage, &_order, _env.block.time)?;
+        let min_health_factor = load_risk_config(_deps.storage)?.liquidation_threshold;
+        ensure_healthy(_deps.storage, &_order.user_address, _env.block.time, min_health_factor)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "execute_order")
+            .add_attribute("user", _order.user_address.clone())
+            .add_attribute("token_in", _order.token_in)
+            .add_attribute("token_out", _order.token_out.clone())
+            .add_attribute("amount_in", _order.amount_in)
+            .add_attribute("amount_out", _order.amount_out)
+            .add_attribute(
+                "cancelled_conditional_orders",
+                settlement.cancelled_orders.len().to_string(),
+            )
+            .add_event(Event::from(settlement.event))
+            .add_events(fee_events("taker", &_order.token_out, settlement.fee))
+            .add_events(cancel_events(&_order.user_address, &settlement.cancelled_orders)))
     }
 
     /**
      * Function to repay borrowed tokens.
      *
      * This function allows users to repay tokens they have borrowed, thereby reducing their borrow balance
-     * and unlocking their unminted tokens.
+     * and unlocking their unminted tokens. The repaid amount is taken from the user's position
+     * in the borrowed token.
      *
      * @param _deps Storage access for contract state.
      * @param _env Contract environment information.
      * @param _info Information about the message sender.
-     * @param _repay_amount Amount of tokens to repay.
+     * @param _token_data Borrowed token and amount of tokens to repay.
      * @return A response object indicating success or failure.
      */
     pub fn repay(
         _deps: DepsMut,
         _env: Env,
         _info: MessageInfo,
-        _repay_amount: Uint128,
+        _token_data: TokenData,
     ) -> Result<Response, ContractError> {
+        let _token_address = _token_data.token_address;
+        let _repay_amount = _token_data.token_amount;
+        let _user_address = resolve_account(
+            _deps.storage,
+            &_env,
+            &_info.sender,
+            _token_data.on_behalf_of,
+            _token_data.sub_account,
+            OperatorScope::Repay,
+        )?;
+
+        repay_from_position(_deps.storage, &_token_address, &_user_address, _repay_amount)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "repay")
+            .add_event(Event::from(LeverageEvent::Repay {
+                balances: account_balances(_deps.storage, &_user_address, &_token_address)?,
+                user: _user_address,
+                asset: _token_address,
+                amount: _repay_amount,
+            })))
+    }
+
+    /// Repays `repay_amount` of the account's debt out of its position in the same token
+    fn repay_from_position(
+        storage: &mut dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+        repay_amount: Uint128,
+    ) -> Result<(), ContractError> {
         // Load user's borrow balance
-        let user_borrow_balance = match USER_BORROW_BALANCE.may_load(_deps.storage, &_info.sender) {
-            Ok(opt_data) => match opt_data {
-                Some(data) => data,
-                None => Uint128::zero(),
-            },
-            Err(_) => return Err(ContractError::BorrowBalanceLoadError {}),
-        };
+        let user_borrow_balance =
+            match USER_BORROW_BALANCE.may_load(storage, (token_address, user_address)) {
+                Ok(opt_data) => match opt_data {
+                    Some(data) => data,
+                    None => Uint128::zero(),
+                },
+                Err(_) => return Err(ContractError::BorrowBalanceLoadError {}),
+            };
 
         // check if the user's borrow balance is less than the repayment amount
-        if user_borrow_balance.lt(&_repay_amount) {
+        if user_borrow_balance.lt(&repay_amount) {
             return Err(ContractError::RepayOverflow {});
         }
 
+        // Take the repayment out of the user's position
+        debit_position(storage, user_address, token_address, repay_amount)?;
+        sub_from_ledger(storage, &TOTAL_BORROWS, token_address, repay_amount)?;
+
         // Update user's borrow balance by subtracting the repayment amount
         USER_BORROW_BALANCE.update(
-            _deps.storage,
-            &_info.sender,
+            storage,
+            (token_address, user_address),
             |opt_borrow_balance| -> Result<Uint128, ContractError> {
                 match opt_borrow_balance {
-                    Some(data) => match data.checked_sub(_repay_amount) {
+                    Some(data) => match data.checked_sub(repay_amount) {
                         Ok(borror_balance) => Ok(borror_balance),
-                        Err(_) => return Err(ContractError::OverflowBalance {}),
+                        Err(_) => Err(ContractError::OverflowBalance {}),
                     },
-                    None => return Err(ContractError::OverflowBalance {}),
+                    None => Err(ContractError::OverflowBalance {}),
                 }
             },
         )?;
 
         // Update user's unminted token balance by adding the repayment amount
         USER_UNMINTED_TOKEN.update(
-            _deps.storage,
-            &_info.sender,
+            storage,
+            (token_address, user_address),
             |opt_unminted_balance| -> Result<Uint128, ContractError> {
                 match opt_unminted_balance {
-                    Some(data) => match data.checked_add(_repay_amount) {
+                    Some(data) => match data.checked_add(repay_amount) {
                         Ok(unminted_balance) => Ok(unminted_balance),
-                        Err(_) => return Err(ContractError::InsufficientBalance {}),
+                        Err(_) => Err(ContractError::InsufficientBalance {}),
                     },
-                    None => Ok(Uint128::zero()),
+                    None => Ok(repay_amount),
                 }
             },
         )?;
 
-        Ok(Response::new())
+        Ok(())
     }
 
     /**
@@ -440,26 +1071,60 @@ pub mod execute {
      * @param _deps Storage access for contract state.
      * @param _env Contract environment information.
      * @param _info Information about the message sender.
-     * @param _token_address Address of the vToken to be burned.
-     * @param _v_token_amount Amount of vTokens to be burned.
+     * @param _token_data Address of the vToken to be burned and amount of vTokens to be burned.
      * @return A response object indicating success or failure.
      */
-
     pub fn burn(
         _deps: DepsMut,
         _env: Env,
         _info: MessageInfo,
-        _token_address: Addr,
-        _v_token_amount: Uint128,
+        _token_data: TokenData,
     ) -> Result<Response, ContractError> {
+        let _token_address = _token_data.token_address;
+        let _v_token_amount = _token_data.token_amount;
+        let _user_address = resolve_account(
+            _deps.storage,
+            &_env,
+            &_info.sender,
+            _token_data.on_behalf_of,
+            _token_data.sub_account,
+            OperatorScope::Full,
+        )?;
+
+        let (v_token_amount, collateral_amount) =
+            burn_profit(_deps.storage, &_token_address, &_user_address, _v_token_amount)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "burn")
+            .add_event(Event::from(LeverageEvent::Burn {
+                balances: account_balances(_deps.storage, &_user_address, &_token_address)?,
+                user: _user_address,
+                asset: _token_address,
+                v_token_amount,
+                collateral_amount,
+            })))
+    }
+
+    /// Converts `v_token_amount` of realized profit vToken into collateral at 10:1 and
+    /// returns the vTokens burned and the collateral credited. Only whole multiples of 10
+    /// convert, a remainder stays in the profit balance.
+    fn burn_profit(
+        storage: &mut dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+        v_token_amount: Uint128,
+    ) -> Result<(Uint128, Uint128), ContractError> {
+        apply_socialized_loss(storage, token_address, user_address)?;
+
         // Load the user's borrow balance from storage
-        let user_borrow_balance = match USER_BORROW_BALANCE.may_load(_deps.storage, &_info.sender) {
-            Ok(opt_data) => match opt_data {
-                Some(data) => data,
-                None => Uint128::zero()
-            },
-            Err(_) => return Err(ContractError::BorrowBalanceLoadError {}),
-        };
+        let user_borrow_balance =
+            match USER_BORROW_BALANCE.may_load(storage, (token_address, user_address)) {
+                Ok(opt_data) => match opt_data {
+                    Some(data) => data,
+                    None => Uint128::zero()
+                },
+                Err(_) => return Err(ContractError::BorrowBalanceLoadError {}),
+            };
 
         // If user's borrow balance is greater than zero, return an error
         if user_borrow_balance.gt(&Uint128::zero()) {
@@ -467,257 +1132,6114 @@ pub mod execute {
         }
 
         // Load the user's profit balance from storage
-        let user_profit_balance = match USER_PROFIT_TOKEN.may_load(_deps.storage, &_info.sender) {
-            Ok(opt_data) => match opt_data {
-                Some(data) => data,
-                None => Uint128::zero()
-            },
-            Err(_) => return Err(ContractError::ProfitBalanceLoadError {}),
-        };
+        let user_profit_balance =
+            match USER_PROFIT_TOKEN.may_load(storage, (token_address, user_address)) {
+                Ok(opt_data) => match opt_data {
+                    Some(data) => data,
+                    None => Uint128::zero()
+                },
+                Err(_) => return Err(ContractError::ProfitBalanceLoadError {}),
+            };
 
         // If user's profit balance is less than the amount to burn, return an error
-        if user_profit_balance.lt(&_v_token_amount) {
+        if user_profit_balance.lt(&v_token_amount) {
             return Err(ContractError::InsufficientBalance {});
         }
 
-        // Calculate the equivalent USDC amount based on the VToken amount burned (assuming 10:1 ratio)
-        let user_usdc_amount = match _v_token_amount.checked_div(Uint128::from(10u128)) {
-            Ok(data) => data,
-            Err(_) => return Err(ContractError::Overflow {}),
-        };
+        // Calculate the equivalent USDC amount based on the VToken amount burned (10:1, rounded down)
+        let user_usdc_amount = Uint128::from(vtoken::to_collateral(v_token_amount.u128()));
+        let burned = Uint128::from(
+            vtoken::to_v_tokens(user_usdc_amount.u128()).ok_or(ContractError::Overflow {})?,
+        );
 
-        // Update user's token balance by adding the calculated USDC amount
-        USER_TOKEN_BALANCE.update(
-            _deps.storage,
-            (&_token_address, &_info.sender),
-            |opt_balance| -> Result<Uint128, ContractError> {
-                match opt_balance {
-                    Some(balance) => match balance.checked_add(user_usdc_amount) {
-                        Ok(data) => Ok(data),
-                        Err(_) => Err(ContractError::Overflow {}),
-                    },
-                    None => Ok(Uint128::zero()),
-                }
-            },
+        // Remove the burned vTokens from the user's profit balance
+        USER_PROFIT_TOKEN.save(
+            storage,
+            (token_address, user_address),
+            &(user_profit_balance - burned),
         )?;
 
-        // Update user's unminted token balance by adding the VToken amount burned
-        USER_UNMINTED_TOKEN.update(
-            _deps.storage,
-            &_info.sender,
-            |opt_balance| -> Result<Uint128, ContractError> {
-                match opt_balance {
-                    Some(balance) => match balance.checked_add(_v_token_amount) {
-                        Ok(data) => Ok(data),
-                        Err(_) => Err(ContractError::Overflow {}),
-                    },
-                    None => Ok(Uint128::zero()),
-                }
-            },
-        )?;
+        // Credit the collateral with its matching unminted vToken (10x)
+        credit_collateral(storage, token_address, user_address, user_usdc_amount)?;
 
-        Ok(Response::new())
+        Ok((burned, user_usdc_amount))
     }
-}
 
-/// Handling contract query
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(_deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::UserTokenBalance {
-            token_address,
-            user_address,
-        } => to_json_binary(&query::fetch_user_token_balance(
-            _deps,
-            _env,
-            token_address,
-            user_address,
-        )?),
-        QueryMsg::UserUnmintedTokenBalance { user_address } => to_json_binary(
-            &query::fetch_user_unminted_token_balance(_deps, _env, user_address)?,
-        ),
-        QueryMsg::UserBorrowTokenBalance { user_address } => to_json_binary(
-            &query::fetch_user_borrow_token_balance(_deps, _env, user_address)?,
-        ),
-        QueryMsg::UserProfitTokenBalance { user_address } => to_json_binary(
-            &query::fetch_user_profit_token_balance(_deps, _env, user_address)?,
-        ),
-        QueryMsg::ListedTokens {} => to_json_binary(&query::fetch_listed_tokens(_deps, _env)?),
-    }
-}
+    /**
+     * Function to set the price of an asset.
+     *
+     * Prices are quoted in a common unit and are used to evaluate conditional order triggers
+     * and the amounts they settle at. Only the contract owner can update prices this way.
+     * The price is stamped with the block time and skips the deviation bound feeders are
+     * held to, so the owner can move it past a genuine jump the feed was refused.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _asset Asset the price is for.
+     * @param _price New price of the asset.
+     * @return A response object indicating success or failure.
+     */
+    pub fn update_asset_price(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _asset: Addr,
+        _price: Decimal,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
 
-pub mod query {
-    use super::*;
+        if _price.is_zero() {
+            return Err(ContractError::PriceNotAvailable {
+                asset: _asset.to_string(),
+            });
+        }
 
-    pub fn fetch_user_unminted_token_balance(
-        _deps: Deps,
+        ASSET_PRICE.save(_deps.storage, &_asset, &_price)?;
+        ASSET_PRICE_TIME.save(_deps.storage, &_asset, &_env.block.time)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_asset_price")
+            .add_attribute("asset", _asset.clone())
+            .add_attribute("price", _price.to_string())
+            .add_event(Event::from(LeverageEvent::PriceUpdate {
+                asset: _asset,
+                price: _price,
+                publish_time: _env.block.time,
+                feeder: _info.sender,
+            })))
+    }
+
+    /**
+     * Function for price feeders to push timestamped prices.
+     *
+     * Every update has to be newer than the stored price and not in the future. When a
+     * maximum age is configured it may not be stale already, and when a deviation bound is
+     * configured it may not move further than that from the last price. The owner can
+     * feed prices as well, under the same guards.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _prices Prices with the time they were observed at.
+     * @return A response object indicating success or failure.
+     */
+    pub fn feed_prices(
+        _deps: DepsMut,
         _env: Env,
-        _user_address: Addr,
-    ) -> StdResult<GetUserUnmintedTokenResponse> {
-        match USER_UNMINTED_TOKEN.may_load(_deps.storage, &_user_address) {
-            Ok(opt_data) => match opt_data {
-                Some(data) => Ok(GetUserUnmintedTokenResponse {
-                    vtoken_amount: data,
-                }),
-                None => Ok(GetUserUnmintedTokenResponse {
-                    vtoken_amount: Uint128::zero(),
-                }),
-            },
-            Err(_) => Err(ContractError::UnmintedTokenQueryFailed {}.into()),
+        _info: MessageInfo,
+        _prices: Vec<PriceUpdate>,
+    ) -> Result<Response, ContractError> {
+        let config = load_price_feed_config(_deps.storage)?;
+        if !config.feeders.contains(&_info.sender) {
+            ensure_owner(_deps.storage, &_info.sender)?;
+        }
+
+        let mut response = Response::new().add_attribute("method", "feed_prices");
+        for update in _prices {
+            let asset = update.asset.to_string();
+            if update.price.is_zero() {
+                return Err(ContractError::PriceNotAvailable { asset });
+            }
+
+            let last_time = ASSET_PRICE_TIME.may_load(_deps.storage, &update.asset)?;
+            if update.publish_time > _env.block.time
+                || last_time.is_some_and(|last_time| update.publish_time <= last_time)
+            {
+                return Err(ContractError::InvalidPriceTime { asset });
+            }
+            if is_price_stale(&config, Some(update.publish_time), _env.block.time) {
+                return Err(ContractError::PriceStale { asset });
+            }
+
+            let last_price = ASSET_PRICE.may_load(_deps.storage, &update.asset)?;
+            if let (Some(max_deviation_bps), Some(last_price)) =
+                (config.max_deviation_bps, last_price)
+            {
+                let deviation_bps = price_deviation_bps(last_price, update.price);
+                if deviation_bps > max_deviation_bps {
+                    return Err(ContractError::PriceDeviationTooLarge {
+                        asset,
+                        deviation_bps,
+                        max_deviation_bps,
+                    });
+                }
+            }
+
+            ASSET_PRICE.save(_deps.storage, &update.asset, &update.price)?;
+            ASSET_PRICE_TIME.save(_deps.storage, &update.asset, &update.publish_time)?;
+            response = response.add_event(Event::from(LeverageEvent::PriceUpdate {
+                asset: update.asset,
+                price: update.price,
+                publish_time: update.publish_time,
+                feeder: _info.sender.clone(),
+            }));
         }
+
+        Ok(response)
     }
 
-    pub fn fetch_user_token_balance(
-        _deps: Deps,
+    pub fn update_price_feed_config(
+        _deps: DepsMut,
         _env: Env,
-        _token_address: Addr,
-        _user_address: Addr,
-    ) -> StdResult<GetUserTokenBalanceResponse> {
-        match USER_TOKEN_BALANCE.may_load(_deps.storage, (&_token_address, &_user_address)) {
-            Ok(opt_data) => match opt_data {
-                Some(data) => Ok(GetUserTokenBalanceResponse { usdc_amount: data }),
-                None => Ok(GetUserTokenBalanceResponse {
-                    usdc_amount: Uint128::zero(),
-                }),
-            },
-            Err(_) => Err(ContractError::UserTokenBalanceQueryFailed {}.into()),
+        _info: MessageInfo,
+        _price_feed_config: PriceFeedConfig,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        if _price_feed_config.max_price_age == Some(0)
+            || _price_feed_config.max_deviation_bps == Some(0)
+        {
+            return Err(ContractError::InvalidPriceFeedConfig {});
         }
+        for feeder in _price_feed_config.feeders.iter() {
+            _deps.api.addr_validate(feeder.as_str())?;
+        }
+        PRICE_FEED_CONFIG.save(_deps.storage, &_price_feed_config)?;
+
+        // Unset bounds are left out, attribute values cannot be empty
+        let bounds = [
+            ("max_price_age", _price_feed_config.max_price_age),
+            ("max_deviation_bps", _price_feed_config.max_deviation_bps),
+        ];
+        Ok(Response::new()
+            .add_attribute("method", "update_price_feed_config")
+            .add_attribute("feeders", _price_feed_config.feeders.len().to_string())
+            .add_attributes(
+                bounds
+                    .into_iter()
+                    .filter_map(|(key, bound)| bound.map(|bound| (key, bound.to_string()))),
+            )
+            .add_event(config_event("price_feed", None, &_price_feed_config)?))
     }
 
-    pub fn fetch_user_borrow_token_balance(
-        _deps: Deps,
+    /**
+     * @dev Sets how long settlement prices are kept and which window, if any, liquidation
+     * checks average them over. The liquidation window cannot exceed the kept history.
+     */
+    pub fn update_twap_config(
+        _deps: DepsMut,
         _env: Env,
-        _user_address: Addr,
-    ) -> StdResult<GetUserBorrowTokenResponse> {
-        match USER_BORROW_BALANCE.may_load(_deps.storage, &_user_address) {
-            Ok(opt_data) => match opt_data {
-                Some(data) => Ok(GetUserBorrowTokenResponse {
-                    borrrow_vtoken_amount: data,
-                }),
-                None => Ok(GetUserBorrowTokenResponse {
-                    borrrow_vtoken_amount: Uint128::zero(),
-                }),
-            },
-            Err(_) => Err(ContractError::UserBorrowTokenBalanceQueryFailed {}.into()),
+        _info: MessageInfo,
+        _twap_config: TwapConfig,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        let liquidation_window = _twap_config.liquidation_window.unwrap_or(1);
+        if _twap_config.max_window == 0
+            || liquidation_window == 0
+            || liquidation_window > _twap_config.max_window
+        {
+            return Err(ContractError::InvalidTwapConfig {});
         }
+        TWAP_CONFIG.save(_deps.storage, &_twap_config)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_twap_config")
+            .add_attribute("max_window", _twap_config.max_window.to_string())
+            .add_attributes(
+                _twap_config
+                    .liquidation_window
+                    .map(|window| ("liquidation_window", window.to_string())),
+            )
+            .add_event(config_event("twap", None, &_twap_config)?))
     }
 
-    pub fn fetch_user_profit_token_balance(
-        _deps: Deps,
+    /**
+     * @dev Sets the sources the price of `asset` is aggregated from, in fallback order, and
+     * how many of them must be fresh for a median. TWAP windows cannot exceed the kept
+     * settlement history.
+     */
+    pub fn update_price_sources(
+        _deps: DepsMut,
         _env: Env,
-        _user_address: Addr,
-    ) -> StdResult<GetProfitTokenResponse> {
-        match USER_PROFIT_TOKEN.may_load(_deps.storage, &_user_address) {
-            Ok(opt_data) => match opt_data {
-                Some(data) => Ok(GetProfitTokenResponse {
-                    profit_vtoken_amount: data,
-                }),
-                None => Ok(GetProfitTokenResponse {
-                    profit_vtoken_amount: Uint128::zero(),
-                }),
-            },
-            Err(_) => Err(ContractError::UserProfitTokenBalanceQueryFailed {}.into()),
+        _info: MessageInfo,
+        _asset: Addr,
+        _price_sources: PriceSources,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        let sources = &_price_sources.sources;
+        if _price_sources.quorum == 0 || _price_sources.quorum as usize > sources.len() {
+            return Err(ContractError::InvalidPriceSources {});
+        }
+        let max_window = load_twap_config(_deps.storage)?.max_window;
+        for (index, source) in sources.iter().enumerate() {
+            if sources[..index].contains(source) {
+                return Err(ContractError::InvalidPriceSources {});
+            }
+            match source {
+                PriceSource::Feed => {}
+                PriceSource::Twap { window } => {
+                    if *window == 0 || *window > max_window {
+                        return Err(ContractError::InvalidPriceSources {});
+                    }
+                }
+                PriceSource::Oracle { contract } => {
+                    _deps.api.addr_validate(contract.as_str())?;
+                }
+            }
         }
+        PRICE_SOURCES.save(_deps.storage, &_asset, &_price_sources)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_price_sources")
+            .add_attribute("asset", _asset.clone())
+            .add_attribute("sources", sources.len().to_string())
+            .add_attribute("quorum", _price_sources.quorum.to_string())
+            .add_event(config_event("price_sources", Some(&_asset), &_price_sources)?))
     }
 
-    pub fn fetch_listed_tokens(deps: Deps, _env: Env) -> StdResult<GetListedTokenResponse> {
-        match LISTED_TOKEN.may_load(deps.storage) {
-            Ok(opt_listed_token) => match opt_listed_token {
-                Some(data) => Ok(GetListedTokenResponse { listed_token: data }),
-                None => Ok(GetListedTokenResponse {
-                    listed_token: vec![],
-                }),
-            },
-            Err(_) => Err(ContractError::UnableToFetchListedToken {}.into()),
+    /**
+     * @dev Pulls the latest price of `asset` from each of its oracle sources. Anyone may
+     * call it, keepers do before acting on the asset. A price not newer than the one already
+     * pulled is skipped, a price from the future is rejected.
+     */
+    pub fn sync_oracle_prices(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _asset: Addr,
+    ) -> Result<Response, ContractError> {
+        let asset = asset_name(_deps.storage, &_asset)?;
+
+        let mut response = Response::new()
+            .add_attribute("method", "sync_oracle_prices")
+            .add_attribute("asset", _asset.clone());
+        for source in load_price_sources(_deps.storage, &_asset)?.sources {
+            let contract = match source {
+                PriceSource::Oracle { contract } => contract,
+                _ => continue,
+            };
+            let oracle_price: PriceOracleResponse = _deps.querier.query_wasm_smart(
+                &contract,
+                &PriceOracleQueryMsg::Price {
+                    asset: asset.clone(),
+                },
+            )?;
+            if oracle_price.price.is_zero() {
+                return Err(ContractError::PriceNotAvailable { asset });
+            }
+            if oracle_price.publish_time > _env.block.time {
+                return Err(ContractError::InvalidPriceTime { asset });
+            }
+
+            let last = ORACLE_PRICES.may_load(_deps.storage, (&_asset, &contract))?;
+            if last.is_some_and(|last| oracle_price.publish_time <= last.publish_time) {
+                continue;
+            }
+            ORACLE_PRICES.save(
+                _deps.storage,
+                (&_asset, &contract),
+                &OraclePrice {
+                    price: oracle_price.price,
+                    publish_time: oracle_price.publish_time,
+                },
+            )?;
+            response = response.add_event(Event::from(LeverageEvent::PriceUpdate {
+                asset: _asset.clone(),
+                price: oracle_price.price,
+                publish_time: oracle_price.publish_time,
+                feeder: contract,
+            }));
         }
+
+        Ok(response)
     }
-}
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn reply(_deps: DepsMut, _env: Env, _msg: Reply) -> Result<Response, ContractError> {
-    Ok(Response::new())
-}
+    /**
+     * Function to place a stop-loss, take-profit or trailing-stop order.
+     *
+     * The order sells `token_in` from the sender's position for `token_out` once the
+     * price of `token_in` (in `token_out`) crosses its trigger. When `amount_in` is not
+     * set the order is attached to the whole position and sells whatever is held at
+     * execution time. The current price is recorded as the reference price, which is
+     * the starting high-water mark of a trailing stop.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _order_data Conditional order parameters.
+     * @return A response object indicating success or failure.
+     */
+    pub fn place_conditional_order(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _order_data: ConditionalOrderData,
+    ) -> Result<Response, ContractError> {
+        let _user_address = resolve_account(
+            _deps.storage,
+            &_env,
+            &_info.sender,
+            _order_data.on_behalf_of,
+            _order_data.sub_account,
+            OperatorScope::Trade,
+        )?;
 
-#[cfg(test)]
-mod test {
-    use std::ops::Add;
+        if _order_data.token_in == _order_data.token_out {
+            return Err(ContractError::InvalidConditionalOrder {});
+        }
 
-    use super::*;
-    use cosmwasm_std::{coins, Addr};
-    use cw_multi_test::{AppBuilder, ContractWrapper, Executor};
+        match &_order_data.kind {
+            ConditionalOrderKind::StopLoss { trigger_price }
+            | ConditionalOrderKind::TakeProfit { trigger_price } => {
+                if trigger_price.is_zero() {
+                    return Err(ContractError::InvalidConditionalOrder {});
+                }
+            }
+            ConditionalOrderKind::TrailingStop { trail_bps } => {
+                if *trail_bps == 0 || *trail_bps >= 10_000 {
+                    return Err(ContractError::InvalidConditionalOrder {});
+                }
+            }
+        }
 
-    #[test]
-    fn cw_multi_instantiate() {
-        let init_funds = coins(100, "DEMON");
-        let mut app = AppBuilder::new().build(|router, _, storage| {
-            router
-                .bank
-                .init_balance(storage, &Addr::unchecked("creator"), init_funds)
-                .unwrap();
-        });
+        // The position has to exist for the order to be attached to it
+        let position = USER_POSITION_BALANCE
+            .may_load(_deps.storage, (&_user_address, &_order_data.token_in))?
+            .unwrap_or_default();
+        if position.is_zero() {
+            return Err(ContractError::InsufficientBalance {});
+        }
+        if let Some(amount_in) = _order_data.amount_in {
+            if amount_in.is_zero() || amount_in.gt(&position) {
+                return Err(ContractError::InsufficientBalance {});
+            }
+        }
 
-        let group_code_id =
-            app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let reference_price = cross_price(
+            _deps.storage,
+            &_order_data.token_in,
+            &_order_data.token_out,
+            Pricing::at(_env.block.time),
+        )?;
 
-        let msg = InstantiateMsg {
-            token_contract_address: String::from("usdc_contract"),
-        };
+        let order_id = CONDITIONAL_ORDER_SEQ.may_load(_deps.storage)?.unwrap_or_default() + 1;
+        CONDITIONAL_ORDER_SEQ.save(_deps.storage, &order_id)?;
 
-        let contract_addr = app.instantiate_contract(
-            group_code_id,
-            Addr::unchecked("creator"),
-            &msg,
-            &[],
-            "leverage_contract",
-            None,
-        );
+        let order = ConditionalOrder {
+            order_id,
+            owner: _user_address.clone(),
+            token_in: _order_data.token_in,
+            token_out: _order_data.token_out,
+            amount_in: _order_data.amount_in,
+            kind: _order_data.kind,
+            bounty: _order_data.bounty,
+            reference_price,
+        };
+        CONDITIONAL_ORDERS.save(_deps.storage, (&_user_address, order_id), &order)?;
 
-        let cont = contract_addr.unwrap();
+        Ok(Response::new()
+            .add_attribute("method", "place_conditional_order")
+            .add_attribute("user", _user_address.clone())
+            .add_attribute("order_id", order_id.to_string())
+            .add_event(Event::from(LeverageEvent::ConditionalOrderPlace {
+                user: _user_address,
+                order_id,
+                token_in: order.token_in,
+                token_out: order.token_out,
+                amount_in: order.amount_in,
+                kind: order.kind,
+                bounty: order.bounty,
+            })))
+    }
 
-        let execute_deposit_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: String::from("user_one"),
-            amount: Uint128::from(1000u128),
-            msg: to_json_binary(&{}).unwrap(),
+    pub fn cancel_conditional_order(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _order_id: u64,
+        _on_behalf_of: Option<Addr>,
+        _sub_account: Option<u32>,
+    ) -> Result<Response, ContractError> {
+        let _user_address = resolve_account(
+            _deps.storage,
+            &_env,
+            &_info.sender,
+            _on_behalf_of,
+            _sub_account,
+            OperatorScope::Trade,
+        )?;
+
+        if !CONDITIONAL_ORDERS.has(_deps.storage, (&_user_address, _order_id)) {
+            return Err(ContractError::ConditionalOrderNotFound {});
+        }
+
+        CONDITIONAL_ORDERS.remove(_deps.storage, (&_user_address, _order_id));
+
+        Ok(Response::new()
+            .add_attribute("method", "cancel_conditional_order")
+            .add_attribute("user", _user_address.clone())
+            .add_attribute("order_id", _order_id.to_string())
+            .add_events(cancel_events(&_user_address, &[_order_id])))
+    }
+
+    /**
+     * Function for keepers to execute a triggered conditional order.
+     *
+     * Anyone can call this. The current price is checked against the order's trigger and,
+     * when triggered, the order is settled through the same path as `ExecuteOrder`: at the
+     * current price, or through the swap router once one is configured. The bounty is
+     * paid out of the `token_out` proceeds as collateral of the keeper, like a
+     * liquidator's reward. A trailing stop that is not triggered but sees a new high
+     * price has its high-water mark raised instead.
+     *
+     * The account's health is not checked afterwards: the order only sells part of an
+     * existing position at the current price, so it never adds exposure, and a stop that
+     * reverts once the account is underwater or a price quorum is missing would fail
+     * exactly when it is needed.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the keeper.
+     * @param _owner Owner of the conditional order.
+     * @param _order_id Id of the conditional order.
+     * @return A response object indicating success or failure.
+     */
+    pub fn execute_conditional_order(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _owner: Addr,
+        _order_id: u64,
+    ) -> Result<Response, ContractError> {
+        let mut order = match CONDITIONAL_ORDERS.may_load(_deps.storage, (&_owner, _order_id))? {
+            Some(order) => order,
+            None => return Err(ContractError::ConditionalOrderNotFound {}),
+        };
+
+        let price = cross_price(
+            _deps.storage,
+            &order.token_in,
+            &order.token_out,
+            Pricing::at(_env.block.time),
+        )?;
+
+        let triggered = match &order.kind {
+            ConditionalOrderKind::StopLoss { trigger_price } => price.le(trigger_price),
+            ConditionalOrderKind::TakeProfit { trigger_price } => price.ge(trigger_price),
+            ConditionalOrderKind::TrailingStop { trail_bps } => {
+                if price.gt(&order.reference_price) {
+                    order.reference_price = price;
+                    false
+                } else {
+                    let stop_price = order.reference_price
+                        * Decimal::from_ratio(10_000u64 - trail_bps, 10_000u64);
+                    price.le(&stop_price)
+                }
+            }
+        };
+
+        if !triggered {
+            if let ConditionalOrderKind::TrailingStop { .. } = order.kind {
+                CONDITIONAL_ORDERS.save(_deps.storage, (&_owner, _order_id), &order)?;
+                return Ok(Response::new()
+                    .add_attribute("method", "execute_conditional_order")
+                    .add_attribute("order_id", _order_id.to_string())
+                    .add_attribute("reference_price", order.reference_price.to_string())
+                    .add_event(Event::from(LeverageEvent::ConditionalOrderUpdate {
+                        user: _owner,
+                        order_id: _order_id,
+                        reference_price: order.reference_price,
+                    })));
+            }
+            return Err(ContractError::ConditionalOrderNotTriggered {});
+        }
+
+        let position = USER_POSITION_BALANCE
+            .may_load(_deps.storage, (&_owner, &order.token_in))?
+            .unwrap_or_default();
+        let amount_in = match order.amount_in {
+            Some(amount_in) => amount_in,
+            None => position,
+        };
+        if amount_in.is_zero() || amount_in.gt(&position) {
+            return Err(ContractError::InsufficientBalance {});
+        }
+
+        CONDITIONAL_ORDERS.remove(_deps.storage, (&_owner, _order_id));
+
+        // A routed order gets what the router pays, at least the price less its slippage
+        if let Some(swap_config) = SWAP_CONFIG.may_load(_deps.storage)? {
+            let routed_order = OrderExecute {
+                user_address: _owner.clone(),
+                token_in: order.token_in.clone(),
+                token_out: order.token_out.clone(),
+                amount_in,
+                amount_out: Uint128::zero(),
+                encrypted_order: None,
+            };
+            let bounty = KeeperBounty {
+                keeper: _info.sender.clone(),
+                order_id: _order_id,
+                amount: order.bounty,
+            };
+            let (swap_msg, min_out) =
+                dispatch_swap(_deps, &_env, &routed_order, swap_config, None, None, Some(bounty))?;
+            return Ok(Response::new()
+                .add_attribute("method", "execute_conditional_order")
+                .add_attribute("order_id", _order_id.to_string())
+                .add_attribute("user", _owner)
+                .add_attribute("keeper", _info.sender)
+                .add_attribute("amount_in", amount_in)
+                .add_attribute("min_out", min_out)
+                .add_submessage(swap_msg));
+        }
+
+        let amount_out = amount_in.mul_floor(price);
+        let settlement = settle_order(
+            _deps.storage,
+            &OrderExecute {
+                user_address: _owner.clone(),
+                token_in: order.token_in.clone(),
+                token_out: order.token_out.clone(),
+                amount_in,
+                amount_out,
+                encrypted_order: None,
+            },
+            _env.block.time,
+        )?;
+        let (bounty, bounty_event) = pay_bounty(
+            _deps.storage,
+            &_owner,
+            &order.token_out,
+            KeeperBounty {
+                keeper: _info.sender.clone(),
+                order_id: _order_id,
+                amount: order.bounty,
+            },
+            amount_out - settlement.fee,
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "execute_conditional_order")
+            .add_attribute("order_id", _order_id.to_string())
+            .add_attribute("user", _owner.clone())
+            .add_attribute("keeper", _info.sender)
+            .add_attribute("amount_in", amount_in)
+            .add_attribute("amount_out", amount_out)
+            .add_attribute("bounty", bounty)
+            .add_event(Event::from(settlement.event))
+            .add_events(fee_events("taker", &order.token_out, settlement.fee))
+            .add_events(cancel_events(&_owner, &settlement.cancelled_orders))
+            .add_event(Event::from(bounty_event)))
+    }
+
+    /// Pays the keeper of a conditional order out of the `proceeds` credited to the owner,
+    /// as collateral like a liquidator's reward. Returns the bounty paid and its event.
+    fn pay_bounty(
+        storage: &mut dyn Storage,
+        owner: &Addr,
+        token_out: &Addr,
+        bounty: KeeperBounty,
+        proceeds: Uint128,
+    ) -> Result<(Uint128, LeverageEvent), ContractError> {
+        let amount = bounty.amount.min(proceeds);
+        if !amount.is_zero() {
+            debit_position(storage, owner, token_out, amount)?;
+            credit_collateral(storage, token_out, &bounty.keeper, amount)?;
+        }
+        let keeper = account_balances(storage, &bounty.keeper, token_out)?;
+        let event = LeverageEvent::KeeperBounty {
+            user: owner.clone(),
+            keeper: bounty.keeper,
+            order_id: bounty.order_id,
+            asset: token_out.clone(),
+            amount,
+            keeper_collateral_balance: keeper.collateral,
+            keeper_unminted_balance: keeper.unminted,
+            balances: account_balances(storage, owner, token_out)?,
+        };
+        Ok((amount, event))
+    }
+
+    pub fn update_paillier_public_key(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _n: Uint512,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        let public_key = PublicKey::new(_n)?;
+        PAILLIER_PUBLIC_KEY.save(_deps.storage, &public_key)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_paillier_public_key")
+            .add_attribute("n", _n.to_string())
+            .add_event(config_event("paillier_key", None, &_n)?))
+    }
+
+    /**
+     * Function to commit an encrypted order value before it is matched.
+     *
+     * `encrypted_order_value` is the Paillier ciphertext of the order amount, as sent to
+     * the matching service. It stays hidden until `ExecuteOrder` reveals and proves it.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _order_id Id of the order in the matching service.
+     * @param _encrypted_order_value Paillier ciphertext of the order amount.
+     * @return A response object indicating success or failure.
+     */
+    pub fn commit_encrypted_order(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _order_id: String,
+        _encrypted_order_value: Uint512,
+        _on_behalf_of: Option<Addr>,
+        _sub_account: Option<u32>,
+    ) -> Result<Response, ContractError> {
+        let _user_address = resolve_account(
+            _deps.storage,
+            &_env,
+            &_info.sender,
+            _on_behalf_of,
+            _sub_account,
+            OperatorScope::Trade,
+        )?;
+
+        let public_key = match PAILLIER_PUBLIC_KEY.may_load(_deps.storage)? {
+            Some(public_key) => public_key,
+            None => return Err(ContractError::InvalidPaillierKey {}),
+        };
+        public_key.ensure_ciphertext(_encrypted_order_value)?;
+
+        if ENCRYPTED_ORDERS.has(_deps.storage, (&_user_address, _order_id.as_str())) {
+            return Err(ContractError::EncryptedOrderExists {});
+        }
+        ENCRYPTED_ORDERS.save(
+            _deps.storage,
+            (&_user_address, _order_id.as_str()),
+            &_encrypted_order_value,
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "commit_encrypted_order")
+            .add_attribute("user", _user_address.clone())
+            .add_attribute("order_id", _order_id.clone())
+            .add_event(Event::from(LeverageEvent::EncryptedOrderCommit {
+                user: _user_address,
+                order_id: _order_id,
+            })))
+    }
+
+    /**
+     * Function to commit to an order without revealing it.
+     *
+     * `commitment` is `sha256(sender ++ to_json_vec(&RevealedOrderData))`. The margin is
+     * moved out of the sender's position into escrow until the order is revealed and
+     * settled, or the commitment expires.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _commitment Hash of the order.
+     * @param _margin_token Token the order will sell, escrowed from the position.
+     * @param _margin_amount Amount escrowed, at least the order's `amount_in`.
+     * @return A response object indicating success or failure.
+     */
+    #[allow(clippy::too_many_arguments)]
+    pub fn commit_order(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _commitment: Binary,
+        _margin_token: Addr,
+        _margin_amount: Uint128,
+        _on_behalf_of: Option<Addr>,
+        _sub_account: Option<u32>,
+    ) -> Result<Response, ContractError> {
+        let _user_address = resolve_account(
+            _deps.storage,
+            &_env,
+            &_info.sender,
+            _on_behalf_of,
+            _sub_account,
+            OperatorScope::Trade,
+        )?;
+
+        if _commitment.len() != 32 || _margin_amount.is_zero() {
+            return Err(ContractError::InvalidOrderCommitment {});
+        }
+
+        debit_position(_deps.storage, &_user_address, &_margin_token, _margin_amount)?;
+
+        let commitment_id = ORDER_COMMITMENT_SEQ.may_load(_deps.storage)?.unwrap_or_default() + 1;
+        ORDER_COMMITMENT_SEQ.save(_deps.storage, &commitment_id)?;
+
+        ORDER_COMMITMENTS.save(
+            _deps.storage,
+            commitment_id,
+            &OrderCommitment {
+                commitment_id,
+                owner: _user_address.clone(),
+                commitment: _commitment,
+                margin_token: _margin_token.clone(),
+                margin_amount: _margin_amount,
+                committed_at: _env.block.height,
+            },
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "commit_order")
+            .add_attribute("user", _user_address.clone())
+            .add_attribute("commitment_id", commitment_id.to_string())
+            .add_event(Event::from(LeverageEvent::OrderCommit {
+                balances: account_balances(_deps.storage, &_user_address, &_margin_token)?,
+                user: _user_address,
+                commitment_id,
+                asset: _margin_token,
+                amount: _margin_amount,
+            })))
+    }
+
+    /**
+     * Function to reveal a committed order.
+     *
+     * The reveal must happen in a later block than the commit and within the reveal
+     * window. The order joins the batch of the current block, margin above `amount_in`
+     * is returned to the position right away.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _commitment_id Id of the commitment.
+     * @param _order Order preimage of the commitment.
+     * @return A response object indicating success or failure.
+     */
+    pub fn reveal_order(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _commitment_id: u64,
+        _order: RevealedOrderData,
+        _on_behalf_of: Option<Addr>,
+        _sub_account: Option<u32>,
+    ) -> Result<Response, ContractError> {
+        let _user_address = resolve_account(
+            _deps.storage,
+            &_env,
+            &_info.sender,
+            _on_behalf_of,
+            _sub_account,
+            OperatorScope::Trade,
+        )?;
+
+        let commitment = match ORDER_COMMITMENTS.may_load(_deps.storage, _commitment_id)? {
+            Some(commitment) => commitment,
+            None => return Err(ContractError::OrderCommitmentNotFound {}),
+        };
+
+        if commitment.owner != _user_address {
+            return Err(ContractError::Unauthorized {});
+        }
+
+        let reveal_window = load_reveal_window(_deps.storage)?;
+        if _env.block.height <= commitment.committed_at
+            || _env.block.height > commitment.committed_at + reveal_window
+        {
+            return Err(ContractError::RevealWindowClosed {});
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(_user_address.as_bytes());
+        hasher.update(to_json_vec(&_order)?);
+        if hasher.finalize().as_slice() != commitment.commitment.as_slice() {
+            return Err(ContractError::InvalidOrderCommitment {});
+        }
+
+        if _order.token_in != commitment.margin_token
+            || _order.token_in == _order.token_out
+            || _order.amount_in.is_zero()
+            || _order.amount_in.gt(&commitment.margin_amount)
+            || _order.limit_price.is_zero()
+        {
+            return Err(ContractError::InvalidOrderCommitment {});
+        }
+
+        ORDER_COMMITMENTS.remove(_deps.storage, _commitment_id);
+
+        let mut response = Response::new()
+            .add_attribute("method", "reveal_order")
+            .add_attribute("user", _user_address.clone())
+            .add_attribute("commitment_id", _commitment_id.to_string())
+            .add_attribute("batch_height", _env.block.height.to_string())
+            .add_event(Event::from(LeverageEvent::OrderReveal {
+                user: _user_address.clone(),
+                commitment_id: _commitment_id,
+                token_in: _order.token_in.clone(),
+                token_out: _order.token_out.clone(),
+                amount_in: _order.amount_in,
+                limit_price: _order.limit_price,
+                batch_height: _env.block.height,
+            }));
+
+        let excess_margin = commitment.margin_amount - _order.amount_in;
+        if !excess_margin.is_zero() {
+            credit_position(_deps.storage, &_user_address, &_order.token_in, excess_margin)?;
+            response = response.add_event(Event::from(LeverageEvent::OrderRefund {
+                user: _user_address.clone(),
+                commitment_id: _commitment_id,
+                kind: String::from("excess_margin"),
+                asset: _order.token_in.clone(),
+                amount: excess_margin,
+                balances: account_balances(_deps.storage, &_user_address, &_order.token_in)?,
+            }));
+        }
+
+        REVEALED_ORDERS.save(
+            _deps.storage,
+            (_env.block.height, _commitment_id),
+            &RevealedOrder {
+                commitment_id: _commitment_id,
+                owner: _user_address,
+                token_in: _order.token_in,
+                token_out: _order.token_out,
+                amount_in: _order.amount_in,
+                limit_price: _order.limit_price,
+            },
+        )?;
+
+        Ok(response)
+    }
+
+    /// Anyone can refund the margin of a commitment that was not revealed in time
+    pub fn expire_order_commitment(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _commitment_id: u64,
+    ) -> Result<Response, ContractError> {
+        let commitment = match ORDER_COMMITMENTS.may_load(_deps.storage, _commitment_id)? {
+            Some(commitment) => commitment,
+            None => return Err(ContractError::OrderCommitmentNotFound {}),
+        };
+
+        let reveal_window = load_reveal_window(_deps.storage)?;
+        if _env.block.height <= commitment.committed_at + reveal_window {
+            return Err(ContractError::RevealWindowOpen {});
+        }
+
+        ORDER_COMMITMENTS.remove(_deps.storage, _commitment_id);
+        credit_position(
+            _deps.storage,
+            &commitment.owner,
+            &commitment.margin_token,
+            commitment.margin_amount,
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "expire_order_commitment")
+            .add_attribute("user", commitment.owner.clone())
+            .add_attribute("commitment_id", _commitment_id.to_string())
+            .add_attribute("refund", commitment.margin_amount)
+            .add_event(Event::from(LeverageEvent::OrderRefund {
+                balances: account_balances(
+                    _deps.storage,
+                    &commitment.owner,
+                    &commitment.margin_token,
+                )?,
+                user: commitment.owner,
+                commitment_id: _commitment_id,
+                kind: String::from("expired"),
+                asset: commitment.margin_token,
+                amount: commitment.margin_amount,
+            })))
+    }
+
+    /**
+     * Function to settle the orders revealed at `height`.
+     *
+     * Anyone can call this once the block at `height` is final. Orders are grouped per
+     * token pair and every pair clears at one uniform price (see `batch::clear_batch`).
+     * Filled amounts are credited to the positions, unfilled margin is refunded. Every fill
+     * is observed for the TWAP of both legs like any other settlement.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _height Block height of the batch.
+     * @return A response object indicating success or failure.
+     */
+    pub fn settle_order_batch(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _height: u64,
+    ) -> Result<Response, ContractError> {
+        if _height >= _env.block.height {
+            return Err(ContractError::RevealWindowOpen {});
+        }
+
+        let revealed_orders: Vec<RevealedOrder> = REVEALED_ORDERS
+            .prefix(_height)
+            .range(_deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, order)| order))
+            .collect::<StdResult<_>>()?;
+
+        // Group the batch by pair, the lower address is the base token
+        let mut pairs: BTreeMap<(Addr, Addr), Vec<RevealedOrder>> = BTreeMap::new();
+        for order in revealed_orders {
+            let pair = if order.token_in < order.token_out {
+                (order.token_in.clone(), order.token_out.clone())
+            } else {
+                (order.token_out.clone(), order.token_in.clone())
+            };
+            pairs.entry(pair).or_default().push(order);
+        }
+
+        let mut response = Response::new()
+            .add_attribute("method", "settle_order_batch")
+            .add_attribute("batch_height", _height.to_string());
+
+        let fee_config = load_fee_config(_deps.storage)?;
+
+        for ((base, quote), orders) in pairs {
+            let batch_orders: Vec<BatchOrder> = orders
+                .iter()
+                .map(|order| {
+                    let sells_base = order.token_in == base;
+                    // A buy order limits the price of quote in base, flip it to quote per base
+                    let limit_price = if sells_base {
+                        order.limit_price
+                    } else {
+                        Decimal::one()
+                            .checked_div(order.limit_price)
+                            .unwrap_or_default()
+                    };
+                    BatchOrder {
+                        order_id: order.commitment_id,
+                        sells_base,
+                        amount_in: order.amount_in,
+                        limit_price,
+                    }
+                })
+                .collect();
+
+            let clearing = clear_batch(&batch_orders);
+
+            for (index, order) in orders.iter().enumerate() {
+                let (spent, received) = match &clearing {
+                    Some(clearing) => (clearing.fills[index].spent, clearing.fills[index].received),
+                    None => (Uint128::zero(), Uint128::zero()),
+                };
+
+                let refund = order.amount_in - spent;
+                if !refund.is_zero() {
+                    credit_position(_deps.storage, &order.owner, &order.token_in, refund)?;
+                }
+                if !received.is_zero() {
+                    observe_settlement(
+                        _deps.storage,
+                        (&order.token_in, spent),
+                        (&order.token_out, received),
+                        _env.block.time,
+                    )?;
+                    let fee =
+                        take_fee(_deps.storage, &order.token_out, received, fee_config.maker_fee_bps)?;
+                    credit_position(
+                        _deps.storage,
+                        &order.owner,
+                        &order.token_out,
+                        received - fee,
+                    )?;
+                    response = response
+                        .add_event(Event::from(LeverageEvent::OrderSettled {
+                            user: order.owner.clone(),
+                            token_in: order.token_in.clone(),
+                            token_out: order.token_out.clone(),
+                            amount_in: spent,
+                            amount_out: received,
+                            fee,
+                            balances: account_balances(
+                                _deps.storage,
+                                &order.owner,
+                                &order.token_out,
+                            )?,
+                        }))
+                        .add_events(fee_events("maker", &order.token_out, fee));
+                }
+                // Emitted for every order, the escrowed `token_in` is settled either way
+                response = response.add_event(Event::from(LeverageEvent::OrderRefund {
+                    user: order.owner.clone(),
+                    commitment_id: order.commitment_id,
+                    kind: String::from("unfilled"),
+                    asset: order.token_in.clone(),
+                    amount: refund,
+                    balances: account_balances(_deps.storage, &order.owner, &order.token_in)?,
+                }));
+                REVEALED_ORDERS.remove(_deps.storage, (_height, order.commitment_id));
+            }
+
+            response = match clearing {
+                Some(clearing) => response
+                    .add_attribute(
+                        format!("clearing_price_{}_{}", base, quote),
+                        clearing.clearing_price.to_string(),
+                    )
+                    .add_attribute(format!("volume_{}_{}", base, quote), clearing.base_volume),
+                None => response.add_attribute(format!("volume_{}_{}", base, quote), "0"),
+            };
+        }
+
+        Ok(response)
+    }
+
+    pub fn update_reveal_window(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _blocks: u64,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        if _blocks == 0 {
+            return Err(ContractError::GenericError {
+                error: String::from("reveal window must be at least one block"),
+            });
+        }
+        ORDER_REVEAL_WINDOW.save(_deps.storage, &_blocks)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_reveal_window")
+            .add_attribute("blocks", _blocks.to_string())
+            .add_event(config_event("reveal_window", None, &_blocks)?))
+    }
+
+    fn load_reveal_window(storage: &dyn Storage) -> Result<u64, ContractError> {
+        Ok(ORDER_REVEAL_WINDOW
+            .may_load(storage)?
+            .unwrap_or(DEFAULT_ORDER_REVEAL_WINDOW))
+    }
+
+    pub fn update_swap_config(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _router: Addr,
+        _max_slippage_bps: u64,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        if _max_slippage_bps >= 10_000 {
+            return Err(ContractError::GenericError {
+                error: String::from("max slippage must be below 10000 bps"),
+            });
+        }
+
+        let router = _deps.api.addr_validate(_router.as_str())?;
+        let swap_config = SwapConfig {
+            router: router.clone(),
+            max_slippage_bps: _max_slippage_bps,
+        };
+        SWAP_CONFIG.save(_deps.storage, &swap_config)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_swap_config")
+            .add_attribute("router", router)
+            .add_attribute("max_slippage_bps", _max_slippage_bps.to_string())
+            .add_event(config_event("swap", None, &swap_config)?))
+    }
+
+    /**
+     * Function to route an order through the swap router.
+     *
+     * The minimum output is the larger of the order's `amount_out` and the price registry
+     * quote reduced by `max_slippage_bps`, both assets must be priced. The contract's
+     * `token_out` balance is recorded before the swap so `reply` can measure the output.
+     */
+    fn swap_order(
+        _deps: DepsMut,
+        _env: Env,
+        _order: OrderExecute,
+        _swap_config: SwapConfig,
+    ) -> Result<Response, ContractError> {
+        let min_health_factor = load_risk_config(_deps.storage)?.liquidation_threshold;
+        let (swap_msg, min_out) = dispatch_swap(
+            _deps,
+            &_env,
+            &_order,
+            _swap_config,
+            Some(min_health_factor),
+            None,
+            None,
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "execute_order")
+            .add_attribute("user", _order.user_address)
+            .add_attribute("token_in", _order.token_in)
+            .add_attribute("token_out", _order.token_out)
+            .add_attribute("amount_in", _order.amount_in)
+            .add_attribute("min_out", min_out)
+            .add_submessage(swap_msg))
+    }
+
+    /**
+     * @dev Sends `amount_in` of the order's position to the swap router and records the
+     * pending swap for `reply`. With `min_health_factor` set, `reply` also reverts when
+     * the account's health factor ends up below it. With `close` set, `reply` finishes
+     * closing the position with the proceeds. With `bounty` set, `reply` pays the keeper
+     * of a conditional order out of the proceeds.
+     *
+     * Both assets must be priced, the price registry quote bounds the output.
+     *
+     * @return The swap submessage and the enforced minimum output.
+     */
+    fn dispatch_swap(
+        _deps: DepsMut,
+        _env: &Env,
+        _order: &OrderExecute,
+        _swap_config: SwapConfig,
+        _min_health_factor: Option<Decimal>,
+        _close: Option<PendingClose>,
+        _bounty: Option<KeeperBounty>,
+    ) -> Result<(SubMsg, Uint128), ContractError> {
+        let position = USER_POSITION_BALANCE
+            .may_load(_deps.storage, (&_order.user_address, &_order.token_in))?
+            .unwrap_or_default();
+        if position.lt(&_order.amount_in) {
+            return Err(ContractError::InsufficientBalance {});
+        }
+
+        let price = cross_price(
+            _deps.storage,
+            &_order.token_in,
+            &_order.token_out,
+            Pricing::at(_env.block.time),
+        )?;
+        let max_slippage = Decimal::from_ratio(_swap_config.max_slippage_bps, 10_000u64);
+        let quoted_min_out = _order
+            .amount_in
+            .mul_floor(price * (Decimal::one() - max_slippage));
+        let min_out = _order.amount_out.max(quoted_min_out);
+
+        let balance_before =
+            query_asset_balance(_deps.as_ref(), &_env.contract.address, &_order.token_out)?;
+
+        let swap_msg = SwapRouterExecuteMsg::Swap {
+            output_asset: asset_name(_deps.storage, &_order.token_out)?,
+            min_output: min_out,
+        };
+        let execute_swap: CosmosMsg =
+            match NATIVE_TOKEN_DENOM.may_load(_deps.storage, &_order.token_in)? {
+                Some(denom) => WasmMsg::Execute {
+                    contract_addr: _swap_config.router.to_string(),
+                    msg: to_json_binary(&swap_msg)?,
+                    funds: coins(_order.amount_in.u128(), denom),
+                }
+                .into(),
+                None => WasmMsg::Execute {
+                    contract_addr: _order.token_in.to_string(),
+                    msg: to_json_binary(&cw20::Cw20ExecuteMsg::Send {
+                        contract: _swap_config.router.to_string(),
+                        amount: _order.amount_in,
+                        msg: to_json_binary(&swap_msg)?,
+                    })?,
+                    funds: vec![],
+                }
+                .into(),
+            };
+
+        PENDING_SWAP.save(
+            _deps.storage,
+            &PendingSwap {
+                user_address: _order.user_address.clone(),
+                token_in: _order.token_in.clone(),
+                token_out: _order.token_out.clone(),
+                amount_in: _order.amount_in,
+                min_out,
+                balance_before,
+                min_health_factor: _min_health_factor,
+                close: _close,
+                bounty: _bounty,
+            },
+        )?;
+
+        Ok((SubMsg::reply_on_success(execute_swap, SWAP_REPLY_ID), min_out))
+    }
+
+    /**
+     * Function to settle a routed order once the swap has completed.
+     *
+     * The output is the increase of the contract's `token_out` balance. The order is
+     * settled with that amount through the regular settlement path, or the whole
+     * transaction reverts when it is below the minimum output.
+     */
+    pub fn handle_swap_reply(_deps: DepsMut, _env: Env) -> Result<Response, ContractError> {
+        let pending_swap = PENDING_SWAP.load(_deps.storage)?;
+        PENDING_SWAP.remove(_deps.storage);
+
+        let balance_after =
+            query_asset_balance(_deps.as_ref(), &_env.contract.address, &pending_swap.token_out)?;
+        let received = match balance_after.checked_sub(pending_swap.balance_before) {
+            Ok(data) => data,
+            Err(_) => return Err(ContractError::Overflow {}),
+        };
+
+        if received.lt(&pending_swap.min_out) {
+            return Err(ContractError::SlippageExceeded {
+                min_out: pending_swap.min_out,
+                received,
+            });
+        }
+
+        let settlement = settle_order(
+            _deps.storage,
+            &OrderExecute {
+                user_address: pending_swap.user_address.clone(),
+                token_in: pending_swap.token_in.clone(),
+                token_out: pending_swap.token_out.clone(),
+                amount_in: pending_swap.amount_in,
+                amount_out: received,
+                encrypted_order: None,
+            },
+            _env.block.time,
+        )?;
+
+        let mut response = Response::new()
+            .add_attribute("method", "settle_swap")
+            .add_attribute("user", pending_swap.user_address.clone())
+            .add_attribute("amount_in", pending_swap.amount_in)
+            .add_attribute("amount_out", received)
+            .add_attribute(
+                "cancelled_conditional_orders",
+                settlement.cancelled_orders.len().to_string(),
+            )
+            .add_event(Event::from(settlement.event))
+            .add_events(fee_events("taker", &pending_swap.token_out, settlement.fee))
+            .add_events(cancel_events(
+                &pending_swap.user_address,
+                &settlement.cancelled_orders,
+            ));
+
+        if let Some(bounty) = pending_swap.bounty {
+            let (bounty, event) = pay_bounty(
+                _deps.storage,
+                &pending_swap.user_address,
+                &pending_swap.token_out,
+                bounty,
+                received - settlement.fee,
+            )?;
+            response = response.add_attribute("bounty", bounty).add_event(Event::from(event));
+        }
+
+        if let Some(min_health_factor) = pending_swap.min_health_factor {
+            ensure_healthy(
+                _deps.storage,
+                &pending_swap.user_address,
+                _env.block.time,
+                min_health_factor,
+            )?;
+        }
+
+        match pending_swap.close {
+            Some(close) => finish_close(
+                _deps.storage,
+                &pending_swap.user_address,
+                &pending_swap.token_out,
+                received - settlement.fee,
+                close,
+                response,
+            ),
+            None => Ok(response),
+        }
+    }
+
+    /**
+     * Function to close all or part of a leveraged position in one message.
+     *
+     * `fraction` of the position in `asset` is sold into `debt_token` through the swap
+     * router (no swap when `asset` is the debt token itself). The proceeds repay the same
+     * `fraction` of the debt, what is left of them is realized into the profit ledger.
+     * Once the debt is fully repaid the profit is burned into collateral and, with
+     * `withdraw_type` set, all collateral in `debt_token` is withdrawn. Everything
+     * settles in `reply` and reverts together when any step fails.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _close_data Position to close, fraction, minimum output and withdraw type.
+     * @return A response object indicating success or failure.
+     */
+    pub fn close_position(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _close_data: ClosePositionData,
+    ) -> Result<Response, ContractError> {
+        if _close_data.fraction.is_zero() || _close_data.fraction > Decimal::one() {
+            return Err(ContractError::InvalidFraction {});
+        }
+        let account =
+            select_sub_account(_deps.storage, _info.sender.clone(), _close_data.sub_account)?;
+
+        let position = USER_POSITION_BALANCE
+            .may_load(_deps.storage, (&account, &_close_data.asset))?
+            .unwrap_or_default();
+        let sell_amount = if _close_data.fraction == Decimal::one() {
+            position
+        } else {
+            position.mul_floor(_close_data.fraction)
+        };
+
+        let close = PendingClose {
+            fraction: _close_data.fraction,
+            withdraw_type: _close_data.withdraw_type,
+        };
+        let response = Response::new()
+            .add_attribute("method", "close_position")
+            .add_attribute("user", account.clone())
+            .add_attribute("asset", _close_data.asset.clone())
+            .add_attribute("sell_amount", sell_amount);
+
+        if _close_data.asset == _close_data.debt_token {
+            return finish_close(
+                _deps.storage,
+                &account,
+                &_close_data.debt_token,
+                sell_amount,
+                close,
+                response,
+            );
+        }
+
+        if sell_amount.is_zero() {
+            return Err(ContractError::InsufficientBalance {});
+        }
+        let swap_config = match SWAP_CONFIG.may_load(_deps.storage)? {
+            Some(swap_config) => swap_config,
+            None => return Err(ContractError::SwapRouterNotConfigured {}),
+        };
+        let order = OrderExecute {
+            user_address: account,
+            token_in: _close_data.asset,
+            token_out: _close_data.debt_token,
+            amount_in: sell_amount,
+            amount_out: _close_data.min_out,
+            encrypted_order: None,
+        };
+        let (swap_msg, min_out) =
+            dispatch_swap(_deps, &_env, &order, swap_config, None, Some(close), None)?;
+
+        Ok(response
+            .add_attribute("min_out", min_out)
+            .add_submessage(swap_msg))
+    }
+
+    /**
+     * @dev Repays `fraction` of the debt in `debt_token` out of the account's position,
+     * realizes the rest of `proceeds` as profit and, once the debt is gone, burns the
+     * profit and withdraws the collateral when requested.
+     */
+    fn finish_close(
+        storage: &mut dyn Storage,
+        account: &Addr,
+        debt_token: &Addr,
+        proceeds: Uint128,
+        close: PendingClose,
+        response: Response,
+    ) -> Result<Response, ContractError> {
+        let debt = USER_BORROW_BALANCE
+            .may_load(storage, (debt_token, account))?
+            .unwrap_or_default();
+        let position = USER_POSITION_BALANCE
+            .may_load(storage, (account, debt_token))?
+            .unwrap_or_default();
+
+        // Repay as much of the fraction of the debt as the position covers
+        let repaid = debt.mul_ceil(close.fraction).min(debt).min(position);
+        let mut events = vec![];
+        if !repaid.is_zero() {
+            repay_from_position(storage, debt_token, account, repaid)?;
+            events.push(LeverageEvent::Repay {
+                user: account.clone(),
+                asset: debt_token.clone(),
+                amount: repaid,
+                balances: account_balances(storage, account, debt_token)?,
+            });
+        }
+
+        // Whatever the sale brought in above the repayment is realized profit, held as
+        // vTokens until it is burned back into collateral at the same 10:1
+        let position = position - repaid;
+        let profit = proceeds.saturating_sub(repaid).min(position);
+        if !profit.is_zero() {
+            debit_position(storage, account, debt_token, profit)?;
+            let v_tokens = vtoken::to_v_tokens(profit.u128()).ok_or(ContractError::Overflow {})?;
+            add_to_ledger_for(
+                storage,
+                &USER_PROFIT_TOKEN,
+                debt_token,
+                account,
+                Uint128::from(v_tokens),
+            )?;
+        }
+
+        let mut response = response
+            .add_attribute("repaid", repaid)
+            .add_attribute("realized_profit", profit);
+
+        let remaining_debt = debt - repaid;
+        if remaining_debt.is_zero() {
+            let profit_balance = USER_PROFIT_TOKEN
+                .may_load(storage, (debt_token, account))?
+                .unwrap_or_default();
+            if !profit_balance.is_zero() {
+                let (v_token_amount, collateral_amount) =
+                    burn_profit(storage, debt_token, account, profit_balance)?;
+                events.push(LeverageEvent::Burn {
+                    user: account.clone(),
+                    asset: debt_token.clone(),
+                    v_token_amount,
+                    collateral_amount,
+                    balances: account_balances(storage, account, debt_token)?,
+                });
+            }
+        }
+
+        response = response.add_events(events.into_iter().map(Event::from));
+
+        let collateral = USER_TOKEN_BALANCE
+            .may_load(storage, (debt_token, account))?
+            .unwrap_or_default();
+        match close.withdraw_type {
+            Some(withdraw_type) if !collateral.is_zero() => {
+                let (transfer_msg, fee) = withdraw_collateral(
+                    storage,
+                    debt_token,
+                    account,
+                    collateral,
+                    &withdraw_type,
+                    None,
+                )?;
+                response = response
+                    .add_attribute("withdrawn", collateral - fee)
+                    .add_message(transfer_msg)
+                    .add_event(Event::from(withdraw_event(
+                        storage, debt_token, account, collateral, fee,
+                    )?))
+                    .add_events(fee_events("withdraw", debt_token, fee));
+            }
+            _ => {}
+        }
+
+        Ok(response.add_attribute("remaining_debt", remaining_debt))
+    }
+
+    /// Adds `amount` to a per-(token, user) ledger
+    fn add_to_ledger_for(
+        storage: &mut dyn Storage,
+        ledger: &Map<(&Addr, &Addr), Uint128>,
+        token_address: &Addr,
+        user_address: &Addr,
+        amount: Uint128,
+    ) -> Result<(), ContractError> {
+        let balance = ledger
+            .may_load(storage, (token_address, user_address))?
+            .unwrap_or_default();
+        match balance.checked_add(amount) {
+            Ok(balance) => Ok(ledger.save(storage, (token_address, user_address), &balance)?),
+            Err(_) => Err(ContractError::Overflow {}),
+        }
+    }
+
+    /**
+     * Function to lend pool liquidity within a single transaction.
+     *
+     * `amount` of `asset` is sent to the sender, then the sender's
+     * `FlashLoanReceiverExecuteMsg::FlashLoanCallback` is executed with `callback`. Before
+     * the callback returns the borrower must pay back principal plus the flash loan fee,
+     * with a plain CW20 `Transfer` or a bank send to this contract. `reply` checks the
+     * contract balance and reverts the whole transaction on a shortfall. Every execute
+     * message is rejected while the loan is out, so tokens the contract credits to a ledger,
+     * like deposits or position proceeds, cannot be passed off as repayment.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the borrower contract.
+     * @param _asset Listed asset to borrow.
+     * @param _amount Amount to borrow.
+     * @param _callback Message passed through to the borrower's callback.
+     * @return A response object indicating success or failure.
+     */
+    pub fn flash_loan(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _asset: Addr,
+        _amount: Uint128,
+        _callback: Binary,
+    ) -> Result<Response, ContractError> {
+        let listed_tokens = LISTED_TOKEN.may_load(_deps.storage)?.unwrap_or_default();
+        if !listed_tokens.contains(&_asset.to_string()) {
+            return Err(ContractError::UnauthorizedToken {});
+        }
+        if _amount.is_zero() {
+            return Err(ContractError::InsufficientBalance {});
+        }
+
+        let balance_before = query_asset_balance(_deps.as_ref(), &_env.contract.address, &_asset)?;
+        if balance_before.lt(&_amount) {
+            return Err(ContractError::InsufficientBalance {});
+        }
+
+        let fee_config = load_fee_config(_deps.storage)?;
+        let fee = _amount.multiply_ratio(fee_config.flash_loan_fee_bps, 10_000u64);
+
+        PENDING_FLASH_LOAN.save(
+            _deps.storage,
+            &PendingFlashLoan {
+                borrower: _info.sender.clone(),
+                asset: _asset.clone(),
+                amount: _amount,
+                fee,
+                balance_before,
+            },
+        )?;
+
+        let callback_msg = WasmMsg::Execute {
+            contract_addr: _info.sender.to_string(),
+            msg: to_json_binary(&FlashLoanReceiverExecuteMsg::FlashLoanCallback {
+                asset: asset_name(_deps.storage, &_asset)?,
+                amount: _amount,
+                fee,
+                msg: _callback,
+            })?,
+            funds: vec![],
+        };
+
+        Ok(Response::new()
+            .add_attribute("method", "flash_loan")
+            .add_attribute("borrower", _info.sender.clone())
+            .add_attribute("asset", _asset.clone())
+            .add_attribute("amount", _amount)
+            .add_attribute("fee", fee)
+            .add_message(transfer_asset_msg(_deps.storage, &_asset, &_info.sender, _amount)?)
+            .add_submessage(SubMsg::reply_on_success(callback_msg, FLASH_LOAN_REPLY_ID)))
+    }
+
+    /// Checks that principal plus fee came back, the fee goes to the treasury
+    pub fn handle_flash_loan_reply(_deps: DepsMut, _env: Env) -> Result<Response, ContractError> {
+        let pending = PENDING_FLASH_LOAN.load(_deps.storage)?;
+        PENDING_FLASH_LOAN.remove(_deps.storage);
+
+        let balance_after =
+            query_asset_balance(_deps.as_ref(), &_env.contract.address, &pending.asset)?;
+        let expected = pending.balance_before + pending.fee;
+        if balance_after.lt(&expected) {
+            return Err(ContractError::FlashLoanNotRepaid {
+                expected,
+                balance: balance_after,
+            });
+        }
+
+        let fee = take_fee(_deps.storage, &pending.asset, pending.fee, 10_000)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "settle_flash_loan")
+            .add_attribute("borrower", pending.borrower.clone())
+            .add_attribute("amount", pending.amount)
+            .add_event(Event::from(LeverageEvent::FlashLoan {
+                borrower: pending.borrower,
+                asset: pending.asset.clone(),
+                amount: pending.amount,
+                fee,
+            }))
+            .add_events(fee_events("flash_loan", &pending.asset, fee)))
+    }
+
+    /// Checked on every execute message, only a plain transfer can repay a flash loan
+    pub fn ensure_no_flash_loan(storage: &dyn Storage) -> Result<(), ContractError> {
+        if PENDING_FLASH_LOAN.exists(storage) {
+            return Err(ContractError::FlashLoanInProgress {});
+        }
+        Ok(())
+    }
+
+    /// Native listed tokens are held under their denom, everything else is a CW20
+    fn query_asset_balance(deps: Deps, address: &Addr, asset: &Addr) -> StdResult<Uint128> {
+        match NATIVE_TOKEN_DENOM.may_load(deps.storage, asset)? {
+            Some(denom) => Ok(deps.querier.query_balance(address, denom)?.amount),
+            None => {
+                let balance: cw20::BalanceResponse = deps.querier.query_wasm_smart(
+                    asset,
+                    &cw20::Cw20QueryMsg::Balance {
+                        address: address.to_string(),
+                    },
+                )?;
+                Ok(balance.balance)
+            }
+        }
+    }
+
+    fn asset_name(storage: &dyn Storage, asset: &Addr) -> StdResult<String> {
+        Ok(NATIVE_TOKEN_DENOM
+            .may_load(storage, asset)?
+            .unwrap_or_else(|| asset.to_string()))
+    }
+
+    pub fn update_fee_config(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _fee_config: FeeConfig,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        let fees_bps = [
+            _fee_config.borrow_fee_bps,
+            _fee_config.taker_fee_bps,
+            _fee_config.maker_fee_bps,
+            _fee_config.withdraw_fee_bps,
+            _fee_config.flash_loan_fee_bps,
+        ];
+        if fees_bps.iter().any(|bps| *bps >= 10_000) || _fee_config.liquidation_fee_bps > 10_000 {
+            return Err(ContractError::InvalidFeeConfig {});
+        }
+        _deps.api.addr_validate(_fee_config.fee_collector.as_str())?;
+
+        FEE_CONFIG.save(_deps.storage, &_fee_config)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_fee_config")
+            .add_attribute("fee_collector", _fee_config.fee_collector.clone())
+            .add_event(config_event("fee", None, &_fee_config)?))
+    }
+
+    /**
+     * Function for the fee collector to claim accrued protocol fees.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the message sender.
+     * @param _asset Asset to claim.
+     * @param _amount Amount to claim, everything accrued when not set.
+     * @return A response object indicating success or failure.
+     */
+    pub fn claim_fees(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _asset: Addr,
+        _amount: Option<Uint128>,
+    ) -> Result<Response, ContractError> {
+        let fee_config = load_fee_config(_deps.storage)?;
+        if fee_config.fee_collector != _info.sender {
+            return Err(ContractError::Unauthorized {});
+        }
+
+        let accrued = TREASURY.may_load(_deps.storage, &_asset)?.unwrap_or_default();
+        let amount = _amount.unwrap_or(accrued);
+        if amount.is_zero() || amount.gt(&accrued) {
+            return Err(ContractError::InsufficientBalance {});
+        }
+        TREASURY.save(_deps.storage, &_asset, &(accrued - amount))?;
+
+        let transfer_msg = transfer_asset_msg(_deps.storage, &_asset, &_info.sender, amount)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "claim_fees")
+            .add_message(transfer_msg)
+            .add_event(Event::from(LeverageEvent::ProtocolFeeClaim {
+                asset: _asset,
+                amount,
+                fee_collector: _info.sender,
+            })))
+    }
+
+    /// Unset fees are zero and the contract owner collects them
+    pub fn load_fee_config(storage: &dyn Storage) -> Result<FeeConfig, ContractError> {
+        match FEE_CONFIG.may_load(storage)? {
+            Some(fee_config) => Ok(fee_config),
+            None => Ok(FeeConfig {
+                borrow_fee_bps: 0,
+                taker_fee_bps: 0,
+                maker_fee_bps: 0,
+                liquidation_fee_bps: 0,
+                withdraw_fee_bps: 0,
+                flash_loan_fee_bps: 0,
+                fee_collector: LEVERAGE_CONTRACT_OWNER.load(storage)?,
+            }),
+        }
+    }
+
+    /// Move `fee_bps` of `amount` (rounded down) into the treasury of `asset`, less the
+    /// insurance fund's share
+    fn take_fee(
+        storage: &mut dyn Storage,
+        asset: &Addr,
+        amount: Uint128,
+        fee_bps: u64,
+    ) -> Result<Uint128, ContractError> {
+        let fee = amount.multiply_ratio(fee_bps, 10_000u64);
+        if fee.is_zero() {
+            return Ok(fee);
+        }
+
+        let insurance_config = load_insurance_config(storage)?;
+        let insurance_share = fee.multiply_ratio(insurance_config.fee_share_bps, 10_000u64);
+        add_to_ledger(storage, &INSURANCE_FUND, asset, insurance_share)?;
+        add_to_ledger(storage, &TREASURY, asset, fee - insurance_share)?;
+        Ok(fee)
+    }
+
+    fn add_to_ledger(
+        storage: &mut dyn Storage,
+        ledger: &Map<&Addr, Uint128>,
+        asset: &Addr,
+        amount: Uint128,
+    ) -> Result<(), ContractError> {
+        if amount.is_zero() {
+            return Ok(());
+        }
+        ledger.update(storage, asset, |opt_balance| -> Result<Uint128, ContractError> {
+            match opt_balance.unwrap_or_default().checked_add(amount) {
+                Ok(data) => Ok(data),
+                Err(_) => Err(ContractError::Overflow {}),
+            }
+        })?;
+        Ok(())
+    }
+
+    fn fee_events(kind: &str, asset: &Addr, fee: Uint128) -> Vec<Event> {
+        if fee.is_zero() {
+            return vec![];
+        }
+        vec![LeverageEvent::ProtocolFee {
+            kind: kind.to_string(),
+            asset: asset.clone(),
+            amount: fee,
+        }
+        .into()]
+    }
+
+    fn config_event(
+        kind: &str,
+        asset: Option<&Addr>,
+        config: &impl serde::Serialize,
+    ) -> Result<Event, ContractError> {
+        Ok(LeverageEvent::ConfigUpdate {
+            kind: kind.to_string(),
+            asset: asset.cloned(),
+            config: to_json_string(config)?,
+        }
+        .into())
+    }
+
+    /// Ledgers of `account` in `asset` reported by the typed events, net of the pending
+    /// socialized loss like the balance queries. The health factor is `None`, and left out
+    /// of the event, when it cannot be computed: without debt or while a price is missing.
+    fn account_balances(
+        storage: &dyn Storage,
+        account: &Addr,
+        asset: &Addr,
+    ) -> Result<AccountBalances, ContractError> {
+        let (collateral, unminted) = collateral_after_loss(storage, asset, account)?;
+        Ok(AccountBalances {
+            collateral,
+            unminted,
+            borrowed: USER_BORROW_BALANCE
+                .may_load(storage, (asset, account))?
+                .unwrap_or_default(),
+            profit: USER_PROFIT_TOKEN
+                .may_load(storage, (asset, account))?
+                .unwrap_or_default(),
+            position: USER_POSITION_BALANCE
+                .may_load(storage, (account, asset))?
+                .unwrap_or_default(),
+            health_factor: margin_health(storage, account, Pricing::default())
+                .ok()
+                .and_then(|health| health.health_factor),
+        })
+    }
+
+    fn withdraw_event(
+        storage: &dyn Storage,
+        asset: &Addr,
+        account: &Addr,
+        amount: Uint128,
+        fee: Uint128,
+    ) -> Result<LeverageEvent, ContractError> {
+        Ok(LeverageEvent::Withdraw {
+            user: account.clone(),
+            asset: asset.clone(),
+            amount,
+            fee,
+            recipient: split_sub_account(account).0,
+            balances: account_balances(storage, account, asset)?,
+        })
+    }
+
+    /// CW20 transfer, or bank send for native listed tokens
+    fn transfer_asset_msg(
+        storage: &dyn Storage,
+        asset: &Addr,
+        recipient: &Addr,
+        amount: Uint128,
+    ) -> Result<CosmosMsg, ContractError> {
+        match NATIVE_TOKEN_DENOM.may_load(storage, asset)? {
+            Some(denom) => Ok(BankMsg::Send {
+                to_address: recipient.to_string(),
+                amount: coins(amount.u128(), denom),
+            }
+            .into()),
+            None => Ok(WasmMsg::Execute {
+                contract_addr: asset.to_string(),
+                msg: to_json_binary(&cw20::Cw20ExecuteMsg::Transfer {
+                    recipient: recipient.to_string(),
+                    amount,
+                })?,
+                funds: vec![],
+            }
+            .into()),
+        }
+    }
+
+    pub fn update_risk_config(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _risk_config: RiskConfig,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        if _risk_config.liquidation_threshold < Decimal::one()
+            || _risk_config.liquidation_penalty_bps >= 10_000
+        {
+            return Err(ContractError::InvalidRiskConfig {});
+        }
+        RISK_CONFIG.save(_deps.storage, &_risk_config)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_risk_config")
+            .add_attribute(
+                "liquidation_threshold",
+                _risk_config.liquidation_threshold.to_string(),
+            )
+            .add_attribute(
+                "liquidation_penalty_bps",
+                _risk_config.liquidation_penalty_bps.to_string(),
+            )
+            .add_event(config_event("risk", None, &_risk_config)?))
+    }
+
+    pub fn update_insurance_config(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _insurance_config: InsuranceConfig,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        if _insurance_config.fee_share_bps > 10_000 || _insurance_config.penalty_share_bps > 10_000 {
+            return Err(ContractError::InvalidFeeConfig {});
+        }
+        INSURANCE_CONFIG.save(_deps.storage, &_insurance_config)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_insurance_config")
+            .add_attribute("fee_share_bps", _insurance_config.fee_share_bps.to_string())
+            .add_attribute(
+                "penalty_share_bps",
+                _insurance_config.penalty_share_bps.to_string(),
+            )
+            .add_event(config_event("insurance", None, &_insurance_config)?))
+    }
+
+    /**
+     * Function to liquidate an unhealthy account's debt in `debt_token`.
+     *
+     * Anyone can liquidate once the account's health factor is below the liquidation
+     * threshold. It performs the following steps:
+     * 1. Closes every position of the user into `debt_token` at the registry price.
+     * 2. Repays the debt from the closed positions, then from the `debt_token` collateral.
+     * 3. Charges the liquidation penalty on what is left, split into the insurance fund
+     *    share, the protocol share (liquidation fee) and the liquidator's reward.
+     * 4. Returns any remainder to the user as `debt_token` collateral.
+     * 5. Covers a shortfall from the insurance fund, and socializes what the fund cannot
+     *    cover across all `debt_token` depositors.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the liquidator.
+     * @param _user_address Account to liquidate.
+     * @param _debt_token Token whose debt is liquidated.
+     * @return A response object indicating success or failure.
+     */
+    pub fn liquidate(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _user_address: Addr,
+        _debt_token: Addr,
+    ) -> Result<Response, ContractError> {
+        let debt = USER_BORROW_BALANCE
+            .may_load(_deps.storage, (&_debt_token, &_user_address))?
+            .unwrap_or_default();
+        if debt.is_zero() {
+            return Err(ContractError::NotLiquidatable {});
+        }
+
+        let risk_config = load_risk_config(_deps.storage)?;
+        let pricing = liquidation_pricing(_deps.storage, _env.block.time)?;
+        let health = margin_health(_deps.storage, &_user_address, pricing)?;
+        if !is_liquidatable(health.health_factor, risk_config.liquidation_threshold) {
+            return Err(ContractError::NotLiquidatable {});
+        }
+
+        apply_socialized_loss(_deps.storage, &_debt_token, &_user_address)?;
+
+        // Close every position into the debt token
+        let positions: Vec<(Addr, Uint128)> = USER_POSITION_BALANCE
+            .prefix(&_user_address)
+            .range(_deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?;
+        let mut seized = Uint128::zero();
+        for (asset, amount) in positions.iter() {
+            let value = if *asset == _debt_token {
+                *amount
+            } else {
+                amount.mul_floor(cross_price(
+                    _deps.storage,
+                    asset,
+                    &_debt_token,
+                    Pricing::at(_env.block.time),
+                )?)
+            };
+            seized = seized.checked_add(value).map_err(|_| ContractError::Overflow {})?;
+            USER_POSITION_BALANCE.remove(_deps.storage, (&_user_address, asset));
+        }
+        let cancelled_orders: Vec<u64> = CONDITIONAL_ORDERS
+            .prefix(&_user_address)
+            .keys(_deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?;
+        for order_id in cancelled_orders.iter() {
+            CONDITIONAL_ORDERS.remove(_deps.storage, (&_user_address, *order_id));
+        }
+
+        // The debt token collateral is seized as well
+        let collateral = USER_TOKEN_BALANCE
+            .may_load(_deps.storage, (&_debt_token, &_user_address))?
+            .unwrap_or_default();
+        sub_total_deposits(_deps.storage, &_debt_token, collateral)?;
+
+        let fee_config = load_fee_config(_deps.storage)?;
+        let insurance_config = load_insurance_config(_deps.storage)?;
+        let split = liquidation_split(
+            seized,
+            collateral,
+            debt,
+            risk_config.liquidation_penalty_bps,
+            insurance_config.penalty_share_bps,
+            fee_config.liquidation_fee_bps,
+        )
+        .ok_or(ContractError::Overflow {})?;
+        let shortfall = split.shortfall;
+        let penalty = split.penalty;
+        let remainder = split.returned_collateral;
+        let insurance_share = split.insurance_share;
+        let protocol_share = split.protocol_share;
+        let liquidator_reward = split.liquidator_reward;
+
+        add_to_ledger(_deps.storage, &INSURANCE_FUND, &_debt_token, insurance_share)?;
+        add_to_ledger(_deps.storage, &TREASURY, &_debt_token, protocol_share)?;
+
+        // Reset the liquidated token's ledgers, the remainder goes back as collateral
+        USER_BORROW_BALANCE.remove(_deps.storage, (&_debt_token, &_user_address));
+        sub_from_ledger(_deps.storage, &TOTAL_BORROWS, &_debt_token, debt)?;
+        USER_TOKEN_BALANCE.remove(_deps.storage, (&_debt_token, &_user_address));
+        USER_UNMINTED_TOKEN.remove(_deps.storage, (&_debt_token, &_user_address));
+        if !remainder.is_zero() {
+            credit_collateral(_deps.storage, &_debt_token, &_user_address, remainder)?;
+        }
+        if !liquidator_reward.is_zero() {
+            credit_collateral(_deps.storage, &_debt_token, &_info.sender, liquidator_reward)?;
+        }
+
+        let mut response = Response::new()
+            .add_attribute("method", "liquidate")
+            .add_event(Event::from(LeverageEvent::Liquidation {
+                user: _user_address.clone(),
+                liquidator: _info.sender,
+                debt_token: _debt_token.clone(),
+                debt,
+                seized_positions: seized,
+                seized_collateral: collateral,
+                penalty,
+                liquidator_reward,
+                protocol_fee: protocol_share,
+                insurance_contribution: insurance_share,
+                returned_collateral: remainder,
+                shortfall,
+            }))
+            .add_events(cancel_events(&_user_address, &cancelled_orders));
+
+        if !shortfall.is_zero() {
+            response = response.add_event(Event::from(cover_bad_debt(
+                _deps.storage,
+                &_debt_token,
+                shortfall,
+            )?));
+        }
+
+        Ok(response)
+    }
+
+    /**
+     * Covers bad debt in `asset` from the insurance fund first. Whatever the fund cannot
+     * cover scales the socialized loss index down by the share of deposits it leaves,
+     * which haircuts every depositor's collateral pro-rata the next time it is touched.
+     */
+    fn cover_bad_debt(
+        storage: &mut dyn Storage,
+        asset: &Addr,
+        shortfall: Uint128,
+    ) -> Result<LeverageEvent, ContractError> {
+        let fund = INSURANCE_FUND.may_load(storage, asset)?.unwrap_or_default();
+        let covered_by_insurance = fund.min(shortfall);
+        INSURANCE_FUND.save(storage, asset, &(fund - covered_by_insurance))?;
+
+        let residual = shortfall - covered_by_insurance;
+        let total_deposits = TOTAL_DEPOSITS.may_load(storage, asset)?.unwrap_or_default();
+        let mut loss_index = SOCIALIZED_LOSS_INDEX.may_load(storage, asset)?.unwrap_or_default();
+
+        let mut stats = BAD_DEBT.may_load(storage, asset)?.unwrap_or_default();
+        stats.covered_by_insurance += covered_by_insurance;
+
+        if !residual.is_zero() {
+            if total_deposits.is_zero() {
+                stats.unrecovered += residual;
+            } else {
+                // Never socialize more than what is deposited
+                let socialized = residual.min(total_deposits);
+                stats.unrecovered += residual - socialized;
+                stats.socialized += socialized;
+                let left = Decimal::from_ratio(total_deposits - socialized, total_deposits);
+                loss_index.index = loss_index
+                    .index
+                    .checked_mul(left)
+                    .map_err(|_| ContractError::Overflow {})?;
+                if loss_index.index.is_zero() {
+                    loss_index = LossIndex {
+                        epoch: loss_index.epoch + 1,
+                        index: Decimal::one(),
+                    };
+                }
+                SOCIALIZED_LOSS_INDEX.save(storage, asset, &loss_index)?;
+                TOTAL_DEPOSITS.save(storage, asset, &(total_deposits - socialized))?;
+            }
+        }
+        BAD_DEBT.save(storage, asset, &stats)?;
+
+        Ok(LeverageEvent::BadDebt {
+            asset: asset.clone(),
+            shortfall,
+            covered_by_insurance,
+            insurance_fund_balance: fund - covered_by_insurance,
+            socialized: stats.socialized,
+            socialized_loss_index: loss_index.index,
+            socialized_loss_epoch: loss_index.epoch,
+        })
+    }
+
+    /// Haircut the user's collateral by the loss socialized since their last checkpoint
+    fn apply_socialized_loss(
+        storage: &mut dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+    ) -> Result<(), ContractError> {
+        let loss_index = SOCIALIZED_LOSS_INDEX
+            .may_load(storage, token_address)?
+            .unwrap_or_default();
+        let balance = USER_TOKEN_BALANCE
+            .may_load(storage, (token_address, user_address))?
+            .unwrap_or_default();
+        let (collateral, unminted) = collateral_after_loss(storage, token_address, user_address)?;
+        USER_LOSS_CHECKPOINT.save(storage, (token_address, user_address), &loss_index)?;
+
+        if collateral == balance {
+            return Ok(());
+        }
+
+        USER_TOKEN_BALANCE.save(storage, (token_address, user_address), &collateral)?;
+        USER_UNMINTED_TOKEN.save(storage, (token_address, user_address), &unminted)?;
+        Ok(())
+    }
+
+    /// Collateral and unminted vTokens of the user once the pending socialized loss is
+    /// applied. Queries and typed events report these rather than the stored ledgers.
+    pub fn collateral_after_loss(
+        storage: &dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+    ) -> StdResult<(Uint128, Uint128)> {
+        let balance = USER_TOKEN_BALANCE
+            .may_load(storage, (token_address, user_address))?
+            .unwrap_or_default();
+        let unminted = USER_UNMINTED_TOKEN
+            .may_load(storage, (token_address, user_address))?
+            .unwrap_or_default();
+        let loss = pending_socialized_loss(storage, token_address, user_address, balance)?;
+        let v_token_loss = vtoken::to_v_tokens(loss.u128()).unwrap_or(u128::MAX);
+        Ok((balance - loss, unminted.saturating_sub(Uint128::from(v_token_loss))))
+    }
+
+    /// Loss not yet applied to `balance`: `balance * (1 - index / checkpoint)`, rounded up so
+    /// the haircuts never leave more collateral than the deposits backing it
+    pub fn pending_socialized_loss(
+        storage: &dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+        balance: Uint128,
+    ) -> StdResult<Uint128> {
+        let loss_index = SOCIALIZED_LOSS_INDEX
+            .may_load(storage, token_address)?
+            .unwrap_or_default();
+        let checkpoint = match USER_LOSS_CHECKPOINT
+            .may_load(storage, (token_address, user_address))?
+        {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(Uint128::zero()),
+        };
+        if checkpoint.epoch < loss_index.epoch {
+            return Ok(balance);
+        }
+        if loss_index.index >= checkpoint.index {
+            return Ok(Uint128::zero());
+        }
+        let left = balance.multiply_ratio(loss_index.index.atomics(), checkpoint.index.atomics());
+        Ok(balance - left)
+    }
+
+    fn add_total_deposits(
+        storage: &mut dyn Storage,
+        token_address: &Addr,
+        amount: Uint128,
+    ) -> Result<(), ContractError> {
+        add_to_ledger(storage, &TOTAL_DEPOSITS, token_address, amount)
+    }
+
+    fn sub_total_deposits(
+        storage: &mut dyn Storage,
+        token_address: &Addr,
+        amount: Uint128,
+    ) -> Result<(), ContractError> {
+        // Socialized losses are taken off the total up front, so it may run short by dust
+        sub_from_ledger(storage, &TOTAL_DEPOSITS, token_address, amount)
+    }
+
+    fn sub_from_ledger(
+        storage: &mut dyn Storage,
+        ledger: &Map<&Addr, Uint128>,
+        asset: &Addr,
+        amount: Uint128,
+    ) -> Result<(), ContractError> {
+        let total = ledger.may_load(storage, asset)?.unwrap_or_default();
+        ledger.save(storage, asset, &total.saturating_sub(amount))?;
+        Ok(())
+    }
+
+    pub fn update_token_caps(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _token_address: Addr,
+        _caps: TokenCaps,
+    ) -> Result<Response, ContractError> {
+        ensure_owner(_deps.storage, &_info.sender)?;
+
+        let listed_tokens = LISTED_TOKEN.may_load(_deps.storage)?.unwrap_or_default();
+        if !listed_tokens.contains(&_token_address.to_string()) {
+            return Err(ContractError::UnauthorizedToken {});
+        }
+        TOKEN_CAPS.save(_deps.storage, &_token_address, &_caps)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "update_token_caps")
+            .add_attribute("token_address", _token_address.clone())
+            .add_event(config_event("token_caps", Some(&_token_address), &_caps)?))
+    }
+
+    /// Rejects a deposit that would exceed the token's global or per-user supply cap
+    fn check_supply_cap(
+        storage: &dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+        amount: Uint128,
+    ) -> Result<(), ContractError> {
+        let capacity = token_capacity(storage, token_address, Some(user_address))?;
+        for remaining in [capacity.remaining_supply, capacity.remaining_user_supply]
+            .into_iter()
+            .flatten()
+        {
+            if amount.gt(&remaining) {
+                return Err(ContractError::SupplyCapExceeded { remaining });
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects a borrow that would exceed the token's global or per-user borrow cap
+    fn check_borrow_cap(
+        storage: &dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+        amount: Uint128,
+    ) -> Result<(), ContractError> {
+        let capacity = token_capacity(storage, token_address, Some(user_address))?;
+        for remaining in [capacity.remaining_borrow, capacity.remaining_user_borrow]
+            .into_iter()
+            .flatten()
+        {
+            if amount.gt(&remaining) {
+                return Err(ContractError::BorrowCapExceeded { remaining });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn token_capacity(
+        storage: &dyn Storage,
+        token_address: &Addr,
+        user_address: Option<&Addr>,
+    ) -> StdResult<TokenCapacityResponse> {
+        let caps = TOKEN_CAPS.may_load(storage, token_address)?.unwrap_or_default();
+        let total_supplied = TOTAL_DEPOSITS.may_load(storage, token_address)?.unwrap_or_default();
+        let total_borrowed = TOTAL_BORROWS.may_load(storage, token_address)?.unwrap_or_default();
+
+        // User caps are shared by every sub-account of the owner
+        let (user_supplied, user_borrowed) = match user_address {
+            Some(user_address) => {
+                let (owner, _) = split_sub_account(user_address);
+                let mut supplied = Uint128::zero();
+                let mut borrowed = Uint128::zero();
+                for index in sub_account_indices(storage, &owner)? {
+                    let account = sub_account_address(&owner, index);
+                    supplied += USER_TOKEN_BALANCE
+                        .may_load(storage, (token_address, &account))?
+                        .unwrap_or_default();
+                    borrowed += USER_BORROW_BALANCE
+                        .may_load(storage, (token_address, &account))?
+                        .unwrap_or_default();
+                }
+                (Some(supplied), Some(borrowed))
+            }
+            None => (None, None),
+        };
+
+        let remaining = |cap: Option<Uint128>, used: Option<Uint128>| match (cap, used) {
+            (Some(cap), Some(used)) => Some(cap.saturating_sub(used)),
+            _ => None,
+        };
+
+        Ok(TokenCapacityResponse {
+            remaining_supply: remaining(caps.supply_cap, Some(total_supplied)),
+            remaining_borrow: remaining(caps.borrow_cap, Some(total_borrowed)),
+            remaining_user_supply: remaining(caps.user_supply_cap, user_supplied),
+            remaining_user_borrow: remaining(caps.user_borrow_cap, user_borrowed),
+            caps,
+            total_supplied,
+            total_borrowed,
+        })
+    }
+
+    /**
+     * @dev Health of the margin a ledger account is checked and liquidated against.
+     *
+     * An isolated sub-account only counts its own collateral, positions and debt. A
+     * cross-margin sub-account shares them with every other cross-margin sub-account of
+     * the same owner, including the main account unless that is isolated.
+     */
+    pub fn margin_health(
+        storage: &dyn Storage,
+        account: &Addr,
+        pricing: Pricing,
+    ) -> Result<AccountHealth, ContractError> {
+        let (owner, index) = split_sub_account(account);
+        if load_margin_mode(storage, &owner, index)? == MarginMode::Isolated {
+            return account_health(storage, account, pricing);
+        }
+
+        let mut collateral_value = Decimal::zero();
+        let mut position_value = Decimal::zero();
+        let mut debt_value = Decimal::zero();
+        for index in sub_account_indices(storage, &owner)? {
+            if load_margin_mode(storage, &owner, index)? == MarginMode::Isolated {
+                continue;
+            }
+            let health = account_health(storage, &sub_account_address(&owner, index), pricing)?;
+            collateral_value += health.collateral_value;
+            position_value += health.position_value;
+            debt_value += health.debt_value;
+        }
+
+        Ok(AccountHealth {
+            collateral_value,
+            position_value,
+            debt_value,
+            health_factor: health_factor(collateral_value, position_value, debt_value),
+        })
+    }
+
+    /// Pricing `liquidate` checks health at, settled over the liquidation window if any
+    pub fn liquidation_pricing(storage: &dyn Storage, now: Timestamp) -> StdResult<Pricing> {
+        Ok(Pricing {
+            twap_window: load_twap_config(storage)?.liquidation_window,
+            ..Pricing::at(now)
+        })
+    }
+
+    /// Reverts a risk-increasing action that leaves the margin of `account` below
+    /// `min_health_factor`, valued at prices that may not fall back below their quorum
+    fn ensure_healthy(
+        storage: &dyn Storage,
+        account: &Addr,
+        now: Timestamp,
+        min_health_factor: Decimal,
+    ) -> Result<(), ContractError> {
+        let pricing = Pricing {
+            risk_increasing: true,
+            ..Pricing::at(now)
+        };
+        let health = margin_health(storage, account, pricing)?;
+        if let Some(health_factor) = health.health_factor {
+            if health_factor < min_health_factor {
+                return Err(ContractError::HealthFactorTooLow {
+                    health_factor,
+                    min_health_factor,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Valuation of one ledger account at registry prices: collateral in every listed
+     * token, every position and every borrow. Fails when an asset the account holds has no
+     * price, so nothing is valued on a missing price.
+     */
+    pub fn account_health(
+        storage: &dyn Storage,
+        user_address: &Addr,
+        pricing: Pricing,
+    ) -> Result<AccountHealth, ContractError> {
+        let listed_tokens = LISTED_TOKEN.may_load(storage)?.unwrap_or_default();
+
+        let mut collateral_value = Decimal::zero();
+        let mut debt_value = Decimal::zero();
+        for token in listed_tokens.iter() {
+            let token_address = Addr::unchecked(token);
+            let collateral = USER_TOKEN_BALANCE
+                .may_load(storage, (&token_address, user_address))?
+                .unwrap_or_default();
+            let collateral = collateral
+                - pending_socialized_loss(storage, &token_address, user_address, collateral)?;
+            let debt = USER_BORROW_BALANCE
+                .may_load(storage, (&token_address, user_address))?
+                .unwrap_or_default();
+            if collateral.is_zero() && debt.is_zero() {
+                continue;
+            }
+            let price = load_price(storage, &token_address, pricing)?;
+            collateral_value += asset_value(collateral, price)?;
+            debt_value += asset_value(debt, price)?;
+        }
+
+        let mut position_value = Decimal::zero();
+        for item in USER_POSITION_BALANCE
+            .prefix(user_address)
+            .range(storage, None, None, Order::Ascending)
+        {
+            let (asset, amount) = item?;
+            if amount.is_zero() {
+                continue;
+            }
+            position_value += asset_value(amount, load_price(storage, &asset, pricing)?)?;
+        }
+
+        Ok(AccountHealth {
+            collateral_value,
+            position_value,
+            debt_value,
+            health_factor: health_factor(collateral_value, position_value, debt_value),
+        })
+    }
+
+    fn asset_value(amount: Uint128, price: Decimal) -> Result<Decimal, ContractError> {
+        match crate::health::asset_value(amount, price) {
+            Some(value) => Ok(value),
+            None => Err(ContractError::Overflow {}),
+        }
+    }
+
+    pub fn load_risk_config(storage: &dyn Storage) -> Result<RiskConfig, ContractError> {
+        match RISK_CONFIG.may_load(storage)? {
+            Some(risk_config) => Ok(risk_config),
+            None => Ok(RiskConfig {
+                liquidation_threshold: DEFAULT_LIQUIDATION_THRESHOLD.parse()?,
+                liquidation_penalty_bps: DEFAULT_LIQUIDATION_PENALTY_BPS,
+            }),
+        }
+    }
+
+    pub fn load_price_feed_config(storage: &dyn Storage) -> StdResult<PriceFeedConfig> {
+        Ok(PRICE_FEED_CONFIG.may_load(storage)?.unwrap_or_default())
+    }
+
+    pub fn load_twap_config(storage: &dyn Storage) -> StdResult<TwapConfig> {
+        match TWAP_CONFIG.may_load(storage)? {
+            Some(twap_config) => Ok(twap_config),
+            None => Ok(TwapConfig {
+                max_window: DEFAULT_TWAP_MAX_WINDOW,
+                liquidation_window: None,
+            }),
+        }
+    }
+
+    pub fn load_insurance_config(storage: &dyn Storage) -> Result<InsuranceConfig, ContractError> {
+        Ok(INSURANCE_CONFIG.may_load(storage)?.unwrap_or(InsuranceConfig {
+            fee_share_bps: 0,
+            penalty_share_bps: 0,
+        }))
+    }
+
+    fn ensure_owner(storage: &dyn Storage, sender: &Addr) -> Result<(), ContractError> {
+        match LEVERAGE_CONTRACT_OWNER.load(storage) {
+            Ok(owner) => {
+                if owner != *sender {
+                    return Err(ContractError::Unauthorized {});
+                }
+                Ok(())
+            }
+            Err(err) => Err(ContractError::GenericError {
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    /**
+     * Function to let an operator manage the sender's account.
+     *
+     * The grant replaces any earlier grant to the same operator. `Trade` covers orders,
+     * `Repay` covers repayments and `Full` covers every handler that accepts
+     * `on_behalf_of`, including borrow and withdraw. Withdrawals always pay the owner.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the account owner.
+     * @param _operator Address allowed to act on the owner's account.
+     * @param _scope Handlers the operator may call.
+     * @param _expires Expiry of the grant, never when `None`.
+     * @return A response object indicating success or failure.
+     */
+    pub fn grant_operator(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _operator: Addr,
+        _scope: OperatorScope,
+        _expires: Option<Expiration>,
+    ) -> Result<Response, ContractError> {
+        let expires = _expires.unwrap_or_default();
+        if _operator == _info.sender || expires.is_expired(&_env.block) {
+            return Err(ContractError::InvalidOperatorGrant {});
+        }
+
+        OPERATOR_GRANTS.save(
+            _deps.storage,
+            (&_info.sender, &_operator),
+            &OperatorGrant {
+                scope: _scope.clone(),
+                expires,
+            },
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "grant_operator")
+            .add_attribute("user", _info.sender.clone())
+            .add_attribute("operator", _operator.clone())
+            .add_attribute("expires", expires.to_string())
+            .add_event(Event::from(LeverageEvent::OperatorGrant {
+                owner: _info.sender,
+                operator: _operator,
+                scope: _scope,
+                expires,
+            })))
+    }
+
+    pub fn revoke_operator(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _operator: Addr,
+    ) -> Result<Response, ContractError> {
+        if !OPERATOR_GRANTS.has(_deps.storage, (&_info.sender, &_operator)) {
+            return Err(ContractError::OperatorNotAuthorized {});
+        }
+
+        OPERATOR_GRANTS.remove(_deps.storage, (&_info.sender, &_operator));
+
+        Ok(Response::new()
+            .add_attribute("method", "revoke_operator")
+            .add_attribute("user", _info.sender.clone())
+            .add_attribute("operator", _operator.clone())
+            .add_event(Event::from(LeverageEvent::OperatorRevoke {
+                owner: _info.sender,
+                operator: _operator,
+            })))
+    }
+
+    /**
+     * @dev Resolves the ledger account a handler acts on.
+     *
+     * Without `on_behalf_of` (or with the sender itself) the sender acts on its own
+     * account. Otherwise the owner must have granted the sender an unexpired grant whose
+     * scope covers `required`. A grant covers all of the owner's sub-accounts.
+     */
+    fn resolve_account(
+        storage: &dyn Storage,
+        env: &Env,
+        sender: &Addr,
+        on_behalf_of: Option<Addr>,
+        sub_account: Option<u32>,
+        required: OperatorScope,
+    ) -> Result<Addr, ContractError> {
+        let owner = match on_behalf_of {
+            Some(owner) if owner != *sender => owner,
+            _ => return select_sub_account(storage, sender.clone(), sub_account),
+        };
+
+        match OPERATOR_GRANTS.may_load(storage, (&owner, sender))? {
+            Some(grant) if !grant.expires.is_expired(&env.block) && grant.scope.allows(&required) => {
+                select_sub_account(storage, owner, sub_account)
+            }
+            _ => Err(ContractError::OperatorNotAuthorized {}),
+        }
+    }
+
+    /**
+     * Function to open a new numbered sub-account.
+     *
+     * Sub-accounts are numbered from 1 per owner, the main account is sub-account 0.
+     * Every ledger keys a sub-account by its own account address (see
+     * `sub_account_address`), so its collateral, debt, positions and orders are kept
+     * apart from the owner's other sub-accounts.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the owner.
+     * @param _margin_mode Whether the sub-account shares margin with the other
+     *        cross-margin sub-accounts or stands on its own.
+     * @return A response object indicating success or failure.
+     */
+    pub fn open_sub_account(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _margin_mode: MarginMode,
+    ) -> Result<Response, ContractError> {
+        let index = SUB_ACCOUNT_SEQ.may_load(_deps.storage, &_info.sender)?.unwrap_or_default() + 1;
+        SUB_ACCOUNT_SEQ.save(_deps.storage, &_info.sender, &index)?;
+        SUB_ACCOUNTS.save(_deps.storage, (&_info.sender, index), &_margin_mode)?;
+
+        let account = sub_account_address(&_info.sender, index);
+        Ok(Response::new()
+            .add_attribute("method", "open_sub_account")
+            .add_attribute("user", _info.sender.clone())
+            .add_attribute("sub_account", index.to_string())
+            .add_attribute("account", account.clone())
+            .add_event(Event::from(LeverageEvent::SubAccountOpen {
+                owner: _info.sender,
+                sub_account: index,
+                account,
+                margin_mode: _margin_mode,
+            })))
+    }
+
+    /// Switches the margin mode of a sub-account, which must not have any debt
+    pub fn set_margin_mode(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _sub_account: u32,
+        _margin_mode: MarginMode,
+    ) -> Result<Response, ContractError> {
+        let account = select_sub_account(_deps.storage, _info.sender.clone(), Some(_sub_account))?;
+
+        let listed_tokens = LISTED_TOKEN.may_load(_deps.storage)?.unwrap_or_default();
+        for token in listed_tokens.iter() {
+            let debt = USER_BORROW_BALANCE
+                .may_load(_deps.storage, (&Addr::unchecked(token), &account))?
+                .unwrap_or_default();
+            if !debt.is_zero() {
+                return Err(ContractError::SubAccountHasDebt {});
+            }
+        }
+
+        SUB_ACCOUNTS.save(_deps.storage, (&_info.sender, _sub_account), &_margin_mode)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "set_margin_mode")
+            .add_attribute("user", _info.sender.clone())
+            .add_attribute("sub_account", _sub_account.to_string())
+            .add_event(Event::from(LeverageEvent::MarginModeUpdate {
+                owner: _info.sender,
+                sub_account: _sub_account,
+                margin_mode: _margin_mode,
+            })))
+    }
+
+    /**
+     * Function to move collateral between two of the sender's sub-accounts.
+     *
+     * Follows the withdraw rules on the source: it must have no debt in the token and
+     * enough unminted vToken to release. The destination is credited like a deposit.
+     *
+     * @param _deps Storage access for contract state.
+     * @param _env Contract environment information.
+     * @param _info Information about the owner.
+     * @param _token_address Collateral token to move.
+     * @param _amount Amount of collateral to move.
+     * @param _from_sub_account Source sub-account, 0 for the main account.
+     * @param _to_sub_account Destination sub-account, 0 for the main account.
+     * @return A response object indicating success or failure.
+     */
+    pub fn transfer_collateral(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _token_address: Addr,
+        _amount: Uint128,
+        _from_sub_account: u32,
+        _to_sub_account: u32,
+    ) -> Result<Response, ContractError> {
+        if _amount.is_zero() || _from_sub_account == _to_sub_account {
+            return Err(ContractError::InsufficientBalance {});
+        }
+        let from = select_sub_account(_deps.storage, _info.sender.clone(), Some(_from_sub_account))?;
+        let to = select_sub_account(_deps.storage, _info.sender.clone(), Some(_to_sub_account))?;
+
+        apply_socialized_loss(_deps.storage, &_token_address, &from)?;
+
+        let debt = USER_BORROW_BALANCE
+            .may_load(_deps.storage, (&_token_address, &from))?
+            .unwrap_or_default();
+        if !debt.is_zero() {
+            return Err(ContractError::BorrowAmountIsNotZero {});
+        }
+
+        USER_TOKEN_BALANCE.update(
+            _deps.storage,
+            (&_token_address, &from),
+            |opt_balance| -> Result<Uint128, ContractError> {
+                match opt_balance.unwrap_or_default().checked_sub(_amount) {
+                    Ok(balance) => Ok(balance),
+                    Err(_) => Err(ContractError::InsufficientBalance {}),
+                }
+            },
+        )?;
+        USER_UNMINTED_TOKEN.update(
+            _deps.storage,
+            (&_token_address, &from),
+            |opt_balance| -> Result<Uint128, ContractError> {
+                let v_tokens =
+                    vtoken::to_v_tokens(_amount.u128()).ok_or(ContractError::Overflow {})?;
+                match opt_balance.unwrap_or_default().checked_sub(Uint128::from(v_tokens)) {
+                    Ok(balance) => Ok(balance),
+                    Err(_) => Err(ContractError::InsufficientUnmintedToken {}),
+                }
+            },
+        )?;
+
+        // Leaves the total unchanged, credit_collateral adds it back
+        sub_total_deposits(_deps.storage, &_token_address, _amount)?;
+        credit_collateral(_deps.storage, &_token_address, &to, _amount)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "transfer_collateral")
+            .add_attribute("user", _info.sender.clone())
+            .add_attribute("token_address", _token_address.clone())
+            .add_attribute("amount", _amount)
+            .add_attribute("from", from.clone())
+            .add_attribute("to", to.clone())
+            .add_event(Event::from(LeverageEvent::CollateralTransfer {
+                user: _info.sender,
+                asset: _token_address,
+                amount: _amount,
+                from,
+                to,
+            })))
+    }
+
+    /// Ledger account of sub-account `index` of `owner`, the owner itself for 0
+    pub fn sub_account_address(owner: &Addr, index: u32) -> Addr {
+        if index == 0 {
+            owner.clone()
+        } else {
+            Addr::unchecked(format!("{}/{}", owner, index))
+        }
+    }
+
+    /// Numbers of the sub-accounts `owner` opened, with the main account 0 first
+    fn sub_account_indices(storage: &dyn Storage, owner: &Addr) -> StdResult<Vec<u32>> {
+        let mut indices: Vec<u32> = SUB_ACCOUNTS
+            .prefix(owner)
+            .keys(storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?;
+        if !indices.contains(&0) {
+            indices.insert(0, 0);
+        }
+        Ok(indices)
+    }
+
+    /// Owner and sub-account number of a ledger account
+    pub fn split_sub_account(account: &Addr) -> (Addr, u32) {
+        match account.as_str().rsplit_once('/') {
+            Some((owner, index)) => match index.parse::<u32>() {
+                Ok(index) => (Addr::unchecked(owner), index),
+                Err(_) => (account.clone(), 0),
+            },
+            None => (account.clone(), 0),
+        }
+    }
+
+    /// Ledger account of an opened sub-account, the main account when `None` or 0
+    fn select_sub_account(
+        storage: &dyn Storage,
+        owner: Addr,
+        sub_account: Option<u32>,
+    ) -> Result<Addr, ContractError> {
+        match sub_account {
+            None | Some(0) => Ok(owner),
+            Some(index) => {
+                if !SUB_ACCOUNTS.has(storage, (&owner, index)) {
+                    return Err(ContractError::SubAccountNotFound {});
+                }
+                Ok(sub_account_address(&owner, index))
+            }
+        }
+    }
+
+    pub fn load_margin_mode(
+        storage: &dyn Storage,
+        owner: &Addr,
+        index: u32,
+    ) -> StdResult<MarginMode> {
+        Ok(SUB_ACCOUNTS.may_load(storage, (owner, index))?.unwrap_or(MarginMode::Cross))
+    }
+
+    /// Credit deposited collateral and the matching unminted vToken (10x)
+    fn credit_collateral(
+        storage: &mut dyn Storage,
+        token_address: &Addr,
+        user_address: &Addr,
+        amount: Uint128,
+    ) -> Result<(), ContractError> {
+        apply_socialized_loss(storage, token_address, user_address)?;
+        add_total_deposits(storage, token_address, amount)?;
+
+        // Update the user's token balance, which must stay convertible to vTokens
+        let balance = USER_TOKEN_BALANCE.update(
+            storage,
+            (token_address, user_address),
+            |opt_balance| -> Result<Uint128, ContractError> {
+                match opt_balance {
+                    Some(balance) => match balance.checked_add(amount) {
+                        Ok(data) => Ok(data),
+                        Err(_) => Err(ContractError::OverflowBalance {}),
+                    },
+                    None => Ok(amount),
+                }
+            },
+        )?;
+        if vtoken::to_v_tokens(balance.u128()).is_none() {
+            return Err(ContractError::OverflowBalance {});
+        }
+
+        // Calculate the unminted token amount and update the user's unminted token balance
+        let unminted_token = match vtoken::to_v_tokens(amount.u128()) {
+            Some(data) => Uint128::from(data),
+            None => return Err(ContractError::Overflow {}),
+        };
+
+        USER_UNMINTED_TOKEN.update(
+            storage,
+            (token_address, user_address),
+            |opt_balance| -> Result<Uint128, ContractError> {
+                match opt_balance {
+                    Some(balance) => match balance.checked_add(unminted_token) {
+                        Ok(data) => Ok(data),
+                        Err(_) => Err(ContractError::OverflowBalance {}),
+                    },
+                    None => Ok(unminted_token),
+                }
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn credit_position(
+        storage: &mut dyn Storage,
+        user_address: &Addr,
+        asset: &Addr,
+        amount: Uint128,
+    ) -> Result<(), ContractError> {
+        USER_POSITION_BALANCE.update(
+            storage,
+            (user_address, asset),
+            |opt_balance| -> Result<Uint128, ContractError> {
+                match opt_balance.unwrap_or_default().checked_add(amount) {
+                    Ok(data) => Ok(data),
+                    Err(_) => Err(ContractError::Overflow {}),
+                }
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Debit a position and return what is left of it
+    fn debit_position(
+        storage: &mut dyn Storage,
+        user_address: &Addr,
+        asset: &Addr,
+        amount: Uint128,
+    ) -> Result<Uint128, ContractError> {
+        USER_POSITION_BALANCE.update(
+            storage,
+            (user_address, asset),
+            |opt_balance| -> Result<Uint128, ContractError> {
+                match opt_balance.unwrap_or_default().checked_sub(amount) {
+                    Ok(data) => Ok(data),
+                    Err(_) => Err(ContractError::InsufficientBalance {}),
+                }
+            },
+        )
+    }
+
+    struct Settlement {
+        cancelled_orders: Vec<u64>,
+        fee: Uint128,
+        event: LeverageEvent,
+    }
+
+    /// Settlement shared by `ExecuteOrder`, routed swaps and conditional orders. The taker
+    /// fee is taken from `amount_out`. Conditional orders selling `token_in` are cancelled
+    /// once the position in it is closed. The fill is observed for the TWAP of both legs.
+    fn settle_order(
+        storage: &mut dyn Storage,
+        order: &OrderExecute,
+        now: Timestamp,
+    ) -> Result<Settlement, ContractError> {
+        observe_settlement(
+            storage,
+            (&order.token_in, order.amount_in),
+            (&order.token_out, order.amount_out),
+            now,
+        )?;
+
+        let fee_config = load_fee_config(storage)?;
+        let fee = take_fee(storage, &order.token_out, order.amount_out, fee_config.taker_fee_bps)?;
+
+        let remaining =
+            debit_position(storage, &order.user_address, &order.token_in, order.amount_in)?;
+        credit_position(
+            storage,
+            &order.user_address,
+            &order.token_out,
+            order.amount_out - fee,
+        )?;
+        let event = LeverageEvent::OrderSettled {
+            user: order.user_address.clone(),
+            token_in: order.token_in.clone(),
+            token_out: order.token_out.clone(),
+            amount_in: order.amount_in,
+            amount_out: order.amount_out,
+            fee,
+            balances: account_balances(storage, &order.user_address, &order.token_out)?,
+        };
+
+        if !remaining.is_zero() {
+            return Ok(Settlement {
+                cancelled_orders: vec![],
+                fee,
+                event,
+            });
+        }
+
+        let attached_orders: Vec<u64> = CONDITIONAL_ORDERS
+            .prefix(&order.user_address)
+            .range(storage, None, None, Order::Ascending)
+            .filter_map(|item| item.ok())
+            .filter(|(_, conditional_order)| conditional_order.token_in == order.token_in)
+            .map(|(order_id, _)| order_id)
+            .collect();
+
+        for order_id in attached_orders.iter() {
+            CONDITIONAL_ORDERS.remove(storage, (&order.user_address, *order_id));
+        }
+
+        Ok(Settlement {
+            cancelled_orders: attached_orders,
+            fee,
+            event,
+        })
+    }
+
+    fn cancel_events(user: &Addr, order_ids: &[u64]) -> Vec<Event> {
+        order_ids
+            .iter()
+            .map(|order_id| {
+                Event::from(LeverageEvent::ConditionalOrderCancel {
+                    user: user.clone(),
+                    order_id: *order_id,
+                })
+            })
+            .collect()
+    }
+
+    /// Records the price a fill of `(token, amount)` in for `(token, amount)` out implies
+    /// for each leg, valued at the other leg's spot price and weighted by the leg's amount.
+    /// A leg is skipped when the other one has no price.
+    fn observe_settlement(
+        storage: &mut dyn Storage,
+        (token_in, amount_in): (&Addr, Uint128),
+        (token_out, amount_out): (&Addr, Uint128),
+        now: Timestamp,
+    ) -> Result<(), ContractError> {
+        let rate = match Decimal::checked_from_ratio(amount_out, amount_in) {
+            Ok(rate) if !rate.is_zero() => rate,
+            _ => return Ok(()),
+        };
+
+        let price_in = ASSET_PRICE.may_load(storage, token_in)?;
+        let price_out = ASSET_PRICE.may_load(storage, token_out)?;
+        if let Some(Ok(price)) = price_out.map(|price_out| rate.checked_mul(price_out)) {
+            observe_price(storage, token_in, price, amount_in, now)?;
+        }
+        if let Some(Ok(price)) = price_in.map(|price_in| price_in.checked_div(rate)) {
+            observe_price(storage, token_out, price, amount_out, now)?;
+        }
+        Ok(())
+    }
+
+    /// Adds an observation of `asset` at `now` and prunes the observations `max_window` no
+    /// longer needs. Fills in the same block average into one observation, weighted by the
+    /// amount of `asset` each traded.
+    fn observe_price(
+        storage: &mut dyn Storage,
+        asset: &Addr,
+        price: Decimal,
+        weight: Uint128,
+        now: Timestamp,
+    ) -> Result<(), ContractError> {
+        let now = now.seconds();
+        let last = PRICE_OBSERVATIONS
+            .prefix(asset)
+            .range(storage, None, None, Order::Descending)
+            .next()
+            .transpose()?;
+        let observation = match last {
+            Some((observed_at, last)) if observed_at == now => {
+                let total = last
+                    .weight
+                    .checked_add(weight)
+                    .map_err(|_| ContractError::Overflow {})?;
+                let price = last
+                    .price
+                    .checked_mul(Decimal::from_ratio(last.weight, total))
+                    .and_then(|earlier| {
+                        price
+                            .checked_mul(Decimal::from_ratio(weight, total))
+                            .and_then(|later| earlier.checked_add(later))
+                    })
+                    .map_err(|_| ContractError::Overflow {})?;
+                PriceObservation {
+                    price,
+                    weight: total,
+                    cumulative_price: last.cumulative_price,
+                }
+            }
+            Some((observed_at, last)) => PriceObservation {
+                price,
+                weight,
+                cumulative_price: cumulative_price_at(&last, observed_at, now)?,
+            },
+            None => PriceObservation {
+                price,
+                weight,
+                cumulative_price: Decimal256::zero(),
+            },
+        };
+        PRICE_OBSERVATIONS.save(storage, (asset, now), &observation)?;
+
+        // The newest observation at or before the cutoff still prices the window's start
+        let cutoff = now.saturating_sub(load_twap_config(storage)?.max_window);
+        let expired: Vec<u64> = PRICE_OBSERVATIONS
+            .prefix(asset)
+            .keys(storage, None, Some(Bound::inclusive(cutoff)), Order::Descending)
+            .skip(1)
+            .collect::<StdResult<_>>()?;
+        for observed_at in expired {
+            PRICE_OBSERVATIONS.remove(storage, (asset, observed_at));
+        }
+        Ok(())
+    }
+
+    /// Cumulative price at `time`, holding the observation made at `observed_at` until then
+    fn cumulative_price_at(
+        observation: &PriceObservation,
+        observed_at: u64,
+        time: u64,
+    ) -> Result<Decimal256, ContractError> {
+        let held = Decimal256::from_ratio(time.saturating_sub(observed_at), 1u64);
+        Decimal256::from(observation.price)
+            .checked_mul(held)
+            .and_then(|held_price| observation.cumulative_price.checked_add(held_price))
+            .map_err(|_| ContractError::Overflow {})
+    }
+
+    /// Settlement price of `asset` averaged over the `window` seconds up to `now`, `None`
+    /// when no observation is as old as the start of the window
+    pub fn twap(
+        storage: &dyn Storage,
+        asset: &Addr,
+        now: Timestamp,
+        window: u64,
+    ) -> Result<Option<Decimal>, ContractError> {
+        let max_window = load_twap_config(storage)?.max_window;
+        if window == 0 || window > max_window {
+            return Err(ContractError::InvalidTwapWindow { max_window });
+        }
+
+        let now = now.seconds();
+        let start = match now.checked_sub(window) {
+            Some(start) => start,
+            None => return Ok(None),
+        };
+        let observation_at = |time: u64| {
+            PRICE_OBSERVATIONS
+                .prefix(asset)
+                .range(storage, None, Some(Bound::inclusive(time)), Order::Descending)
+                .next()
+                .transpose()
+        };
+        let (first_at, first) = match observation_at(start)? {
+            Some(observation) => observation,
+            None => return Ok(None),
+        };
+        let (last_at, last) = match observation_at(now)? {
+            Some(observation) => observation,
+            None => return Ok(None),
+        };
+
+        let cumulative_price = cumulative_price_at(&last, last_at, now)?
+            - cumulative_price_at(&first, first_at, start)?;
+        let twap = cumulative_price / Decimal256::from_ratio(window, 1u64);
+        Decimal::try_from(twap).map(Some).map_err(|_| ContractError::Overflow {})
+    }
+
+    /// Price of `token_in` expressed in `token_out`
+    fn cross_price(
+        storage: &dyn Storage,
+        token_in: &Addr,
+        token_out: &Addr,
+        pricing: Pricing,
+    ) -> Result<Decimal, ContractError> {
+        let price_in = load_price(storage, token_in, pricing)?;
+        let price_out = load_price(storage, token_out, pricing)?;
+
+        match price_in.checked_div(price_out) {
+            Ok(price) => Ok(price),
+            Err(_) => Err(ContractError::PriceNotAvailable {
+                asset: token_out.to_string(),
+            }),
+        }
+    }
+
+    /**
+     * @dev Price of `asset` aggregated from its sources: the median once a quorum of them is
+     * fresh at `pricing.at`, otherwise the first fresh one in fallback order unless the
+     * action is risk-increasing. Without `at` the age is not checked, which is only for
+     * reporting. With a `twap_window` the settlement price averaged over that window
+     * replaces it, and the price is refused while the observations do not cover the
+     * window rather than falling back to the spot price the window guards against.
+     */
+    pub fn load_price(
+        storage: &dyn Storage,
+        asset: &Addr,
+        pricing: Pricing,
+    ) -> Result<Decimal, ContractError> {
+        let (price_sources, states) = price_source_states(storage, asset, pricing.at)?;
+        let prices: Vec<Option<Decimal>> = states
+            .iter()
+            .map(|state| state.price.filter(|_| state.fresh))
+            .collect();
+        let price = match oracle::aggregate(&prices, price_sources.quorum) {
+            Aggregate::Median(price) => price,
+            Aggregate::Fallback(_) if pricing.risk_increasing => {
+                return Err(ContractError::PriceQuorumNotMet {
+                    asset: asset.to_string(),
+                    fresh: prices.iter().flatten().count() as u32,
+                    quorum: price_sources.quorum,
+                })
+            }
+            Aggregate::Fallback(price) => price,
+            Aggregate::Unavailable if states.iter().any(|state| state.price.is_some()) => {
+                return Err(ContractError::PriceStale {
+                    asset: asset.to_string(),
+                })
+            }
+            Aggregate::Unavailable => {
+                return Err(ContractError::PriceNotAvailable {
+                    asset: asset.to_string(),
+                })
+            }
+        };
+
+        if let (Some(now), Some(window)) = (pricing.at, pricing.twap_window) {
+            return match twap(storage, asset, now, window)? {
+                Some(twap) => Ok(twap),
+                None => Err(ContractError::TwapNotAvailable {
+                    asset: asset.to_string(),
+                    window,
+                }),
+            };
+        }
+        Ok(price)
+    }
+
+    pub fn load_price_sources(storage: &dyn Storage, asset: &Addr) -> StdResult<PriceSources> {
+        match PRICE_SOURCES.may_load(storage, asset)? {
+            Some(price_sources) => Ok(price_sources),
+            None => Ok(PriceSources {
+                sources: vec![PriceSource::Feed],
+                quorum: 1,
+            }),
+        }
+    }
+
+    /// Last price of each source of `asset`, checked for freshness at `at` when given.
+    /// TWAP sources are always fresh, they only have a price at `at`.
+    pub fn price_source_states(
+        storage: &dyn Storage,
+        asset: &Addr,
+        at: Option<Timestamp>,
+    ) -> Result<(PriceSources, Vec<PriceSourceState>), ContractError> {
+        let price_sources = load_price_sources(storage, asset)?;
+        let feed_config = load_price_feed_config(storage)?;
+
+        let mut states = Vec::with_capacity(price_sources.sources.len());
+        for source in price_sources.sources.iter() {
+            let (price, publish_time) = match source {
+                PriceSource::Feed => (
+                    ASSET_PRICE.may_load(storage, asset)?,
+                    ASSET_PRICE_TIME.may_load(storage, asset)?,
+                ),
+                PriceSource::Twap { window } => match at {
+                    Some(now) => (twap(storage, asset, now, *window)?, None),
+                    None => (None, None),
+                },
+                PriceSource::Oracle { contract } => {
+                    match ORACLE_PRICES.may_load(storage, (asset, contract))? {
+                        Some(oracle_price) => {
+                            (Some(oracle_price.price), Some(oracle_price.publish_time))
+                        }
+                        None => (None, None),
+                    }
+                }
+            };
+            let fresh = price.is_some()
+                && match (source, at) {
+                    (PriceSource::Twap { .. }, _) | (_, None) => true,
+                    (_, Some(now)) => !is_price_stale(&feed_config, publish_time, now),
+                };
+            states.push(PriceSourceState {
+                source: source.clone(),
+                price,
+                publish_time,
+                fresh,
+            });
+        }
+        Ok((price_sources, states))
+    }
+
+    /// Whether a price published at `publish_time` is too old at `now`. Prices without a
+    /// time predate the feed and are stale as soon as a maximum age is configured.
+    pub fn is_price_stale(
+        config: &PriceFeedConfig,
+        publish_time: Option<Timestamp>,
+        now: Timestamp,
+    ) -> bool {
+        match (config.max_price_age, publish_time) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(max_price_age), Some(publish_time)) => {
+                now.seconds().saturating_sub(publish_time.seconds()) > max_price_age
+            }
+        }
+    }
+
+    /// Move from `last` to `price` in basis points of `last`, rounded up
+    fn price_deviation_bps(last: Decimal, price: Decimal) -> u64 {
+        let change = if price > last { price - last } else { last - price };
+        match change.checked_div(last) {
+            Ok(ratio) => u64::try_from(Uint128::new(10_000).mul_ceil(ratio).u128())
+                .unwrap_or(u64::MAX),
+            Err(_) => u64::MAX,
+        }
+    }
+}
+
+/// Handling contract query
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(_deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::UserCollateralTokenBalance(query_data) => to_json_binary(
+            &query::fetch_user_token_balance(_deps, _env, query_data)?,
+        ),
+        QueryMsg::UserWrappedTokenBalance(query_data) => to_json_binary(
+            &query::fetch_user_unminted_token_balance(_deps, _env, query_data)?,
+        ),
+        QueryMsg::UserBorrowTokenBalance(query_data) => to_json_binary(
+            &query::fetch_user_borrow_token_balance(_deps, _env, query_data)?,
+        ),
+        QueryMsg::UserVTokenBalance(query_data) => to_json_binary(
+            &query::fetch_user_profit_token_balance(_deps, _env, query_data)?,
+        ),
+        QueryMsg::ListedTokens {} => to_json_binary(&query::fetch_listed_tokens(_deps, _env)?),
+        QueryMsg::UserPositionBalance(query_data) => to_json_binary(
+            &query::fetch_user_position_balance(_deps, _env, query_data)?,
+        ),
+        QueryMsg::AssetPrice { asset } => {
+            to_json_binary(&query::fetch_asset_price(_deps, _env, asset)?)
+        }
+        QueryMsg::LiquidationPrice { asset } => {
+            to_json_binary(&query::fetch_liquidation_price(_deps, _env, asset)?)
+        }
+        QueryMsg::UserConditionalOrders { user_address } => to_json_binary(
+            &query::fetch_user_conditional_orders(_deps, _env, user_address)?,
+        ),
+        QueryMsg::PaillierPublicKey {} => to_json_binary(&PAILLIER_PUBLIC_KEY.may_load(_deps.storage)?),
+        QueryMsg::EncryptedOrder {
+            user_address,
+            order_id,
+        } => to_json_binary(
+            &ENCRYPTED_ORDERS.may_load(_deps.storage, (&user_address, order_id.as_str()))?,
+        ),
+        QueryMsg::OrderCommitment { commitment_id } => {
+            to_json_binary(&ORDER_COMMITMENTS.may_load(_deps.storage, commitment_id)?)
+        }
+        QueryMsg::RevealedOrders { height } => {
+            to_json_binary(&query::fetch_revealed_orders(_deps, _env, height)?)
+        }
+        QueryMsg::SwapConfig {} => to_json_binary(&SWAP_CONFIG.may_load(_deps.storage)?),
+        QueryMsg::FeeConfig {} => to_json_binary(&query::fetch_fee_config(_deps, _env)?),
+        QueryMsg::AccumulatedFees {} => {
+            to_json_binary(&query::fetch_accumulated_fees(_deps, _env)?)
+        }
+        QueryMsg::RiskConfig {} => {
+            to_json_binary(&execute::load_risk_config(_deps.storage).map_err(StdError::from)?)
+        }
+        QueryMsg::AccountHealth { user_address } => to_json_binary(
+            &execute::margin_health(_deps.storage, &user_address, Pricing::at(_env.block.time))
+                .map_err(StdError::from)?,
+        ),
+        QueryMsg::InsuranceFund { asset } => {
+            to_json_binary(&query::fetch_insurance_fund(_deps, _env, asset)?)
+        }
+        QueryMsg::TokenCapacity {
+            token_address,
+            user_address,
+        } => to_json_binary(&execute::token_capacity(
+            _deps.storage,
+            &token_address,
+            user_address.as_ref(),
+        )?),
+        QueryMsg::OperatorGrants { owner } => {
+            to_json_binary(&query::fetch_operator_grants(_deps, _env, owner)?)
+        }
+        QueryMsg::SubAccounts { owner } => {
+            to_json_binary(&query::fetch_sub_accounts(_deps, _env, owner)?)
+        }
+        QueryMsg::Borrows { start_after, limit } => {
+            to_json_binary(&query::fetch_borrows(_deps, _env, start_after, limit)?)
+        }
+        QueryMsg::AccountLedgers { user_address } => {
+            to_json_binary(&query::fetch_account_ledgers(_deps, _env, user_address)?)
+        }
+        QueryMsg::InsuranceConfig {} => to_json_binary(
+            &execute::load_insurance_config(_deps.storage).map_err(StdError::from)?,
+        ),
+        QueryMsg::PriceFeedConfig {} => {
+            to_json_binary(&execute::load_price_feed_config(_deps.storage)?)
+        }
+        QueryMsg::PriceInfo { asset } => {
+            to_json_binary(&query::fetch_price_info(_deps, _env, asset)?)
+        }
+        QueryMsg::TwapConfig {} => to_json_binary(&execute::load_twap_config(_deps.storage)?),
+        QueryMsg::Twap { asset, window } => to_json_binary(
+            &execute::twap(_deps.storage, &asset, _env.block.time, window)
+                .map_err(StdError::from)?,
+        ),
+        QueryMsg::PriceSources { asset } => {
+            to_json_binary(&query::fetch_price_sources(_deps, _env, asset)?)
+        }
+    }
+}
+
+pub mod query {
+    use super::*;
+
+    pub fn fetch_user_unminted_token_balance(
+        _deps: Deps,
+        _env: Env,
+        _query_data: QueryTokenData,
+    ) -> StdResult<Uint128> {
+        // Net of socialized losses not yet applied, like the collateral balance
+        match execute::collateral_after_loss(
+            _deps.storage,
+            &_query_data.token_address,
+            &_query_data.user_address,
+        ) {
+            Ok((_, unminted)) => Ok(unminted),
+            Err(_) => Err(ContractError::UnmintedTokenQueryFailed {}.into()),
+        }
+    }
+
+    pub fn fetch_user_token_balance(
+        _deps: Deps,
+        _env: Env,
+        _query_data: QueryTokenData,
+    ) -> StdResult<Uint128> {
+        let balance = match USER_TOKEN_BALANCE.may_load(
+            _deps.storage,
+            (&_query_data.token_address, &_query_data.user_address),
+        ) {
+            Ok(opt_data) => match opt_data {
+                Some(data) => data,
+                None => Uint128::zero(),
+            },
+            Err(_) => return Err(ContractError::UserTokenBalanceQueryFailed {}.into()),
+        };
+
+        // Report the balance net of socialized losses not yet applied
+        let loss = execute::pending_socialized_loss(
+            _deps.storage,
+            &_query_data.token_address,
+            &_query_data.user_address,
+            balance,
+        )?;
+        Ok(balance - loss)
+    }
+
+    pub fn fetch_user_borrow_token_balance(
+        _deps: Deps,
+        _env: Env,
+        _query_data: QueryTokenData,
+    ) -> StdResult<Uint128> {
+        match USER_BORROW_BALANCE.may_load(
+            _deps.storage,
+            (&_query_data.token_address, &_query_data.user_address),
+        ) {
+            Ok(opt_data) => match opt_data {
+                Some(data) => Ok(data),
+                None => Ok(Uint128::zero()),
+            },
+            Err(_) => Err(ContractError::UserBorrowTokenBalanceQueryFailed {}.into()),
+        }
+    }
+
+    pub fn fetch_user_profit_token_balance(
+        _deps: Deps,
+        _env: Env,
+        _query_data: QueryTokenData,
+    ) -> StdResult<Uint128> {
+        match USER_PROFIT_TOKEN.may_load(
+            _deps.storage,
+            (&_query_data.token_address, &_query_data.user_address),
+        ) {
+            Ok(opt_data) => match opt_data {
+                Some(data) => Ok(data),
+                None => Ok(Uint128::zero()),
+            },
+            Err(_) => Err(ContractError::UserProfitTokenBalanceQueryFailed {}.into()),
+        }
+    }
+
+    pub fn fetch_user_position_balance(
+        _deps: Deps,
+        _env: Env,
+        _query_data: QueryTokenData,
+    ) -> StdResult<Uint128> {
+        let balance = USER_POSITION_BALANCE.may_load(
+            _deps.storage,
+            (&_query_data.user_address, &_query_data.token_address),
+        )?;
+        Ok(balance.unwrap_or_default())
+    }
+
+    /// Price of `_asset` as the contract values it, through the same checks as execution
+    pub fn fetch_asset_price(_deps: Deps, _env: Env, _asset: Addr) -> StdResult<Decimal> {
+        let pricing = Pricing::at(_env.block.time);
+        Ok(execute::load_price(_deps.storage, &_asset, pricing)?)
+    }
+
+    pub fn fetch_liquidation_price(_deps: Deps, _env: Env, _asset: Addr) -> StdResult<Decimal> {
+        let pricing = execute::liquidation_pricing(_deps.storage, _env.block.time)?;
+        Ok(execute::load_price(_deps.storage, &_asset, pricing)?)
+    }
+
+    pub fn fetch_price_info(
+        _deps: Deps,
+        _env: Env,
+        _asset: Addr,
+    ) -> StdResult<Option<PriceInfoResponse>> {
+        let price = match ASSET_PRICE.may_load(_deps.storage, &_asset)? {
+            Some(price) => price,
+            None => return Ok(None),
+        };
+        let publish_time = ASSET_PRICE_TIME.may_load(_deps.storage, &_asset)?;
+        let config = execute::load_price_feed_config(_deps.storage)?;
+
+        Ok(Some(PriceInfoResponse {
+            price,
+            publish_time,
+            stale: execute::is_price_stale(&config, publish_time, _env.block.time),
+        }))
+    }
+
+    pub fn fetch_price_sources(
+        _deps: Deps,
+        _env: Env,
+        _asset: Addr,
+    ) -> StdResult<PriceSourcesResponse> {
+        let (price_sources, states) =
+            execute::price_source_states(_deps.storage, &_asset, Some(_env.block.time))?;
+        let prices: Vec<Option<Decimal>> = states
+            .iter()
+            .map(|state| state.price.filter(|_| state.fresh))
+            .collect();
+        let aggregate = oracle::aggregate(&prices, price_sources.quorum);
+
+        Ok(PriceSourcesResponse {
+            sources: states,
+            quorum: price_sources.quorum,
+            price: match aggregate {
+                Aggregate::Median(price) | Aggregate::Fallback(price) => Some(price),
+                Aggregate::Unavailable => None,
+            },
+            quorum_met: matches!(aggregate, Aggregate::Median(_)),
+        })
+    }
+
+    pub fn fetch_user_conditional_orders(
+        _deps: Deps,
+        _env: Env,
+        _user_address: Addr,
+    ) -> StdResult<Vec<ConditionalOrder>> {
+        CONDITIONAL_ORDERS
+            .prefix(&_user_address)
+            .range(_deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, order)| order))
+            .collect()
+    }
+
+    pub fn fetch_revealed_orders(
+        _deps: Deps,
+        _env: Env,
+        _height: u64,
+    ) -> StdResult<Vec<RevealedOrder>> {
+        REVEALED_ORDERS
+            .prefix(_height)
+            .range(_deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, order)| order))
+            .collect()
+    }
+
+    pub fn fetch_fee_config(_deps: Deps, _env: Env) -> StdResult<FeeConfig> {
+        Ok(execute::load_fee_config(_deps.storage)?)
+    }
+
+    pub fn fetch_accumulated_fees(_deps: Deps, _env: Env) -> StdResult<Vec<AssetAmount>> {
+        TREASURY
+            .range(_deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(asset, amount)| AssetAmount { asset, amount }))
+            .collect()
+    }
+
+    pub fn fetch_insurance_fund(
+        _deps: Deps,
+        _env: Env,
+        _asset: Addr,
+    ) -> StdResult<InsuranceFundResponse> {
+        Ok(InsuranceFundResponse {
+            balance: INSURANCE_FUND.may_load(_deps.storage, &_asset)?.unwrap_or_default(),
+            bad_debt: BAD_DEBT.may_load(_deps.storage, &_asset)?.unwrap_or_default(),
+            socialized_loss_index: SOCIALIZED_LOSS_INDEX
+                .may_load(_deps.storage, &_asset)?
+                .unwrap_or_default()
+                .index,
+            total_deposits: TOTAL_DEPOSITS.may_load(_deps.storage, &_asset)?.unwrap_or_default(),
+        })
+    }
+
+    /// Unexpired grants the owner has given to operators
+    pub fn fetch_operator_grants(
+        _deps: Deps,
+        _env: Env,
+        _owner: Addr,
+    ) -> StdResult<Vec<OperatorGrantResponse>> {
+        OPERATOR_GRANTS
+            .prefix(&_owner)
+            .range(_deps.storage, None, None, Order::Ascending)
+            .filter(|item| match item {
+                Ok((_, grant)) => !grant.expires.is_expired(&_env.block),
+                Err(_) => true,
+            })
+            .map(|item| {
+                item.map(|(operator, grant)| OperatorGrantResponse {
+                    operator,
+                    scope: grant.scope,
+                    expires: grant.expires,
+                })
+            })
+            .collect()
+    }
+
+    /// Main account followed by every opened sub-account of `owner`
+    pub fn fetch_sub_accounts(
+        _deps: Deps,
+        _env: Env,
+        _owner: Addr,
+    ) -> StdResult<Vec<SubAccountResponse>> {
+        let mut sub_accounts = vec![SubAccountResponse {
+            sub_account: 0,
+            account: _owner.clone(),
+            margin_mode: execute::load_margin_mode(_deps.storage, &_owner, 0)?,
+        }];
+        for item in SUB_ACCOUNTS
+            .prefix(&_owner)
+            .range(_deps.storage, None, None, Order::Ascending)
+        {
+            let (index, margin_mode) = item?;
+            if index == 0 {
+                continue;
+            }
+            sub_accounts.push(SubAccountResponse {
+                sub_account: index,
+                account: execute::sub_account_address(&_owner, index),
+                margin_mode,
+            });
+        }
+        Ok(sub_accounts)
+    }
+
+    /**
+     * @dev Outstanding borrows ordered by (token, account), for keepers scanning for
+     * liquidatable accounts.
+     *
+     * @param _start_after Last (token, account) of the previous page.
+     * @param _limit Page size, 10 by default and at most 30.
+     */
+    pub fn fetch_borrows(
+        _deps: Deps,
+        _env: Env,
+        _start_after: Option<(Addr, Addr)>,
+        _limit: Option<u32>,
+    ) -> StdResult<Vec<BorrowResponse>> {
+        let limit = _limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT) as usize;
+        let start = _start_after
+            .as_ref()
+            .map(|(token_address, user_address)| Bound::exclusive((token_address, user_address)));
+
+        USER_BORROW_BALANCE
+            .range(_deps.storage, start, None, Order::Ascending)
+            .filter(|item| !matches!(item, Ok((_, amount)) if amount.is_zero()))
+            .take(limit)
+            .map(|item| {
+                item.map(|((token_address, user_address), amount)| BorrowResponse {
+                    token_address,
+                    user_address,
+                    amount,
+                })
+            })
+            .collect()
+    }
+
+    /// Non-zero ledgers of an account, collateral net of pending socialized losses
+    pub fn fetch_account_ledgers(
+        _deps: Deps,
+        _env: Env,
+        _user_address: Addr,
+    ) -> StdResult<AccountLedgersResponse> {
+        let mut ledgers = AccountLedgersResponse {
+            collateral: vec![],
+            debt: vec![],
+            positions: vec![],
+        };
+
+        for token in LISTED_TOKEN.may_load(_deps.storage)?.unwrap_or_default() {
+            let asset = Addr::unchecked(token);
+            let collateral = USER_TOKEN_BALANCE
+                .may_load(_deps.storage, (&asset, &_user_address))?
+                .unwrap_or_default();
+            let collateral = collateral
+                - execute::pending_socialized_loss(_deps.storage, &asset, &_user_address, collateral)?;
+            if !collateral.is_zero() {
+                ledgers.collateral.push(AssetAmount {
+                    asset: asset.clone(),
+                    amount: collateral,
+                });
+            }
+            let debt = USER_BORROW_BALANCE
+                .may_load(_deps.storage, (&asset, &_user_address))?
+                .unwrap_or_default();
+            if !debt.is_zero() {
+                ledgers.debt.push(AssetAmount { asset, amount: debt });
+            }
+        }
+
+        for item in USER_POSITION_BALANCE
+            .prefix(&_user_address)
+            .range(_deps.storage, None, None, Order::Ascending)
+        {
+            let (asset, amount) = item?;
+            if !amount.is_zero() {
+                ledgers.positions.push(AssetAmount { asset, amount });
+            }
+        }
+        Ok(ledgers)
+    }
+
+    pub fn fetch_listed_tokens(deps: Deps, _env: Env) -> StdResult<Vec<String>> {
+        match LISTED_TOKEN.may_load(deps.storage) {
+            Ok(opt_listed_token) => match opt_listed_token {
+                Some(data) => Ok(data),
+                None => Ok(vec![]),
+            },
+            Err(_) => Err(ContractError::UnableToFetchListedToken {}.into()),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(_deps: DepsMut, _env: Env, _msg: Reply) -> Result<Response, ContractError> {
+    match _msg.id {
+        SWAP_REPLY_ID => execute::handle_swap_reply(_deps, _env),
+        FLASH_LOAN_REPLY_ID => execute::handle_flash_loan_reply(_deps, _env),
+        id => Err(ContractError::UnknownReplyId { id }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::msg::{ConditionalOrderData, EncryptedOrderReveal};
+    use cosmwasm_std::{coin, coins, Addr};
+    use cw_multi_test::error::AnyResult;
+    use cw_multi_test::{App, AppBuilder, AppResponse, ContractWrapper, Executor};
+
+    #[test]
+    fn cw_multi_instantiate() {
+        let init_funds = coins(100, "DEMON");
+        let mut app = AppBuilder::new().build(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &Addr::unchecked("creator"), init_funds)
+                .unwrap();
+        });
+
+        let group_code_id =
+            app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+
+        let msg = InstantiateMsg {
+            token_contract_address: String::from("usdc_contract"),
+        };
+
+        let contract_addr = app.instantiate_contract(
+            group_code_id,
+            Addr::unchecked("creator"),
+            &msg,
+            &[],
+            "leverage_contract",
+            None,
+        );
+
+        let cont = contract_addr.unwrap();
+        set_price(&mut app, &cont, "usdc_contract", "1");
+
+        let execute_deposit_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("user_one"),
+            amount: Uint128::from(1000u128),
+            msg: to_json_binary(&{}).unwrap(),
+        });
+
+        app.execute_contract(
+            Addr::unchecked("usdc_contract"),
+            cont.clone(),
+            &execute_deposit_msg,
+            &[],
+        )
+        .unwrap();
+
+        let balance = |app: &App, query: fn(QueryTokenData) -> QueryMsg| -> Uint128 {
+            app.wrap()
+                .query_wasm_smart(
+                    cont.clone(),
+                    &query(QueryTokenData {
+                        token_address: Addr::unchecked("usdc_contract"),
+                        user_address: Addr::unchecked("user_one"),
+                    }),
+                )
+                .unwrap()
+        };
+        assert_eq!(balance(&app, QueryMsg::UserCollateralTokenBalance), Uint128::from(1000u128));
+        assert_eq!(balance(&app, QueryMsg::UserWrappedTokenBalance), Uint128::from(10000u128));
+
+        let borrow_exe_msg = ExecuteMsg::Borrow(TokenData {
+            token_address: Addr::unchecked("usdc_contract"),
+            token_amount: Uint128::from(100u128),
+            on_behalf_of: None,
+            sub_account: None,
+        });
+        app.execute_contract(Addr::unchecked("user_one"), cont.clone(), &borrow_exe_msg, &[])
+            .unwrap();
+        assert_eq!(balance(&app, QueryMsg::UserBorrowTokenBalance), Uint128::from(100u128));
+        assert_eq!(balance(&app, QueryMsg::UserWrappedTokenBalance), Uint128::from(9900u128));
+
+        // Collateral stays locked while the borrow is outstanding
+        let withdraw_exe_msg = ExecuteMsg::WithdrawToken(WithdrawData {
+            token_address: Addr::unchecked("usdc_contract"),
+            token_amount: Uint128::from(10u128),
+            withdraw_type: String::from("fungible"),
+            native: None,
+            on_behalf_of: None,
+            sub_account: None,
+        });
+        let err = app
+            .execute_contract(Addr::unchecked("user_one"), cont.clone(), &withdraw_exe_msg, &[])
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Borrow amount is not zero");
+        assert_eq!(balance(&app, QueryMsg::UserCollateralTokenBalance), Uint128::from(1000u128));
+    }
+
+    fn set_price(app: &mut App, cont: &Addr, asset: &str, price: &str) {
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdateAssetPrice {
+                asset: Addr::unchecked(asset),
+                price: price.parse().unwrap(),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    fn position(app: &App, cont: &Addr, user: &str, asset: &str) -> Uint128 {
+        app.wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::UserPositionBalance(QueryTokenData {
+                    token_address: Addr::unchecked(asset),
+                    user_address: Addr::unchecked(user),
+                }),
+            )
+            .unwrap()
+    }
+
+    /// Contract priced at 1 usdc and 2 osmo, with `deposits` of usdc collateral
+    fn usdc_app(deposits: &[(&str, u128)]) -> (App, Addr) {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(
+            ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+        ));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        set_price(&mut app, &cont, "usdc_contract", "1");
+        set_price(&mut app, &cont, "osmo", "2");
+
+        for (user, amount) in deposits {
+            app.execute_contract(
+                Addr::unchecked("usdc_contract"),
+                cont.clone(),
+                &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                    sender: String::from(*user),
+                    amount: Uint128::from(*amount),
+                    msg: to_json_binary(&{}).unwrap(),
+                }),
+                &[],
+            )
+            .unwrap();
+        }
+        (app, cont)
+    }
+
+    fn borrow_usdc(app: &mut App, cont: &Addr, user: &str, amount: u128) -> AnyResult<AppResponse> {
+        app.execute_contract(
+            Addr::unchecked(user),
+            cont.clone(),
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::from(amount),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+            &[],
+        )
+    }
+
+    #[test]
+    fn execute_order_checks_the_encrypted_commitment() {
+        let (mut app, cont) = usdc_app(&[("user_one", 100)]);
+        borrow_usdc(&mut app, &cont, "user_one", 1000).unwrap();
+
+        // Key hard-coded in constant/constant.js
+        let public_key =
+            PublicKey::new("2110635290356708079658926219106600858277".parse().unwrap()).unwrap();
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdatePaillierPublicKey { n: public_key.n },
+            &[],
+        )
+        .unwrap();
+
+        let randomness = Uint512::from(123_456_789u128);
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::CommitEncryptedOrder {
+                order_id: String::from("order-1"),
+                encrypted_order_value: public_key
+                    .encrypt_with(Uint512::from(600u128), randomness)
+                    .unwrap(),
+                on_behalf_of: None,
+                sub_account: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+        let partial = |amount_in: u128, order_amount: Option<u128>, randomness: Uint512| {
+            ExecuteMsg::ExecuteOrder(OrderExecute {
+                user_address: Addr::unchecked("user_one"),
+                token_in: Addr::unchecked("usdc_contract"),
+                token_out: Addr::unchecked("osmo"),
+                amount_in: Uint128::from(amount_in),
+                amount_out: Uint128::from(amount_in / 2),
+                encrypted_order: Some(EncryptedOrderReveal {
+                    order_id: String::from("order-1"),
+                    randomness,
+                    order_amount: order_amount.map(Uint128::from),
+                }),
+            })
+        };
+        let order = |amount_in: u128, randomness: Uint512| partial(amount_in, None, randomness);
+
+        // Neither a different amount nor different randomness opens the commitment
+        for msg in [order(700, randomness), order(600, Uint512::from(987_654_321u128))] {
+            let err = app
+                .execute_contract(Addr::unchecked("creator"), cont.clone(), &msg, &[])
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                "Order amount does not match the encrypted order"
+            );
+        }
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::zero());
+
+        // Partial fills add up to the committed amount and no further
+        let fill = partial(400, Some(600), randomness);
+        app.execute_contract(Addr::unchecked("creator"), cont.clone(), &fill, &[])
+            .unwrap();
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::from(200u128));
+        let err = app
+            .execute_contract(Addr::unchecked("creator"), cont.clone(), &fill, &[])
+            .unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Fill exceeds the unfilled amount of the encrypted order"
+        );
+        let fill = partial(200, Some(600), randomness);
+        app.execute_contract(Addr::unchecked("creator"), cont.clone(), &fill, &[])
+            .unwrap();
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::from(300u128));
+
+        // The commitment is consumed once it is filled
+        let err = app
+            .execute_contract(Addr::unchecked("creator"), cont.clone(), &fill, &[])
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Encrypted order not found");
+    }
+
+    #[test]
+    fn committed_orders_reveal_and_settle_in_a_batch() {
+        let (mut app, cont) = usdc_app(&[("user_one", 100), ("user_two", 100)]);
+        for user in ["user_one", "user_two"] {
+            borrow_usdc(&mut app, &cont, user, 1000).unwrap();
+        }
+        // user_two holds 500 osmo to sell
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::ExecuteOrder(OrderExecute {
+                user_address: Addr::unchecked("user_two"),
+                token_in: Addr::unchecked("usdc_contract"),
+                token_out: Addr::unchecked("osmo"),
+                amount_in: Uint128::from(1000u128),
+                amount_out: Uint128::from(500u128),
+                encrypted_order: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let order = |token_in: &str, token_out: &str, amount_in: u128, limit_price: &str| {
+            RevealedOrderData {
+                token_in: Addr::unchecked(token_in),
+                token_out: Addr::unchecked(token_out),
+                amount_in: Uint128::from(amount_in),
+                limit_price: limit_price.parse().unwrap(),
+                salt: String::from("salt"),
+            }
+        };
+        let commit = |app: &mut App, user: &str, order: &RevealedOrderData, margin: u128| {
+            let mut hasher = Sha256::new();
+            hasher.update(user.as_bytes());
+            hasher.update(to_json_vec(order).unwrap());
+            app.execute_contract(
+                Addr::unchecked(user),
+                cont.clone(),
+                &ExecuteMsg::CommitOrder {
+                    commitment: Binary::from(hasher.finalize().as_slice()),
+                    margin_token: order.token_in.clone(),
+                    margin_amount: Uint128::from(margin),
+                    on_behalf_of: None,
+                    sub_account: None,
+                },
+                &[],
+            )
+            .unwrap();
+        };
+        let reveal = |app: &mut App, user: &str, commitment_id: u64, order: &RevealedOrderData| {
+            app.execute_contract(
+                Addr::unchecked(user),
+                cont.clone(),
+                &ExecuteMsg::RevealOrder {
+                    commitment_id,
+                    order: order.clone(),
+                    on_behalf_of: None,
+                    sub_account: None,
+                },
+                &[],
+            )
+        };
+        let next_block =
+            |app: &mut App, blocks: u64| app.update_block(|block| block.height += blocks);
+
+        // At least 0.5 osmo per usdc, at least 1.8 usdc per osmo, and one never revealed
+        let buy = order("usdc_contract", "osmo", 400, "0.5");
+        let sell = order("osmo", "usdc_contract", 100, "1.8");
+        commit(&mut app, "user_one", &buy, 500);
+        commit(&mut app, "user_two", &sell, 150);
+        commit(&mut app, "user_one", &order("usdc_contract", "osmo", 50, "1"), 50);
+        assert_eq!(position(&app, &cont, "user_one", "usdc_contract"), Uint128::from(450u128));
+        assert_eq!(position(&app, &cont, "user_two", "osmo"), Uint128::from(350u128));
+
+        let err = reveal(&mut app, "user_one", 1, &buy).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Reveal window is closed");
+
+        next_block(&mut app, 1);
+        let batch_height = app.block_info().height;
+        let salted = RevealedOrderData {
+            salt: String::from("other"),
+            ..buy.clone()
+        };
+        let err = reveal(&mut app, "user_one", 1, &salted).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Revealed order does not match the commitment"
+        );
+        let err = reveal(&mut app, "user_two", 1, &buy).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Unauthorized");
+
+        // Margin above `amount_in` comes back on reveal
+        let res = reveal(&mut app, "user_one", 1, &buy).unwrap();
+        assert_eq!(event_attribute(&res, "order_refund", "kind"), "excess_margin");
+        assert_eq!(event_attribute(&res, "order_refund", "position_balance"), "550");
+        reveal(&mut app, "user_two", 2, &sell).unwrap();
+        assert_eq!(position(&app, &cont, "user_one", "usdc_contract"), Uint128::from(550u128));
+        assert_eq!(position(&app, &cont, "user_two", "osmo"), Uint128::from(400u128));
+
+        let settle = ExecuteMsg::SettleOrderBatch {
+            height: batch_height,
+        };
+        let err = app
+            .execute_contract(Addr::unchecked("keeper"), cont.clone(), &settle, &[])
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Reveal window is still open");
+
+        // The book clears 100 osmo at 2 usdc a minute later, with osmo's spot price at 3.
+        // The buyer's unspent 200 usdc is refunded.
+        next_block(&mut app, 1);
+        app.update_block(|block| block.time = block.time.plus_seconds(60));
+        set_price(&mut app, &cont, "osmo", "3");
+        let res = app
+            .execute_contract(Addr::unchecked("keeper"), cont.clone(), &settle, &[])
+            .unwrap();
+        let clearing_price = res.events.iter().find_map(|event| {
+            event
+                .attributes
+                .iter()
+                .find(|attribute| attribute.key == "clearing_price_osmo_usdc_contract")
+        });
+        assert_eq!(clearing_price.unwrap().value, "2");
+        assert_eq!(event_attribute(&res, "order_refund", "kind"), "unfilled");
+        assert_eq!(event_attribute(&res, "order_refund", "amount"), "200");
+        assert_eq!(event_attribute(&res, "order_refund", "position_balance"), "750");
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::from(100u128));
+        assert_eq!(position(&app, &cont, "user_one", "usdc_contract"), Uint128::from(750u128));
+        assert_eq!(position(&app, &cont, "user_two", "usdc_contract"), Uint128::from(200u128));
+        assert_eq!(position(&app, &cont, "user_two", "osmo"), Uint128::from(400u128));
+
+        // Both fills are observed, valuing usdc at 1.5 against the osmo spot price
+        app.update_block(|block| block.time = block.time.plus_seconds(60));
+        let twap: Option<Decimal> = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::Twap {
+                    asset: Addr::unchecked("usdc_contract"),
+                    window: 60,
+                },
+            )
+            .unwrap();
+        assert_eq!(twap, Some(Decimal::percent(150)));
+
+        // Anyone can refund a commitment once its reveal window has passed
+        let expire = ExecuteMsg::ExpireOrderCommitment { commitment_id: 3 };
+        let err = app
+            .execute_contract(Addr::unchecked("keeper"), cont.clone(), &expire, &[])
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Reveal window is still open");
+        next_block(&mut app, DEFAULT_ORDER_REVEAL_WINDOW);
+        app.execute_contract(Addr::unchecked("keeper"), cont.clone(), &expire, &[])
+            .unwrap();
+        assert_eq!(position(&app, &cont, "user_one", "usdc_contract"), Uint128::from(800u128));
+        let err = reveal(&mut app, "user_one", 3, &order("usdc_contract", "osmo", 50, "1"))
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Order commitment not found");
+    }
+
+    #[test]
+    fn stop_loss_executed_by_keeper() {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        set_price(&mut app, &cont, "usdc_contract", "1");
+        set_price(&mut app, &cont, "osmo", "2");
+
+        app.execute_contract(
+            Addr::unchecked("usdc_contract"),
+            cont.clone(),
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: String::from("user_one"),
+                amount: Uint128::from(100u128),
+                msg: to_json_binary(&{}).unwrap(),
+            }),
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::from(1000u128),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        // Buy 500 osmo with the borrowed 1000 usdc
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::ExecuteOrder(OrderExecute {
+                user_address: Addr::unchecked("user_one"),
+                token_in: Addr::unchecked("usdc_contract"),
+                token_out: Addr::unchecked("osmo"),
+                amount_in: Uint128::from(1000u128),
+                amount_out: Uint128::from(500u128),
+                encrypted_order: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::PlaceConditionalOrder(ConditionalOrderData {
+                token_in: Addr::unchecked("osmo"),
+                token_out: Addr::unchecked("usdc_contract"),
+                amount_in: None,
+                kind: ConditionalOrderKind::StopLoss {
+                    trigger_price: "1.5".parse().unwrap(),
+                },
+                bounty: Uint128::from(10u128),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let execute_msg = ExecuteMsg::ExecuteConditionalOrder {
+            owner: Addr::unchecked("user_one"),
+            order_id: 1,
+        };
+        app.execute_contract(Addr::unchecked("keeper"), cont.clone(), &execute_msg, &[])
+            .unwrap_err();
+
+        // 100 collateral + 500 osmo at 1.4 = 800 against 1000 debt, the stop still sells
+        set_price(&mut app, &cont, "osmo", "1.4");
+        let res = app
+            .execute_contract(Addr::unchecked("keeper"), cont.clone(), &execute_msg, &[])
+            .unwrap();
+        assert_eq!(event_attribute(&res, "keeper_bounty", "amount"), "10");
+        assert_eq!(event_attribute(&res, "keeper_bounty", "position_balance"), "690");
+        assert_eq!(event_attribute(&res, "keeper_bounty", "keeper_collateral_balance"), "10");
+
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::zero());
+        assert_eq!(
+            position(&app, &cont, "user_one", "usdc_contract"),
+            Uint128::from(690u128)
+        );
+
+        let keeper_bounty: Uint128 = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::UserCollateralTokenBalance(QueryTokenData {
+                    token_address: Addr::unchecked("usdc_contract"),
+                    user_address: Addr::unchecked("keeper"),
+                }),
+            )
+            .unwrap();
+        assert_eq!(keeper_bounty, Uint128::from(10u128));
+
+        let orders: Vec<ConditionalOrder> = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::UserConditionalOrders {
+                    user_address: Addr::unchecked("user_one"),
+                },
+            )
+            .unwrap();
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn risk_increasing_actions_require_a_healthy_account() {
+        let (mut app, cont) = usdc_app(&[("user_one", 200)]);
+        borrow_usdc(&mut app, &cont, "user_one", 1000).unwrap();
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::ExecuteOrder(OrderExecute {
+                user_address: Addr::unchecked("user_one"),
+                token_in: Addr::unchecked("usdc_contract"),
+                token_out: Addr::unchecked("osmo"),
+                amount_in: Uint128::from(1000u128),
+                amount_out: Uint128::from(500u128),
+                encrypted_order: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        // 200 collateral + 500 osmo at 1.6 = 1000 against 1000 debt
+        set_price(&mut app, &cont, "osmo", "1.6");
+        let err = borrow_usdc(&mut app, &cont, "user_one", 10).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Health factor 1 is below 1.05");
+
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdatePriceFeedConfig(PriceFeedConfig {
+                feeders: vec![],
+                max_price_age: Some(60),
+                max_deviation_bps: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        app.update_block(|block| block.time = block.time.plus_seconds(61));
+        let err = app
+            .wrap()
+            .query_wasm_smart::<Decimal>(
+                cont.clone(),
+                &QueryMsg::AssetPrice {
+                    asset: Addr::unchecked("osmo"),
+                },
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("Price of osmo is stale"));
+    }
+
+    #[test]
+    fn liquidation_shortfall_is_socialized() {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        set_price(&mut app, &cont, "usdc_contract", "1");
+        set_price(&mut app, &cont, "osmo", "2");
+
+        for (user, amount) in [("user_one", 100u128), ("user_two", 1000u128)] {
+            app.execute_contract(
+                Addr::unchecked("usdc_contract"),
+                cont.clone(),
+                &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                    sender: String::from(user),
+                    amount: Uint128::from(amount),
+                    msg: to_json_binary(&{}).unwrap(),
+                }),
+                &[],
+            )
+            .unwrap();
+        }
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::from(1000u128),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::ExecuteOrder(OrderExecute {
+                user_address: Addr::unchecked("user_one"),
+                token_in: Addr::unchecked("usdc_contract"),
+                token_out: Addr::unchecked("osmo"),
+                amount_in: Uint128::from(1000u128),
+                amount_out: Uint128::from(500u128),
+                encrypted_order: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let liquidate_msg = ExecuteMsg::Liquidate {
+            user_address: Addr::unchecked("user_one"),
+            debt_token: Addr::unchecked("usdc_contract"),
+        };
+        app.execute_contract(Addr::unchecked("keeper"), cont.clone(), &liquidate_msg, &[])
+            .unwrap_err();
+
+        // 100 collateral + 500 osmo at 1.5 = 850 against 1000 debt
+        set_price(&mut app, &cont, "osmo", "1.5");
+        app.execute_contract(Addr::unchecked("keeper"), cont.clone(), &liquidate_msg, &[])
+            .unwrap();
+
+        let insurance: InsuranceFundResponse = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::InsuranceFund {
+                    asset: Addr::unchecked("usdc_contract"),
+                },
+            )
+            .unwrap();
+        assert_eq!(insurance.bad_debt.socialized, Uint128::from(150u128));
+        assert_eq!(insurance.total_deposits, Uint128::from(850u128));
+
+        let user_two_balance: Uint128 = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::UserCollateralTokenBalance(QueryTokenData {
+                    token_address: Addr::unchecked("usdc_contract"),
+                    user_address: Addr::unchecked("user_two"),
+                }),
+            )
+            .unwrap();
+        assert_eq!(user_two_balance, Uint128::from(850u128));
+    }
+
+    #[test]
+    fn deposits_and_borrows_respect_token_caps() {
+        let (mut app, cont) = usdc_app(&[("user_one", 100)]);
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdateTokenCaps {
+                token_address: Addr::unchecked("usdc_contract"),
+                caps: TokenCaps {
+                    supply_cap: Some(Uint128::from(800u128)),
+                    borrow_cap: Some(Uint128::from(500u128)),
+                    user_supply_cap: Some(Uint128::from(400u128)),
+                    user_borrow_cap: Some(Uint128::from(300u128)),
+                },
+            },
+            &[],
+        )
+        .unwrap();
+        let deposit = |app: &mut App, user: &str, amount: u128| {
+            app.execute_contract(
+                Addr::unchecked("usdc_contract"),
+                cont.clone(),
+                &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                    sender: String::from(user),
+                    amount: Uint128::from(amount),
+                    msg: to_json_binary(&{}).unwrap(),
+                }),
+                &[],
+            )
+        };
+
+        // Per-user cap first, then the global cap once other users filled it
+        let err = deposit(&mut app, "user_one", 301).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Supply cap exceeded, remaining capacity: 300"
+        );
+        deposit(&mut app, "user_two", 350).unwrap();
+        let err = deposit(&mut app, "user_three", 351).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Supply cap exceeded, remaining capacity: 350"
+        );
+
+        let err = borrow_usdc(&mut app, &cont, "user_one", 301).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Borrow cap exceeded, remaining capacity: 300"
+        );
+        borrow_usdc(&mut app, &cont, "user_one", 300).unwrap();
+        let err = borrow_usdc(&mut app, &cont, "user_two", 201).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Borrow cap exceeded, remaining capacity: 200"
+        );
+
+        let capacity = |user_address: Option<&str>| -> TokenCapacityResponse {
+            app.wrap()
+                .query_wasm_smart(
+                    cont.clone(),
+                    &QueryMsg::TokenCapacity {
+                        token_address: Addr::unchecked("usdc_contract"),
+                        user_address: user_address.map(Addr::unchecked),
+                    },
+                )
+                .unwrap()
+        };
+        let user_two = capacity(Some("user_two"));
+        assert_eq!(user_two.total_supplied, Uint128::from(450u128));
+        assert_eq!(user_two.total_borrowed, Uint128::from(300u128));
+        assert_eq!(user_two.remaining_supply, Some(Uint128::from(350u128)));
+        assert_eq!(user_two.remaining_borrow, Some(Uint128::from(200u128)));
+        assert_eq!(user_two.remaining_user_supply, Some(Uint128::from(50u128)));
+        assert_eq!(user_two.remaining_user_borrow, Some(Uint128::from(300u128)));
+        let global = capacity(None);
+        assert_eq!(global.caps.supply_cap, Some(Uint128::from(800u128)));
+        assert_eq!(global.remaining_user_supply, None);
+        assert_eq!(global.remaining_user_borrow, None);
+    }
+
+    #[test]
+    fn user_caps_are_shared_across_sub_accounts() {
+        let (mut app, cont) = usdc_app(&[]);
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdateTokenCaps {
+                token_address: Addr::unchecked("usdc_contract"),
+                caps: TokenCaps {
+                    supply_cap: None,
+                    borrow_cap: None,
+                    user_supply_cap: Some(Uint128::from(400u128)),
+                    user_borrow_cap: Some(Uint128::from(300u128)),
+                },
+            },
+            &[],
+        )
+        .unwrap();
+        for _ in 0..2 {
+            app.execute_contract(
+                Addr::unchecked("user_one"),
+                cont.clone(),
+                &ExecuteMsg::OpenSubAccount {
+                    margin_mode: MarginMode::Cross,
+                },
+                &[],
+            )
+            .unwrap();
+        }
+        let deposit = |app: &mut App, amount: u128, sub_account: Option<u32>| {
+            app.execute_contract(
+                Addr::unchecked("usdc_contract"),
+                cont.clone(),
+                &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                    sender: String::from("user_one"),
+                    amount: Uint128::from(amount),
+                    msg: to_json_binary(&DepositReceiveMsg {
+                        sub_account,
+                        open_position: None,
+                    })
+                    .unwrap(),
+                }),
+                &[],
+            )
+        };
+        let borrow = |app: &mut App, amount: u128, sub_account: u32| {
+            app.execute_contract(
+                Addr::unchecked("user_one"),
+                cont.clone(),
+                &ExecuteMsg::Borrow(TokenData {
+                    token_address: Addr::unchecked("usdc_contract"),
+                    token_amount: Uint128::from(amount),
+                    on_behalf_of: None,
+                    sub_account: Some(sub_account),
+                }),
+                &[],
+            )
+        };
+
+        deposit(&mut app, 300, None).unwrap();
+        deposit(&mut app, 100, Some(1)).unwrap();
+        let err = deposit(&mut app, 1, Some(2)).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Supply cap exceeded, remaining capacity: 0"
+        );
+
+        borrow(&mut app, 200, 1).unwrap();
+        let err = borrow(&mut app, 101, 0).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Borrow cap exceeded, remaining capacity: 100"
+        );
+
+        let capacity: TokenCapacityResponse = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::TokenCapacity {
+                    token_address: Addr::unchecked("usdc_contract"),
+                    user_address: Some(Addr::unchecked("user_one/2")),
+                },
+            )
+            .unwrap();
+        assert_eq!(capacity.remaining_user_supply, Some(Uint128::zero()));
+        assert_eq!(capacity.remaining_user_borrow, Some(Uint128::from(100u128)));
+    }
+
+    #[test]
+    fn sequential_shortfalls_compound_the_loss_index() {
+        let (mut app, cont) =
+            usdc_app(&[("user_one", 100), ("user_three", 100), ("user_two", 2000)]);
+        for user in ["user_one", "user_three"] {
+            borrow_usdc(&mut app, &cont, user, 1000).unwrap();
+            app.execute_contract(
+                Addr::unchecked("creator"),
+                cont.clone(),
+                &ExecuteMsg::ExecuteOrder(OrderExecute {
+                    user_address: Addr::unchecked(user),
+                    token_in: Addr::unchecked("usdc_contract"),
+                    token_out: Addr::unchecked("osmo"),
+                    amount_in: Uint128::from(1000u128),
+                    amount_out: Uint128::from(500u128),
+                    encrypted_order: None,
+                }),
+                &[],
+            )
+            .unwrap();
+        }
+
+        set_price(&mut app, &cont, "osmo", "1.5");
+        for user in ["user_one", "user_three"] {
+            app.execute_contract(
+                Addr::unchecked("keeper"),
+                cont.clone(),
+                &ExecuteMsg::Liquidate {
+                    user_address: Addr::unchecked(user),
+                    debt_token: Addr::unchecked("usdc_contract"),
+                },
+                &[],
+            )
+            .unwrap();
+        }
+
+        let insurance: InsuranceFundResponse = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::InsuranceFund {
+                    asset: Addr::unchecked("usdc_contract"),
+                },
+            )
+            .unwrap();
+        let user_two_balance: Uint128 = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::UserCollateralTokenBalance(QueryTokenData {
+                    token_address: Addr::unchecked("usdc_contract"),
+                    user_address: Addr::unchecked("user_two"),
+                }),
+            )
+            .unwrap();
+        // 150 socialized out of 2100 then 158 out of 1858, the additive index would leave 1687
+        assert_eq!(insurance.bad_debt.socialized, Uint128::from(308u128));
+        assert_eq!(insurance.total_deposits, Uint128::from(1700u128));
+        assert_eq!(user_two_balance, Uint128::from(1699u128));
+    }
+
+    fn event_attribute(res: &cw_multi_test::AppResponse, ty: &str, key: &str) -> String {
+        res.events
+            .iter()
+            .find(|event| event.ty == format!("wasm-{}", ty))
+            .and_then(|event| event.attributes.iter().find(|attribute| attribute.key == key))
+            .map(|attribute| attribute.value.clone())
+            .unwrap_or_else(|| panic!("missing {} in {} event", key, ty))
+    }
+
+    #[test]
+    fn repay_and_burn_emit_typed_events() {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        set_price(&mut app, &cont, "usdc_contract", "1");
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked("usdc_contract"),
+                cont.clone(),
+                &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                    sender: String::from("user_one"),
+                    amount: Uint128::from(100u128),
+                    msg: to_json_binary(&{}).unwrap(),
+                }),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(event_attribute(&res, "deposit", "amount"), "100");
+        assert_eq!(event_attribute(&res, "deposit", "unminted_balance"), "1000");
+
+        let token_data = |amount: u128| TokenData {
+            token_address: Addr::unchecked("usdc_contract"),
+            token_amount: Uint128::from(amount),
+            on_behalf_of: None,
+            sub_account: None,
+        };
+        let res = app
+            .execute_contract(
+                Addr::unchecked("user_one"),
+                cont.clone(),
+                &ExecuteMsg::Borrow(token_data(500)),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(event_attribute(&res, "borrow", "borrow_balance"), "500");
+        assert_eq!(event_attribute(&res, "borrow", "position_balance"), "500");
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked("user_one"),
+                cont.clone(),
+                &ExecuteMsg::Repay(token_data(500)),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(event_attribute(&res, "repay", "user"), "user_one");
+        assert_eq!(event_attribute(&res, "repay", "asset"), "usdc_contract");
+        assert_eq!(event_attribute(&res, "repay", "amount"), "500");
+        assert_eq!(event_attribute(&res, "repay", "borrow_balance"), "0");
+        assert_eq!(event_attribute(&res, "repay", "unminted_balance"), "1000");
+        // Without debt the event carries no health factor
+        let repay = res.events.iter().find(|event| event.ty == "wasm-repay").unwrap();
+        assert!(repay.attributes.iter().all(|attribute| attribute.key != "health_factor"));
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked("user_one"),
+                cont.clone(),
+                &ExecuteMsg::Burn(token_data(0)),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(event_attribute(&res, "burn", "user"), "user_one");
+        assert_eq!(event_attribute(&res, "burn", "v_token_amount"), "0");
+        assert_eq!(event_attribute(&res, "burn", "collateral_amount"), "0");
+        assert_eq!(event_attribute(&res, "burn", "collateral_balance"), "100");
+    }
+
+    #[test]
+    fn operator_acts_within_granted_scope() {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        set_price(&mut app, &cont, "usdc_contract", "1");
+
+        app.execute_contract(
+            Addr::unchecked("usdc_contract"),
+            cont.clone(),
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: String::from("user_one"),
+                amount: Uint128::from(100u128),
+                msg: to_json_binary(&{}).unwrap(),
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let borrow_msg = ExecuteMsg::Borrow(TokenData {
+            token_address: Addr::unchecked("usdc_contract"),
+            token_amount: Uint128::from(500u128),
+            on_behalf_of: Some(Addr::unchecked("user_one")),
+            sub_account: None,
+        });
+        let repay_msg = ExecuteMsg::Repay(TokenData {
+            token_address: Addr::unchecked("usdc_contract"),
+            token_amount: Uint128::from(200u128),
+            on_behalf_of: Some(Addr::unchecked("user_one")),
+            sub_account: None,
+        });
+
+        // No grant yet
+        app.execute_contract(Addr::unchecked("bot"), cont.clone(), &borrow_msg, &[])
+            .unwrap_err();
+
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::GrantOperator {
+                operator: Addr::unchecked("bot"),
+                scope: OperatorScope::Full,
+                expires: Some(Expiration::AtHeight(app.block_info().height + 10)),
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(Addr::unchecked("bot"), cont.clone(), &borrow_msg, &[])
+            .unwrap();
+        assert_eq!(position(&app, &cont, "user_one", "usdc_contract"), Uint128::from(500u128));
+        assert_eq!(position(&app, &cont, "bot", "usdc_contract"), Uint128::zero());
+
+        // A repay-only grant no longer covers borrowing
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::GrantOperator {
+                operator: Addr::unchecked("bot"),
+                scope: OperatorScope::Repay,
+                expires: None,
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(Addr::unchecked("bot"), cont.clone(), &borrow_msg, &[])
+            .unwrap_err();
+        app.execute_contract(Addr::unchecked("bot"), cont.clone(), &repay_msg, &[])
+            .unwrap();
+        assert_eq!(position(&app, &cont, "user_one", "usdc_contract"), Uint128::from(300u128));
+
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::RevokeOperator {
+                operator: Addr::unchecked("bot"),
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(Addr::unchecked("bot"), cont.clone(), &repay_msg, &[])
+            .unwrap_err();
+    }
+
+    #[test]
+    fn isolated_sub_account_is_liquidated_alone() {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        set_price(&mut app, &cont, "usdc_contract", "1");
+        set_price(&mut app, &cont, "osmo", "2");
+
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::OpenSubAccount {
+                margin_mode: MarginMode::Isolated,
+            },
+            &[],
+        )
+        .unwrap();
+        let sub_accounts: Vec<SubAccountResponse> = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::SubAccounts {
+                    owner: Addr::unchecked("user_one"),
+                },
+            )
+            .unwrap();
+        let isolated = sub_accounts[1].account.clone();
+        assert_eq!(isolated, Addr::unchecked("user_one/1"));
+
+        for (amount, sub_account) in [(1000u128, None), (100u128, Some(1))] {
+            app.execute_contract(
+                Addr::unchecked("usdc_contract"),
+                cont.clone(),
+                &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                    sender: String::from("user_one"),
+                    amount: Uint128::from(amount),
+                    msg: to_json_binary(&DepositReceiveMsg {
+                        sub_account,
+                        open_position: None,
+                    })
+                    .unwrap(),
+                }),
+                &[],
+            )
+            .unwrap();
+        }
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::from(1000u128),
+                on_behalf_of: None,
+                sub_account: Some(1),
+            }),
+            &[],
+        )
+        .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::ExecuteOrder(OrderExecute {
+                user_address: isolated.clone(),
+                token_in: Addr::unchecked("usdc_contract"),
+                token_out: Addr::unchecked("osmo"),
+                amount_in: Uint128::from(1000u128),
+                amount_out: Uint128::from(500u128),
+                encrypted_order: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        // 100 collateral + 500 osmo at 1.8 = 1000 against 1000 debt, the main account's
+        // 1000 collateral does not count
+        set_price(&mut app, &cont, "osmo", "1.8");
+        app.execute_contract(
+            Addr::unchecked("keeper"),
+            cont.clone(),
+            &ExecuteMsg::Liquidate {
+                user_address: isolated.clone(),
+                debt_token: Addr::unchecked("usdc_contract"),
+            },
+            &[],
+        )
+        .unwrap();
+
+        let collateral = |app: &App, user: &Addr| -> Uint128 {
+            app.wrap()
+                .query_wasm_smart(
+                    cont.clone(),
+                    &QueryMsg::UserCollateralTokenBalance(QueryTokenData {
+                        token_address: Addr::unchecked("usdc_contract"),
+                        user_address: user.clone(),
+                    }),
+                )
+                .unwrap()
+        };
+        assert_eq!(collateral(&app, &isolated), Uint128::zero());
+        assert_eq!(collateral(&app, &Addr::unchecked("user_one")), Uint128::from(1000u128));
+        assert_eq!(position(&app, &cont, "user_one/1", "osmo"), Uint128::zero());
+    }
+
+    fn flash_borrower_execute(
+        _deps: DepsMut,
+        _env: Env,
+        info: MessageInfo,
+        msg: FlashLoanReceiverExecuteMsg,
+    ) -> StdResult<Response> {
+        match msg {
+            FlashLoanReceiverExecuteMsg::FlashLoanCallback {
+                asset,
+                amount,
+                fee,
+                msg,
+            } => {
+                // The callback message is the amount the borrower keeps for itself and a
+                // message it sends back to the lender before repaying
+                let (kept, reentry): (Uint128, Option<ExecuteMsg>) = from_json(&msg)?;
+                let mut res = Response::new();
+                if let Some(reentry) = reentry {
+                    res = res.add_message(WasmMsg::Execute {
+                        contract_addr: info.sender.to_string(),
+                        msg: to_json_binary(&reentry)?,
+                        funds: vec![],
+                    });
+                }
+                Ok(res.add_message(BankMsg::Send {
+                    to_address: info.sender.to_string(),
+                    amount: coins((amount + fee - kept).u128(), asset),
+                }))
+            }
+        }
+    }
+
+    fn flash_borrower_instantiate(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _msg: cosmwasm_std::Empty,
+    ) -> StdResult<Response> {
+        Ok(Response::new())
+    }
+
+    fn flash_borrower_query(_deps: Deps, _env: Env, _msg: cosmwasm_std::Empty) -> StdResult<Binary> {
+        Ok(Binary::default())
+    }
+
+    #[test]
+    fn flash_loan_reverts_unless_repaid() {
+        let mut app = AppBuilder::new().build(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &Addr::unchecked("user_one"), coins(1000, "uosmo"))
+                .unwrap();
+        });
+        let code_id = app.store_code(Box::new(
+            ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+        ));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        let borrower_code_id = app.store_code(Box::new(ContractWrapper::new(
+            flash_borrower_execute,
+            flash_borrower_instantiate,
+            flash_borrower_query,
+        )));
+        let borrower = app
+            .instantiate_contract(
+                borrower_code_id,
+                Addr::unchecked("creator"),
+                &cosmwasm_std::Empty {},
+                &[],
+                "flash_borrower",
+                None,
+            )
+            .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::ListTokenOnLeverage {
+                token_address: String::from("osmo"),
+                native_denom: Some(String::from("uosmo")),
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::DepositNative {
+                token_address: String::from("osmo"),
+                sub_account: None,
+            },
+            &coins(1000, "uosmo"),
+        )
+        .unwrap();
+
+        let flash_loan = |kept: u128, reentry: Option<ExecuteMsg>| ExecuteMsg::FlashLoan {
+            asset: Addr::unchecked("osmo"),
+            amount: Uint128::from(600u128),
+            callback: to_json_binary(&(Uint128::from(kept), reentry)).unwrap(),
+        };
+
+        app.execute_contract(borrower.clone(), cont.clone(), &flash_loan(0, None), &[])
+            .unwrap();
+
+        // Keeping a single unit reverts the loan along with the transfer out
+        let err = app
+            .execute_contract(borrower.clone(), cont.clone(), &flash_loan(1, None), &[])
+            .unwrap_err();
+        assert!(err.root_cause().to_string().contains("Flash loan not repaid"));
+
+        // Ledger changing handlers are closed to the borrower until the loan settles
+        let withdraw = ExecuteMsg::WithdrawToken(WithdrawData {
+            token_address: Addr::unchecked("osmo"),
+            token_amount: Uint128::one(),
+            withdraw_type: String::from("native"),
+            native: Some(String::from("uosmo")),
+            on_behalf_of: None,
+            sub_account: None,
         });
+        let err = app
+            .execute_contract(borrower.clone(), cont.clone(), &flash_loan(0, Some(withdraw)), &[])
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "A flash loan is in progress");
+
+        let balance = app.wrap().query_balance(&cont, "uosmo").unwrap();
+        assert_eq!(balance.amount, Uint128::from(1000u128));
+        let balance = app.wrap().query_balance(&borrower, "uosmo").unwrap();
+        assert_eq!(balance.amount, Uint128::zero());
+    }
+
+    /// Router paying out the input times the rate it was instantiated with
+    fn router_execute(
+        deps: DepsMut,
+        _env: Env,
+        info: MessageInfo,
+        msg: SwapRouterExecuteMsg,
+    ) -> StdResult<Response> {
+        match msg {
+            SwapRouterExecuteMsg::Swap { output_asset, .. } => {
+                let rate: Decimal = from_json(deps.storage.get(b"rate").unwrap())?;
+                Ok(Response::new().add_message(BankMsg::Send {
+                    to_address: info.sender.to_string(),
+                    amount: coins(info.funds[0].amount.mul_floor(rate).u128(), output_asset),
+                }))
+            }
+        }
+    }
+
+    fn router_instantiate(
+        deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        rate: Decimal,
+    ) -> StdResult<Response> {
+        deps.storage.set(b"rate", &to_json_vec(&rate)?);
+        Ok(Response::new())
+    }
+
+    /// Contract with native osmo (price 1) and atom (price 2) listed, 1000 osmo of
+    /// liquidity, and one funded router per rate
+    fn router_app(rates: &[&str]) -> (App, Addr, Vec<Addr>) {
+        let mut app = AppBuilder::new().build(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &Addr::unchecked("user_one"), coins(300, "uosmo"))
+                .unwrap();
+            router
+                .bank
+                .init_balance(
+                    storage,
+                    &Addr::unchecked("creator"),
+                    vec![coin(10_000, "uatom"), coin(10_000, "uosmo")],
+                )
+                .unwrap();
+        });
+        let code_id = app.store_code(Box::new(
+            ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+        ));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        let router_code_id = app.store_code(Box::new(ContractWrapper::new(
+            router_execute,
+            router_instantiate,
+            flash_borrower_query,
+        )));
+        let routers: Vec<Addr> = rates
+            .iter()
+            .map(|rate| {
+                app.instantiate_contract(
+                    router_code_id,
+                    Addr::unchecked("creator"),
+                    &rate.parse::<Decimal>().unwrap(),
+                    &[coin(1000, "uatom"), coin(1000, "uosmo")],
+                    "router",
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        for (token, denom) in [("osmo", "uosmo"), ("atom", "uatom")] {
+            app.execute_contract(
+                Addr::unchecked("creator"),
+                cont.clone(),
+                &ExecuteMsg::ListTokenOnLeverage {
+                    token_address: String::from(token),
+                    native_denom: Some(String::from(denom)),
+                },
+                &[],
+            )
+            .unwrap();
+        }
+        // The osmo deposit is the liquidity loans are swapped from
+        for (token, funds) in [("atom", coins(10, "uatom")), ("osmo", coins(1000, "uosmo"))] {
+            app.execute_contract(
+                Addr::unchecked("creator"),
+                cont.clone(),
+                &ExecuteMsg::DepositNative {
+                    token_address: String::from(token),
+                    sub_account: None,
+                },
+                &funds,
+            )
+            .unwrap();
+        }
+        set_price(&mut app, &cont, "osmo", "1");
+        set_price(&mut app, &cont, "atom", "2");
+
+        (app, cont, routers)
+    }
+
+    fn use_router(app: &mut App, cont: &Addr, router: &Addr) {
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdateSwapConfig {
+                router: router.clone(),
+                max_slippage_bps: 5000,
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    fn open_msg(min_out: u128) -> ExecuteMsg {
+        ExecuteMsg::OpenLeveragedPosition {
+            collateral: String::from("osmo"),
+            target_leverage: "3".parse().unwrap(),
+            asset_to_buy: Addr::unchecked("atom"),
+            min_out: Uint128::from(min_out),
+            sub_account: None,
+        }
+    }
+
+    #[test]
+    fn native_deposit_takes_the_listed_denom_only() {
+        let (mut app, cont, _) = router_app(&[]);
+        let deposit = |token: &str| ExecuteMsg::DepositNative {
+            token_address: String::from(token),
+            sub_account: None,
+        };
 
-        let exe = app
+        let err = app
             .execute_contract(
-                Addr::unchecked("usdc_contract"),
+                Addr::unchecked("creator"),
+                cont.clone(),
+                &deposit("osmo"),
+                &coins(100, "uatom"),
+            )
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Invalid native deposit");
+
+        // A token listed without a denom is a CW20, it takes no coins at all
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::ListTokenOnLeverage {
+                token_address: String::from("juno"),
+                native_denom: None,
+            },
+            &[],
+        )
+        .unwrap();
+        let err = app
+            .execute_contract(
+                Addr::unchecked("creator"),
+                cont.clone(),
+                &deposit("juno"),
+                &coins(100, "uatom"),
+            )
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Invalid native deposit");
+
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &deposit("osmo"),
+            &coins(100, "uosmo"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn open_leveraged_position_checks_health_and_slippage() {
+        let (mut app, cont, routers) = router_app(&["0.5", "0.25"]);
+
+        // 300 osmo borrowed at half the oracle price: (100 + 75 * 2) / 300 is below 1.05
+        use_router(&mut app, &cont, &routers[1]);
+        let err = app
+            .execute_contract(
+                Addr::unchecked("user_one"),
+                cont.clone(),
+                &open_msg(0),
+                &coins(100, "uosmo"),
+            )
+            .unwrap_err();
+        assert!(err.root_cause().to_string().contains("Health factor"));
+
+        // A fair fill of 150 atom is below the requested 200
+        use_router(&mut app, &cont, &routers[0]);
+        let err = app
+            .execute_contract(
+                Addr::unchecked("user_one"),
+                cont.clone(),
+                &open_msg(200),
+                &coins(100, "uosmo"),
+            )
+            .unwrap_err();
+        assert!(err.root_cause().to_string().contains("below the minimum"));
+
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &open_msg(150),
+            &coins(100, "uosmo"),
+        )
+        .unwrap();
+        assert_eq!(position(&app, &cont, "user_one", "atom"), Uint128::from(150u128));
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::zero());
+    }
+
+    #[test]
+    fn routed_orders_settle_in_reply_within_slippage() {
+        let (mut app, cont, routers) = router_app(&["0.5", "0.2", "1.5"]);
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::DepositNative {
+                token_address: String::from("osmo"),
+                sub_account: None,
+            },
+            &coins(100, "uosmo"),
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("osmo"),
+                token_amount: Uint128::from(200u128),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let order = |token_out: &str, amount_out: u128| {
+            ExecuteMsg::ExecuteOrder(OrderExecute {
+                user_address: Addr::unchecked("user_one"),
+                token_in: Addr::unchecked("osmo"),
+                token_out: Addr::unchecked(token_out),
+                amount_in: Uint128::from(200u128),
+                amount_out: Uint128::from(amount_out),
+                encrypted_order: None,
+            })
+        };
+        let settle = |app: &mut App, msg: &ExecuteMsg| {
+            app.execute_contract(Addr::unchecked("creator"), cont.clone(), msg, &[])
+        };
+
+        // 40 atom is below the 200 * 0.5 quote less the 50% slippage bound
+        use_router(&mut app, &cont, &routers[1]);
+        let err = settle(&mut app, &order("atom", 0)).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Swap returned 40, below the minimum of 50");
+
+        // The order's own `amount_out` is a tighter bound
+        use_router(&mut app, &cont, &routers[0]);
+        let err = settle(&mut app, &order("atom", 120)).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Swap returned 100, below the minimum of 120");
+
+        // Without a price there is no quote to bound the output with
+        let err = settle(&mut app, &order("juno", 0)).unwrap_err();
+        assert!(err.root_cause().to_string().contains("juno"));
+
+        let res = settle(&mut app, &order("atom", 90)).unwrap();
+        assert_eq!(event_attribute(&res, "order_settled", "amount_out"), "100");
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::zero());
+        assert_eq!(position(&app, &cont, "user_one", "atom"), Uint128::from(100u128));
+
+        // A triggered stop-loss is swapped too, the keeper is paid out of the router's output
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::PlaceConditionalOrder(ConditionalOrderData {
+                token_in: Addr::unchecked("atom"),
+                token_out: Addr::unchecked("osmo"),
+                amount_in: None,
+                kind: ConditionalOrderKind::StopLoss {
+                    trigger_price: "1.8".parse().unwrap(),
+                },
+                bounty: Uint128::from(5u128),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        set_price(&mut app, &cont, "atom", "1.5");
+        use_router(&mut app, &cont, &routers[2]);
+        let res = app
+            .execute_contract(
+                Addr::unchecked("keeper"),
+                cont.clone(),
+                &ExecuteMsg::ExecuteConditionalOrder {
+                    owner: Addr::unchecked("user_one"),
+                    order_id: 1,
+                },
+                &[],
+            )
+            .unwrap();
+        assert_eq!(event_attribute(&res, "order_settled", "amount_out"), "150");
+        assert_eq!(position(&app, &cont, "user_one", "atom"), Uint128::zero());
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::from(145u128));
+        let keeper_collateral: Uint128 = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::UserCollateralTokenBalance(QueryTokenData {
+                    token_address: Addr::unchecked("osmo"),
+                    user_address: Addr::unchecked("keeper"),
+                }),
+            )
+            .unwrap();
+        assert_eq!(keeper_collateral, Uint128::from(5u128));
+    }
+
+    #[test]
+    fn fees_accrue_to_the_treasury_for_the_collector() {
+        let (mut app, cont, _) = router_app(&[]);
+        let owner_msgs = [
+            ExecuteMsg::UpdateFeeConfig(FeeConfig {
+                borrow_fee_bps: 100,
+                taker_fee_bps: 200,
+                maker_fee_bps: 0,
+                liquidation_fee_bps: 0,
+                withdraw_fee_bps: 0,
+                flash_loan_fee_bps: 0,
+                fee_collector: Addr::unchecked("collector"),
+            }),
+            ExecuteMsg::UpdateInsuranceConfig(InsuranceConfig {
+                fee_share_bps: 2000,
+                penalty_share_bps: 0,
+            }),
+        ];
+        for msg in owner_msgs.iter() {
+            app.execute_contract(Addr::unchecked("creator"), cont.clone(), msg, &[])
+                .unwrap();
+        }
+
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::DepositNative {
+                token_address: String::from("osmo"),
+                sub_account: None,
+            },
+            &coins(100, "uosmo"),
+        )
+        .unwrap();
+        // 10 osmo origination fee, 20% of it to the insurance fund
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("osmo"),
+                token_amount: Uint128::from(1000u128),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::from(990u128));
+
+        // 2% of the 495 atom output, rounded down to 9
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::ExecuteOrder(OrderExecute {
+                user_address: Addr::unchecked("user_one"),
+                token_in: Addr::unchecked("osmo"),
+                token_out: Addr::unchecked("atom"),
+                amount_in: Uint128::from(990u128),
+                amount_out: Uint128::from(495u128),
+                encrypted_order: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(position(&app, &cont, "user_one", "atom"), Uint128::from(486u128));
+
+        let fees: Vec<AssetAmount> = app
+            .wrap()
+            .query_wasm_smart(cont.clone(), &QueryMsg::AccumulatedFees {})
+            .unwrap();
+        let amount = |asset: &str, amount: u128| AssetAmount {
+            asset: Addr::unchecked(asset),
+            amount: Uint128::from(amount),
+        };
+        assert_eq!(fees, vec![amount("atom", 8), amount("osmo", 8)]);
+        for (asset, share) in [("atom", 1u128), ("osmo", 2)] {
+            let fund: InsuranceFundResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    cont.clone(),
+                    &QueryMsg::InsuranceFund {
+                        asset: Addr::unchecked(asset),
+                    },
+                )
+                .unwrap();
+            assert_eq!(fund.balance, Uint128::from(share));
+        }
+
+        let claim = |app: &mut App, sender: &str, asset: &str, amount: Option<u128>| {
+            app.execute_contract(
+                Addr::unchecked(sender),
                 cont.clone(),
-                &execute_deposit_msg,
+                &ExecuteMsg::ClaimFees {
+                    asset: Addr::unchecked(asset),
+                    amount: amount.map(Uint128::from),
+                },
                 &[],
             )
+        };
+
+        // Only the fee collector claims, not even the owner
+        for sender in ["user_one", "creator"] {
+            let err = claim(&mut app, sender, "osmo", None).unwrap_err();
+            assert_eq!(err.root_cause().to_string(), "Unauthorized");
+        }
+        let err = claim(&mut app, "collector", "osmo", Some(9)).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Insufficient balance");
+
+        claim(&mut app, "collector", "osmo", Some(3)).unwrap();
+        claim(&mut app, "collector", "osmo", None).unwrap();
+        claim(&mut app, "collector", "atom", None).unwrap();
+        let err = claim(&mut app, "collector", "osmo", None).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Insufficient balance");
+
+        for (denom, claimed) in [("uosmo", 8u128), ("uatom", 8)] {
+            let balance = app.wrap().query_balance("collector", denom).unwrap();
+            assert_eq!(balance.amount, Uint128::from(claimed));
+        }
+        let fees: Vec<AssetAmount> = app
+            .wrap()
+            .query_wasm_smart(cont.clone(), &QueryMsg::AccumulatedFees {})
+            .unwrap();
+        assert_eq!(fees, vec![amount("atom", 0), amount("osmo", 0)]);
+    }
+
+    #[test]
+    fn burn_converts_whole_multiples_of_ten() {
+        use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+
+        let mut deps = mock_dependencies();
+        let usdc = Addr::unchecked("usdc_contract");
+        let user = Addr::unchecked("user_one");
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            InstantiateMsg {
+                token_contract_address: usdc.to_string(),
+            },
+        )
+        .unwrap();
+        USER_PROFIT_TOKEN
+            .save(deps.as_mut().storage, (&usdc, &user), &Uint128::from(25u128))
             .unwrap();
+        let burn = |deps: &mut cosmwasm_std::OwnedDeps<_, _, _>, amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("user_one", &[]),
+                ExecuteMsg::Burn(TokenData {
+                    token_address: usdc.clone(),
+                    token_amount: Uint128::from(amount),
+                    on_behalf_of: None,
+                    sub_account: None,
+                }),
+            )
+            .unwrap()
+        };
+        let attribute = |res: &Response, key: &str| -> String {
+            res.events[0]
+                .attributes
+                .iter()
+                .find(|attribute| attribute.key == key)
+                .map(|attribute| attribute.value.clone())
+                .unwrap()
+        };
+
+        // 25 vTokens burn 20 into 2 collateral, the 5 left over stay profit
+        let res = burn(&mut deps, 25);
+        assert_eq!(attribute(&res, "v_token_amount"), "20");
+        assert_eq!(attribute(&res, "collateral_amount"), "2");
+        assert_eq!(attribute(&res, "profit_balance"), "5");
+        assert_eq!(attribute(&res, "collateral_balance"), "2");
+        assert_eq!(attribute(&res, "unminted_balance"), "20");
+
+        // A remainder below 10 converts to nothing and is kept
+        let res = burn(&mut deps, 5);
+        assert_eq!(attribute(&res, "v_token_amount"), "0");
+        assert_eq!(attribute(&res, "profit_balance"), "5");
+        assert_eq!(attribute(&res, "collateral_balance"), "2");
+    }
 
-        println!("{:?}", exe);
+    #[test]
+    fn close_position_repays_and_withdraws() {
+        let (mut app, cont, routers) = router_app(&["0.5", "2.5"]);
+
+        use_router(&mut app, &cont, &routers[0]);
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &open_msg(150),
+            &coins(100, "uosmo"),
+        )
+        .unwrap();
+
+        // atom rallies to 2.5: 150 atom sell for 375 osmo, 300 repay the loan
+        set_price(&mut app, &cont, "atom", "2.5");
+        use_router(&mut app, &cont, &routers[1]);
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::ClosePosition(ClosePositionData {
+                asset: Addr::unchecked("atom"),
+                debt_token: Addr::unchecked("osmo"),
+                fraction: Decimal::one(),
+                min_out: Uint128::from(375u128),
+                withdraw_type: Some(String::from("native")),
+                sub_account: None,
+            }),
+            &[],
+        )
+        .unwrap();
 
-        let res_query_user_token_balance: GetUserTokenBalanceResponse = app
+        assert_eq!(position(&app, &cont, "user_one", "atom"), Uint128::zero());
+        assert_eq!(position(&app, &cont, "user_one", "osmo"), Uint128::zero());
+        let debt: Uint128 = app
             .wrap()
             .query_wasm_smart(
                 cont.clone(),
-                &QueryMsg::UserTokenBalance {
-                    token_address: Addr::unchecked("usdc_contract"),
+                &QueryMsg::UserBorrowTokenBalance(QueryTokenData {
+                    token_address: Addr::unchecked("osmo"),
+                    user_address: Addr::unchecked("user_one"),
+                }),
+            )
+            .unwrap();
+        assert_eq!(debt, Uint128::zero());
+
+        // The 75 osmo profit is held as 750 vTokens and burns back into 75 osmo, withdrawn
+        // with the 100 deposited
+        let balance = app.wrap().query_balance("user_one", "uosmo").unwrap();
+        assert_eq!(balance.amount, Uint128::from(375u128));
+        let profit: Uint128 = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::UserVTokenBalance(QueryTokenData {
+                    token_address: Addr::unchecked("osmo"),
+                    user_address: Addr::unchecked("user_one"),
+                }),
+            )
+            .unwrap();
+        assert_eq!(profit, Uint128::zero());
+    }
+
+    #[test]
+    fn price_feed_rejects_stale_and_deviating_prices() {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdatePriceFeedConfig(PriceFeedConfig {
+                feeders: vec![Addr::unchecked("feeder")],
+                max_price_age: Some(60),
+                max_deviation_bps: Some(1_000),
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let feed = |asset: &str, price: &str, publish_time: Timestamp| ExecuteMsg::FeedPrices {
+            prices: vec![PriceUpdate {
+                asset: Addr::unchecked(asset),
+                price: price.parse().unwrap(),
+                publish_time,
+            }],
+        };
+        let now = app.block_info().time;
+        let feed_error = |app: &mut App, msg: &ExecuteMsg| {
+            app.execute_contract(Addr::unchecked("feeder"), cont.clone(), msg, &[])
+                .unwrap_err()
+                .root_cause()
+                .to_string()
+        };
+
+        let err = app
+            .execute_contract(
+                Addr::unchecked("user_one"),
+                cont.clone(),
+                &feed("usdc_contract", "1", now),
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Unauthorized");
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked("feeder"),
+                cont.clone(),
+                &feed("usdc_contract", "1", now.minus_seconds(10)),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(event_attribute(&res, "price_update", "price"), "1");
+        assert_eq!(event_attribute(&res, "price_update", "feeder"), "feeder");
+
+        assert_eq!(
+            feed_error(&mut app, &feed("usdc_contract", "1", now.minus_seconds(20))),
+            "Price of usdc_contract is not newer than the current one or is in the future"
+        );
+        assert_eq!(
+            feed_error(&mut app, &feed("osmo", "2", now.minus_seconds(61))),
+            "Price of osmo is stale"
+        );
+        assert_eq!(
+            feed_error(&mut app, &feed("usdc_contract", "1.15", now)),
+            "Price of usdc_contract moves 1500 bps, the bound is 1000"
+        );
+        app.execute_contract(
+            Addr::unchecked("feeder"),
+            cont.clone(),
+            &feed("usdc_contract", "1.1", now),
+            &[],
+        )
+        .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked("usdc_contract"),
+            cont.clone(),
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: String::from("user_one"),
+                amount: Uint128::from(100u128),
+                msg: to_json_binary(&{}).unwrap(),
+            }),
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::from(500u128),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        let health_query = QueryMsg::AccountHealth {
+            user_address: Addr::unchecked("user_one"),
+        };
+        app.wrap()
+            .query_wasm_smart::<AccountHealth>(cont.clone(), &health_query)
+            .unwrap();
+
+        // A minute later the price is stale and nothing is valued on it
+        app.update_block(|block| block.time = block.time.plus_seconds(61));
+        let err = app
+            .wrap()
+            .query_wasm_smart::<AccountHealth>(cont.clone(), &health_query)
+            .unwrap_err();
+        assert!(err.to_string().contains("Price of usdc_contract is stale"));
+        let info: Option<PriceInfoResponse> = app
+            .wrap()
+            .query_wasm_smart(
+                cont.clone(),
+                &QueryMsg::PriceInfo {
+                    asset: Addr::unchecked("usdc_contract"),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            info,
+            Some(PriceInfoResponse {
+                price: "1.1".parse().unwrap(),
+                publish_time: Some(now),
+                stale: true,
+            })
+        );
+        let err = app
+            .execute_contract(
+                Addr::unchecked("keeper"),
+                cont.clone(),
+                &ExecuteMsg::Liquidate {
                     user_address: Addr::unchecked("user_one"),
+                    debt_token: Addr::unchecked("usdc_contract"),
+                },
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Price of usdc_contract is stale");
+
+        // The owner's update is stamped with the block time and skips the deviation bound
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdateAssetPrice {
+                asset: Addr::unchecked("usdc_contract"),
+                price: Decimal::percent(200),
+            },
+            &[],
+        )
+        .unwrap();
+        app.wrap()
+            .query_wasm_smart::<AccountHealth>(cont, &health_query)
+            .unwrap();
+    }
+
+    #[test]
+    fn liquidation_checks_use_settlement_twap() {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let cont = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
                 },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        set_price(&mut app, &cont, "usdc_contract", "1");
+        set_price(&mut app, &cont, "osmo", "2");
+
+        app.execute_contract(
+            Addr::unchecked("usdc_contract"),
+            cont.clone(),
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: String::from("user_one"),
+                amount: Uint128::from(100u128),
+                msg: to_json_binary(&{}).unwrap(),
+            }),
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::from(1000u128),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked("usdc_contract"),
+            cont.clone(),
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: String::from("user_two"),
+                amount: Uint128::from(10000u128),
+                msg: to_json_binary(&{}).unwrap(),
+            }),
+            &[],
+        )
+        .unwrap();
+        borrow_usdc(&mut app, &cont, "user_two", 1000).unwrap();
+
+        let err = app
+            .execute_contract(
+                Addr::unchecked("creator"),
+                cont.clone(),
+                &ExecuteMsg::UpdateTwapConfig(TwapConfig {
+                    max_window: 600,
+                    liquidation_window: Some(900),
+                }),
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Invalid TWAP config");
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdateTwapConfig(TwapConfig {
+                max_window: 3600,
+                liquidation_window: Some(600),
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let fill =
+            |user: &str, token_in: &str, token_out: &str, amount_in: u128, amount_out: u128| {
+                ExecuteMsg::ExecuteOrder(OrderExecute {
+                    user_address: Addr::unchecked(user),
+                    token_in: Addr::unchecked(token_in),
+                    token_out: Addr::unchecked(token_out),
+                    amount_in: Uint128::from(amount_in),
+                    amount_out: Uint128::from(amount_out),
+                    encrypted_order: None,
+                })
+            };
+        let twap = |app: &App, window: u64| -> Option<Decimal> {
+            app.wrap()
+                .query_wasm_smart(
+                    cont.clone(),
+                    &QueryMsg::Twap {
+                        asset: Addr::unchecked("osmo"),
+                        window,
+                    },
+                )
+                .unwrap()
+        };
+        let liquidate_msg = ExecuteMsg::Liquidate {
+            user_address: Addr::unchecked("user_one"),
+            debt_token: Addr::unchecked("usdc_contract"),
+        };
+
+        // Both buy 500 osmo at 2, the account cannot be checked until the TWAP covers the window
+        for user in ["user_one", "user_two"] {
+            app.execute_contract(
+                Addr::unchecked("creator"),
+                cont.clone(),
+                &fill(user, "usdc_contract", "osmo", 1000, 500),
+                &[],
+            )
+            .unwrap();
+        }
+        let err = app
+            .execute_contract(Addr::unchecked("keeper"), cont.clone(), &liquidate_msg, &[])
+            .unwrap_err();
+        assert!(err.root_cause().to_string().contains("covers the last 600 seconds"));
+        app.update_block(|block| block.time = block.time.plus_seconds(600));
+        assert_eq!(twap(&app, 600), Some(Decimal::percent(200)));
+        assert_eq!(twap(&app, 1200), None);
+
+        // Spot drops to 1.5 and user_two sells 50 osmo in one block, 40 at 1.5 and 10 at 1.8
+        // for an average of 1.56: 100 collateral + 500 osmo at 1.5 = 850 against 1000 debt,
+        // but the account is still at 1100 priced at the TWAP
+        set_price(&mut app, &cont, "osmo", "1.5");
+        for (amount_in, amount_out) in [(10, 15), (30, 45), (10, 18)] {
+            app.execute_contract(
+                Addr::unchecked("creator"),
+                cont.clone(),
+                &fill("user_two", "osmo", "usdc_contract", amount_in, amount_out),
+                &[],
             )
             .unwrap();
+        }
+        let err = app
+            .execute_contract(Addr::unchecked("keeper"), cont.clone(), &liquidate_msg, &[])
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Account is not liquidatable");
+
+        // Half the window at 2 and half at 1.56: 100 + 500 osmo at 1.78 = 990, the last fill
+        // alone would have held 1.8 and left the account at 1050
+        app.update_block(|block| block.time = block.time.plus_seconds(300));
+        assert_eq!(twap(&app, 600), Some(Decimal::percent(178)));
+        app.execute_contract(Addr::unchecked("keeper"), cont.clone(), &liquidate_msg, &[])
+            .unwrap();
+
+        let err = app
+            .wrap()
+            .query_wasm_smart::<Option<Decimal>>(
+                cont.clone(),
+                &QueryMsg::Twap {
+                    asset: Addr::unchecked("osmo"),
+                    window: 7200,
+                },
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("TWAP window must be between 1 and 3600 seconds"));
+    }
 
-        println!("Response: {:?}", res_query_user_token_balance);
+    /// Oracle answering with the price it was instantiated with, published at the query
+    fn oracle_query(deps: Deps, env: Env, msg: PriceOracleQueryMsg) -> StdResult<Binary> {
+        match msg {
+            PriceOracleQueryMsg::Price { .. } => to_json_binary(&PriceOracleResponse {
+                price: from_json(deps.storage.get(b"rate").unwrap())?,
+                publish_time: env.block.time,
+            }),
+        }
+    }
+
+    #[test]
+    fn price_sources_aggregate_median_with_quorum() {
+        let (mut app, cont, routers) = router_app(&["0.5"]);
+        use_router(&mut app, &cont, &routers[0]);
+
+        let oracle_code_id = app.store_code(Box::new(ContractWrapper::new(
+            router_execute,
+            router_instantiate,
+            oracle_query,
+        )));
+        let oracles: Vec<Addr> = ["2.2", "1.9"]
+            .iter()
+            .map(|price| {
+                app.instantiate_contract(
+                    oracle_code_id,
+                    Addr::unchecked("creator"),
+                    &price.parse::<Decimal>().unwrap(),
+                    &[],
+                    "oracle",
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            cont.clone(),
+            &ExecuteMsg::UpdatePriceFeedConfig(PriceFeedConfig {
+                feeders: vec![],
+                max_price_age: Some(60),
+                max_deviation_bps: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let update_sources = |app: &mut App, quorum: u32| {
+            app.execute_contract(
+                Addr::unchecked("creator"),
+                cont.clone(),
+                &ExecuteMsg::UpdatePriceSources {
+                    asset: Addr::unchecked("atom"),
+                    price_sources: PriceSources {
+                        sources: vec![
+                            PriceSource::Feed,
+                            PriceSource::Oracle {
+                                contract: oracles[0].clone(),
+                            },
+                            PriceSource::Oracle {
+                                contract: oracles[1].clone(),
+                            },
+                        ],
+                        quorum,
+                    },
+                },
+                &[],
+            )
+        };
+        let err = update_sources(&mut app, 4).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Invalid price sources");
+        update_sources(&mut app, 2).unwrap();
 
+        let sources = |app: &App| -> PriceSourcesResponse {
+            app.wrap()
+                .query_wasm_smart(
+                    cont.clone(),
+                    &QueryMsg::PriceSources {
+                        asset: Addr::unchecked("atom"),
+                    },
+                )
+                .unwrap()
+        };
+        let sync = |app: &mut App| {
+            app.execute_contract(
+                Addr::unchecked("keeper"),
+                cont.clone(),
+                &ExecuteMsg::SyncOraclePrices {
+                    asset: Addr::unchecked("atom"),
+                },
+                &[],
+            )
+            .unwrap()
+        };
+        let position_value = |app: &App| -> Decimal {
+            let health: AccountHealth = app
+                .wrap()
+                .query_wasm_smart(
+                    cont.clone(),
+                    &QueryMsg::AccountHealth {
+                        user_address: Addr::unchecked("user_one"),
+                    },
+                )
+                .unwrap();
+            health.position_value
+        };
 
-        // let borrow_exe_msg = ExecuteMsg::Borrow { borrow_amount: Uint128::from(100u128) };
-        // let borrow_exe = app.execute_contract(Addr::unchecked("user_one"), cont.clone(), &borrow_exe_msg, &[]);
-        // println!("{:?}", borrow_exe);
+        // Only the feed has a price, opening a position is refused below the quorum
+        let response = sources(&app);
+        assert_eq!(response.price, Some(Decimal::percent(200)));
+        assert!(!response.quorum_met);
+        assert_eq!(response.sources[1].price, None);
+        let err = app
+            .execute_contract(
+                Addr::unchecked("user_one"),
+                cont.clone(),
+                &open_msg(150),
+                &coins(100, "uosmo"),
+            )
+            .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "Only 1 of 2 price sources of atom are fresh");
 
-        // let res_query_user_borrow_balance: GetUserBorrowTokenResponse = app
-        //     .wrap()
-        //     .query_wasm_smart(cont.clone(), &QueryMsg::UserBorrowTokenBalance { user_address: Addr::unchecked("user_one") })
-        //     .unwrap();
+        let res = sync(&mut app);
+        assert_eq!(event_attribute(&res, "price_update", "price"), "2.2");
+        let response = sources(&app);
+        assert!(response.quorum_met);
+        assert_eq!(response.price, Some(Decimal::percent(200)));
+        app.execute_contract(
+            Addr::unchecked("user_one"),
+            cont.clone(),
+            &open_msg(150),
+            &coins(100, "uosmo"),
+        )
+        .unwrap();
+        assert_eq!(position_value(&app), Decimal::percent(30_000));
 
-        // println!("Response: {:?}", res_query_user_borrow_balance);
+        // Every source goes stale, then the oracles are synced again without the feed
+        app.update_block(|block| block.time = block.time.plus_seconds(61));
+        let err = app
+            .wrap()
+            .query_wasm_smart::<AccountHealth>(
+                cont.clone(),
+                &QueryMsg::AccountHealth {
+                    user_address: Addr::unchecked("user_one"),
+                },
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("Price of osmo is stale"));
+        set_price(&mut app, &cont, "osmo", "1");
+        sync(&mut app);
+        let response = sources(&app);
+        assert!(!response.sources[0].fresh);
+        assert!(response.sources[1].fresh && response.sources[2].fresh);
+        assert_eq!(position_value(&app), "307.5".parse::<Decimal>().unwrap());
 
-        let withdraw_exe_msg = ExecuteMsg::WithdrawToken { token_address: Addr::unchecked("usdc_contract"), amount: Uint128::from(10u128) };
-        let withdraw_exe = app.execute_contract(Addr::unchecked("user_one"), cont.clone(), &withdraw_exe_msg, &[]);
-        println!("{:?}", withdraw_exe);
+        // Two fresh sources of three fall back to the first, the 2.2 oracle
+        update_sources(&mut app, 3).unwrap();
+        assert!(!sources(&app).quorum_met);
+        assert_eq!(position_value(&app), Decimal::percent(33_000));
     }
 }
@@ -0,0 +1,211 @@
+use cosmwasm_std::{Decimal, StdError, Uint128};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ContractError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("Unauthorized")]
+    Unauthorized {},
+
+    #[error("{error}")]
+    GenericError { error: String },
+
+    #[error("Failed to update listed token")]
+    UpdateTokenListFailed {},
+
+    #[error("Token is not listed on leverage")]
+    UnauthorizedToken {},
+
+    #[error("Balance overflow")]
+    OverflowBalance {},
+
+    #[error("Overflow")]
+    Overflow {},
+
+    #[error("Insufficient balance")]
+    InsufficientBalance {},
+
+    #[error("Borrow amount is not zero")]
+    BorrowAmountIsNotZero {},
+
+    #[error("Pay the borrowed amount first")]
+    PayBorrowAmount {},
+
+    #[error("Repay amount exceeds borrow balance")]
+    RepayOverflow {},
+
+    #[error("Insufficient unminted token")]
+    InsufficientUnmintedToken {},
+
+    #[error("Failed to load unminted balance")]
+    UnmintedBalanceLoadError {},
+
+    #[error("Failed to load borrow balance")]
+    BorrowBalanceLoadError {},
+
+    #[error("Failed to load profit balance")]
+    ProfitBalanceLoadError {},
+
+    #[error("Unminted token query failed")]
+    UnmintedTokenQueryFailed {},
+
+    #[error("User token balance query failed")]
+    UserTokenBalanceQueryFailed {},
+
+    #[error("User borrow token balance query failed")]
+    UserBorrowTokenBalanceQueryFailed {},
+
+    #[error("User profit token balance query failed")]
+    UserProfitTokenBalanceQueryFailed {},
+
+    #[error("Unable to fetch listed token")]
+    UnableToFetchListedToken {},
+
+    #[error("Invalid native deposit")]
+    InvalidNativeDeposit {},
+
+    #[error("Invalid withdraw type: {withdraw_type}")]
+    InvalidWithdrawType { withdraw_type: String },
+
+    #[error("No price available for {asset}")]
+    PriceNotAvailable { asset: String },
+
+    #[error("Invalid Paillier public key")]
+    InvalidPaillierKey {},
+
+    #[error("Invalid Paillier ciphertext")]
+    InvalidCiphertext {},
+
+    #[error("Encrypted order not found")]
+    EncryptedOrderNotFound {},
+
+    #[error("Encrypted order already committed")]
+    EncryptedOrderExists {},
+
+    #[error("Order amount does not match the encrypted order")]
+    EncryptedOrderMismatch {},
+
+    #[error("Fill exceeds the unfilled amount of the encrypted order")]
+    EncryptedOrderOverfilled {},
+
+    #[error("Order commitment not found")]
+    OrderCommitmentNotFound {},
+
+    #[error("Revealed order does not match the commitment")]
+    InvalidOrderCommitment {},
+
+    #[error("Reveal window is closed")]
+    RevealWindowClosed {},
+
+    #[error("Reveal window is still open")]
+    RevealWindowOpen {},
+
+    #[error("Swap returned {received}, below the minimum of {min_out}")]
+    SlippageExceeded { min_out: Uint128, received: Uint128 },
+
+    #[error("Flash loan not repaid: expected balance {expected}, got {balance}")]
+    FlashLoanNotRepaid { expected: Uint128, balance: Uint128 },
+
+    #[error("A flash loan is in progress")]
+    FlashLoanInProgress {},
+
+    #[error("Health factor {health_factor} is below {min_health_factor}")]
+    HealthFactorTooLow {
+        health_factor: Decimal,
+        min_health_factor: Decimal,
+    },
+
+    #[error("Invalid leverage")]
+    InvalidLeverage {},
+
+    #[error("Fraction must be above 0 and at most 1")]
+    InvalidFraction {},
+
+    #[error("Swap router is not configured")]
+    SwapRouterNotConfigured {},
+
+    #[error("Unknown reply id: {id}")]
+    UnknownReplyId { id: u64 },
+
+    #[error("Invalid fee config")]
+    InvalidFeeConfig {},
+
+    #[error("Account is not liquidatable")]
+    NotLiquidatable {},
+
+    #[error("Invalid risk config")]
+    InvalidRiskConfig {},
+
+    #[error("Supply cap exceeded, remaining capacity: {remaining}")]
+    SupplyCapExceeded { remaining: Uint128 },
+
+    #[error("Borrow cap exceeded, remaining capacity: {remaining}")]
+    BorrowCapExceeded { remaining: Uint128 },
+
+    #[error("Operator is not authorized for this account")]
+    OperatorNotAuthorized {},
+
+    #[error("Invalid operator grant")]
+    InvalidOperatorGrant {},
+
+    #[error("Sub-account not found")]
+    SubAccountNotFound {},
+
+    #[error("Sub-account has outstanding debt")]
+    SubAccountHasDebt {},
+
+    #[error("Conditional order not found")]
+    ConditionalOrderNotFound {},
+
+    #[error("Conditional order is not triggered")]
+    ConditionalOrderNotTriggered {},
+
+    #[error("Invalid conditional order")]
+    InvalidConditionalOrder {},
+
+    #[error("Price of {asset} is stale")]
+    PriceStale { asset: String },
+
+    #[error("Price of {asset} moves {deviation_bps} bps, the bound is {max_deviation_bps}")]
+    PriceDeviationTooLarge {
+        asset: String,
+        deviation_bps: u64,
+        max_deviation_bps: u64,
+    },
+
+    #[error("Price of {asset} is not newer than the current one or is in the future")]
+    InvalidPriceTime { asset: String },
+
+    #[error("Invalid price feed config")]
+    InvalidPriceFeedConfig {},
+
+    #[error("Invalid TWAP config")]
+    InvalidTwapConfig {},
+
+    #[error("TWAP window must be between 1 and {max_window} seconds")]
+    InvalidTwapWindow { max_window: u64 },
+
+    #[error("No settlement TWAP of {asset} covers the last {window} seconds")]
+    TwapNotAvailable { asset: String, window: u64 },
+
+    #[error("Invalid price sources")]
+    InvalidPriceSources {},
+
+    #[error("Only {fresh} of {quorum} price sources of {asset} are fresh")]
+    PriceQuorumNotMet {
+        asset: String,
+        fresh: u32,
+        quorum: u32,
+    },
+}
+
+impl From<ContractError> for StdError {
+    fn from(err: ContractError) -> Self {
+        match err {
+            ContractError::Std(std_err) => std_err,
+            _ => StdError::generic_err(err.to_string()),
+        }
+    }
+}
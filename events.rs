@@ -0,0 +1,1011 @@
+//! Event schema emitted by the contract for indexers.
+//!
+//! Every state-changing action emits at least one typed event next to the free-form
+//! `method` attributes. Event types and attribute keys are stable, amounts are integer
+//! strings, decimals use `Decimal`'s string form and a missing value leaves its key out.
+//!
+//! | type                       | keys                                                            |
+//! |----------------------------|-----------------------------------------------------------------|
+//! | `deposit`                  | user, asset, amount, + balances                                 |
+//! | `withdraw`                 | user, asset, amount, fee, recipient, + balances                 |
+//! | `borrow`                   | user, asset, amount, fee, + balances                            |
+//! | `repay`                    | user, asset, amount, + balances                                 |
+//! | `burn`                     | user, asset, v_token_amount, collateral_amount, + balances      |
+//! | `order_settled`            | user, token_in, token_out, amount_in, amount_out,               |
+//! |                            | fee, + balances                                                 |
+//! | `collateral_transfer`      | user, asset, amount, from, to                                   |
+//! | `flash_loan`               | borrower, asset, amount, fee                                    |
+//! | `liquidation`              | user, liquidator, debt_token, debt, seized_positions,           |
+//! |                            | seized_collateral, penalty, liquidator_reward, protocol_fee,    |
+//! |                            | insurance_contribution, returned_collateral, shortfall          |
+//! | `bad_debt`                 | asset, shortfall, covered_by_insurance, insurance_fund_balance, |
+//! |                            | socialized, socialized_loss_index, socialized_loss_epoch        |
+//! | `protocol_fee`             | kind, asset, amount                                             |
+//! | `protocol_fee_claim`       | asset, amount, fee_collector                                    |
+//! | `price_update`             | asset, price, publish_time, feeder                              |
+//! | `order_commit`             | user, commitment_id, asset, amount, + balances                  |
+//! | `order_reveal`             | user, commitment_id, token_in, token_out, amount_in,            |
+//! |                            | limit_price, batch_height                                       |
+//! | `order_refund`             | user, commitment_id, kind, asset, amount, + balances            |
+//! | `encrypted_order_commit`   | user, order_id                                                  |
+//! | `conditional_order_place`  | user, order_id, token_in, token_out, amount_in, kind,           |
+//! |                            | trigger_price or trail_bps, bounty                              |
+//! | `conditional_order_update` | user, order_id, reference_price                                 |
+//! | `conditional_order_cancel` | user, order_id                                                  |
+//! | `keeper_bounty`            | user, keeper, order_id, asset, amount,                          |
+//! |                            | keeper_collateral_balance, keeper_unminted_balance, + balances  |
+//! | `operator_grant`           | owner, operator, scope, expires                                 |
+//! | `operator_revoke`          | owner, operator                                                 |
+//! | `sub_account_open`         | owner, sub_account, account, margin_mode                        |
+//! | `margin_mode_update`       | owner, sub_account, margin_mode                                 |
+//! | `config_update`            | kind, asset, config                                             |
+//!
+//! "balances" are the account's ledgers in the event's asset after the action:
+//! collateral_balance, unminted_balance, borrow_balance, profit_balance, position_balance
+//! and health_factor (left out when the account has no debt or a price is missing). For
+//! `order_settled` they are taken in `token_out`. Collateral and unminted balances are net
+//! of the socialized losses the account has not settled yet, every holder of the asset of
+//! a `bad_debt` is haircut by the share of `socialized_loss_index` it lost, or loses its
+//! collateral when `socialized_loss_epoch` moves on. `publish_time` is in seconds.
+//!
+//! Order margin is escrowed out of the position by `order_commit`, so the `order_settled`
+//! of a batch fill leaves `token_in` alone. Every batch order is closed by an
+//! `order_refund` of kind `unfilled`, possibly of zero, carrying the `token_in` ledgers.
+//! A conditional order execution is followed by its `keeper_bounty`, paid out of the
+//! `token_out` position after `order_settled`. `config_update` carries the new
+//! configuration as JSON, its kind is one of token_listing, fee, insurance, paillier_key,
+//! price_feed, price_sources, reveal_window, risk, swap, token_caps or twap.
+
+use cosmwasm_std::{Addr, Decimal, Event, Timestamp, Uint128};
+use cw20::Expiration;
+
+use crate::state::{ConditionalOrderKind, MarginMode, OperatorScope};
+
+/// Account ledgers in one asset, after the action that emitted the event
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccountBalances {
+    pub collateral: Uint128,
+    pub unminted: Uint128,
+    pub borrowed: Uint128,
+    pub profit: Uint128,
+    pub position: Uint128,
+    /// `None` when the account has no debt or a price is missing
+    pub health_factor: Option<Decimal>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LeverageEvent {
+    Deposit {
+        user: Addr,
+        asset: Addr,
+        amount: Uint128,
+        balances: AccountBalances,
+    },
+    Withdraw {
+        user: Addr,
+        asset: Addr,
+        amount: Uint128,
+        fee: Uint128,
+        recipient: Addr,
+        balances: AccountBalances,
+    },
+    Borrow {
+        user: Addr,
+        asset: Addr,
+        amount: Uint128,
+        fee: Uint128,
+        balances: AccountBalances,
+    },
+    Repay {
+        user: Addr,
+        asset: Addr,
+        amount: Uint128,
+        balances: AccountBalances,
+    },
+    Burn {
+        user: Addr,
+        asset: Addr,
+        v_token_amount: Uint128,
+        collateral_amount: Uint128,
+        balances: AccountBalances,
+    },
+    OrderSettled {
+        user: Addr,
+        token_in: Addr,
+        token_out: Addr,
+        amount_in: Uint128,
+        amount_out: Uint128,
+        fee: Uint128,
+        balances: AccountBalances,
+    },
+    CollateralTransfer {
+        user: Addr,
+        asset: Addr,
+        amount: Uint128,
+        from: Addr,
+        to: Addr,
+    },
+    FlashLoan {
+        borrower: Addr,
+        asset: Addr,
+        amount: Uint128,
+        fee: Uint128,
+    },
+    Liquidation {
+        user: Addr,
+        liquidator: Addr,
+        debt_token: Addr,
+        debt: Uint128,
+        seized_positions: Uint128,
+        seized_collateral: Uint128,
+        penalty: Uint128,
+        liquidator_reward: Uint128,
+        protocol_fee: Uint128,
+        insurance_contribution: Uint128,
+        returned_collateral: Uint128,
+        shortfall: Uint128,
+    },
+    BadDebt {
+        asset: Addr,
+        shortfall: Uint128,
+        covered_by_insurance: Uint128,
+        insurance_fund_balance: Uint128,
+        socialized: Uint128,
+        socialized_loss_index: Decimal,
+        /// Bumped when a loss wipes out every deposit and the index starts over at one
+        socialized_loss_epoch: u64,
+    },
+    ProtocolFee {
+        kind: String,
+        asset: Addr,
+        amount: Uint128,
+    },
+    ProtocolFeeClaim {
+        asset: Addr,
+        amount: Uint128,
+        fee_collector: Addr,
+    },
+    PriceUpdate {
+        asset: Addr,
+        price: Decimal,
+        publish_time: Timestamp,
+        /// Owner or feeder that pushed the price, or the oracle contract it was pulled from
+        feeder: Addr,
+    },
+    /// Margin escrowed out of the `asset` position for a committed order
+    OrderCommit {
+        user: Addr,
+        commitment_id: u64,
+        asset: Addr,
+        amount: Uint128,
+        balances: AccountBalances,
+    },
+    OrderReveal {
+        user: Addr,
+        commitment_id: u64,
+        token_in: Addr,
+        token_out: Addr,
+        amount_in: Uint128,
+        limit_price: Decimal,
+        batch_height: u64,
+    },
+    /// Escrowed margin credited back to the `asset` position
+    OrderRefund {
+        user: Addr,
+        commitment_id: u64,
+        /// `excess_margin` at reveal, `expired` or `unfilled` at batch settlement
+        kind: String,
+        asset: Addr,
+        amount: Uint128,
+        balances: AccountBalances,
+    },
+    EncryptedOrderCommit {
+        user: Addr,
+        order_id: String,
+    },
+    ConditionalOrderPlace {
+        user: Addr,
+        order_id: u64,
+        token_in: Addr,
+        token_out: Addr,
+        /// `None` sells the whole position
+        amount_in: Option<Uint128>,
+        kind: ConditionalOrderKind,
+        bounty: Uint128,
+    },
+    /// New highest price seen by a trailing stop
+    ConditionalOrderUpdate {
+        user: Addr,
+        order_id: u64,
+        reference_price: Decimal,
+    },
+    /// Cancelled by the owner, once its position is closed or by a liquidation
+    ConditionalOrderCancel {
+        user: Addr,
+        order_id: u64,
+    },
+    /// Paid out of the owner's `asset` position into the keeper's collateral
+    KeeperBounty {
+        user: Addr,
+        keeper: Addr,
+        order_id: u64,
+        asset: Addr,
+        amount: Uint128,
+        keeper_collateral_balance: Uint128,
+        keeper_unminted_balance: Uint128,
+        balances: AccountBalances,
+    },
+    OperatorGrant {
+        owner: Addr,
+        operator: Addr,
+        scope: OperatorScope,
+        expires: Expiration,
+    },
+    OperatorRevoke {
+        owner: Addr,
+        operator: Addr,
+    },
+    SubAccountOpen {
+        owner: Addr,
+        sub_account: u32,
+        account: Addr,
+        margin_mode: MarginMode,
+    },
+    MarginModeUpdate {
+        owner: Addr,
+        sub_account: u32,
+        margin_mode: MarginMode,
+    },
+    /// Owner configuration replaced, `config` is the new value as JSON
+    ConfigUpdate {
+        kind: String,
+        /// Asset the configuration belongs to, `None` for contract-wide ones
+        asset: Option<Addr>,
+        config: String,
+    },
+}
+
+impl LeverageEvent {
+    /// Event type indexers filter on
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            LeverageEvent::Deposit { .. } => "deposit",
+            LeverageEvent::Withdraw { .. } => "withdraw",
+            LeverageEvent::Borrow { .. } => "borrow",
+            LeverageEvent::Repay { .. } => "repay",
+            LeverageEvent::Burn { .. } => "burn",
+            LeverageEvent::OrderSettled { .. } => "order_settled",
+            LeverageEvent::CollateralTransfer { .. } => "collateral_transfer",
+            LeverageEvent::FlashLoan { .. } => "flash_loan",
+            LeverageEvent::Liquidation { .. } => "liquidation",
+            LeverageEvent::BadDebt { .. } => "bad_debt",
+            LeverageEvent::ProtocolFee { .. } => "protocol_fee",
+            LeverageEvent::ProtocolFeeClaim { .. } => "protocol_fee_claim",
+            LeverageEvent::PriceUpdate { .. } => "price_update",
+            LeverageEvent::OrderCommit { .. } => "order_commit",
+            LeverageEvent::OrderReveal { .. } => "order_reveal",
+            LeverageEvent::OrderRefund { .. } => "order_refund",
+            LeverageEvent::EncryptedOrderCommit { .. } => "encrypted_order_commit",
+            LeverageEvent::ConditionalOrderPlace { .. } => "conditional_order_place",
+            LeverageEvent::ConditionalOrderUpdate { .. } => "conditional_order_update",
+            LeverageEvent::ConditionalOrderCancel { .. } => "conditional_order_cancel",
+            LeverageEvent::KeeperBounty { .. } => "keeper_bounty",
+            LeverageEvent::OperatorGrant { .. } => "operator_grant",
+            LeverageEvent::OperatorRevoke { .. } => "operator_revoke",
+            LeverageEvent::SubAccountOpen { .. } => "sub_account_open",
+            LeverageEvent::MarginModeUpdate { .. } => "margin_mode_update",
+            LeverageEvent::ConfigUpdate { .. } => "config_update",
+        }
+    }
+}
+
+fn margin_mode_name(margin_mode: MarginMode) -> &'static str {
+    match margin_mode {
+        MarginMode::Cross => "cross",
+        MarginMode::Isolated => "isolated",
+    }
+}
+
+fn with_balances(event: Event, balances: AccountBalances) -> Event {
+    event
+        .add_attribute("collateral_balance", balances.collateral)
+        .add_attribute("unminted_balance", balances.unminted)
+        .add_attribute("borrow_balance", balances.borrowed)
+        .add_attribute("profit_balance", balances.profit)
+        .add_attribute("position_balance", balances.position)
+        .add_attributes(
+            balances
+                .health_factor
+                .map(|health_factor| ("health_factor", health_factor.to_string())),
+        )
+}
+
+impl From<LeverageEvent> for Event {
+    fn from(leverage_event: LeverageEvent) -> Event {
+        let event = Event::new(leverage_event.event_type());
+        match leverage_event {
+            LeverageEvent::Deposit {
+                user,
+                asset,
+                amount,
+                balances,
+            } => with_balances(
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("asset", asset)
+                    .add_attribute("amount", amount),
+                balances,
+            ),
+            LeverageEvent::Withdraw {
+                user,
+                asset,
+                amount,
+                fee,
+                recipient,
+                balances,
+            } => with_balances(
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("asset", asset)
+                    .add_attribute("amount", amount)
+                    .add_attribute("fee", fee)
+                    .add_attribute("recipient", recipient),
+                balances,
+            ),
+            LeverageEvent::Borrow {
+                user,
+                asset,
+                amount,
+                fee,
+                balances,
+            } => with_balances(
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("asset", asset)
+                    .add_attribute("amount", amount)
+                    .add_attribute("fee", fee),
+                balances,
+            ),
+            LeverageEvent::Repay {
+                user,
+                asset,
+                amount,
+                balances,
+            } => with_balances(
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("asset", asset)
+                    .add_attribute("amount", amount),
+                balances,
+            ),
+            LeverageEvent::Burn {
+                user,
+                asset,
+                v_token_amount,
+                collateral_amount,
+                balances,
+            } => with_balances(
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("asset", asset)
+                    .add_attribute("v_token_amount", v_token_amount)
+                    .add_attribute("collateral_amount", collateral_amount),
+                balances,
+            ),
+            LeverageEvent::OrderSettled {
+                user,
+                token_in,
+                token_out,
+                amount_in,
+                amount_out,
+                fee,
+                balances,
+            } => with_balances(
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("token_in", token_in)
+                    .add_attribute("token_out", token_out)
+                    .add_attribute("amount_in", amount_in)
+                    .add_attribute("amount_out", amount_out)
+                    .add_attribute("fee", fee),
+                balances,
+            ),
+            LeverageEvent::CollateralTransfer {
+                user,
+                asset,
+                amount,
+                from,
+                to,
+            } => event
+                .add_attribute("user", user)
+                .add_attribute("asset", asset)
+                .add_attribute("amount", amount)
+                .add_attribute("from", from)
+                .add_attribute("to", to),
+            LeverageEvent::FlashLoan {
+                borrower,
+                asset,
+                amount,
+                fee,
+            } => event
+                .add_attribute("borrower", borrower)
+                .add_attribute("asset", asset)
+                .add_attribute("amount", amount)
+                .add_attribute("fee", fee),
+            LeverageEvent::Liquidation {
+                user,
+                liquidator,
+                debt_token,
+                debt,
+                seized_positions,
+                seized_collateral,
+                penalty,
+                liquidator_reward,
+                protocol_fee,
+                insurance_contribution,
+                returned_collateral,
+                shortfall,
+            } => event
+                .add_attribute("user", user)
+                .add_attribute("liquidator", liquidator)
+                .add_attribute("debt_token", debt_token)
+                .add_attribute("debt", debt)
+                .add_attribute("seized_positions", seized_positions)
+                .add_attribute("seized_collateral", seized_collateral)
+                .add_attribute("penalty", penalty)
+                .add_attribute("liquidator_reward", liquidator_reward)
+                .add_attribute("protocol_fee", protocol_fee)
+                .add_attribute("insurance_contribution", insurance_contribution)
+                .add_attribute("returned_collateral", returned_collateral)
+                .add_attribute("shortfall", shortfall),
+            LeverageEvent::BadDebt {
+                asset,
+                shortfall,
+                covered_by_insurance,
+                insurance_fund_balance,
+                socialized,
+                socialized_loss_index,
+                socialized_loss_epoch,
+            } => event
+                .add_attribute("asset", asset)
+                .add_attribute("shortfall", shortfall)
+                .add_attribute("covered_by_insurance", covered_by_insurance)
+                .add_attribute("insurance_fund_balance", insurance_fund_balance)
+                .add_attribute("socialized", socialized)
+                .add_attribute("socialized_loss_index", socialized_loss_index.to_string())
+                .add_attribute("socialized_loss_epoch", socialized_loss_epoch.to_string()),
+            LeverageEvent::ProtocolFee {
+                kind,
+                asset,
+                amount,
+            } => event
+                .add_attribute("kind", kind)
+                .add_attribute("asset", asset)
+                .add_attribute("amount", amount),
+            LeverageEvent::ProtocolFeeClaim {
+                asset,
+                amount,
+                fee_collector,
+            } => event
+                .add_attribute("asset", asset)
+                .add_attribute("amount", amount)
+                .add_attribute("fee_collector", fee_collector),
+            LeverageEvent::PriceUpdate {
+                asset,
+                price,
+                publish_time,
+                feeder,
+            } => event
+                .add_attribute("asset", asset)
+                .add_attribute("price", price.to_string())
+                .add_attribute("publish_time", publish_time.seconds().to_string())
+                .add_attribute("feeder", feeder),
+            LeverageEvent::OrderCommit {
+                user,
+                commitment_id,
+                asset,
+                amount,
+                balances,
+            } => with_balances(
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("commitment_id", commitment_id.to_string())
+                    .add_attribute("asset", asset)
+                    .add_attribute("amount", amount),
+                balances,
+            ),
+            LeverageEvent::OrderReveal {
+                user,
+                commitment_id,
+                token_in,
+                token_out,
+                amount_in,
+                limit_price,
+                batch_height,
+            } => event
+                .add_attribute("user", user)
+                .add_attribute("commitment_id", commitment_id.to_string())
+                .add_attribute("token_in", token_in)
+                .add_attribute("token_out", token_out)
+                .add_attribute("amount_in", amount_in)
+                .add_attribute("limit_price", limit_price.to_string())
+                .add_attribute("batch_height", batch_height.to_string()),
+            LeverageEvent::OrderRefund {
+                user,
+                commitment_id,
+                kind,
+                asset,
+                amount,
+                balances,
+            } => with_balances(
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("commitment_id", commitment_id.to_string())
+                    .add_attribute("kind", kind)
+                    .add_attribute("asset", asset)
+                    .add_attribute("amount", amount),
+                balances,
+            ),
+            LeverageEvent::EncryptedOrderCommit { user, order_id } => event
+                .add_attribute("user", user)
+                .add_attribute("order_id", order_id),
+            LeverageEvent::ConditionalOrderPlace {
+                user,
+                order_id,
+                token_in,
+                token_out,
+                amount_in,
+                kind,
+                bounty,
+            } => {
+                let (kind, trigger) = match kind {
+                    ConditionalOrderKind::StopLoss { trigger_price } => {
+                        ("stop_loss", ("trigger_price", trigger_price.to_string()))
+                    }
+                    ConditionalOrderKind::TakeProfit { trigger_price } => {
+                        ("take_profit", ("trigger_price", trigger_price.to_string()))
+                    }
+                    ConditionalOrderKind::TrailingStop { trail_bps } => {
+                        ("trailing_stop", ("trail_bps", trail_bps.to_string()))
+                    }
+                };
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("order_id", order_id.to_string())
+                    .add_attribute("token_in", token_in)
+                    .add_attribute("token_out", token_out)
+                    .add_attributes(amount_in.map(|amount_in| ("amount_in", amount_in)))
+                    .add_attribute("kind", kind)
+                    .add_attribute(trigger.0, trigger.1)
+                    .add_attribute("bounty", bounty)
+            }
+            LeverageEvent::ConditionalOrderUpdate {
+                user,
+                order_id,
+                reference_price,
+            } => event
+                .add_attribute("user", user)
+                .add_attribute("order_id", order_id.to_string())
+                .add_attribute("reference_price", reference_price.to_string()),
+            LeverageEvent::ConditionalOrderCancel { user, order_id } => event
+                .add_attribute("user", user)
+                .add_attribute("order_id", order_id.to_string()),
+            LeverageEvent::KeeperBounty {
+                user,
+                keeper,
+                order_id,
+                asset,
+                amount,
+                keeper_collateral_balance,
+                keeper_unminted_balance,
+                balances,
+            } => with_balances(
+                event
+                    .add_attribute("user", user)
+                    .add_attribute("keeper", keeper)
+                    .add_attribute("order_id", order_id.to_string())
+                    .add_attribute("asset", asset)
+                    .add_attribute("amount", amount)
+                    .add_attribute("keeper_collateral_balance", keeper_collateral_balance)
+                    .add_attribute("keeper_unminted_balance", keeper_unminted_balance),
+                balances,
+            ),
+            LeverageEvent::OperatorGrant {
+                owner,
+                operator,
+                scope,
+                expires,
+            } => {
+                let scope = match scope {
+                    OperatorScope::Trade => "trade",
+                    OperatorScope::Repay => "repay",
+                    OperatorScope::Full => "full",
+                };
+                event
+                    .add_attribute("owner", owner)
+                    .add_attribute("operator", operator)
+                    .add_attribute("scope", scope)
+                    .add_attribute("expires", expires.to_string())
+            }
+            LeverageEvent::OperatorRevoke { owner, operator } => event
+                .add_attribute("owner", owner)
+                .add_attribute("operator", operator),
+            LeverageEvent::SubAccountOpen {
+                owner,
+                sub_account,
+                account,
+                margin_mode,
+            } => event
+                .add_attribute("owner", owner)
+                .add_attribute("sub_account", sub_account.to_string())
+                .add_attribute("account", account)
+                .add_attribute("margin_mode", margin_mode_name(margin_mode)),
+            LeverageEvent::MarginModeUpdate {
+                owner,
+                sub_account,
+                margin_mode,
+            } => event
+                .add_attribute("owner", owner)
+                .add_attribute("sub_account", sub_account.to_string())
+                .add_attribute("margin_mode", margin_mode_name(margin_mode)),
+            LeverageEvent::ConfigUpdate {
+                kind,
+                asset,
+                config,
+            } => event
+                .add_attribute("kind", kind)
+                .add_attributes(asset.map(|asset| ("asset", asset)))
+                .add_attribute("config", config),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BALANCE_KEYS: [&str; 6] = [
+        "collateral_balance",
+        "unminted_balance",
+        "borrow_balance",
+        "profit_balance",
+        "position_balance",
+        "health_factor",
+    ];
+
+    fn keys(event: &Event) -> Vec<&str> {
+        event
+            .attributes
+            .iter()
+            .map(|attribute| attribute.key.as_str())
+            .collect()
+    }
+
+    fn with_balance_keys<'a>(keys: &[&'a str]) -> Vec<&'a str> {
+        keys.iter().copied().chain(BALANCE_KEYS).collect()
+    }
+
+    fn balances() -> AccountBalances {
+        AccountBalances {
+            collateral: Uint128::new(100),
+            unminted: Uint128::new(500),
+            borrowed: Uint128::new(500),
+            profit: Uint128::zero(),
+            position: Uint128::new(495),
+            health_factor: Some(Decimal::percent(120)),
+        }
+    }
+
+    #[test]
+    fn account_events_carry_resulting_balances() {
+        let event = Event::from(LeverageEvent::Borrow {
+            user: Addr::unchecked("user"),
+            asset: Addr::unchecked("usdc"),
+            amount: Uint128::new(500),
+            fee: Uint128::new(5),
+            balances: balances(),
+        });
+        assert_eq!(event.ty, "borrow");
+        assert_eq!(
+            keys(&event),
+            with_balance_keys(&["user", "asset", "amount", "fee"])
+        );
+        let value = |key: &str| {
+            event
+                .attributes
+                .iter()
+                .find(|attribute| attribute.key == key)
+                .unwrap()
+                .value
+                .clone()
+        };
+        assert_eq!(value("position_balance"), "495");
+        assert_eq!(value("health_factor"), "1.2");
+
+        let event = Event::from(LeverageEvent::Repay {
+            user: Addr::unchecked("user"),
+            asset: Addr::unchecked("usdc"),
+            amount: Uint128::new(500),
+            balances: AccountBalances::default(),
+        });
+        assert_eq!(event.ty, "repay");
+        // Without debt there is no health factor to report
+        let mut expected = with_balance_keys(&["user", "asset", "amount"]);
+        expected.retain(|key| *key != "health_factor");
+        assert_eq!(keys(&event), expected);
+
+        let event = Event::from(LeverageEvent::Burn {
+            user: Addr::unchecked("user"),
+            asset: Addr::unchecked("usdc"),
+            v_token_amount: Uint128::new(50),
+            collateral_amount: Uint128::new(5),
+            balances: balances(),
+        });
+        assert_eq!(event.ty, "burn");
+        assert_eq!(
+            keys(&event),
+            with_balance_keys(&["user", "asset", "v_token_amount", "collateral_amount"])
+        );
+
+        let event = Event::from(LeverageEvent::OrderSettled {
+            user: Addr::unchecked("user"),
+            token_in: Addr::unchecked("usdc"),
+            token_out: Addr::unchecked("osmo"),
+            amount_in: Uint128::new(100),
+            amount_out: Uint128::new(50),
+            fee: Uint128::zero(),
+            balances: balances(),
+        });
+        assert_eq!(event.ty, "order_settled");
+        assert_eq!(
+            keys(&event),
+            with_balance_keys(&["user", "token_in", "token_out", "amount_in", "amount_out", "fee"])
+        );
+    }
+
+    #[test]
+    fn protocol_events_keep_their_keys() {
+        let event = Event::from(LeverageEvent::ProtocolFee {
+            kind: "taker".to_string(),
+            asset: Addr::unchecked("usdc"),
+            amount: Uint128::new(3),
+        });
+        assert_eq!(event.ty, "protocol_fee");
+        assert_eq!(keys(&event), vec!["kind", "asset", "amount"]);
+
+        let event = Event::from(LeverageEvent::BadDebt {
+            asset: Addr::unchecked("usdc"),
+            shortfall: Uint128::new(10),
+            covered_by_insurance: Uint128::new(4),
+            insurance_fund_balance: Uint128::zero(),
+            socialized: Uint128::new(6),
+            socialized_loss_index: Decimal::percent(6),
+            socialized_loss_epoch: 0,
+        });
+        assert_eq!(event.ty, "bad_debt");
+        assert_eq!(
+            keys(&event),
+            vec![
+                "asset",
+                "shortfall",
+                "covered_by_insurance",
+                "insurance_fund_balance",
+                "socialized",
+                "socialized_loss_index",
+                "socialized_loss_epoch",
+            ]
+        );
+
+        let event = Event::from(LeverageEvent::FlashLoan {
+            borrower: Addr::unchecked("borrower"),
+            asset: Addr::unchecked("usdc"),
+            amount: Uint128::new(1000),
+            fee: Uint128::new(9),
+        });
+        assert_eq!(event.ty, "flash_loan");
+        assert_eq!(keys(&event), vec!["borrower", "asset", "amount", "fee"]);
+    }
+
+    #[test]
+    fn order_escrow_events_carry_resulting_balances() {
+        let event = Event::from(LeverageEvent::OrderCommit {
+            user: Addr::unchecked("user"),
+            commitment_id: 1,
+            asset: Addr::unchecked("usdc"),
+            amount: Uint128::new(100),
+            balances: balances(),
+        });
+        assert_eq!(event.ty, "order_commit");
+        assert_eq!(
+            keys(&event),
+            with_balance_keys(&["user", "commitment_id", "asset", "amount"])
+        );
+
+        let event = Event::from(LeverageEvent::OrderReveal {
+            user: Addr::unchecked("user"),
+            commitment_id: 1,
+            token_in: Addr::unchecked("usdc"),
+            token_out: Addr::unchecked("osmo"),
+            amount_in: Uint128::new(80),
+            limit_price: Decimal::percent(50),
+            batch_height: 12,
+        });
+        assert_eq!(event.ty, "order_reveal");
+        assert_eq!(
+            keys(&event),
+            vec![
+                "user",
+                "commitment_id",
+                "token_in",
+                "token_out",
+                "amount_in",
+                "limit_price",
+                "batch_height",
+            ]
+        );
+
+        let event = Event::from(LeverageEvent::OrderRefund {
+            user: Addr::unchecked("user"),
+            commitment_id: 1,
+            kind: "unfilled".to_string(),
+            asset: Addr::unchecked("usdc"),
+            amount: Uint128::zero(),
+            balances: balances(),
+        });
+        assert_eq!(event.ty, "order_refund");
+        assert_eq!(
+            keys(&event),
+            with_balance_keys(&["user", "commitment_id", "kind", "asset", "amount"])
+        );
+
+        let event = Event::from(LeverageEvent::KeeperBounty {
+            user: Addr::unchecked("user"),
+            keeper: Addr::unchecked("keeper"),
+            order_id: 3,
+            asset: Addr::unchecked("usdc"),
+            amount: Uint128::new(2),
+            keeper_collateral_balance: Uint128::new(2),
+            keeper_unminted_balance: Uint128::new(2),
+            balances: balances(),
+        });
+        assert_eq!(event.ty, "keeper_bounty");
+        assert_eq!(
+            keys(&event),
+            with_balance_keys(&[
+                "user",
+                "keeper",
+                "order_id",
+                "asset",
+                "amount",
+                "keeper_collateral_balance",
+                "keeper_unminted_balance",
+            ])
+        );
+    }
+
+    #[test]
+    fn order_events_keep_their_keys() {
+        let event = Event::from(LeverageEvent::EncryptedOrderCommit {
+            user: Addr::unchecked("user"),
+            order_id: "order-1".to_string(),
+        });
+        assert_eq!(event.ty, "encrypted_order_commit");
+        assert_eq!(keys(&event), vec!["user", "order_id"]);
+
+        let event = Event::from(LeverageEvent::ConditionalOrderPlace {
+            user: Addr::unchecked("user"),
+            order_id: 3,
+            token_in: Addr::unchecked("osmo"),
+            token_out: Addr::unchecked("usdc"),
+            amount_in: Some(Uint128::new(10)),
+            kind: ConditionalOrderKind::StopLoss {
+                trigger_price: Decimal::one(),
+            },
+            bounty: Uint128::new(2),
+        });
+        assert_eq!(event.ty, "conditional_order_place");
+        assert_eq!(
+            keys(&event),
+            vec![
+                "user",
+                "order_id",
+                "token_in",
+                "token_out",
+                "amount_in",
+                "kind",
+                "trigger_price",
+                "bounty",
+            ]
+        );
+
+        // A trailing stop over the whole position has no amount and a trail instead
+        let event = Event::from(LeverageEvent::ConditionalOrderPlace {
+            user: Addr::unchecked("user"),
+            order_id: 4,
+            token_in: Addr::unchecked("osmo"),
+            token_out: Addr::unchecked("usdc"),
+            amount_in: None,
+            kind: ConditionalOrderKind::TrailingStop { trail_bps: 500 },
+            bounty: Uint128::zero(),
+        });
+        assert_eq!(
+            keys(&event),
+            vec!["user", "order_id", "token_in", "token_out", "kind", "trail_bps", "bounty"]
+        );
+        assert_eq!(event.attributes[4].value, "trailing_stop");
+
+        let event = Event::from(LeverageEvent::ConditionalOrderUpdate {
+            user: Addr::unchecked("user"),
+            order_id: 4,
+            reference_price: Decimal::percent(250),
+        });
+        assert_eq!(event.ty, "conditional_order_update");
+        assert_eq!(keys(&event), vec!["user", "order_id", "reference_price"]);
+
+        let event = Event::from(LeverageEvent::ConditionalOrderCancel {
+            user: Addr::unchecked("user"),
+            order_id: 4,
+        });
+        assert_eq!(event.ty, "conditional_order_cancel");
+        assert_eq!(keys(&event), vec!["user", "order_id"]);
+    }
+
+    #[test]
+    fn account_management_events_keep_their_keys() {
+        let event = Event::from(LeverageEvent::OperatorGrant {
+            owner: Addr::unchecked("owner"),
+            operator: Addr::unchecked("operator"),
+            scope: OperatorScope::Trade,
+            expires: Expiration::Never {},
+        });
+        assert_eq!(event.ty, "operator_grant");
+        assert_eq!(keys(&event), vec!["owner", "operator", "scope", "expires"]);
+        assert_eq!(event.attributes[2].value, "trade");
+
+        let event = Event::from(LeverageEvent::OperatorRevoke {
+            owner: Addr::unchecked("owner"),
+            operator: Addr::unchecked("operator"),
+        });
+        assert_eq!(event.ty, "operator_revoke");
+        assert_eq!(keys(&event), vec!["owner", "operator"]);
+
+        let event = Event::from(LeverageEvent::SubAccountOpen {
+            owner: Addr::unchecked("owner"),
+            sub_account: 1,
+            account: Addr::unchecked("owner/1"),
+            margin_mode: MarginMode::Isolated,
+        });
+        assert_eq!(event.ty, "sub_account_open");
+        assert_eq!(
+            keys(&event),
+            vec!["owner", "sub_account", "account", "margin_mode"]
+        );
+        assert_eq!(event.attributes[3].value, "isolated");
+
+        let event = Event::from(LeverageEvent::MarginModeUpdate {
+            owner: Addr::unchecked("owner"),
+            sub_account: 1,
+            margin_mode: MarginMode::Cross,
+        });
+        assert_eq!(event.ty, "margin_mode_update");
+        assert_eq!(keys(&event), vec!["owner", "sub_account", "margin_mode"]);
+    }
+
+    #[test]
+    fn config_update_leaves_out_a_missing_asset() {
+        let event = Event::from(LeverageEvent::ConfigUpdate {
+            kind: "reveal_window".to_string(),
+            asset: None,
+            config: "5".to_string(),
+        });
+        assert_eq!(event.ty, "config_update");
+        assert_eq!(keys(&event), vec!["kind", "config"]);
+
+        let event = Event::from(LeverageEvent::ConfigUpdate {
+            kind: "token_caps".to_string(),
+            asset: Some(Addr::unchecked("usdc")),
+            config: "{}".to_string(),
+        });
+        assert_eq!(keys(&event), vec!["kind", "asset", "config"]);
+    }
+}
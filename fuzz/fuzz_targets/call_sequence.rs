@@ -0,0 +1,14 @@
+//! Arbitrary sequences of deposits, borrows, trades, price moves, liquidations and
+//! withdrawals across the senders, with the storage checked after every call
+
+#![no_main]
+
+use leverage_fuzz::{Call, Contract};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|calls: Vec<Call>| {
+    let mut contract = Contract::new();
+    for call in calls.iter() {
+        contract.call(call);
+    }
+});
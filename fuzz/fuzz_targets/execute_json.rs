@@ -0,0 +1,15 @@
+//! Arbitrary JSON as an `ExecuteMsg`, from any of the senders, on the set up contract
+
+#![no_main]
+
+use leverage_contract::msg::ExecuteMsg;
+use leverage_fuzz::{parse, sender, Contract, RawCall};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|call: RawCall| {
+    let Some(msg) = parse::<ExecuteMsg>(call.msg) else {
+        return;
+    };
+    let mut contract = Contract::new();
+    let _ = contract.execute(sender(call.sender), call.funds, msg);
+});
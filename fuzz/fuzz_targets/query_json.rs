@@ -0,0 +1,14 @@
+//! Arbitrary JSON as a `QueryMsg` on the set up contract
+
+#![no_main]
+
+use leverage_contract::msg::QueryMsg;
+use leverage_fuzz::{parse, Contract};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some(msg) = parse::<QueryMsg>(data) else {
+        return;
+    };
+    let _ = Contract::new().query(msg);
+});
@@ -0,0 +1,406 @@
+//! Fuzzing harness for the leverage contract, shared by the `cargo fuzz` targets in
+//! `fuzz_targets/`. The contract runs against the `cosmwasm_std::testing` mocks, so the
+//! targets need neither a chain nor the network:
+//!
+//! ```text
+//! cargo fuzz run --debug-assertions execute_json
+//! cargo fuzz run --debug-assertions query_json
+//! cargo fuzz run --debug-assertions call_sequence
+//! ```
+//!
+//! A call that fails is rolled back the way a transaction would be. After every call that
+//! succeeds, [`check_storage`] decodes the ledgers and checks their invariants, panicking
+//! on the first violation so the fuzzer records the input. Panics inside the contract,
+//! including `Uint128` overflows, are reported the same way.
+
+use arbitrary::Arbitrary;
+use cosmwasm_std::testing::{
+    mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+};
+use cosmwasm_std::{
+    coins, from_json, to_json_binary, Addr, Binary, Decimal, Env, Order, OwnedDeps, Response,
+    StdResult, Storage, Uint128,
+};
+use cw_storage_plus::Map;
+use leverage_contract::contract::{execute, instantiate, query};
+use leverage_contract::msg::{
+    Cw20ReceiveMsg, ExecuteMsg, InstantiateMsg, OrderExecute, QueryMsg, TokenData, WithdrawData,
+};
+use leverage_contract::state::{
+    ASSET_PRICE, INSURANCE_FUND, LEVERAGE_CONTRACT_OWNER, LISTED_TOKEN, TOTAL_BORROWS,
+    TOTAL_DEPOSITS, TREASURY, USER_BORROW_BALANCE, USER_POSITION_BALANCE, USER_PROFIT_TOKEN,
+    USER_TOKEN_BALANCE, USER_UNMINTED_TOKEN,
+};
+use leverage_contract::ContractError;
+use leverage_math::vtoken;
+
+pub const OWNER: &str = "creator";
+pub const USDC: &str = "usdc_contract";
+
+/// Senders picked by index, the last one is a CW20 that is never listed
+pub const SENDERS: [&str; 6] = [OWNER, USDC, "user_one", "user_two", "keeper", "fake_cw20"];
+
+/// Tokens picked by index, the last one is never listed
+pub const TOKENS: [&str; 3] = [USDC, "osmo", "fake_cw20"];
+
+/// Longest single `AdvanceTime` step, so block time stays far from overflowing
+const WEEK: u64 = 7 * 24 * 60 * 60;
+
+pub fn sender(index: u8) -> &'static str {
+    SENDERS[index as usize % SENDERS.len()]
+}
+
+pub fn token(index: u8) -> Addr {
+    Addr::unchecked(TOKENS[index as usize % TOKENS.len()])
+}
+
+/// An execute message as raw JSON, sent with `funds` uosmo
+#[derive(Arbitrary, Debug)]
+pub struct RawCall<'a> {
+    pub sender: u8,
+    pub funds: u32,
+    pub msg: &'a [u8],
+}
+
+/// One step of a call sequence, senders, users and tokens are indices
+#[derive(Arbitrary, Debug)]
+pub enum Call {
+    /// CW20 `Receive` sent by a token, which may not be listed: token, sender, amount
+    Receive(u8, u8, u128),
+    /// sender, token, uosmo sent
+    DepositNative(u8, u8, u32),
+    /// sender, token, amount
+    Borrow(u8, u8, u128),
+    /// sender, token, amount
+    Repay(u8, u8, u128),
+    /// sender, token, vToken amount
+    Burn(u8, u8, u128),
+    /// sender, token, amount, native
+    Withdraw(u8, u8, u128, bool),
+    /// Order settled by the owner: user, token in, token out, amount in, amount out
+    Trade(u8, u8, u8, u128, u128),
+    /// token, price in thousandths
+    SetPrice(u8, u64),
+    /// liquidator, user, debt token
+    Liquidate(u8, u8, u8),
+    /// seconds up to a week, the height moves by one
+    AdvanceTime(u32),
+}
+
+/// The contract on mocked dependencies, instantiated with usdc and osmo listed, both
+/// priced and some usdc deposited
+pub struct Contract {
+    pub deps: OwnedDeps<MockStorage, MockApi, MockQuerier>,
+    pub env: Env,
+}
+
+impl Default for Contract {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Contract {
+    pub fn new() -> Self {
+        let mut contract = Contract {
+            deps: mock_dependencies(),
+            env: mock_env(),
+        };
+        instantiate(
+            contract.deps.as_mut(),
+            contract.env.clone(),
+            mock_info(OWNER, &[]),
+            InstantiateMsg {
+                token_contract_address: USDC.to_string(),
+            },
+        )
+        .unwrap();
+
+        let setup = [
+            (
+                OWNER,
+                ExecuteMsg::ListTokenOnLeverage {
+                    token_address: "osmo".to_string(),
+                    native_denom: Some("uosmo".to_string()),
+                },
+            ),
+            (
+                OWNER,
+                ExecuteMsg::UpdateAssetPrice {
+                    asset: Addr::unchecked(USDC),
+                    price: Decimal::one(),
+                },
+            ),
+            (
+                OWNER,
+                ExecuteMsg::UpdateAssetPrice {
+                    asset: Addr::unchecked("osmo"),
+                    price: Decimal::percent(200),
+                },
+            ),
+            (USDC, receive("user_one", 1_000)),
+            (USDC, receive("user_two", 1_000)),
+        ];
+        for (sender, msg) in setup {
+            contract.execute(sender, 0, msg).unwrap();
+        }
+        contract
+    }
+
+    /**
+     * @dev Executes `msg` from `sender` with `funds` uosmo. A failed call leaves the storage
+     * as it was, a successful one must leave it consistent.
+     */
+    pub fn execute(
+        &mut self,
+        sender: &str,
+        funds: u32,
+        msg: ExecuteMsg,
+    ) -> Result<Response, ContractError> {
+        let snapshot: Vec<(Vec<u8>, Vec<u8>)> = self
+            .deps
+            .storage
+            .range(None, None, Order::Ascending)
+            .collect();
+        let info = match funds {
+            0 => mock_info(sender, &[]),
+            funds => mock_info(sender, &coins(funds.into(), "uosmo")),
+        };
+
+        let result = execute(self.deps.as_mut(), self.env.clone(), info, msg);
+        match result {
+            Ok(_) => {
+                if let Err(violation) = check_storage(&self.deps.storage) {
+                    panic!("storage corrupted: {}", violation);
+                }
+            }
+            Err(_) => {
+                let mut storage = MockStorage::new();
+                for (key, value) in snapshot {
+                    storage.set(&key, &value);
+                }
+                self.deps.storage = storage;
+            }
+        }
+        result
+    }
+
+    pub fn query(&self, msg: QueryMsg) -> StdResult<Binary> {
+        query(self.deps.as_ref(), self.env.clone(), msg)
+    }
+
+    pub fn call(&mut self, call: &Call) {
+        let (sender, funds, msg) = match *call {
+            Call::Receive(token_index, sender_index, amount) => {
+                let token = token(token_index);
+                let result = self.execute(token.as_str(), 0, receive(sender(sender_index), amount));
+                // Only listed CW20s vouch for the `sender` of a `Receive`
+                if result.is_ok() && !self.is_listed(&token) {
+                    panic!("{} credited a deposit without being listed", token);
+                }
+                return;
+            }
+            Call::DepositNative(sender_index, token_index, funds) => (
+                sender(sender_index),
+                funds,
+                ExecuteMsg::DepositNative {
+                    token_address: token(token_index).to_string(),
+                    sub_account: None,
+                },
+            ),
+            Call::Borrow(sender_index, token_index, amount) => (
+                sender(sender_index),
+                0,
+                ExecuteMsg::Borrow(token_data(token_index, amount)),
+            ),
+            Call::Repay(sender_index, token_index, amount) => (
+                sender(sender_index),
+                0,
+                ExecuteMsg::Repay(token_data(token_index, amount)),
+            ),
+            Call::Burn(sender_index, token_index, amount) => (
+                sender(sender_index),
+                0,
+                ExecuteMsg::Burn(token_data(token_index, amount)),
+            ),
+            Call::Withdraw(sender_index, token_index, amount, native) => (
+                sender(sender_index),
+                0,
+                ExecuteMsg::WithdrawToken(WithdrawData {
+                    token_address: token(token_index),
+                    token_amount: Uint128::from(amount),
+                    withdraw_type: if native { "native" } else { "fungible" }.to_string(),
+                    native: None,
+                    on_behalf_of: None,
+                    sub_account: None,
+                }),
+            ),
+            Call::Trade(user, token_in, token_out, amount_in, amount_out) => (
+                OWNER,
+                0,
+                ExecuteMsg::ExecuteOrder(OrderExecute {
+                    user_address: Addr::unchecked(sender(user)),
+                    token_in: token(token_in),
+                    token_out: token(token_out),
+                    amount_in: Uint128::from(amount_in),
+                    amount_out: Uint128::from(amount_out),
+                    encrypted_order: None,
+                }),
+            ),
+            Call::SetPrice(token_index, price) => (
+                OWNER,
+                0,
+                ExecuteMsg::UpdateAssetPrice {
+                    asset: token(token_index),
+                    price: Decimal::from_ratio(price, 1_000u128),
+                },
+            ),
+            Call::Liquidate(sender_index, user, token_index) => (
+                sender(sender_index),
+                0,
+                ExecuteMsg::Liquidate {
+                    user_address: Addr::unchecked(sender(user)),
+                    debt_token: token(token_index),
+                },
+            ),
+            Call::AdvanceTime(seconds) => {
+                self.env.block.height += 1;
+                self.env.block.time = self.env.block.time.plus_seconds(u64::from(seconds) % WEEK);
+                return;
+            }
+        };
+        let _ = self.execute(sender, funds, msg);
+    }
+
+    fn is_listed(&self, token: &Addr) -> bool {
+        LISTED_TOKEN
+            .may_load(&self.deps.storage)
+            .unwrap()
+            .unwrap_or_default()
+            .contains(&token.to_string())
+    }
+}
+
+fn receive(sender: &str, amount: u128) -> ExecuteMsg {
+    ExecuteMsg::Receive(Cw20ReceiveMsg {
+        sender: sender.to_string(),
+        amount: Uint128::from(amount),
+        msg: to_json_binary(&{}).unwrap(),
+    })
+}
+
+fn token_data(token_index: u8, amount: u128) -> TokenData {
+    TokenData {
+        token_address: token(token_index),
+        token_amount: Uint128::from(amount),
+        on_behalf_of: None,
+        sub_account: None,
+    }
+}
+
+/// Parses `msg` the way the contract's entry points do, `None` if it is not a message
+pub fn parse<T: serde::de::DeserializeOwned>(msg: &[u8]) -> Option<T> {
+    from_json(msg).ok()
+}
+
+/**
+ * @dev Decodes every ledger and checks that
+ * - unminted vToken is collateral × 10 less the debt, socialized losses only ever
+ *   saturate it at zero
+ * - the borrow totals match the user ledgers
+ * - no total overflows
+ */
+pub fn check_storage(storage: &dyn Storage) -> Result<(), String> {
+    LEVERAGE_CONTRACT_OWNER
+        .load(storage)
+        .map_err(|error| format!("owner: {}", error))?;
+    LISTED_TOKEN
+        .load(storage)
+        .map_err(|error| format!("listed tokens: {}", error))?;
+
+    for (name, ledger) in [
+        ("collateral", USER_TOKEN_BALANCE),
+        ("unminted", USER_UNMINTED_TOKEN),
+        ("debt", USER_BORROW_BALANCE),
+        ("profit", USER_PROFIT_TOKEN),
+        ("positions", USER_POSITION_BALANCE),
+    ] {
+        for item in ledger.range(storage, None, None, Order::Ascending) {
+            item.map_err(|error| format!("{} ledger: {}", name, error))?;
+        }
+    }
+    for (name, ledger) in [
+        ("total deposits", TOTAL_DEPOSITS),
+        ("total borrows", TOTAL_BORROWS),
+        ("treasury", TREASURY),
+        ("insurance fund", INSURANCE_FUND),
+    ] {
+        for item in ledger.range(storage, None, None, Order::Ascending) {
+            item.map_err(|error| format!("{}: {}", name, error))?;
+        }
+    }
+    for item in ASSET_PRICE.range(storage, None, None, Order::Ascending) {
+        item.map_err(|error| format!("prices: {}", error))?;
+    }
+
+    // Every account with any of the three ledgers
+    let mut accounts: Vec<(Addr, Addr)> = vec![];
+    for ledger in [USER_TOKEN_BALANCE, USER_UNMINTED_TOKEN, USER_BORROW_BALANCE] {
+        for key in ledger.keys(storage, None, None, Order::Ascending) {
+            let key = key.map_err(|error| error.to_string())?;
+            if !accounts.contains(&key) {
+                accounts.push(key);
+            }
+        }
+    }
+
+    let mut borrowed: Vec<(Addr, Uint128)> = vec![];
+    for (token, user) in accounts {
+        let collateral = load(storage, USER_TOKEN_BALANCE, (&token, &user))?;
+        let unminted = load(storage, USER_UNMINTED_TOKEN, (&token, &user))?;
+        let debt = load(storage, USER_BORROW_BALANCE, (&token, &user))?;
+
+        let v_tokens = vtoken::to_v_tokens(collateral.u128())
+            .ok_or_else(|| format!("vTokens of {} in {} overflow", user, token))?;
+        if unminted.u128() != v_tokens.saturating_sub(debt.u128()) {
+            return Err(format!(
+                "{} in {} has {} unminted with {} collateral and {} debt",
+                user, token, unminted, collateral, debt
+            ));
+        }
+
+        match borrowed.iter_mut().find(|(asset, _)| *asset == token) {
+            Some((_, total)) => {
+                *total = total
+                    .checked_add(debt)
+                    .map_err(|_| format!("debt in {} overflows", token))?;
+            }
+            None => borrowed.push((token, debt)),
+        }
+    }
+
+    for (token, total) in borrowed {
+        let total_borrows = TOTAL_BORROWS
+            .may_load(storage, &token)
+            .map_err(|error| error.to_string())?
+            .unwrap_or_default();
+        if total_borrows != total {
+            return Err(format!(
+                "total borrows of {} is {}, the ledgers add up to {}",
+                token, total_borrows, total
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn load(
+    storage: &dyn Storage,
+    ledger: Map<(&Addr, &Addr), Uint128>,
+    key: (&Addr, &Addr),
+) -> Result<Uint128, String> {
+    ledger
+        .may_load(storage, key)
+        .map(Option::unwrap_or_default)
+        .map_err(|error| error.to_string())
+}
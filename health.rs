@@ -0,0 +1,126 @@
+//! `cosmwasm_std` typed wrappers around `leverage_math`, see its docs for rounding.
+
+use cosmwasm_std::{Decimal, Uint128};
+use leverage_math::health;
+
+/// Value of `amount` base units at `price`, `None` on overflow
+pub fn asset_value(amount: Uint128, price: Decimal) -> Option<Decimal> {
+    health::asset_value(amount.u128(), price.into()).map(Decimal::from)
+}
+
+/// `(collateral_value + position_value) / debt_value`, `None` without debt
+pub fn health_factor(
+    collateral_value: Decimal,
+    position_value: Decimal,
+    debt_value: Decimal,
+) -> Option<Decimal> {
+    health::health_factor(collateral_value.into(), position_value.into(), debt_value.into())
+        .map(Decimal::from)
+}
+
+/// Accounts without debt are never liquidatable
+pub fn is_liquidatable(health_factor: Option<Decimal>, liquidation_threshold: Decimal) -> bool {
+    health::is_liquidatable(health_factor.map(Into::into), liquidation_threshold.into())
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LiquidationSplit {
+    pub repaid: Uint128,
+    /// Debt the seized value could not cover, bad debt
+    pub shortfall: Uint128,
+    pub penalty: Uint128,
+    pub insurance_share: Uint128,
+    pub protocol_share: Uint128,
+    pub liquidator_reward: Uint128,
+    /// Credited back to the liquidated account as collateral
+    pub returned_collateral: Uint128,
+}
+
+impl From<leverage_math::liquidation::LiquidationSplit> for LiquidationSplit {
+    fn from(split: leverage_math::liquidation::LiquidationSplit) -> Self {
+        LiquidationSplit {
+            repaid: split.repaid.into(),
+            shortfall: split.shortfall.into(),
+            penalty: split.penalty.into(),
+            insurance_share: split.insurance_share.into(),
+            protocol_share: split.protocol_share.into(),
+            liquidator_reward: split.liquidator_reward.into(),
+            returned_collateral: split.returned_collateral.into(),
+        }
+    }
+}
+
+/// See `leverage_math::liquidation::liquidation_split`, `None` on overflow
+pub fn liquidation_split(
+    seized: Uint128,
+    collateral: Uint128,
+    debt: Uint128,
+    liquidation_penalty_bps: u64,
+    insurance_share_bps: u64,
+    liquidation_fee_bps: u64,
+) -> Option<LiquidationSplit> {
+    leverage_math::liquidation::liquidation_split(
+        seized.u128(),
+        collateral.u128(),
+        debt.u128(),
+        liquidation_penalty_bps,
+        insurance_share_bps,
+        liquidation_fee_bps,
+    )
+    .map(LiquidationSplit::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_factor_needs_debt() {
+        assert_eq!(
+            health_factor(Decimal::percent(10_000), Decimal::zero(), Decimal::zero()),
+            None
+        );
+        let health = health_factor(
+            Decimal::percent(10_000),
+            Decimal::percent(5_000),
+            Decimal::percent(12_500),
+        );
+        assert_eq!(health, Some(Decimal::percent(120)));
+        assert!(!is_liquidatable(health, Decimal::percent(105)));
+        assert!(is_liquidatable(health, Decimal::percent(125)));
+        assert!(!is_liquidatable(None, Decimal::percent(125)));
+    }
+
+    #[test]
+    fn liquidation_split_pays_debt_before_penalty() {
+        // 480 seized against 450 of debt, 5% penalty capped by the 30 surplus
+        let split = liquidation_split(
+            Uint128::new(430),
+            Uint128::new(50),
+            Uint128::new(450),
+            500,
+            2_000,
+            5_000,
+        )
+        .unwrap();
+        assert_eq!(split.repaid, Uint128::new(450));
+        assert_eq!(split.penalty, Uint128::new(22));
+        assert_eq!(split.insurance_share, Uint128::new(4));
+        assert_eq!(split.protocol_share, Uint128::new(9));
+        assert_eq!(split.liquidator_reward, Uint128::new(9));
+        assert_eq!(split.returned_collateral, Uint128::new(8));
+        assert_eq!(split.shortfall, Uint128::zero());
+
+        let split = liquidation_split(
+            Uint128::new(300),
+            Uint128::zero(),
+            Uint128::new(450),
+            500,
+            0,
+            0,
+        )
+        .unwrap();
+        assert_eq!(split.shortfall, Uint128::new(150));
+        assert_eq!(split.penalty, Uint128::zero());
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::BufRead;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::IndexerError;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawEvent {
+    pub ty: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxResult {
+    /// Non-zero for failed transactions, whose events are not applied
+    pub code: u32,
+    pub events: Vec<RawEvent>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub height: u64,
+    pub txs: Vec<TxResult>,
+}
+
+#[derive(Deserialize)]
+struct RpcBlockResults {
+    height: String,
+    #[serde(default)]
+    txs_results: Option<Vec<RpcTxResult>>,
+}
+
+#[derive(Deserialize)]
+struct RpcTxResult {
+    #[serde(default)]
+    code: u32,
+    #[serde(default)]
+    events: Vec<RpcEvent>,
+}
+
+#[derive(Deserialize)]
+struct RpcEvent {
+    #[serde(rename = "type")]
+    ty: String,
+    #[serde(default)]
+    attributes: Vec<RpcAttribute>,
+}
+
+#[derive(Deserialize)]
+struct RpcAttribute {
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+impl Block {
+    /// Parses a `block_results` result, either bare or wrapped in its JSON-RPC response
+    pub fn from_json(value: Value) -> Result<Block, IndexerError> {
+        let value = match value {
+            Value::Object(mut object) if object.contains_key("result") => {
+                object.remove("result").unwrap_or_default()
+            }
+            value => value,
+        };
+        let results: RpcBlockResults = serde_json::from_value(value)?;
+        let height = results
+            .height
+            .parse::<u64>()
+            .map_err(|_| IndexerError::InvalidBlock {
+                reason: format!("height {}", results.height),
+            })?;
+
+        let txs = results
+            .txs_results
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tx| TxResult {
+                code: tx.code,
+                events: tx
+                    .events
+                    .into_iter()
+                    .map(|event| RawEvent {
+                        ty: event.ty,
+                        attributes: event
+                            .attributes
+                            .into_iter()
+                            .map(|attribute| (attribute.key, attribute.value))
+                            .collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Ok(Block { height, txs })
+    }
+}
+
+pub trait BlockSource {
+    /// The next block in height order, `None` once the source is exhausted
+    fn next_block(&mut self) -> Result<Option<Block>, IndexerError>;
+}
+
+/// Recorded block dump, one `block_results` JSON document per line
+pub struct DumpSource<R: BufRead> {
+    lines: std::io::Lines<R>,
+}
+
+impl<R: BufRead> DumpSource<R> {
+    pub fn new(reader: R) -> Self {
+        DumpSource {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R: BufRead> BlockSource for DumpSource<R> {
+    fn next_block(&mut self) -> Result<Option<Block>, IndexerError> {
+        for line in self.lines.by_ref() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Ok(Some(Block::from_json(serde_json::from_str(&line)?)?));
+        }
+        Ok(None)
+    }
+}
+
+/**
+ * @dev Reads `block_results` from a node's CometBFT RPC, from `from_height` up to the
+ * latest height reported by `/status` when the source is created.
+ */
+pub struct RpcSource {
+    url: String,
+    next_height: u64,
+    latest_height: u64,
+}
+
+impl RpcSource {
+    pub fn new(url: impl Into<String>, from_height: u64) -> Result<Self, IndexerError> {
+        let url = url.into().trim_end_matches('/').to_string();
+        let status = rpc_get(&format!("{}/status", url))?;
+        let latest_height = status["result"]["sync_info"]["latest_block_height"]
+            .as_str()
+            .and_then(|height| height.parse::<u64>().ok())
+            .ok_or_else(|| IndexerError::Node {
+                error: "status without latest_block_height".to_string(),
+            })?;
+
+        Ok(RpcSource {
+            url,
+            next_height: from_height.max(1),
+            latest_height,
+        })
+    }
+}
+
+impl BlockSource for RpcSource {
+    fn next_block(&mut self) -> Result<Option<Block>, IndexerError> {
+        if self.next_height > self.latest_height {
+            return Ok(None);
+        }
+        let response = rpc_get(&format!(
+            "{}/block_results?height={}",
+            self.url, self.next_height
+        ))?;
+        if let Some(error) = response.get("error") {
+            return Err(IndexerError::Node {
+                error: error.to_string(),
+            });
+        }
+        self.next_height += 1;
+        Ok(Some(Block::from_json(response)?))
+    }
+}
+
+fn rpc_get(url: &str) -> Result<Value, IndexerError> {
+    let response = ureq::get(url).call().map_err(|error| IndexerError::Node {
+        error: error.to_string(),
+    })?;
+    Ok(response.into_json::<Value>()?)
+}
@@ -0,0 +1,22 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum IndexerError {
+    #[error("{0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Node request failed: {error}")]
+    Node { error: String },
+
+    #[error("Invalid block: {reason}")]
+    InvalidBlock { reason: String },
+
+    #[error("Invalid {event_type} event: {reason}")]
+    InvalidEvent { event_type: String, reason: String },
+}
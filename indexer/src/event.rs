@@ -0,0 +1,441 @@
+use crate::block::RawEvent;
+use crate::IndexerError;
+
+/// Account ledgers in one asset as reported by the event, after the action
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Balances {
+    pub collateral: u128,
+    pub unminted: u128,
+    pub borrowed: u128,
+    pub profit: u128,
+    pub position: u128,
+    /// Decimal string, `None` when the account has no debt or a price is missing
+    pub health_factor: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Liquidation {
+    pub user: String,
+    pub liquidator: String,
+    pub debt_token: String,
+    pub debt: u128,
+    pub seized_positions: u128,
+    pub seized_collateral: u128,
+    pub penalty: u128,
+    pub liquidator_reward: u128,
+    pub protocol_fee: u128,
+    pub insurance_contribution: u128,
+    pub returned_collateral: u128,
+    pub shortfall: u128,
+}
+
+/// Contract events the indexer applies, mirroring the contract's event schema
+#[derive(Clone, Debug, PartialEq)]
+pub enum ContractEvent {
+    Deposit {
+        user: String,
+        asset: String,
+        amount: u128,
+        balances: Balances,
+    },
+    Withdraw {
+        user: String,
+        asset: String,
+        amount: u128,
+        fee: u128,
+        balances: Balances,
+    },
+    Borrow {
+        user: String,
+        asset: String,
+        amount: u128,
+        fee: u128,
+        balances: Balances,
+    },
+    Repay {
+        user: String,
+        asset: String,
+        amount: u128,
+        balances: Balances,
+    },
+    Burn {
+        user: String,
+        asset: String,
+        v_token_amount: u128,
+        collateral_amount: u128,
+        balances: Balances,
+    },
+    OrderSettled {
+        user: String,
+        token_in: String,
+        token_out: String,
+        amount_in: u128,
+        amount_out: u128,
+        fee: u128,
+        balances: Balances,
+    },
+    CollateralTransfer {
+        asset: String,
+        amount: u128,
+        from: String,
+        to: String,
+    },
+    Liquidation(Liquidation),
+    BadDebt {
+        asset: String,
+        shortfall: u128,
+        /// Decimal atomics, 18 fractional digits
+        socialized_loss_index: u128,
+        socialized_loss_epoch: u64,
+    },
+    ProtocolFee {
+        kind: String,
+        asset: String,
+        amount: u128,
+    },
+    OrderCommit {
+        user: String,
+        commitment_id: u64,
+        asset: String,
+        amount: u128,
+        balances: Balances,
+    },
+    OrderRefund {
+        user: String,
+        commitment_id: u64,
+        kind: String,
+        asset: String,
+        amount: u128,
+        balances: Balances,
+    },
+    KeeperBounty {
+        user: String,
+        keeper: String,
+        asset: String,
+        amount: u128,
+        keeper_collateral: u128,
+        keeper_unminted: u128,
+        balances: Balances,
+    },
+}
+
+struct Attributes<'a> {
+    event_type: &'a str,
+    attributes: &'a [(String, String)],
+}
+
+impl<'a> Attributes<'a> {
+    fn get(&self, key: &str) -> Result<&'a str, IndexerError> {
+        self.optional(key)
+            .ok_or_else(|| self.invalid(format!("missing {}", key)))
+    }
+
+    fn optional(&self, key: &str) -> Option<&'a str> {
+        self.attributes
+            .iter()
+            .find(|(attribute_key, _)| attribute_key == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn string(&self, key: &str) -> Result<String, IndexerError> {
+        Ok(self.get(key)?.to_string())
+    }
+
+    fn amount(&self, key: &str) -> Result<u128, IndexerError> {
+        let value = self.get(key)?;
+        value
+            .parse::<u128>()
+            .map_err(|_| self.invalid(format!("{} is not an amount: {}", key, value)))
+    }
+
+    fn id(&self, key: &str) -> Result<u64, IndexerError> {
+        let value = self.get(key)?;
+        value
+            .parse::<u64>()
+            .map_err(|_| self.invalid(format!("{} is not an id: {}", key, value)))
+    }
+
+    /// Atomics of a `Decimal` string, which has at most 18 fractional digits
+    fn decimal(&self, key: &str) -> Result<u128, IndexerError> {
+        let value = self.get(key)?;
+        let invalid = || self.invalid(format!("{} is not a decimal: {}", key, value));
+        let (whole, fractional) = value.split_once('.').unwrap_or((value, ""));
+        if fractional.len() > 18 {
+            return Err(invalid());
+        }
+        let whole = whole.parse::<u128>().map_err(|_| invalid())?;
+        let fractional = match fractional {
+            "" => 0,
+            digits => {
+                digits.parse::<u128>().map_err(|_| invalid())?
+                    * 10u128.pow(18 - digits.len() as u32)
+            }
+        };
+        whole
+            .checked_mul(10u128.pow(18))
+            .and_then(|whole| whole.checked_add(fractional))
+            .ok_or_else(invalid)
+    }
+
+    fn balances(&self) -> Result<Balances, IndexerError> {
+        Ok(Balances {
+            collateral: self.amount("collateral_balance")?,
+            unminted: self.amount("unminted_balance")?,
+            borrowed: self.amount("borrow_balance")?,
+            profit: self.amount("profit_balance")?,
+            position: self.amount("position_balance")?,
+            health_factor: self.optional("health_factor").map(str::to_string),
+        })
+    }
+
+    fn invalid(&self, reason: String) -> IndexerError {
+        IndexerError::InvalidEvent {
+            event_type: self.event_type.to_string(),
+            reason,
+        }
+    }
+}
+
+impl ContractEvent {
+    /**
+     * @dev Parses a wasm event emitted by `contract`.
+     *
+     * Events of other contracts, the untyped `wasm` event and event types that leave the
+     * ledgers alone (flash loans, fee claims, prices, order reveals, conditional orders,
+     * operators, sub-accounts and configuration) return `None`. A tracked event
+     * with missing or malformed keys is an error, it means the schema changed.
+     */
+    pub fn parse(event: &RawEvent, contract: &str) -> Result<Option<ContractEvent>, IndexerError> {
+        let event_type = match event.ty.strip_prefix("wasm-") {
+            Some(event_type) => event_type,
+            None => return Ok(None),
+        };
+        let emitted_by = event
+            .attributes
+            .iter()
+            .find(|(key, _)| key == "_contract_address")
+            .map(|(_, value)| value.as_str());
+        if emitted_by != Some(contract) {
+            return Ok(None);
+        }
+
+        let attributes = Attributes {
+            event_type,
+            attributes: &event.attributes,
+        };
+        let event = match event_type {
+            "deposit" => ContractEvent::Deposit {
+                user: attributes.string("user")?,
+                asset: attributes.string("asset")?,
+                amount: attributes.amount("amount")?,
+                balances: attributes.balances()?,
+            },
+            "withdraw" => ContractEvent::Withdraw {
+                user: attributes.string("user")?,
+                asset: attributes.string("asset")?,
+                amount: attributes.amount("amount")?,
+                fee: attributes.amount("fee")?,
+                balances: attributes.balances()?,
+            },
+            "borrow" => ContractEvent::Borrow {
+                user: attributes.string("user")?,
+                asset: attributes.string("asset")?,
+                amount: attributes.amount("amount")?,
+                fee: attributes.amount("fee")?,
+                balances: attributes.balances()?,
+            },
+            "repay" => ContractEvent::Repay {
+                user: attributes.string("user")?,
+                asset: attributes.string("asset")?,
+                amount: attributes.amount("amount")?,
+                balances: attributes.balances()?,
+            },
+            "burn" => ContractEvent::Burn {
+                user: attributes.string("user")?,
+                asset: attributes.string("asset")?,
+                v_token_amount: attributes.amount("v_token_amount")?,
+                collateral_amount: attributes.amount("collateral_amount")?,
+                balances: attributes.balances()?,
+            },
+            "order_settled" => ContractEvent::OrderSettled {
+                user: attributes.string("user")?,
+                token_in: attributes.string("token_in")?,
+                token_out: attributes.string("token_out")?,
+                amount_in: attributes.amount("amount_in")?,
+                amount_out: attributes.amount("amount_out")?,
+                fee: attributes.amount("fee")?,
+                balances: attributes.balances()?,
+            },
+            "collateral_transfer" => ContractEvent::CollateralTransfer {
+                asset: attributes.string("asset")?,
+                amount: attributes.amount("amount")?,
+                from: attributes.string("from")?,
+                to: attributes.string("to")?,
+            },
+            "liquidation" => ContractEvent::Liquidation(Liquidation {
+                user: attributes.string("user")?,
+                liquidator: attributes.string("liquidator")?,
+                debt_token: attributes.string("debt_token")?,
+                debt: attributes.amount("debt")?,
+                seized_positions: attributes.amount("seized_positions")?,
+                seized_collateral: attributes.amount("seized_collateral")?,
+                penalty: attributes.amount("penalty")?,
+                liquidator_reward: attributes.amount("liquidator_reward")?,
+                protocol_fee: attributes.amount("protocol_fee")?,
+                insurance_contribution: attributes.amount("insurance_contribution")?,
+                returned_collateral: attributes.amount("returned_collateral")?,
+                shortfall: attributes.amount("shortfall")?,
+            }),
+            "bad_debt" => ContractEvent::BadDebt {
+                asset: attributes.string("asset")?,
+                shortfall: attributes.amount("shortfall")?,
+                socialized_loss_index: attributes.decimal("socialized_loss_index")?,
+                socialized_loss_epoch: attributes.id("socialized_loss_epoch")?,
+            },
+            "protocol_fee" => ContractEvent::ProtocolFee {
+                kind: attributes.string("kind")?,
+                asset: attributes.string("asset")?,
+                amount: attributes.amount("amount")?,
+            },
+            "order_commit" => ContractEvent::OrderCommit {
+                user: attributes.string("user")?,
+                commitment_id: attributes.id("commitment_id")?,
+                asset: attributes.string("asset")?,
+                amount: attributes.amount("amount")?,
+                balances: attributes.balances()?,
+            },
+            "order_refund" => ContractEvent::OrderRefund {
+                user: attributes.string("user")?,
+                commitment_id: attributes.id("commitment_id")?,
+                kind: attributes.string("kind")?,
+                asset: attributes.string("asset")?,
+                amount: attributes.amount("amount")?,
+                balances: attributes.balances()?,
+            },
+            "keeper_bounty" => ContractEvent::KeeperBounty {
+                user: attributes.string("user")?,
+                keeper: attributes.string("keeper")?,
+                asset: attributes.string("asset")?,
+                amount: attributes.amount("amount")?,
+                keeper_collateral: attributes.amount("keeper_collateral_balance")?,
+                keeper_unminted: attributes.amount("keeper_unminted_balance")?,
+                balances: attributes.balances()?,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_event(ty: &str, attributes: &[(&str, &str)]) -> RawEvent {
+        RawEvent {
+            ty: ty.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parses_only_events_of_the_contract() {
+        let attributes = [
+            ("_contract_address", "leverage"),
+            ("user", "user_one"),
+            ("asset", "usdc"),
+            ("amount", "500"),
+            ("collateral_balance", "100"),
+            ("unminted_balance", "1000"),
+            ("borrow_balance", "0"),
+            ("profit_balance", "0"),
+            ("position_balance", "0"),
+        ];
+
+        let event = ContractEvent::parse(&raw_event("wasm-repay", &attributes), "leverage")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event,
+            ContractEvent::Repay {
+                user: "user_one".to_string(),
+                asset: "usdc".to_string(),
+                amount: 500,
+                balances: Balances {
+                    collateral: 100,
+                    unminted: 1000,
+                    ..Balances::default()
+                },
+            }
+        );
+
+        assert_eq!(
+            ContractEvent::parse(&raw_event("wasm-repay", &attributes), "other").unwrap(),
+            None
+        );
+        assert_eq!(
+            ContractEvent::parse(&raw_event("wasm", &attributes), "leverage").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_the_socialized_loss_index_into_atomics() {
+        let attributes = |index: &str| {
+            raw_event(
+                "wasm-bad_debt",
+                &[
+                    ("_contract_address", "leverage"),
+                    ("asset", "usdc"),
+                    ("shortfall", "850"),
+                    ("socialized_loss_index", index),
+                    ("socialized_loss_epoch", "0"),
+                ],
+            )
+        };
+
+        let event = ContractEvent::parse(&attributes("0.227272727272727272"), "leverage")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event,
+            ContractEvent::BadDebt {
+                asset: "usdc".to_string(),
+                shortfall: 850,
+                socialized_loss_index: 227_272_727_272_727_272,
+                socialized_loss_epoch: 0,
+            }
+        );
+        let event = ContractEvent::parse(&attributes("1"), "leverage")
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            event,
+            ContractEvent::BadDebt {
+                socialized_loss_index: 1_000_000_000_000_000_000,
+                ..
+            }
+        ));
+        assert!(ContractEvent::parse(&attributes("0.5.1"), "leverage").is_err());
+    }
+
+    #[test]
+    fn rejects_events_missing_schema_keys() {
+        let event = raw_event(
+            "wasm-borrow",
+            &[
+                ("_contract_address", "leverage"),
+                ("user", "user_one"),
+                ("asset", "usdc"),
+                ("amount", "ten"),
+            ],
+        );
+        assert!(matches!(
+            ContractEvent::parse(&event, "leverage"),
+            Err(IndexerError::InvalidEvent { .. })
+        ));
+    }
+}
@@ -0,0 +1,76 @@
+//! Off-chain indexer for the leverage contract.
+//!
+//! Replays the contract's typed events (see `events.rs` in the contract) from a node's
+//! `block_results` or from recorded block dumps and rebuilds every account ledger into
+//! SQLite, together with the history of each position, realized PnL flows and
+//! liquidations.
+
+pub mod block;
+mod error;
+pub mod event;
+pub mod store;
+
+pub use crate::error::IndexerError;
+
+use crate::block::{Block, BlockSource};
+use crate::event::ContractEvent;
+use crate::store::Store;
+
+pub struct Indexer {
+    store: Store,
+    contract: String,
+}
+
+impl Indexer {
+    pub fn new(store: Store, contract: impl Into<String>) -> Self {
+        Indexer {
+            store,
+            contract: contract.into(),
+        }
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /**
+     * @dev Indexes the contract events of one block in a single SQLite transaction.
+     *
+     * Failed transactions and events of other contracts are skipped. Blocks at or below
+     * the last indexed height are ignored, so replaying a dump twice is harmless.
+     *
+     * @return The number of contract events applied.
+     */
+    pub fn index_block(&mut self, block: &Block) -> Result<usize, IndexerError> {
+        if let Some(last_height) = self.store.last_height()? {
+            if block.height <= last_height {
+                return Ok(0);
+            }
+        }
+
+        let mut events = vec![];
+        for (tx_index, tx) in block.txs.iter().enumerate() {
+            if tx.code != 0 {
+                continue;
+            }
+            for (event_index, raw_event) in tx.events.iter().enumerate() {
+                if let Some(event) = ContractEvent::parse(raw_event, &self.contract)? {
+                    events.push((tx_index, event_index, event));
+                }
+            }
+        }
+
+        self.store.apply_block(block.height, &events)?;
+        Ok(events.len())
+    }
+
+    /// Indexes every block `source` yields and returns the number of blocks read
+    pub fn run<S: BlockSource>(&mut self, source: &mut S) -> Result<u64, IndexerError> {
+        let mut blocks = 0;
+        while let Some(block) = source.next_block()? {
+            self.index_block(&block)?;
+            blocks += 1;
+        }
+        Ok(blocks)
+    }
+}
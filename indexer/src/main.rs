@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::process::exit;
+
+use leverage_indexer::block::{DumpSource, RpcSource};
+use leverage_indexer::store::Store;
+use leverage_indexer::{Indexer, IndexerError};
+
+const USAGE: &str = "usage: leverage-indexer --db <path> --contract <address> \
+    (--dump <blocks.jsonl> | --node <rpc url> [--from <height>])";
+
+struct Args {
+    db: String,
+    contract: String,
+    dump: Option<String>,
+    node: Option<String>,
+    from: Option<u64>,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut db = None;
+    let mut contract = None;
+    let mut dump = None;
+    let mut node = None;
+    let mut from = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next()?;
+        match flag.as_str() {
+            "--db" => db = Some(value),
+            "--contract" => contract = Some(value),
+            "--dump" => dump = Some(value),
+            "--node" => node = Some(value),
+            "--from" => from = Some(value.parse().ok()?),
+            _ => return None,
+        }
+    }
+    if dump.is_some() == node.is_some() {
+        return None;
+    }
+
+    Some(Args {
+        db: db?,
+        contract: contract?,
+        dump,
+        node,
+        from,
+    })
+}
+
+fn run(args: Args) -> Result<u64, IndexerError> {
+    let mut indexer = Indexer::new(Store::open(&args.db)?, args.contract);
+
+    if let Some(dump) = args.dump {
+        return indexer.run(&mut DumpSource::new(BufReader::new(File::open(dump)?)));
+    }
+
+    // Resume after the last indexed block unless told otherwise
+    let from = match args.from {
+        Some(from) => from,
+        None => indexer
+            .store()
+            .last_height()?
+            .map_or(1, |height| height + 1),
+    };
+    let node = args.node.unwrap_or_default();
+    indexer.run(&mut RpcSource::new(node, from)?)
+}
+
+fn main() {
+    let args = match parse_args() {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    match run(args) {
+        Ok(blocks) => println!("indexed {} blocks", blocks),
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1);
+        }
+    }
+}
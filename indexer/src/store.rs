@@ -0,0 +1,726 @@
+use std::path::Path;
+
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::event::{Balances, ContractEvent, Liquidation};
+use crate::IndexerError;
+
+/// Amounts are `Uint128` on chain, they are stored as TEXT since SQLite integers are i64
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS cursor (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        height INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS ledgers (
+        user TEXT NOT NULL,
+        asset TEXT NOT NULL,
+        height INTEGER NOT NULL,
+        action TEXT NOT NULL,
+        collateral TEXT NOT NULL,
+        unminted TEXT NOT NULL,
+        borrowed TEXT NOT NULL,
+        profit TEXT NOT NULL,
+        position TEXT NOT NULL,
+        health_factor TEXT,
+        PRIMARY KEY (user, asset)
+    );
+    CREATE TABLE IF NOT EXISTS ledger_history (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        user TEXT NOT NULL,
+        asset TEXT NOT NULL,
+        height INTEGER NOT NULL,
+        action TEXT NOT NULL,
+        collateral TEXT NOT NULL,
+        unminted TEXT NOT NULL,
+        borrowed TEXT NOT NULL,
+        profit TEXT NOT NULL,
+        position TEXT NOT NULL,
+        health_factor TEXT,
+        tx_index INTEGER NOT NULL,
+        event_index INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS ledger_history_account
+        ON ledger_history (user, asset, height);
+    CREATE TABLE IF NOT EXISTS flows (
+        user TEXT NOT NULL,
+        asset TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        height INTEGER NOT NULL,
+        tx_index INTEGER NOT NULL,
+        event_index INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS flows_account ON flows (user, asset);
+    CREATE TABLE IF NOT EXISTS liquidations (
+        user TEXT NOT NULL,
+        liquidator TEXT NOT NULL,
+        debt_token TEXT NOT NULL,
+        debt TEXT NOT NULL,
+        seized_positions TEXT NOT NULL,
+        seized_collateral TEXT NOT NULL,
+        penalty TEXT NOT NULL,
+        liquidator_reward TEXT NOT NULL,
+        protocol_fee TEXT NOT NULL,
+        insurance_contribution TEXT NOT NULL,
+        returned_collateral TEXT NOT NULL,
+        shortfall TEXT NOT NULL,
+        height INTEGER NOT NULL,
+        tx_index INTEGER NOT NULL,
+        event_index INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS loss_indices (
+        asset TEXT PRIMARY KEY,
+        epoch INTEGER NOT NULL,
+        loss_index TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS protocol_fees (
+        kind TEXT NOT NULL,
+        asset TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        height INTEGER NOT NULL,
+        tx_index INTEGER NOT NULL,
+        event_index INTEGER NOT NULL
+    );
+";
+
+const LEDGER_COLUMNS: &str =
+    "user, asset, height, action, collateral, unminted, borrowed, profit, position, health_factor";
+
+const LIQUIDATION_COLUMNS: &str = "user, liquidator, debt_token, debt, seized_positions, \
+    seized_collateral, penalty, liquidator_reward, protocol_fee, insurance_contribution, \
+    returned_collateral, shortfall, height";
+
+/// Ledgers of an account in one asset after the last event that touched them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ledger {
+    pub user: String,
+    pub asset: String,
+    pub height: u64,
+    /// Event type that produced this state
+    pub action: String,
+    /// The health factor is the one reported by the last event carrying balances
+    pub balances: Balances,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedLiquidation {
+    pub height: u64,
+    pub liquidation: Liquidation,
+}
+
+/**
+ * @dev Realized PnL of an account in one collateral asset.
+ *
+ * Collateral moved into the account (deposits, transfers in) is the cost basis, what
+ * left it (withdrawals net of their fee, transfers out) plus what is still held is the
+ * value. Profit burned into collateral and liquidator rewards count as gains, fees,
+ * seized collateral and socialized losses as losses. Open positions and vToken profit
+ * that is not burned yet are unrealized and not included.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pnl {
+    pub deposited: u128,
+    pub withdrawn: u128,
+    pub collateral: u128,
+    /// Realized vToken profit not converted into collateral yet
+    pub unburned_profit: u128,
+    pub realized: i128,
+}
+
+/// Position of an event in the chain
+struct At {
+    height: u64,
+    tx_index: usize,
+    event_index: usize,
+}
+
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, IndexerError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self, IndexerError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, IndexerError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Store { conn })
+    }
+
+    /// Height of the last indexed block
+    pub fn last_height(&self) -> Result<Option<u64>, IndexerError> {
+        let height: Option<i64> = self
+            .conn
+            .query_row("SELECT height FROM cursor WHERE id = 0", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(height.map(|height| height as u64))
+    }
+
+    /// Applies the contract events of a block, atomically with the cursor update
+    pub fn apply_block(
+        &mut self,
+        height: u64,
+        events: &[(usize, usize, ContractEvent)],
+    ) -> Result<(), IndexerError> {
+        let tx = self.conn.transaction()?;
+        for (tx_index, event_index, event) in events {
+            let at = At {
+                height,
+                tx_index: *tx_index,
+                event_index: *event_index,
+            };
+            apply_event(&tx, &at, event)?;
+        }
+        tx.execute(
+            "INSERT INTO cursor (id, height) VALUES (0, ?1)
+             ON CONFLICT (id) DO UPDATE SET height = excluded.height",
+            params![height as i64],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn ledger(&self, user: &str, asset: &str) -> Result<Option<Ledger>, IndexerError> {
+        load_ledger(&self.conn, user, asset)
+    }
+
+    /// Current ledgers of `user` in every asset it touched
+    pub fn ledgers(&self, user: &str) -> Result<Vec<Ledger>, IndexerError> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM ledgers WHERE user = ?1 ORDER BY asset",
+            LEDGER_COLUMNS
+        ))?;
+        let ledgers = statement
+            .query_map(params![user], ledger_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(ledgers)
+    }
+
+    /// Ledgers of `user` as they were at the end of block `height`
+    pub fn ledgers_at(&self, user: &str, height: u64) -> Result<Vec<Ledger>, IndexerError> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM ledger_history
+             WHERE seq IN (
+                 SELECT MAX(seq) FROM ledger_history
+                 WHERE user = ?1 AND height <= ?2
+                 GROUP BY asset
+             )
+             ORDER BY asset",
+            LEDGER_COLUMNS
+        ))?;
+        let ledgers = statement
+            .query_map(params![user, height as i64], ledger_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(ledgers)
+    }
+
+    /// Every state the ledgers of `user` in `asset` went through, oldest first
+    pub fn position_history(&self, user: &str, asset: &str) -> Result<Vec<Ledger>, IndexerError> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM ledger_history WHERE user = ?1 AND asset = ?2 ORDER BY seq",
+            LEDGER_COLUMNS
+        ))?;
+        let ledgers = statement
+            .query_map(params![user, asset], ledger_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(ledgers)
+    }
+
+    pub fn pnl(&self, user: &str, asset: &str) -> Result<Pnl, IndexerError> {
+        let mut pnl = Pnl::default();
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT kind, amount FROM flows WHERE user = ?1 AND asset = ?2")?;
+        let mut rows = statement.query(params![user, asset])?;
+        while let Some(row) = rows.next()? {
+            let kind: String = row.get(0)?;
+            let amount = amount_column(row, 1)?;
+            match kind.as_str() {
+                "deposit" | "transfer_in" => pnl.deposited += amount,
+                _ => pnl.withdrawn += amount,
+            }
+        }
+
+        if let Some(ledger) = self.ledger(user, asset)? {
+            pnl.collateral = ledger.balances.collateral;
+            pnl.unburned_profit = ledger.balances.profit;
+        }
+        pnl.realized = (pnl.collateral + pnl.withdrawn) as i128 - pnl.deposited as i128;
+        Ok(pnl)
+    }
+
+    /// Liquidations of `user`, or of every account when `None`, oldest first
+    pub fn liquidations(
+        &self,
+        user: Option<&str>,
+    ) -> Result<Vec<IndexedLiquidation>, IndexerError> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM liquidations WHERE ?1 IS NULL OR user = ?1
+             ORDER BY height, tx_index, event_index",
+            LIQUIDATION_COLUMNS
+        ))?;
+        let liquidations = statement
+            .query_map(params![user], |row| {
+                Ok(IndexedLiquidation {
+                    liquidation: Liquidation {
+                        user: row.get(0)?,
+                        liquidator: row.get(1)?,
+                        debt_token: row.get(2)?,
+                        debt: amount_column(row, 3)?,
+                        seized_positions: amount_column(row, 4)?,
+                        seized_collateral: amount_column(row, 5)?,
+                        penalty: amount_column(row, 6)?,
+                        liquidator_reward: amount_column(row, 7)?,
+                        protocol_fee: amount_column(row, 8)?,
+                        insurance_contribution: amount_column(row, 9)?,
+                        returned_collateral: amount_column(row, 10)?,
+                        shortfall: amount_column(row, 11)?,
+                    },
+                    height: row.get::<_, i64>(12)? as u64,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(liquidations)
+    }
+}
+
+fn apply_event(conn: &Connection, at: &At, event: &ContractEvent) -> Result<(), IndexerError> {
+    match event {
+        ContractEvent::Deposit {
+            user,
+            asset,
+            amount,
+            balances,
+        } => {
+            snapshot(conn, at, user, asset, "deposit", balances)?;
+            record_flow(conn, at, user, asset, "deposit", *amount)?;
+        }
+        ContractEvent::Withdraw {
+            user,
+            asset,
+            amount,
+            fee,
+            balances,
+        } => {
+            snapshot(conn, at, user, asset, "withdraw", balances)?;
+            record_flow(
+                conn,
+                at,
+                user,
+                asset,
+                "withdraw",
+                amount.saturating_sub(*fee),
+            )?;
+        }
+        ContractEvent::Borrow {
+            user,
+            asset,
+            balances,
+            ..
+        } => snapshot(conn, at, user, asset, "borrow", balances)?,
+        ContractEvent::Repay {
+            user,
+            asset,
+            balances,
+            ..
+        } => snapshot(conn, at, user, asset, "repay", balances)?,
+        ContractEvent::Burn {
+            user,
+            asset,
+            balances,
+            ..
+        } => snapshot(conn, at, user, asset, "burn", balances)?,
+        ContractEvent::OrderSettled {
+            user,
+            token_in,
+            token_out,
+            amount_in,
+            balances,
+            ..
+        } => {
+            // Balances are reported in `token_out`, the sold position is debited here
+            update(conn, at, user, token_in, "order_settled", |balances| {
+                balances.position = balances.position.saturating_sub(*amount_in);
+            })?;
+            snapshot(conn, at, user, token_out, "order_settled", balances)?;
+        }
+        ContractEvent::CollateralTransfer {
+            asset,
+            amount,
+            from,
+            to,
+        } => {
+            update(conn, at, from, asset, "collateral_transfer", |balances| {
+                balances.collateral = balances.collateral.saturating_sub(*amount);
+                balances.unminted = balances.unminted.saturating_sub(amount * 10);
+            })?;
+            update(conn, at, to, asset, "collateral_transfer", |balances| {
+                balances.collateral += amount;
+                balances.unminted += amount * 10;
+            })?;
+            record_flow(conn, at, from, asset, "transfer_out", *amount)?;
+            record_flow(conn, at, to, asset, "transfer_in", *amount)?;
+        }
+        ContractEvent::Liquidation(liquidation) => apply_liquidation(conn, at, liquidation)?,
+        ContractEvent::BadDebt {
+            asset,
+            socialized_loss_index,
+            socialized_loss_epoch,
+            ..
+        } => apply_socialized_loss(
+            conn,
+            at,
+            asset,
+            *socialized_loss_epoch,
+            *socialized_loss_index,
+        )?,
+        ContractEvent::ProtocolFee {
+            kind,
+            asset,
+            amount,
+        } => {
+            conn.execute(
+                "INSERT INTO protocol_fees (kind, asset, amount, height, tx_index, event_index)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    kind,
+                    asset,
+                    amount.to_string(),
+                    at.height as i64,
+                    at.tx_index as i64,
+                    at.event_index as i64
+                ],
+            )?;
+        }
+        ContractEvent::OrderCommit {
+            user,
+            asset,
+            balances,
+            ..
+        } => snapshot(conn, at, user, asset, "order_commit", balances)?,
+        // Closes every batch order, so it also undoes the `token_in` debit `order_settled`
+        // applies to batch fills, whose margin already left the position at commit
+        ContractEvent::OrderRefund {
+            user,
+            asset,
+            balances,
+            ..
+        } => snapshot(conn, at, user, asset, "order_refund", balances)?,
+        ContractEvent::KeeperBounty {
+            user,
+            keeper,
+            asset,
+            keeper_collateral,
+            keeper_unminted,
+            balances,
+            ..
+        } => {
+            snapshot(conn, at, user, asset, "keeper_bounty", balances)?;
+            update(conn, at, keeper, asset, "keeper_bounty", |balances| {
+                balances.collateral = *keeper_collateral;
+                balances.unminted = *keeper_unminted;
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * @dev Mirrors the socialized loss of a `bad_debt` on every ledger holding collateral in
+ * `asset`.
+ *
+ * The contract only lowers the asset's loss index and haircuts each holder when it next
+ * touches the account, the indexer applies the haircut right away: collateral shrinks by
+ * the ratio of the new index to the previous one, all of it when the epoch moved on, and
+ * unminted vTokens by the same amount in vTokens. An account haircut by several losses
+ * between two of its own events may be off by rounding dust until its next event.
+ */
+fn apply_socialized_loss(
+    conn: &Connection,
+    at: &At,
+    asset: &str,
+    epoch: u64,
+    loss_index: u128,
+) -> Result<(), IndexerError> {
+    let previous: Option<(i64, String)> = conn
+        .query_row(
+            "SELECT epoch, loss_index FROM loss_indices WHERE asset = ?1",
+            params![asset],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let (previous_epoch, previous_index) = match previous {
+        Some((epoch, index)) => (
+            epoch as u64,
+            index
+                .parse::<u128>()
+                .map_err(|_| IndexerError::InvalidEvent {
+                    event_type: "bad_debt".to_string(),
+                    reason: format!("stored loss index {}", index),
+                })?,
+        ),
+        None => (0, 10u128.pow(18)),
+    };
+
+    let mut statement =
+        conn.prepare("SELECT user FROM ledgers WHERE asset = ?1 AND collateral != '0'")?;
+    let holders: Vec<String> = statement
+        .query_map(params![asset], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for holder in holders.iter() {
+        update(conn, at, holder, asset, "socialized_loss", |balances| {
+            let left = if epoch > previous_epoch {
+                0
+            } else {
+                mul_div_floor(balances.collateral, loss_index, previous_index)
+            };
+            let loss = balances.collateral - left.min(balances.collateral);
+            balances.collateral -= loss;
+            balances.unminted = balances.unminted.saturating_sub(loss.saturating_mul(10));
+        })?;
+    }
+
+    conn.execute(
+        "INSERT OR REPLACE INTO loss_indices (asset, epoch, loss_index) VALUES (?1, ?2, ?3)",
+        params![asset, epoch as i64, loss_index.to_string()],
+    )?;
+    Ok(())
+}
+
+/// `value * numerator / denominator` rounded down, without overflowing when
+/// `numerator <= denominator`
+fn mul_div_floor(value: u128, numerator: u128, denominator: u128) -> u128 {
+    if denominator == 0 {
+        return value;
+    }
+    let whole = value / denominator;
+    let remainder = value % denominator;
+    whole * numerator + remainder * numerator / denominator
+}
+
+/// Mirrors `liquidate`: every position is closed, the debt token ledgers are reset to the
+/// returned collateral and the liquidator is credited its reward as collateral
+fn apply_liquidation(
+    conn: &Connection,
+    at: &At,
+    liquidation: &Liquidation,
+) -> Result<(), IndexerError> {
+    let mut statement = conn.prepare("SELECT asset FROM ledgers WHERE user = ?1")?;
+    let mut assets: Vec<String> = statement
+        .query_map(params![liquidation.user], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    if !assets.contains(&liquidation.debt_token) {
+        assets.push(liquidation.debt_token.clone());
+    }
+
+    for asset in assets.iter() {
+        update(
+            conn,
+            at,
+            &liquidation.user,
+            asset,
+            "liquidation",
+            |balances| {
+                balances.position = 0;
+                balances.health_factor = None;
+                if *asset == liquidation.debt_token {
+                    balances.borrowed = 0;
+                    balances.collateral = liquidation.returned_collateral;
+                    balances.unminted = liquidation.returned_collateral * 10;
+                }
+            },
+        )?;
+    }
+    if liquidation.liquidator_reward != 0 {
+        update(
+            conn,
+            at,
+            &liquidation.liquidator,
+            &liquidation.debt_token,
+            "liquidation_reward",
+            |balances| {
+                balances.collateral += liquidation.liquidator_reward;
+                balances.unminted += liquidation.liquidator_reward * 10;
+            },
+        )?;
+    }
+
+    conn.execute(
+        &format!(
+            "INSERT INTO liquidations ({}, tx_index, event_index)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            LIQUIDATION_COLUMNS
+        ),
+        params![
+            liquidation.user,
+            liquidation.liquidator,
+            liquidation.debt_token,
+            liquidation.debt.to_string(),
+            liquidation.seized_positions.to_string(),
+            liquidation.seized_collateral.to_string(),
+            liquidation.penalty.to_string(),
+            liquidation.liquidator_reward.to_string(),
+            liquidation.protocol_fee.to_string(),
+            liquidation.insurance_contribution.to_string(),
+            liquidation.returned_collateral.to_string(),
+            liquidation.shortfall.to_string(),
+            at.height as i64,
+            at.tx_index as i64,
+            at.event_index as i64
+        ],
+    )?;
+    Ok(())
+}
+
+fn snapshot(
+    conn: &Connection,
+    at: &At,
+    user: &str,
+    asset: &str,
+    action: &str,
+    balances: &Balances,
+) -> Result<(), IndexerError> {
+    save_ledger(
+        conn,
+        at,
+        &Ledger {
+            user: user.to_string(),
+            asset: asset.to_string(),
+            height: at.height,
+            action: action.to_string(),
+            balances: balances.clone(),
+        },
+    )
+}
+
+/// Applies a change the event does not report balances for to the stored ledgers
+fn update(
+    conn: &Connection,
+    at: &At,
+    user: &str,
+    asset: &str,
+    action: &str,
+    change: impl FnOnce(&mut Balances),
+) -> Result<(), IndexerError> {
+    let mut balances = match load_ledger(conn, user, asset)? {
+        Some(ledger) => ledger.balances,
+        None => Balances::default(),
+    };
+    change(&mut balances);
+    snapshot(conn, at, user, asset, action, &balances)
+}
+
+fn record_flow(
+    conn: &Connection,
+    at: &At,
+    user: &str,
+    asset: &str,
+    kind: &str,
+    amount: u128,
+) -> Result<(), IndexerError> {
+    conn.execute(
+        "INSERT INTO flows (user, asset, kind, amount, height, tx_index, event_index)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            user,
+            asset,
+            kind,
+            amount.to_string(),
+            at.height as i64,
+            at.tx_index as i64,
+            at.event_index as i64
+        ],
+    )?;
+    Ok(())
+}
+
+fn load_ledger(conn: &Connection, user: &str, asset: &str) -> Result<Option<Ledger>, IndexerError> {
+    let ledger = conn
+        .query_row(
+            &format!(
+                "SELECT {} FROM ledgers WHERE user = ?1 AND asset = ?2",
+                LEDGER_COLUMNS
+            ),
+            params![user, asset],
+            ledger_from_row,
+        )
+        .optional()?;
+    Ok(ledger)
+}
+
+fn save_ledger(conn: &Connection, at: &At, ledger: &Ledger) -> Result<(), IndexerError> {
+    let balances = &ledger.balances;
+    conn.execute(
+        &format!(
+            "INSERT OR REPLACE INTO ledgers ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            LEDGER_COLUMNS
+        ),
+        params![
+            ledger.user,
+            ledger.asset,
+            ledger.height as i64,
+            ledger.action,
+            balances.collateral.to_string(),
+            balances.unminted.to_string(),
+            balances.borrowed.to_string(),
+            balances.profit.to_string(),
+            balances.position.to_string(),
+            balances.health_factor
+        ],
+    )?;
+    conn.execute(
+        &format!(
+            "INSERT INTO ledger_history ({}, tx_index, event_index)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            LEDGER_COLUMNS
+        ),
+        params![
+            ledger.user,
+            ledger.asset,
+            ledger.height as i64,
+            ledger.action,
+            balances.collateral.to_string(),
+            balances.unminted.to_string(),
+            balances.borrowed.to_string(),
+            balances.profit.to_string(),
+            balances.position.to_string(),
+            balances.health_factor,
+            at.tx_index as i64,
+            at.event_index as i64
+        ],
+    )?;
+    Ok(())
+}
+
+fn ledger_from_row(row: &Row) -> rusqlite::Result<Ledger> {
+    Ok(Ledger {
+        user: row.get(0)?,
+        asset: row.get(1)?,
+        height: row.get::<_, i64>(2)? as u64,
+        action: row.get(3)?,
+        balances: Balances {
+            collateral: amount_column(row, 4)?,
+            unminted: amount_column(row, 5)?,
+            borrowed: amount_column(row, 6)?,
+            profit: amount_column(row, 7)?,
+            position: amount_column(row, 8)?,
+            health_factor: row.get(9)?,
+        },
+    })
+}
+
+fn amount_column(row: &Row, index: usize) -> rusqlite::Result<u128> {
+    let value: String = row.get(index)?;
+    value.parse::<u128>().map_err(|error| {
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(error))
+    })
+}
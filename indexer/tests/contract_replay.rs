@@ -0,0 +1,354 @@
+use cosmwasm_std::{to_json_binary, to_json_vec, Addr, Binary, Uint128};
+use cw_multi_test::{App, ContractWrapper, Executor};
+use leverage_contract::contract::{execute, instantiate, query, reply};
+use leverage_contract::msg::{
+    ConditionalOrderData, Cw20ReceiveMsg, ExecuteMsg, InstantiateMsg, OrderExecute, QueryMsg,
+    QueryTokenData, RevealedOrderData, TokenData,
+};
+use leverage_contract::state::ConditionalOrderKind;
+use leverage_indexer::block::{Block, RawEvent, TxResult};
+use leverage_indexer::event::Balances;
+use leverage_indexer::store::Store;
+use leverage_indexer::Indexer;
+use sha2::{Digest, Sha256};
+
+const USERS: [&str; 4] = ["user_one", "user_two", "user_three", "keeper"];
+const ASSETS: [&str; 2] = ["usdc_contract", "osmo"];
+
+/// Runs the contract in `cw-multi-test` and records every transaction's events as blocks
+struct Recorder {
+    app: App,
+    contract: Addr,
+    blocks: Vec<Block>,
+}
+
+impl Recorder {
+    fn new() -> Self {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(
+            ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+        ));
+        let contract = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: String::from("usdc_contract"),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        Recorder {
+            app,
+            contract,
+            blocks: vec![],
+        }
+    }
+
+    fn execute(&mut self, sender: &str, msg: &ExecuteMsg) {
+        let res = self
+            .app
+            .execute_contract(Addr::unchecked(sender), self.contract.clone(), msg, &[])
+            .unwrap();
+        let tx = TxResult {
+            code: 0,
+            events: res
+                .events
+                .into_iter()
+                .map(|event| RawEvent {
+                    ty: event.ty,
+                    attributes: event
+                        .attributes
+                        .into_iter()
+                        .map(|attribute| (attribute.key, attribute.value))
+                        .collect(),
+                })
+                .collect(),
+        };
+
+        let height = self.app.block_info().height;
+        match self.blocks.last_mut() {
+            Some(block) if block.height == height => block.txs.push(tx),
+            _ => self.blocks.push(Block {
+                height,
+                txs: vec![tx],
+            }),
+        }
+    }
+
+    fn next_block(&mut self) {
+        self.app.update_block(|block| {
+            block.height += 1;
+            block.time = block.time.plus_seconds(5);
+        });
+    }
+
+    fn set_price(&mut self, asset: &str, price: &str) {
+        self.execute(
+            "creator",
+            &ExecuteMsg::UpdateAssetPrice {
+                asset: Addr::unchecked(asset),
+                price: price.parse().unwrap(),
+            },
+        );
+    }
+
+    fn deposit(&mut self, user: &str, amount: u128) {
+        self.execute(
+            "usdc_contract",
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: String::from(user),
+                amount: Uint128::from(amount),
+                msg: to_json_binary(&{}).unwrap(),
+            }),
+        );
+    }
+
+    fn commit(&mut self, user: &str, order: &RevealedOrderData, margin: u128) {
+        let mut hasher = Sha256::new();
+        hasher.update(user.as_bytes());
+        hasher.update(to_json_vec(order).unwrap());
+        self.execute(
+            user,
+            &ExecuteMsg::CommitOrder {
+                commitment: Binary::from(hasher.finalize().as_slice()),
+                margin_token: order.token_in.clone(),
+                margin_amount: Uint128::from(margin),
+                on_behalf_of: None,
+                sub_account: None,
+            },
+        );
+    }
+
+    fn query(&self, msg: fn(QueryTokenData) -> QueryMsg, user: &str, asset: &str) -> u128 {
+        let balance: Uint128 = self
+            .app
+            .wrap()
+            .query_wasm_smart(
+                self.contract.clone(),
+                &msg(QueryTokenData {
+                    token_address: Addr::unchecked(asset),
+                    user_address: Addr::unchecked(user),
+                }),
+            )
+            .unwrap();
+        balance.u128()
+    }
+
+    /// Ledgers as the contract reports them, the health factor is left out
+    fn ledgers(&self, user: &str, asset: &str) -> Balances {
+        Balances {
+            collateral: self.query(QueryMsg::UserCollateralTokenBalance, user, asset),
+            unminted: self.query(QueryMsg::UserWrappedTokenBalance, user, asset),
+            borrowed: self.query(QueryMsg::UserBorrowTokenBalance, user, asset),
+            profit: self.query(QueryMsg::UserVTokenBalance, user, asset),
+            position: self.query(QueryMsg::UserPositionBalance, user, asset),
+            health_factor: None,
+        }
+    }
+
+    /// Indexes the recorded blocks and checks every ledger against the contract queries
+    fn assert_indexed_ledgers_match(&self) -> Indexer {
+        let mut indexer = Indexer::new(Store::in_memory().unwrap(), self.contract.as_str());
+        for block in self.blocks.iter() {
+            indexer.index_block(block).unwrap();
+        }
+        for user in USERS {
+            for asset in ASSETS {
+                let indexed = indexer
+                    .store()
+                    .ledger(user, asset)
+                    .unwrap()
+                    .map(|ledger| Balances {
+                        health_factor: None,
+                        ..ledger.balances
+                    })
+                    .unwrap_or_default();
+                assert_eq!(indexed, self.ledgers(user, asset), "{} in {}", user, asset);
+            }
+        }
+        indexer
+    }
+}
+
+fn order(token_in: &str, token_out: &str, amount_in: u128, limit_price: &str) -> RevealedOrderData {
+    RevealedOrderData {
+        token_in: Addr::unchecked(token_in),
+        token_out: Addr::unchecked(token_out),
+        amount_in: Uint128::from(amount_in),
+        limit_price: limit_price.parse().unwrap(),
+        salt: String::from("salt"),
+    }
+}
+
+/// `user_one` and `user_two` lever 100 usdc each, `user_two` into 500 osmo bought at 2.
+/// `user_three` only lends.
+fn leveraged_market() -> Recorder {
+    let mut recorder = Recorder::new();
+    recorder.set_price("usdc_contract", "1");
+    recorder.set_price("osmo", "2");
+    for (user, amount) in [("user_one", 100), ("user_two", 100), ("user_three", 1000)] {
+        recorder.deposit(user, amount);
+    }
+    for user in ["user_one", "user_two"] {
+        recorder.execute(
+            user,
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::from(1000u128),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+        );
+    }
+    recorder.execute(
+        "creator",
+        &ExecuteMsg::ExecuteOrder(OrderExecute {
+            user_address: Addr::unchecked("user_two"),
+            token_in: Addr::unchecked("usdc_contract"),
+            token_out: Addr::unchecked("osmo"),
+            amount_in: Uint128::from(1000u128),
+            amount_out: Uint128::from(500u128),
+            encrypted_order: None,
+        }),
+    );
+    recorder.next_block();
+    recorder
+}
+
+#[test]
+fn rebuilds_order_escrow_and_keeper_bounties() {
+    let mut recorder = leveraged_market();
+
+    // An order escrowed over three blocks: commit, reveal with excess margin, settle with
+    // the unfilled part refunded. One commitment is never revealed and expires.
+    let buy = order("usdc_contract", "osmo", 400, "0.5");
+    let sell = order("osmo", "usdc_contract", 100, "1.8");
+    recorder.commit("user_one", &buy, 500);
+    recorder.commit("user_two", &sell, 150);
+    recorder.commit("user_one", &order("usdc_contract", "osmo", 50, "1"), 50);
+    recorder.next_block();
+    let batch_height = recorder.app.block_info().height;
+    for (user, commitment_id, order) in [("user_one", 1, &buy), ("user_two", 2, &sell)] {
+        recorder.execute(
+            user,
+            &ExecuteMsg::RevealOrder {
+                commitment_id,
+                order: order.clone(),
+                on_behalf_of: None,
+                sub_account: None,
+            },
+        );
+    }
+    recorder.next_block();
+    recorder.execute(
+        "keeper",
+        &ExecuteMsg::SettleOrderBatch {
+            height: batch_height,
+        },
+    );
+    recorder.assert_indexed_ledgers_match();
+    assert_eq!(recorder.ledgers("user_one", "osmo").position, 100);
+
+    recorder.app.update_block(|block| block.height += 100);
+    recorder.execute(
+        "keeper",
+        &ExecuteMsg::ExpireOrderCommitment { commitment_id: 3 },
+    );
+
+    // A stop loss pays its keeper out of the proceeds
+    recorder.execute(
+        "user_one",
+        &ExecuteMsg::PlaceConditionalOrder(ConditionalOrderData {
+            token_in: Addr::unchecked("osmo"),
+            token_out: Addr::unchecked("usdc_contract"),
+            amount_in: None,
+            kind: ConditionalOrderKind::StopLoss {
+                trigger_price: "1.5".parse().unwrap(),
+            },
+            bounty: Uint128::from(5u128),
+            on_behalf_of: None,
+            sub_account: None,
+        }),
+    );
+    recorder.next_block();
+    recorder.set_price("osmo", "1.4");
+    recorder.execute(
+        "keeper",
+        &ExecuteMsg::ExecuteConditionalOrder {
+            owner: Addr::unchecked("user_one"),
+            order_id: 1,
+        },
+    );
+
+    let indexer = recorder.assert_indexed_ledgers_match();
+    assert_eq!(recorder.ledgers("keeper", "usdc_contract").collateral, 5);
+    let history: Vec<String> = indexer
+        .store()
+        .position_history("user_one", "usdc_contract")
+        .unwrap()
+        .into_iter()
+        .map(|ledger| ledger.action)
+        .collect();
+    assert_eq!(
+        history,
+        vec![
+            "deposit",
+            "borrow",
+            "order_commit",
+            "order_commit",
+            "order_refund",
+            "order_settled",
+            "order_refund",
+            "order_refund",
+            "order_settled",
+            "keeper_bounty",
+        ]
+    );
+}
+
+#[test]
+fn rebuilds_socialized_losses() {
+    let mut recorder = leveraged_market();
+
+    // osmo crashes: user_two's 100 collateral + 500 osmo at 0.1 cover 150 of 1000 debt,
+    // the rest is socialized over the remaining usdc deposits
+    recorder.set_price("osmo", "0.1");
+    recorder.execute(
+        "keeper",
+        &ExecuteMsg::Liquidate {
+            user_address: Addr::unchecked("user_two"),
+            debt_token: Addr::unchecked("usdc_contract"),
+        },
+    );
+    let indexer = recorder.assert_indexed_ledgers_match();
+    let user_three = recorder.ledgers("user_three", "usdc_contract");
+    assert!(user_three.collateral < 1000);
+    assert_eq!(
+        indexer
+            .store()
+            .ledger("user_three", "usdc_contract")
+            .unwrap()
+            .unwrap()
+            .action,
+        "socialized_loss"
+    );
+
+    // The contract settles the haircut once the account is touched again
+    recorder.next_block();
+    recorder.deposit("user_three", 10);
+    recorder.execute(
+        "keeper",
+        &ExecuteMsg::Liquidate {
+            user_address: Addr::unchecked("user_one"),
+            debt_token: Addr::unchecked("usdc_contract"),
+        },
+    );
+    recorder.assert_indexed_ledgers_match();
+    assert_eq!(
+        recorder.ledgers("user_three", "usdc_contract").collateral,
+        user_three.collateral + 10
+    );
+}
@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use leverage_indexer::block::DumpSource;
+use leverage_indexer::event::Balances;
+use leverage_indexer::store::Store;
+use leverage_indexer::Indexer;
+
+const CONTRACT: &str = "leverage1contract";
+
+fn replay() -> Indexer {
+    let mut indexer = Indexer::new(Store::in_memory().unwrap(), CONTRACT);
+    let dump = File::open(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/blocks.jsonl"
+    ))
+    .unwrap();
+    let blocks = indexer
+        .run(&mut DumpSource::new(BufReader::new(dump)))
+        .unwrap();
+    assert_eq!(blocks, 7);
+    indexer
+}
+
+fn balances(ledgers: &[leverage_indexer::store::Ledger], asset: &str) -> Balances {
+    ledgers
+        .iter()
+        .find(|ledger| ledger.asset == asset)
+        .map(|ledger| ledger.balances.clone())
+        .unwrap_or_default()
+}
+
+#[test]
+fn rebuilds_ledgers_from_replayed_blocks() {
+    let indexer = replay();
+    let store = indexer.store();
+    assert_eq!(store.last_height().unwrap(), Some(16));
+
+    // The failed transaction and the other contract's deposit are ignored
+    let user_one = store.ledgers("user_one").unwrap();
+    assert_eq!(
+        balances(&user_one, "usdc"),
+        Balances {
+            position: 100,
+            ..Balances::default()
+        }
+    );
+    assert_eq!(balances(&user_one, "osmo").position, 0);
+
+    // Both legs of the trade at height 12 are visible historically
+    let at_twelve = store.ledgers_at("user_one", 12).unwrap();
+    assert_eq!(balances(&at_twelve, "usdc").position, 0);
+    assert_eq!(balances(&at_twelve, "osmo").position, 250);
+    assert_eq!(
+        balances(&at_twelve, "osmo").health_factor,
+        Some("1.1".to_string())
+    );
+
+    let history: Vec<String> = store
+        .position_history("user_one", "usdc")
+        .unwrap()
+        .into_iter()
+        .map(|ledger| ledger.action)
+        .collect();
+    assert_eq!(
+        history,
+        vec![
+            "deposit",
+            "borrow",
+            "order_settled",
+            "order_settled",
+            "repay",
+            "withdraw"
+        ]
+    );
+}
+
+#[test]
+fn exposes_liquidations_and_pnl() {
+    let indexer = replay();
+    let store = indexer.store();
+
+    let liquidations = store.liquidations(None).unwrap();
+    assert_eq!(liquidations.len(), 1);
+    assert_eq!(liquidations[0].height, 14);
+    assert_eq!(liquidations[0].liquidation.user, "user_two");
+    assert_eq!(liquidations[0].liquidation.penalty, 22);
+    assert!(store.liquidations(Some("user_one")).unwrap().is_empty());
+
+    let user_two = store.ledger("user_two", "usdc").unwrap().unwrap();
+    assert_eq!(user_two.action, "liquidation");
+    assert_eq!(
+        user_two.balances,
+        Balances {
+            collateral: 8,
+            unminted: 80,
+            ..Balances::default()
+        }
+    );
+    let keeper = store.ledger("keeper", "usdc").unwrap().unwrap();
+    assert_eq!(keeper.balances.collateral, 22);
+
+    assert_eq!(store.pnl("user_one", "usdc").unwrap().realized, -1);
+    assert_eq!(store.pnl("user_two", "usdc").unwrap().realized, -42);
+    assert_eq!(store.pnl("keeper", "usdc").unwrap().realized, 22);
+}
+
+#[test]
+fn replaying_twice_is_idempotent() {
+    let mut indexer = replay();
+    let dump = File::open(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/blocks.jsonl"
+    ))
+    .unwrap();
+    indexer
+        .run(&mut DumpSource::new(BufReader::new(dump)))
+        .unwrap();
+
+    let store = indexer.store();
+    assert_eq!(store.position_history("user_one", "usdc").unwrap().len(), 6);
+    assert_eq!(store.pnl("user_one", "usdc").unwrap().deposited, 100);
+}
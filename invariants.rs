@@ -0,0 +1,251 @@
+//! Property tests running random action sequences across several users through the
+//! scenario harness, checking the ledger invariants after every step:
+//!
+//! - the contract holds enough of each token to pay out every depositor's collateral,
+//!   net of socialized losses, plus the treasury and the insurance fund
+//! - unminted vToken is collateral × 10 less the debt, on the raw ledgers
+//! - no ledger or total overflows, and the borrow totals match the user ledgers
+//! - a liquidation succeeds exactly when the account is below the liquidation threshold,
+//!   and no account is left below it once the keepers have been through
+//!
+//! Trades are settled at the oracle price with whole osmo prices, so they never move an
+//! account's value.
+
+use cosmwasm_std::{from_json, Addr, Uint128};
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use serde::de::DeserializeOwned;
+
+use leverage_math::vtoken;
+
+use crate::health::is_liquidatable;
+use crate::msg::{AccountHealth, QueryMsg};
+use crate::scenario::{Harness, Step};
+use crate::state::{
+    RiskConfig, INSURANCE_FUND, TOTAL_BORROWS, TOTAL_DEPOSITS, TREASURY, USER_BORROW_BALANCE,
+    USER_TOKEN_BALANCE, USER_UNMINTED_TOKEN,
+};
+
+const USERS: [&str; 4] = ["user_one", "user_two", "user_three", "user_four"];
+
+/// Prices osmo moves between, usdc stays at 1
+const OSMO_PRICES: [&str; 5] = ["1", "2", "3", "4", "5"];
+
+/// Users are indices into `USERS`, usdc is the only borrowed token
+#[derive(Clone, Debug)]
+enum Action {
+    DepositUsdc(usize, u128),
+    DepositOsmo(usize, u128),
+    Borrow(usize, u128),
+    /// Buys this much osmo with the usdc position
+    BuyOsmo(usize, u128),
+    /// Sells this much of the osmo position for usdc
+    SellOsmo(usize, u128),
+    Repay(usize, u128),
+    WithdrawUsdc(usize, u128),
+    WithdrawOsmo(usize, u128),
+    /// Index into `OSMO_PRICES`
+    SetOsmoPrice(usize),
+    /// liquidator, user
+    Liquidate(usize, usize),
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    let user = 0..USERS.len();
+    prop_oneof![
+        (user.clone(), 1u128..=500).prop_map(|(user, amount)| Action::DepositUsdc(user, amount)),
+        (user.clone(), 1u128..=500).prop_map(|(user, amount)| Action::DepositOsmo(user, amount)),
+        (user.clone(), 1u128..=2_000).prop_map(|(user, amount)| Action::Borrow(user, amount)),
+        (user.clone(), 1u128..=400).prop_map(|(user, amount)| Action::BuyOsmo(user, amount)),
+        (user.clone(), 1u128..=400).prop_map(|(user, amount)| Action::SellOsmo(user, amount)),
+        (user.clone(), 1u128..=2_000).prop_map(|(user, amount)| Action::Repay(user, amount)),
+        (user.clone(), 1u128..=500).prop_map(|(user, amount)| Action::WithdrawUsdc(user, amount)),
+        (user.clone(), 1u128..=500).prop_map(|(user, amount)| Action::WithdrawOsmo(user, amount)),
+        (0..OSMO_PRICES.len()).prop_map(Action::SetOsmoPrice),
+        (user.clone(), user).prop_map(|(liquidator, user)| Action::Liquidate(liquidator, user)),
+    ]
+}
+
+fn step(action: &Action, osmo_price: u128) -> Step {
+    match *action {
+        Action::DepositUsdc(user, amount) => Step::DepositCw20(USERS[user], amount),
+        Action::DepositOsmo(user, amount) => {
+            Step::DepositNative(USERS[user], "osmo", amount, "uosmo")
+        }
+        Action::Borrow(user, amount) => Step::Borrow(USERS[user], "usdc", amount),
+        Action::BuyOsmo(user, amount) => Step::Trade {
+            user: USERS[user],
+            token_in: "usdc",
+            token_out: "osmo",
+            amount_in: amount * osmo_price,
+            amount_out: amount,
+        },
+        Action::SellOsmo(user, amount) => Step::Trade {
+            user: USERS[user],
+            token_in: "osmo",
+            token_out: "usdc",
+            amount_in: amount,
+            amount_out: amount * osmo_price,
+        },
+        Action::Repay(user, amount) => Step::Repay(USERS[user], "usdc", amount),
+        Action::WithdrawUsdc(user, amount) => {
+            Step::Withdraw(USERS[user], "usdc", amount, "fungible")
+        }
+        Action::WithdrawOsmo(user, amount) => Step::Withdraw(USERS[user], "osmo", amount, "native"),
+        Action::SetOsmoPrice(index) => Step::SetPrice("osmo", OSMO_PRICES[index]),
+        Action::Liquidate(liquidator, user) => {
+            Step::Liquidate(USERS[liquidator], USERS[user], "usdc")
+        }
+    }
+}
+
+/// Reads a value straight from the contract's storage, bypassing the queries
+fn raw<T: DeserializeOwned + Default>(harness: &Harness, key: &[u8]) -> T {
+    harness
+        .app
+        .wrap()
+        .query_wasm_raw(harness.contract.clone(), key.to_vec())
+        .unwrap()
+        .map(|value| from_json(value).unwrap())
+        .unwrap_or_default()
+}
+
+fn liquidatable(harness: &Harness, user: &str) -> bool {
+    let health: AccountHealth = harness
+        .app
+        .wrap()
+        .query_wasm_smart(
+            harness.contract.clone(),
+            &QueryMsg::AccountHealth {
+                user_address: Addr::unchecked(user),
+            },
+        )
+        .unwrap();
+    let risk_config: RiskConfig = harness
+        .app
+        .wrap()
+        .query_wasm_smart(harness.contract.clone(), &QueryMsg::RiskConfig {})
+        .unwrap();
+    is_liquidatable(health.health_factor, risk_config.liquidation_threshold)
+}
+
+fn check_ledgers(harness: &Harness) -> Result<(), TestCaseError> {
+    for alias in ["usdc", "osmo"] {
+        let token = harness.token(alias);
+        let mut claims = Uint128::zero();
+        let mut borrowed = Uint128::zero();
+
+        for user in USERS {
+            let account = Addr::unchecked(user);
+            let collateral: Uint128 = raw(harness, &USER_TOKEN_BALANCE.key((&token, &account)));
+            let unminted: Uint128 = raw(harness, &USER_UNMINTED_TOKEN.key((&token, &account)));
+            let debt: Uint128 = raw(harness, &USER_BORROW_BALANCE.key((&token, &account)));
+
+            // Socialized losses take the vTokens off unminted first, never into the debt
+            let v_tokens = vtoken::to_v_tokens(collateral.u128());
+            prop_assert!(v_tokens.is_some(), "vTokens of {} in {} overflow", user, alias);
+            prop_assert_eq!(
+                unminted.u128(),
+                v_tokens.unwrap().saturating_sub(debt.u128()),
+                "unminted of {} in {} with collateral {} and debt {}",
+                user,
+                alias,
+                collateral,
+                debt
+            );
+
+            let net_collateral = harness.ledger(QueryMsg::UserCollateralTokenBalance, user, alias);
+            let sum = claims.checked_add(Uint128::from(net_collateral));
+            prop_assert!(sum.is_ok(), "collateral of {} overflows", alias);
+            claims = sum.unwrap();
+            let sum = borrowed.checked_add(debt);
+            prop_assert!(sum.is_ok(), "debt of {} overflows", alias);
+            borrowed = sum.unwrap();
+        }
+
+        let total_borrows: Uint128 = raw(harness, &TOTAL_BORROWS.key(&token));
+        prop_assert_eq!(total_borrows, borrowed, "total borrows of {}", alias);
+
+        let treasury: Uint128 = raw(harness, &TREASURY.key(&token));
+        let insurance: Uint128 = raw(harness, &INSURANCE_FUND.key(&token));
+        let sum = claims.checked_add(treasury).and_then(|sum| sum.checked_add(insurance));
+        prop_assert!(sum.is_ok(), "claims on {} overflow", alias);
+        let claims = sum.unwrap();
+
+        let holdings = match alias {
+            "usdc" => harness.cw20_balance(harness.contract.as_str()),
+            _ => {
+                let balance = harness.app.wrap().query_balance(&harness.contract, "uosmo");
+                balance.unwrap().amount.u128()
+            }
+        };
+        prop_assert!(
+            holdings >= claims.u128(),
+            "contract holds {} {} against {} of claims",
+            holdings,
+            alias,
+            claims
+        );
+        let total_deposits: Uint128 = raw(harness, &TOTAL_DEPOSITS.key(&token));
+        prop_assert!(
+            holdings >= total_deposits.u128(),
+            "contract holds {} {} against {} of total deposits",
+            holdings,
+            alias,
+            total_deposits
+        );
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn random_actions_keep_ledgers_consistent(
+        actions in prop::collection::vec(action(), 1..40)
+    ) {
+        let mut harness = Harness::with_users(&USERS, 10_000);
+        harness.run(&Step::SetPrice("usdc", "1")).unwrap();
+        harness.run(&Step::SetPrice("osmo", "2")).unwrap();
+        let mut osmo_price = 2;
+
+        for action in actions.iter() {
+            let liquidatable_before = match *action {
+                Action::Liquidate(_, user) => liquidatable(&harness, USERS[user]),
+                _ => false,
+            };
+            let result = harness.run(&step(action, osmo_price));
+
+            match (action, &result) {
+                (Action::SetOsmoPrice(index), Ok(_)) => osmo_price = *index as u128 + 1,
+                (Action::Liquidate(_, user), _) => {
+                    prop_assert_eq!(
+                        result.is_ok(),
+                        liquidatable_before,
+                        "liquidating {} gave {:?}",
+                        USERS[*user],
+                        result.as_ref().err()
+                    );
+                    if result.is_ok() {
+                        let debt =
+                            harness.ledger(QueryMsg::UserBorrowTokenBalance, USERS[*user], "usdc");
+                        prop_assert_eq!(debt, 0, "debt of {} after liquidation", USERS[*user]);
+                    }
+                }
+                _ => {}
+            }
+            check_ledgers(&harness)?;
+        }
+
+        // Keepers liquidate whatever is left below the threshold
+        for user in USERS {
+            if liquidatable(&harness, user) {
+                let result = harness.run(&Step::Liquidate("user_one", user, "usdc"));
+                prop_assert!(result.is_ok(), "liquidating {} gave {:?}", user, result.err());
+                check_ledgers(&harness)?;
+            }
+            prop_assert!(!liquidatable(&harness, user), "{} is still liquidatable", user);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::process::Command;
+
+use cosmwasm_std::Addr;
+use leverage_contract::msg::{ExecuteMsg, QueryMsg};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::KeeperError;
+
+/// What the keeper needs from a chain: smart queries against the contract and
+/// submitting liquidations signed by the keeper's key
+pub trait Chain {
+    fn query<T: DeserializeOwned>(&self, msg: &QueryMsg) -> Result<T, KeeperError>;
+
+    /// Submits `Liquidate` and returns the transaction hash
+    fn liquidate(&mut self, user_address: &Addr, debt_token: &Addr) -> Result<String, KeeperError>;
+}
+
+/**
+ * @dev Talks to a node through its CLI binary (`osmosisd`, `wasmd`, ...), which does the
+ * signing with a key from its keyring.
+ */
+pub struct CliChain {
+    pub binary: String,
+    pub node: String,
+    pub chain_id: String,
+    pub contract: String,
+    /// Keyring name of the keeper's key
+    pub from: String,
+    pub gas_prices: String,
+}
+
+impl CliChain {
+    fn run(&self, args: &[&str]) -> Result<Value, String> {
+        let output = Command::new(&self.binary)
+            .args(args)
+            .args(["--node", &self.node, "--output", "json"])
+            .output()
+            .map_err(|error| error.to_string())?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        serde_json::from_slice(&output.stdout).map_err(|error| error.to_string())
+    }
+}
+
+impl Chain for CliChain {
+    fn query<T: DeserializeOwned>(&self, msg: &QueryMsg) -> Result<T, KeeperError> {
+        let msg = serde_json::to_string(msg)?;
+        let response = self
+            .run(&[
+                "query",
+                "wasm",
+                "contract-state",
+                "smart",
+                &self.contract,
+                &msg,
+            ])
+            .map_err(|error| KeeperError::Query { error })?;
+        Ok(serde_json::from_value(response["data"].clone())?)
+    }
+
+    fn liquidate(&mut self, user_address: &Addr, debt_token: &Addr) -> Result<String, KeeperError> {
+        let msg = serde_json::to_string(&ExecuteMsg::Liquidate {
+            user_address: user_address.clone(),
+            debt_token: debt_token.clone(),
+        })?;
+        let failed = |error: String| KeeperError::Liquidation {
+            user: user_address.to_string(),
+            error,
+        };
+
+        let response = self
+            .run(&[
+                "tx",
+                "wasm",
+                "execute",
+                &self.contract,
+                &msg,
+                "--from",
+                &self.from,
+                "--chain-id",
+                &self.chain_id,
+                "--gas",
+                "auto",
+                "--gas-adjustment",
+                "1.3",
+                "--gas-prices",
+                &self.gas_prices,
+                "--yes",
+            ])
+            .map_err(failed)?;
+
+        // CheckTx failures come back with a non-zero code and a zero exit status
+        if response["code"].as_u64().unwrap_or(0) != 0 {
+            return Err(failed(
+                response["raw_log"].as_str().unwrap_or_default().to_string(),
+            ));
+        }
+        match response["txhash"].as_str() {
+            Some(txhash) => Ok(txhash.to_string()),
+            None => Err(failed("response without txhash".to_string())),
+        }
+    }
+}
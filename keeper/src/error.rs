@@ -0,0 +1,22 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum KeeperError {
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Query failed: {error}")]
+    Query { error: String },
+
+    #[error("Liquidation of {user} failed: {error}")]
+    Liquidation { user: String, error: String },
+
+    #[error("Price not available for {asset}")]
+    PriceNotAvailable { asset: String },
+
+    #[error("Overflow")]
+    Overflow {},
+}
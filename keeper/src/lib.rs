@@ -0,0 +1,113 @@
+//! Liquidation keeper for the leverage contract.
+//!
+//! Every round the keeper pages through the contract's outstanding borrows, recomputes
+//! the health of each borrower with the contract's `health` module and liquidates the
+//! accounts below the liquidation threshold whose reward covers the configured costs.
+
+pub mod chain;
+mod error;
+pub mod scan;
+
+pub use crate::error::KeeperError;
+
+use cosmwasm_std::{Addr, Decimal};
+
+use crate::chain::Chain;
+use crate::scan::{borrowers, check_account, Candidate, Market};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeeperConfig {
+    /// Smallest reward worth liquidating for, after `gas_cost`, in the price unit
+    pub min_profit: Decimal,
+    /// Estimated cost of a liquidation transaction, in the price unit
+    pub gas_cost: Decimal,
+    /// Page size of the `Borrows` scan
+    pub page_limit: u32,
+}
+
+impl Default for KeeperConfig {
+    fn default() -> Self {
+        KeeperConfig {
+            min_profit: Decimal::zero(),
+            gas_cost: Decimal::zero(),
+            page_limit: 30,
+        }
+    }
+}
+
+impl KeeperConfig {
+    pub fn is_profitable(&self, candidate: &Candidate) -> bool {
+        candidate.reward_value > self.gas_cost
+            && candidate.reward_value - self.gas_cost >= self.min_profit
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Liquidatable accounts, most rewarding first
+    pub candidates: Vec<Candidate>,
+    /// Accounts that could not be checked, e.g. because a price is missing
+    pub skipped: Vec<(Addr, KeeperError)>,
+}
+
+#[derive(Debug)]
+pub struct Attempt {
+    pub candidate: Candidate,
+    /// Transaction hash, or why the liquidation failed
+    pub result: Result<String, KeeperError>,
+}
+
+#[derive(Debug, Default)]
+pub struct Round {
+    pub attempts: Vec<Attempt>,
+    /// Liquidatable accounts left alone because the reward does not cover the costs
+    pub unprofitable: Vec<Candidate>,
+    pub skipped: Vec<(Addr, KeeperError)>,
+}
+
+pub struct Keeper<C: Chain> {
+    pub chain: C,
+    pub config: KeeperConfig,
+}
+
+impl<C: Chain> Keeper<C> {
+    pub fn new(chain: C, config: KeeperConfig) -> Self {
+        Keeper { chain, config }
+    }
+
+    pub fn scan(&self) -> Result<Scan, KeeperError> {
+        let mut market = Market::load(&self.chain)?;
+        let mut scan = Scan::default();
+        for account in borrowers(&self.chain, self.config.page_limit)? {
+            match check_account(&self.chain, &mut market, &account) {
+                Ok(Some(candidate)) => scan.candidates.push(candidate),
+                Ok(None) => {}
+                Err(error) => scan.skipped.push((account, error)),
+            }
+        }
+        scan.candidates
+            .sort_by_key(|candidate| std::cmp::Reverse(candidate.reward_value));
+        Ok(scan)
+    }
+
+    /// Scans once and submits every profitable liquidation. A failed liquidation, e.g.
+    /// because another keeper got there first, does not stop the round.
+    pub fn run_once(&mut self) -> Result<Round, KeeperError> {
+        let scan = self.scan()?;
+        let mut round = Round {
+            skipped: scan.skipped,
+            ..Round::default()
+        };
+        for candidate in scan.candidates {
+            if !self.config.is_profitable(&candidate) {
+                round.unprofitable.push(candidate);
+                continue;
+            }
+            let result = self
+                .chain
+                .liquidate(&candidate.user_address, &candidate.debt_token);
+            round.attempts.push(Attempt { candidate, result });
+        }
+        Ok(round)
+    }
+}
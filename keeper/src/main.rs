@@ -0,0 +1,87 @@
+use std::process::exit;
+use std::thread::sleep;
+use std::time::Duration;
+
+use leverage_keeper::chain::CliChain;
+use leverage_keeper::{Keeper, KeeperConfig};
+
+const USAGE: &str = "usage: leverage-keeper --contract <address> --from <key> --chain-id <id> \
+    [--binary osmosisd] [--node http://localhost:26657] [--gas-prices 0.025uosmo] \
+    [--min-profit <value>] [--gas-cost <value>] [--interval <seconds>]";
+
+fn parse_args() -> Option<(Keeper<CliChain>, u64)> {
+    let mut chain = CliChain {
+        binary: "osmosisd".to_string(),
+        node: "http://localhost:26657".to_string(),
+        chain_id: String::new(),
+        contract: String::new(),
+        from: String::new(),
+        gas_prices: "0.025uosmo".to_string(),
+    };
+    let mut config = KeeperConfig::default();
+    let mut interval = 6;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next()?;
+        match flag.as_str() {
+            "--binary" => chain.binary = value,
+            "--node" => chain.node = value,
+            "--chain-id" => chain.chain_id = value,
+            "--contract" => chain.contract = value,
+            "--from" => chain.from = value,
+            "--gas-prices" => chain.gas_prices = value,
+            "--min-profit" => config.min_profit = value.parse().ok()?,
+            "--gas-cost" => config.gas_cost = value.parse().ok()?,
+            "--interval" => interval = value.parse().ok()?,
+            _ => return None,
+        }
+    }
+    if chain.contract.is_empty() || chain.from.is_empty() || chain.chain_id.is_empty() {
+        return None;
+    }
+
+    Some((Keeper::new(chain, config), interval))
+}
+
+fn main() {
+    let (mut keeper, interval) = match parse_args() {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    loop {
+        match keeper.run_once() {
+            Ok(round) => {
+                for attempt in round.attempts {
+                    let candidate = attempt.candidate;
+                    match attempt.result {
+                        Ok(txhash) => println!(
+                            "liquidated {} in {} (health {}, reward {}): {}",
+                            candidate.user_address,
+                            candidate.debt_token,
+                            candidate.health_factor,
+                            candidate.split.liquidator_reward,
+                            txhash
+                        ),
+                        Err(error) => eprintln!("{}", error),
+                    }
+                }
+                for candidate in round.unprofitable {
+                    println!(
+                        "skipped {}: reward {} below costs",
+                        candidate.user_address, candidate.reward_value
+                    );
+                }
+                for (account, error) in round.skipped {
+                    eprintln!("could not check {}: {}", account, error);
+                }
+            }
+            Err(error) => eprintln!("scan failed: {}", error),
+        }
+        sleep(Duration::from_secs(interval));
+    }
+}
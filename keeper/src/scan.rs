@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use leverage_contract::contract::execute::split_sub_account;
+use leverage_contract::health::{
+    asset_value, health_factor, is_liquidatable, liquidation_split, LiquidationSplit,
+};
+use leverage_contract::msg::{
+    AccountLedgersResponse, BorrowResponse, QueryMsg, SubAccountResponse,
+};
+use leverage_contract::state::{FeeConfig, InsuranceConfig, MarginMode, RiskConfig};
+
+use crate::chain::Chain;
+use crate::KeeperError;
+
+/// Liquidation of `user_address` in `debt_token` as the contract would execute it now
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub user_address: Addr,
+    pub debt_token: Addr,
+    /// Health of the margin the account is checked against
+    pub health_factor: Decimal,
+    pub split: LiquidationSplit,
+    /// Liquidator reward valued at the debt token price
+    pub reward_value: Decimal,
+}
+
+/// Risk parameters and prices of one scan, prices are queried once per asset
+pub struct Market {
+    pub risk_config: RiskConfig,
+    pub fee_config: FeeConfig,
+    pub insurance_config: InsuranceConfig,
+    prices: BTreeMap<Addr, Decimal>,
+    /// Prices health is checked at, the TWAP over the liquidation window if configured
+    liquidation_prices: BTreeMap<Addr, Decimal>,
+}
+
+impl Market {
+    pub fn load<C: Chain>(chain: &C) -> Result<Self, KeeperError> {
+        Ok(Market {
+            risk_config: chain.query(&QueryMsg::RiskConfig {})?,
+            fee_config: chain.query(&QueryMsg::FeeConfig {})?,
+            insurance_config: chain.query(&QueryMsg::InsuranceConfig {})?,
+            prices: BTreeMap::new(),
+            liquidation_prices: BTreeMap::new(),
+        })
+    }
+
+    /// Price positions are seized at
+    pub fn price<C: Chain>(&mut self, chain: &C, asset: &Addr) -> Result<Decimal, KeeperError> {
+        let query = QueryMsg::AssetPrice {
+            asset: asset.clone(),
+        };
+        cached_price(&mut self.prices, chain, asset, &query)
+    }
+
+    /// Price `Liquidate` checks health at
+    pub fn liquidation_price<C: Chain>(
+        &mut self,
+        chain: &C,
+        asset: &Addr,
+    ) -> Result<Decimal, KeeperError> {
+        let query = QueryMsg::LiquidationPrice {
+            asset: asset.clone(),
+        };
+        cached_price(&mut self.liquidation_prices, chain, asset, &query)
+    }
+
+    /// Value of `amount` at the liquidation price
+    fn value<C: Chain>(
+        &mut self,
+        chain: &C,
+        asset: &Addr,
+        amount: Uint128,
+    ) -> Result<Decimal, KeeperError> {
+        let price = self.liquidation_price(chain, asset)?;
+        asset_value(amount, price).ok_or(KeeperError::Overflow {})
+    }
+}
+
+fn cached_price<C: Chain>(
+    prices: &mut BTreeMap<Addr, Decimal>,
+    chain: &C,
+    asset: &Addr,
+    query: &QueryMsg,
+) -> Result<Decimal, KeeperError> {
+    if let Some(price) = prices.get(asset) {
+        return Ok(*price);
+    }
+    let price: Decimal = chain
+        .query(query)
+        .map_err(|_| KeeperError::PriceNotAvailable {
+            asset: asset.to_string(),
+        })?;
+    prices.insert(asset.clone(), price);
+    Ok(price)
+}
+
+/// Every account with outstanding debt, read page by page through `Borrows` until an empty
+/// page, since the contract may clamp `page_limit` to a smaller page
+pub fn borrowers<C: Chain>(chain: &C, page_limit: u32) -> Result<BTreeSet<Addr>, KeeperError> {
+    let mut accounts = BTreeSet::new();
+    let mut start_after = None;
+    loop {
+        let page: Vec<BorrowResponse> = chain.query(&QueryMsg::Borrows {
+            start_after: start_after.clone(),
+            limit: Some(page_limit),
+        })?;
+        let last = match page.last() {
+            Some(last) => (last.token_address.clone(), last.user_address.clone()),
+            None => break,
+        };
+        accounts.extend(page.into_iter().map(|borrow| borrow.user_address));
+        start_after = Some(last);
+    }
+    Ok(accounts)
+}
+
+/// Accounts sharing margin with `account`, mirrors the contract's `margin_health`
+pub fn margin_accounts<C: Chain>(chain: &C, account: &Addr) -> Result<Vec<Addr>, KeeperError> {
+    let (owner, index) = split_sub_account(account);
+    let sub_accounts: Vec<SubAccountResponse> = chain.query(&QueryMsg::SubAccounts { owner })?;
+
+    let margin_mode = sub_accounts
+        .iter()
+        .find(|sub_account| sub_account.sub_account == index)
+        .map(|sub_account| sub_account.margin_mode.clone())
+        .unwrap_or(MarginMode::Cross);
+    if margin_mode == MarginMode::Isolated {
+        return Ok(vec![account.clone()]);
+    }
+
+    Ok(sub_accounts
+        .into_iter()
+        .filter(|sub_account| sub_account.margin_mode == MarginMode::Cross)
+        .map(|sub_account| sub_account.account)
+        .collect())
+}
+
+/**
+ * @dev Checks one account and returns its most rewarding liquidation, `None` when the
+ * account is healthy.
+ *
+ * Health is computed over the account's margin with the contract's own math, at the
+ * liquidation prices `Liquidate` checks it at. The liquidation seizes the account's
+ * positions (valued in the debt token at the spot cross price) and its collateral in the
+ * debt token, see `liquidation_split`.
+ */
+pub fn check_account<C: Chain>(
+    chain: &C,
+    market: &mut Market,
+    account: &Addr,
+) -> Result<Option<Candidate>, KeeperError> {
+    let mut collateral_value = Decimal::zero();
+    let mut position_value = Decimal::zero();
+    let mut debt_value = Decimal::zero();
+    let mut own_ledgers = None;
+    for margin_account in margin_accounts(chain, account)? {
+        let ledgers: AccountLedgersResponse = chain.query(&QueryMsg::AccountLedgers {
+            user_address: margin_account.clone(),
+        })?;
+        for collateral in ledgers.collateral.iter() {
+            collateral_value += market.value(chain, &collateral.asset, collateral.amount)?;
+        }
+        for position in ledgers.positions.iter() {
+            position_value += market.value(chain, &position.asset, position.amount)?;
+        }
+        for debt in ledgers.debt.iter() {
+            debt_value += market.value(chain, &debt.asset, debt.amount)?;
+        }
+        if margin_account == *account {
+            own_ledgers = Some(ledgers);
+        }
+    }
+
+    let health = health_factor(collateral_value, position_value, debt_value);
+    if !is_liquidatable(health, market.risk_config.liquidation_threshold) {
+        return Ok(None);
+    }
+    let ledgers = match own_ledgers {
+        Some(ledgers) => ledgers,
+        None => return Ok(None),
+    };
+
+    let mut best: Option<Candidate> = None;
+    for debt in ledgers.debt.iter() {
+        let debt_price = market.price(chain, &debt.asset)?;
+        let mut seized = Uint128::zero();
+        for position in ledgers.positions.iter() {
+            let value = if position.asset == debt.asset {
+                position.amount
+            } else {
+                let cross_price = market
+                    .price(chain, &position.asset)?
+                    .checked_div(debt_price)
+                    .map_err(|_| KeeperError::PriceNotAvailable {
+                        asset: debt.asset.to_string(),
+                    })?;
+                position.amount.mul_floor(cross_price)
+            };
+            seized = seized
+                .checked_add(value)
+                .map_err(|_| KeeperError::Overflow {})?;
+        }
+        let collateral = ledgers
+            .collateral
+            .iter()
+            .find(|collateral| collateral.asset == debt.asset)
+            .map(|collateral| collateral.amount)
+            .unwrap_or_default();
+
+        let split = liquidation_split(
+            seized,
+            collateral,
+            debt.amount,
+            market.risk_config.liquidation_penalty_bps,
+            market.insurance_config.penalty_share_bps,
+            market.fee_config.liquidation_fee_bps,
+        )
+        .ok_or(KeeperError::Overflow {})?;
+        let reward_value =
+            asset_value(split.liquidator_reward, debt_price).ok_or(KeeperError::Overflow {})?;
+
+        let candidate = Candidate {
+            user_address: account.clone(),
+            debt_token: debt.asset.clone(),
+            health_factor: health.unwrap_or_default(),
+            split,
+            reward_value,
+        };
+        best = match best {
+            Some(best) if best.reward_value >= candidate.reward_value => Some(best),
+            _ => Some(candidate),
+        };
+    }
+    Ok(best)
+}
@@ -0,0 +1,316 @@
+use cosmwasm_std::{to_json_binary, Addr, Decimal, Uint128};
+use cw_multi_test::{App, ContractWrapper, Executor};
+use leverage_contract::contract::{execute, instantiate, query, reply};
+use leverage_contract::msg::{
+    Cw20ReceiveMsg, ExecuteMsg, InstantiateMsg, OrderExecute, QueryMsg, QueryTokenData, TokenData,
+};
+use leverage_contract::state::{PriceFeedConfig, TwapConfig};
+use leverage_keeper::chain::Chain;
+use leverage_keeper::scan::borrowers;
+use leverage_keeper::{Keeper, KeeperConfig, KeeperError};
+use serde::de::DeserializeOwned;
+
+/// Chain stand-in running the contract in `cw-multi-test`
+struct MultiTestChain {
+    app: App,
+    contract: Addr,
+    keeper: Addr,
+}
+
+impl Chain for MultiTestChain {
+    fn query<T: DeserializeOwned>(&self, msg: &QueryMsg) -> Result<T, KeeperError> {
+        self.app
+            .wrap()
+            .query_wasm_smart(self.contract.clone(), msg)
+            .map_err(|error| KeeperError::Query {
+                error: error.to_string(),
+            })
+    }
+
+    fn liquidate(&mut self, user_address: &Addr, debt_token: &Addr) -> Result<String, KeeperError> {
+        self.app
+            .execute_contract(
+                self.keeper.clone(),
+                self.contract.clone(),
+                &ExecuteMsg::Liquidate {
+                    user_address: user_address.clone(),
+                    debt_token: debt_token.clone(),
+                },
+                &[],
+            )
+            .map_err(|error| KeeperError::Liquidation {
+                user: user_address.to_string(),
+                error: error.to_string(),
+            })?;
+        Ok(format!("block {}", self.app.block_info().height))
+    }
+}
+
+impl MultiTestChain {
+    fn execute(&mut self, sender: &str, msg: &ExecuteMsg) {
+        self.app
+            .execute_contract(Addr::unchecked(sender), self.contract.clone(), msg, &[])
+            .unwrap();
+    }
+
+    fn set_price(&mut self, asset: &str, price: &str) {
+        self.execute(
+            "creator",
+            &ExecuteMsg::UpdateAssetPrice {
+                asset: Addr::unchecked(asset),
+                price: price.parse().unwrap(),
+            },
+        );
+    }
+
+    fn collateral(&self, user: &str) -> Uint128 {
+        self.query(&QueryMsg::UserCollateralTokenBalance(QueryTokenData {
+            token_address: Addr::unchecked("usdc_contract"),
+            user_address: Addr::unchecked(user),
+        }))
+        .unwrap()
+    }
+}
+
+/// `user_one` levers 100 usdc into 500 osmo bought at 2, `user_three` only borrows
+fn leveraged_market() -> MultiTestChain {
+    let mut app = App::default();
+    let code_id = app.store_code(Box::new(
+        ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+    ));
+    let contract = app
+        .instantiate_contract(
+            code_id,
+            Addr::unchecked("creator"),
+            &InstantiateMsg {
+                token_contract_address: String::from("usdc_contract"),
+            },
+            &[],
+            "leverage_contract",
+            None,
+        )
+        .unwrap();
+    let mut chain = MultiTestChain {
+        app,
+        contract,
+        keeper: Addr::unchecked("keeper"),
+    };
+    chain.set_price("usdc_contract", "1");
+    chain.set_price("osmo", "2");
+
+    let deposits = [
+        ("user_one", 100u128),
+        ("user_two", 1000u128),
+        ("user_three", 100u128),
+    ];
+    for (user, amount) in deposits {
+        chain.execute(
+            "usdc_contract",
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: String::from(user),
+                amount: Uint128::from(amount),
+                msg: to_json_binary(&{}).unwrap(),
+            }),
+        );
+    }
+    for (user, amount) in [("user_one", 1000u128), ("user_three", 200u128)] {
+        chain.execute(
+            user,
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::from(amount),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+        );
+    }
+
+    chain.execute(
+        "creator",
+        &ExecuteMsg::ExecuteOrder(OrderExecute {
+            user_address: Addr::unchecked("user_one"),
+            token_in: Addr::unchecked("usdc_contract"),
+            token_out: Addr::unchecked("osmo"),
+            amount_in: Uint128::from(1000u128),
+            amount_out: Uint128::from(500u128),
+            encrypted_order: None,
+        }),
+    );
+    chain
+}
+
+fn config(min_profit: u64) -> KeeperConfig {
+    KeeperConfig {
+        min_profit: Decimal::from_atomics(min_profit, 0).unwrap(),
+        gas_cost: Decimal::one(),
+        // One borrow per page, so the scan has to follow the pagination
+        page_limit: 1,
+    }
+}
+
+#[test]
+fn healthy_market_has_no_candidates() {
+    let keeper = Keeper::new(leveraged_market(), config(0));
+    let scan = keeper.scan().unwrap();
+    assert!(scan.candidates.is_empty());
+    assert!(scan.skipped.is_empty());
+}
+
+#[test]
+fn liquidates_only_when_reward_covers_costs() {
+    let mut chain = leveraged_market();
+    // 100 collateral + 500 osmo at 1.85 = 1025 against 1000 of debt
+    chain.set_price("osmo", "1.85");
+
+    let mut keeper = Keeper::new(chain, config(30));
+    let round = keeper.run_once().unwrap();
+    assert!(round.attempts.is_empty());
+    assert_eq!(round.unprofitable.len(), 1);
+    let candidate = &round.unprofitable[0];
+    assert_eq!(candidate.user_address, Addr::unchecked("user_one"));
+    assert_eq!(candidate.health_factor, "1.025".parse::<Decimal>().unwrap());
+    assert_eq!(candidate.split.liquidator_reward, Uint128::from(25u128));
+    assert_eq!(keeper.chain.collateral("keeper"), Uint128::zero());
+
+    keeper.config = config(20);
+    let round = keeper.run_once().unwrap();
+    assert_eq!(round.attempts.len(), 1);
+    assert!(round.attempts[0].result.is_ok());
+    // The keeper's estimate matches what the contract paid out
+    assert_eq!(keeper.chain.collateral("keeper"), Uint128::from(25u128));
+
+    let scan = keeper.scan().unwrap();
+    assert!(scan.candidates.is_empty());
+}
+
+#[test]
+fn accounts_without_prices_are_skipped() {
+    let mut chain = leveraged_market();
+    chain.set_price("atom", "10");
+    chain.execute(
+        "creator",
+        &ExecuteMsg::ExecuteOrder(OrderExecute {
+            user_address: Addr::unchecked("user_three"),
+            token_in: Addr::unchecked("usdc_contract"),
+            token_out: Addr::unchecked("atom"),
+            amount_in: Uint128::from(200u128),
+            amount_out: Uint128::from(20u128),
+            encrypted_order: None,
+        }),
+    );
+    chain.execute(
+        "creator",
+        &ExecuteMsg::UpdatePriceFeedConfig(PriceFeedConfig {
+            feeders: vec![],
+            max_price_age: Some(60),
+            max_deviation_bps: None,
+        }),
+    );
+    chain
+        .app
+        .update_block(|block| block.time = block.time.plus_seconds(61));
+    chain.set_price("usdc_contract", "1");
+    chain.set_price("osmo", "1.85");
+
+    // Nobody can value the stale atom position, the contract would refuse to liquidate too
+    let scan = Keeper::new(chain, config(0)).scan().unwrap();
+    assert_eq!(scan.candidates.len(), 1);
+    assert_eq!(scan.skipped.len(), 1);
+    assert_eq!(scan.skipped[0].0, Addr::unchecked("user_three"));
+    assert!(matches!(
+        scan.skipped[0].1,
+        KeeperError::PriceNotAvailable { .. }
+    ));
+}
+
+#[test]
+fn health_is_checked_at_the_liquidation_twap() {
+    let mut chain = leveraged_market();
+    chain.execute(
+        "creator",
+        &ExecuteMsg::UpdateTwapConfig(TwapConfig {
+            max_window: 3600,
+            liquidation_window: Some(600),
+        }),
+    );
+    chain
+        .app
+        .update_block(|block| block.time = block.time.plus_seconds(600));
+
+    // Spot osmo at 1.85 leaves user_one at 1.025, but the TWAP still holds 2 and 1.1
+    chain.set_price("osmo", "1.85");
+    let mut keeper = Keeper::new(chain, config(0));
+    let round = keeper.run_once().unwrap();
+    assert!(round.attempts.is_empty());
+    assert!(round.unprofitable.is_empty());
+    // The contract refuses it as well
+    let liquidation = keeper.chain.liquidate(
+        &Addr::unchecked("user_one"),
+        &Addr::unchecked("usdc_contract"),
+    );
+    assert!(matches!(liquidation, Err(KeeperError::Liquidation { .. })));
+
+    // user_three buys 200 osmo at 1 with spot back at 2, and the TWAP settles there. The
+    // fill values usdc at 2 against osmo's spot: 200 collateral + 500 osmo at 1 = 700
+    // against 2000 of debt, while spot prices keep user_one at 1.1.
+    keeper.chain.set_price("osmo", "2");
+    keeper.chain.execute(
+        "creator",
+        &ExecuteMsg::ExecuteOrder(OrderExecute {
+            user_address: Addr::unchecked("user_three"),
+            token_in: Addr::unchecked("usdc_contract"),
+            token_out: Addr::unchecked("osmo"),
+            amount_in: Uint128::from(200u128),
+            amount_out: Uint128::from(200u128),
+            encrypted_order: None,
+        }),
+    );
+    keeper
+        .chain
+        .app
+        .update_block(|block| block.time = block.time.plus_seconds(600));
+
+    let scan = keeper.scan().unwrap();
+    let candidate = scan
+        .candidates
+        .iter()
+        .find(|candidate| candidate.user_address == Addr::unchecked("user_one"))
+        .unwrap();
+    assert_eq!(candidate.health_factor, Decimal::percent(35));
+    // Positions are still seized at spot, 100 collateral + 500 osmo at 2 cover the debt
+    assert_eq!(candidate.split.repaid, Uint128::from(1000u128));
+    assert_eq!(candidate.split.shortfall, Uint128::zero());
+    let round = keeper.run_once().unwrap();
+    assert!(round.attempts.iter().any(|attempt| {
+        attempt.candidate.user_address == Addr::unchecked("user_one") && attempt.result.is_ok()
+    }));
+}
+
+#[test]
+fn scan_pages_past_the_contract_page_limit() {
+    let mut chain = leveraged_market();
+    for index in 0..40 {
+        let user = format!("borrower_{}", index);
+        chain.execute(
+            "usdc_contract",
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: user.clone(),
+                amount: Uint128::from(100u128),
+                msg: to_json_binary(&{}).unwrap(),
+            }),
+        );
+        chain.execute(
+            &user,
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::one(),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+        );
+    }
+
+    // Pages come back clamped to 30 however many the keeper asks for
+    let accounts = borrowers(&chain, 100).unwrap();
+    assert_eq!(accounts.len(), 42);
+}
@@ -0,0 +1,15 @@
+pub mod batch;
+pub mod contract;
+pub mod events;
+pub mod health;
+mod error;
+#[cfg(test)]
+mod invariants;
+pub mod msg;
+pub mod oracle;
+pub mod paillier;
+#[cfg(test)]
+mod scenario;
+pub mod state;
+
+pub use crate::error::ContractError;
@@ -0,0 +1,47 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MatcherError {
+    #[error("{0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Invalid order: {reason}")]
+    InvalidOrder { reason: String },
+
+    #[error("Order {order_id} already exists")]
+    OrderExists { order_id: String },
+
+    #[error("Execution {id} not found")]
+    ExecutionNotFound { id: i64 },
+
+    #[error("Token {symbol} is not listed")]
+    UnknownToken { symbol: String },
+
+    #[error("Invalid Paillier key")]
+    InvalidPaillierKey {},
+
+    #[error("Price not available for {asset}")]
+    PriceNotAvailable { asset: String },
+}
+
+impl IntoResponse for MatcherError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            MatcherError::InvalidOrder { .. } | MatcherError::UnknownToken { .. } => {
+                StatusCode::BAD_REQUEST
+            }
+            MatcherError::OrderExists { .. } => StatusCode::CONFLICT,
+            MatcherError::ExecutionNotFound { .. } => StatusCode::NOT_FOUND,
+            MatcherError::PriceNotAvailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
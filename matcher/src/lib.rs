@@ -0,0 +1,246 @@
+//! Order-matching service for encrypted orders, replacing the frontend's
+//! `localhost:5000` order API.
+//!
+//! The frontend posts orders whose amount is a Paillier ciphertext. Before posting,
+//! the user commits the same ciphertext on-chain with `CommitEncryptedOrder` under the
+//! same `order_id`. The matcher decrypts the amount with the private key matching the
+//! contract's `paillier_public_key` and matches the order against open orders on the
+//! opposite side of the market, oldest first, at the contract's oracle cross price.
+//! Quantities are netted: every match fills the smaller of the two remaining amounts,
+//! leaving the rest of the larger order on the book. Each match queues one
+//! `ExecuteOrder` per side for the owner to submit, carrying the committed amount and
+//! the recovered encryption randomness, which the contract checks against the
+//! commitment while it adds up the partial fills.
+//!
+//! Decrypted amounts are in the token's base units. Rounding of a fill goes against
+//! the incoming order.
+
+mod error;
+pub mod order;
+pub mod paillier;
+pub mod price;
+pub mod server;
+pub mod store;
+
+pub use crate::error::MatcherError;
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use cosmwasm_std::{Addr, Decimal, Uint128, Uint512};
+use leverage_contract::msg::{EncryptedOrderReveal, ExecuteMsg, OrderExecute};
+use num_bigint::BigUint;
+use num_traits::Zero;
+
+use crate::order::{Execution, Fill, Order, STATUS_OPEN};
+use crate::paillier::PrivateKey;
+use crate::price::PriceFeed;
+use crate::store::Store;
+
+/// Decrypted order, kept in memory only while it is matched
+struct Revealed {
+    order: Order,
+    token_in: Addr,
+    token_out: Addr,
+    /// Committed amount sold, filled and unfilled
+    amount_in: Uint128,
+    filled: Uint128,
+    randomness: Uint512,
+}
+
+impl Revealed {
+    fn remaining(&self) -> Uint128 {
+        self.amount_in - self.filled
+    }
+}
+
+pub struct Matcher {
+    store: Store,
+    key: PrivateKey,
+    /// Token symbol used by the frontend to token address
+    tokens: BTreeMap<String, Addr>,
+    prices: Box<dyn PriceFeed + Send>,
+}
+
+impl Matcher {
+    pub fn new(
+        store: Store,
+        key: PrivateKey,
+        tokens: BTreeMap<String, Addr>,
+        prices: Box<dyn PriceFeed + Send>,
+    ) -> Self {
+        Matcher {
+            store,
+            key,
+            tokens,
+            prices,
+        }
+    }
+
+    /// Stores a new order and matches it against the book until it is filled
+    pub fn add_order(&mut self, order: Order) -> Result<Order, MatcherError> {
+        if order.status != STATUS_OPEN {
+            return Err(MatcherError::InvalidOrder {
+                reason: format!("status must be {}", STATUS_OPEN),
+            });
+        }
+        if order.order_id.is_empty() || order.user_address.is_empty() {
+            return Err(MatcherError::InvalidOrder {
+                reason: "missing order_id or user_address".to_string(),
+            });
+        }
+        if order.buy_token == order.sell_token {
+            return Err(MatcherError::InvalidOrder {
+                reason: "buy and sell tokens are the same".to_string(),
+            });
+        }
+        if self.store.order(&order.order_id)?.is_some() {
+            return Err(MatcherError::OrderExists {
+                order_id: order.order_id,
+            });
+        }
+        let order = Order {
+            matched_with: None,
+            ..order
+        };
+        let mut taker = self.reveal(order, Uint128::zero())?;
+
+        let makers = self
+            .store
+            .open_orders(&taker.order.buy_token, &taker.order.sell_token)?;
+        let mut fills = vec![];
+        if !makers.is_empty() {
+            let rate = self.rate(&taker.token_in, &taker.token_out)?;
+            for maker in makers {
+                if taker.remaining().is_zero() {
+                    break;
+                }
+                let filled = self.store.filled(&maker.order_id)?;
+                let mut maker = self.reveal(maker, filled)?;
+                let (sold, bought) = fill_amounts(taker.remaining(), maker.remaining(), rate);
+                if bought.is_zero() {
+                    continue;
+                }
+
+                taker.filled += sold;
+                maker.filled += bought;
+                fills.push(self.fill(&taker, &maker, sold, bought));
+                fills.push(self.fill(&maker, &taker, bought, sold));
+            }
+        }
+
+        self.store.insert_order(&taker.order, &fills)?;
+
+        self.store
+            .order(&taker.order.order_id)?
+            .ok_or(MatcherError::InvalidOrder {
+                reason: "order was not stored".to_string(),
+            })
+    }
+
+    pub fn orders(&self, user_address: Option<&str>) -> Result<Vec<Order>, MatcherError> {
+        self.store.orders(user_address)
+    }
+
+    /// Settlement messages not yet submitted by the owner
+    pub fn pending_executions(&self) -> Result<Vec<Execution>, MatcherError> {
+        self.store.pending_executions()
+    }
+
+    pub fn mark_settled(&mut self, id: i64) -> Result<Execution, MatcherError> {
+        self.store.mark_settled(id)
+    }
+
+    fn token(&self, symbol: &str) -> Result<Addr, MatcherError> {
+        self.tokens
+            .get(symbol)
+            .cloned()
+            .ok_or_else(|| MatcherError::UnknownToken {
+                symbol: symbol.to_string(),
+            })
+    }
+
+    fn reveal(&self, order: Order, filled: Uint128) -> Result<Revealed, MatcherError> {
+        let token_in = self.token(&order.sell_token)?;
+        let token_out = self.token(&order.buy_token)?;
+
+        let ciphertext = BigUint::from_str(order.encrypted_order_value.trim()).map_err(|_| {
+            MatcherError::InvalidOrder {
+                reason: "encrypted_order_value is not a decimal integer".to_string(),
+            }
+        })?;
+        let amount = self.key.decrypt(&ciphertext)?;
+        if amount.is_zero() || amount.bits() > 128 {
+            return Err(MatcherError::InvalidOrder {
+                reason: "order amount out of range".to_string(),
+            });
+        }
+        let amount_in =
+            Uint128::from_str(&amount.to_string()).map_err(|_| MatcherError::InvalidOrder {
+                reason: "order amount out of range".to_string(),
+            })?;
+        let randomness = Uint512::from_str(&self.key.randomness(&ciphertext)?.to_string())
+            .map_err(|_| MatcherError::InvalidOrder {
+                reason: "ciphertext out of range".to_string(),
+            })?;
+
+        if filled.gt(&amount_in) {
+            return Err(MatcherError::InvalidOrder {
+                reason: "order is overfilled".to_string(),
+            });
+        }
+
+        Ok(Revealed {
+            order,
+            token_in,
+            token_out,
+            amount_in,
+            filled,
+            randomness,
+        })
+    }
+
+    /// Oracle cross price, units of `token_out` per unit of `token_in`
+    fn rate(&self, token_in: &Addr, token_out: &Addr) -> Result<Decimal, MatcherError> {
+        let price_in = self.prices.price(token_in)?;
+        let price_out = self.prices.price(token_out)?;
+        price_in
+            .checked_div(price_out)
+            .map_err(|_| MatcherError::PriceNotAvailable {
+                asset: token_out.to_string(),
+            })
+    }
+
+    /// `ExecuteOrder` settling `sold` of `revealed` for `bought`, after `filled` was updated
+    fn fill(&self, revealed: &Revealed, other: &Revealed, sold: Uint128, bought: Uint128) -> Fill {
+        Fill {
+            order_id: revealed.order.order_id.clone(),
+            matched_with: other.order.order_id.clone(),
+            filled: revealed.filled,
+            complete: revealed.remaining().is_zero(),
+            msg: ExecuteMsg::ExecuteOrder(OrderExecute {
+                user_address: Addr::unchecked(&revealed.order.user_address),
+                token_in: revealed.token_in.clone(),
+                token_out: revealed.token_out.clone(),
+                amount_in: sold,
+                amount_out: bought,
+                encrypted_order: Some(EncryptedOrderReveal {
+                    order_id: revealed.order.order_id.clone(),
+                    randomness: revealed.randomness,
+                    order_amount: Some(revealed.amount_in),
+                }),
+            }),
+        }
+    }
+}
+
+/// Largest fill of a taker with `taker` left to sell against a maker with `maker` left,
+/// at `rate` maker tokens per taker token. Returns what the taker sells and buys, the
+/// maker's side is the reverse. The taker's proceeds are rounded down.
+pub fn fill_amounts(taker: Uint128, maker: Uint128, rate: Decimal) -> (Uint128, Uint128) {
+    let sold = maker
+        .checked_div_ceil(rate)
+        .map_or(taker, |sold| sold.min(taker));
+    let bought = sold.checked_mul_floor(rate).unwrap_or(maker).min(maker);
+    (sold, bought)
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::process::exit;
+use std::str::FromStr;
+
+use cosmwasm_std::Addr;
+use leverage_matcher::paillier::PrivateKey;
+use leverage_matcher::price::ContractPrices;
+use leverage_matcher::server::router;
+use leverage_matcher::store::Store;
+use leverage_matcher::Matcher;
+use num_bigint::BigUint;
+
+const USAGE: &str = "usage: leverage-matcher --db <path> --contract <address> --lcd <url> \
+    --paillier-p <prime> --paillier-q <prime> --token <SYMBOL=address>... \
+    [--listen 0.0.0.0:5000]";
+
+struct Args {
+    db: String,
+    contract: String,
+    lcd: String,
+    p: BigUint,
+    q: BigUint,
+    tokens: BTreeMap<String, Addr>,
+    listen: String,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut db = None;
+    let mut contract = None;
+    let mut lcd = None;
+    let mut p = None;
+    let mut q = None;
+    let mut tokens = BTreeMap::new();
+    let mut listen = "0.0.0.0:5000".to_string();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next()?;
+        match flag.as_str() {
+            "--db" => db = Some(value),
+            "--contract" => contract = Some(value),
+            "--lcd" => lcd = Some(value),
+            "--paillier-p" => p = Some(BigUint::from_str(&value).ok()?),
+            "--paillier-q" => q = Some(BigUint::from_str(&value).ok()?),
+            "--token" => {
+                let (symbol, address) = value.split_once('=')?;
+                tokens.insert(symbol.to_string(), Addr::unchecked(address));
+            }
+            "--listen" => listen = value,
+            _ => return None,
+        }
+    }
+    if tokens.is_empty() {
+        return None;
+    }
+
+    Some(Args {
+        db: db?,
+        contract: contract?,
+        lcd: lcd?,
+        p: p?,
+        q: q?,
+        tokens,
+        listen,
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    let matcher = PrivateKey::new(args.p, args.q).and_then(|key| {
+        let prices = ContractPrices {
+            lcd: args.lcd,
+            contract: args.contract,
+        };
+        Ok(Matcher::new(
+            Store::open(&args.db)?,
+            key,
+            args.tokens,
+            Box::new(prices),
+        ))
+    });
+    let matcher = match matcher {
+        Ok(matcher) => matcher,
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1);
+        }
+    };
+
+    let listener = match tokio::net::TcpListener::bind(&args.listen).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1);
+        }
+    };
+    println!("listening on {}", args.listen);
+    if let Err(error) = axum::serve(listener, router(matcher)).await {
+        eprintln!("{}", error);
+        exit(1);
+    }
+}
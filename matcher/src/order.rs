@@ -0,0 +1,53 @@
+use cosmwasm_std::Uint128;
+use leverage_contract::msg::ExecuteMsg;
+use serde::{Deserialize, Serialize};
+
+pub const STATUS_OPEN: u8 = 1;
+pub const STATUS_MATCHED: u8 = 2;
+pub const STATUS_SETTLED: u8 = 3;
+
+/// Order as posted by the frontend's `Swap.jsx` to `/add_order`. The decrypted amount
+/// is never returned.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Order {
+    pub order_id: String,
+    pub user_address: String,
+    #[serde(rename = "selectedMarket", default)]
+    pub selected_market: String,
+    pub status: u8,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    /// Paillier ciphertext of the amount sold, as a decimal string
+    pub encrypted_order_value: String,
+    #[serde(rename = "buyToken")]
+    pub buy_token: String,
+    #[serde(rename = "sellToken")]
+    pub sell_token: String,
+    #[serde(default)]
+    pub trader_address: String,
+    #[serde(default)]
+    pub chain: String,
+    /// Order this one was last matched against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matched_with: Option<String>,
+}
+
+/// One side of a match: `msg` settles part of `order_id`, bringing its filled amount to
+/// `filled` of the amount sold. The order leaves the book once `complete`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub order_id: String,
+    pub matched_with: String,
+    pub filled: Uint128,
+    pub complete: bool,
+    pub msg: ExecuteMsg,
+}
+
+/// `ExecuteOrder` message settling one side of a match, to be submitted by the owner
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Execution {
+    pub id: i64,
+    pub order_id: String,
+    pub msg: ExecuteMsg,
+    pub settled: bool,
+}
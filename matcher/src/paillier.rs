@@ -0,0 +1,115 @@
+use std::str::FromStr;
+
+use cosmwasm_std::Uint512;
+use leverage_contract::paillier::PublicKey;
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer;
+use num_traits::{One, Zero};
+
+use crate::MatcherError;
+
+/**
+ * @dev Paillier private key matching the contract's `PublicKey` (`g = n + 1`).
+ *
+ * The matcher decrypts order values with it and recovers the encryption randomness,
+ * which the contract accepts as proof that a committed ciphertext decrypts to the
+ * settled `amount_in`.
+ */
+pub struct PrivateKey {
+    n: BigUint,
+    n_squared: BigUint,
+    lambda: BigUint,
+    /// `lambda^-1 mod n`
+    mu: BigUint,
+    /// `n^-1 mod lambda`, recovers `r` from `c mod n = r^n mod n`
+    n_inv: BigUint,
+}
+
+impl PrivateKey {
+    pub fn new(p: BigUint, q: BigUint) -> Result<Self, MatcherError> {
+        let one = BigUint::one();
+        if p <= one || q <= one || p == q {
+            return Err(MatcherError::InvalidPaillierKey {});
+        }
+        let n = &p * &q;
+        let lambda = (&p - &one).lcm(&(&q - &one));
+        let mu = mod_inverse(&lambda, &n).ok_or(MatcherError::InvalidPaillierKey {})?;
+        let n_inv = mod_inverse(&n, &lambda).ok_or(MatcherError::InvalidPaillierKey {})?;
+
+        Ok(PrivateKey {
+            n_squared: &n * &n,
+            n,
+            lambda,
+            mu,
+            n_inv,
+        })
+    }
+
+    /// The public key to configure on the contract
+    pub fn public_key(&self) -> Result<PublicKey, MatcherError> {
+        let n = Uint512::from_str(&self.n.to_string())
+            .map_err(|_| MatcherError::InvalidPaillierKey {})?;
+        PublicKey::new(n).map_err(|_| MatcherError::InvalidPaillierKey {})
+    }
+
+    fn check(&self, ciphertext: &BigUint) -> Result<(), MatcherError> {
+        if ciphertext.is_zero()
+            || *ciphertext >= self.n_squared
+            || !ciphertext.gcd(&self.n).is_one()
+        {
+            return Err(MatcherError::InvalidOrder {
+                reason: "ciphertext out of range".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// `m = L(c^lambda mod n^2) * mu mod n` with `L(x) = (x - 1) / n`
+    pub fn decrypt(&self, ciphertext: &BigUint) -> Result<BigUint, MatcherError> {
+        self.check(ciphertext)?;
+        let x = ciphertext.modpow(&self.lambda, &self.n_squared);
+        let l = (x - BigUint::one()) / &self.n;
+        Ok((l * &self.mu) % &self.n)
+    }
+
+    /// Randomness `r` the ciphertext was encrypted with
+    pub fn randomness(&self, ciphertext: &BigUint) -> Result<BigUint, MatcherError> {
+        self.check(ciphertext)?;
+        Ok((ciphertext % &self.n).modpow(&self.n_inv, &self.n))
+    }
+}
+
+fn mod_inverse(value: &BigUint, modulus: &BigUint) -> Option<BigUint> {
+    let modulus = BigInt::from(modulus.clone());
+    let extended = BigInt::from(value.clone()).extended_gcd(&modulus);
+    if !extended.gcd.is_one() {
+        return None;
+    }
+    extended.x.mod_floor(&modulus).to_biguint()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovers_plaintext_and_randomness() {
+        let key = PrivateKey::new(
+            BigUint::from(2_147_483_647u64),
+            BigUint::from(2_305_843_009_213_693_951u64),
+        )
+        .unwrap();
+        let public_key = key.public_key().unwrap();
+
+        let ciphertext = public_key
+            .encrypt_with(Uint512::from(1234u128), Uint512::from(987_654_321u128))
+            .unwrap();
+        let ciphertext = BigUint::from_str(&ciphertext.to_string()).unwrap();
+
+        assert_eq!(key.decrypt(&ciphertext).unwrap(), BigUint::from(1234u32));
+        assert_eq!(
+            key.randomness(&ciphertext).unwrap(),
+            BigUint::from(987_654_321u32)
+        );
+    }
+}
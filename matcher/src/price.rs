@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+
+use cosmwasm_std::{to_json_binary, Addr, Decimal};
+use leverage_contract::msg::QueryMsg;
+use serde_json::Value;
+
+use crate::MatcherError;
+
+/// Reference prices orders are matched at, in the contract's price unit
+pub trait PriceFeed {
+    fn price(&self, asset: &Addr) -> Result<Decimal, MatcherError>;
+}
+
+/// Prices registered on the leverage contract, read through a node's LCD
+pub struct ContractPrices {
+    pub lcd: String,
+    pub contract: String,
+}
+
+impl PriceFeed for ContractPrices {
+    fn price(&self, asset: &Addr) -> Result<Decimal, MatcherError> {
+        let not_available = || MatcherError::PriceNotAvailable {
+            asset: asset.to_string(),
+        };
+        let query = to_json_binary(&QueryMsg::AssetPrice {
+            asset: asset.clone(),
+        })
+        .map_err(|_| not_available())?
+        .to_base64()
+        .replace('+', "%2B")
+        .replace('/', "%2F")
+        .replace('=', "%3D");
+        let url = format!(
+            "{}/cosmwasm/wasm/v1/contract/{}/smart/{}",
+            self.lcd.trim_end_matches('/'),
+            self.contract,
+            query
+        );
+
+        let response: Value = ureq::get(&url)
+            .call()
+            .map_err(|_| not_available())?
+            .into_json()
+            .map_err(|_| not_available())?;
+        serde_json::from_value(response["data"].clone()).map_err(|_| not_available())
+    }
+}
+
+/// Fixed prices, for tests and local runs
+pub struct FixedPrices(pub BTreeMap<Addr, Decimal>);
+
+impl PriceFeed for FixedPrices {
+    fn price(&self, asset: &Addr) -> Result<Decimal, MatcherError> {
+        self.0
+            .get(asset)
+            .copied()
+            .ok_or_else(|| MatcherError::PriceNotAvailable {
+                asset: asset.to_string(),
+            })
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use tower_http::cors::CorsLayer;
+
+use crate::order::{Execution, Order};
+use crate::{Matcher, MatcherError};
+
+#[derive(Clone)]
+pub struct AppState(Arc<Mutex<Matcher>>);
+
+impl AppState {
+    fn matcher(&self) -> MutexGuard<'_, Matcher> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OrdersQuery {
+    pub user_address: Option<String>,
+}
+
+/// Routes served to the frontend and to the owner's settlement job
+pub fn router(matcher: Matcher) -> Router {
+    Router::new()
+        .route("/add_order", post(add_order))
+        .route("/orders", get(orders))
+        .route("/executions", get(executions))
+        .route("/executions/:id/settled", post(mark_settled))
+        .layer(CorsLayer::permissive())
+        .with_state(AppState(Arc::new(Mutex::new(matcher))))
+}
+
+async fn add_order(
+    State(state): State<AppState>,
+    Json(order): Json<Order>,
+) -> Result<Json<Order>, MatcherError> {
+    state.matcher().add_order(order).map(Json)
+}
+
+async fn orders(
+    State(state): State<AppState>,
+    Query(query): Query<OrdersQuery>,
+) -> Result<Json<Vec<Order>>, MatcherError> {
+    state
+        .matcher()
+        .orders(query.user_address.as_deref())
+        .map(Json)
+}
+
+async fn executions(State(state): State<AppState>) -> Result<Json<Vec<Execution>>, MatcherError> {
+    state.matcher().pending_executions().map(Json)
+}
+
+async fn mark_settled(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<Execution>, MatcherError> {
+    state.matcher().mark_settled(id).map(Json)
+}
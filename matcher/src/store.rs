@@ -0,0 +1,249 @@
+use std::path::Path;
+
+use std::str::FromStr;
+
+use cosmwasm_std::Uint128;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::order::{Execution, Fill, Order, STATUS_MATCHED, STATUS_OPEN, STATUS_SETTLED};
+use crate::MatcherError;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS orders (
+        order_id TEXT PRIMARY KEY,
+        user_address TEXT NOT NULL,
+        selected_market TEXT NOT NULL,
+        status INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        encrypted_order_value TEXT NOT NULL,
+        buy_token TEXT NOT NULL,
+        sell_token TEXT NOT NULL,
+        trader_address TEXT NOT NULL,
+        chain TEXT NOT NULL,
+        matched_with TEXT,
+        filled TEXT NOT NULL DEFAULT '0'
+    );
+    CREATE INDEX IF NOT EXISTS orders_book ON orders (status, sell_token, buy_token);
+    CREATE TABLE IF NOT EXISTS executions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        order_id TEXT NOT NULL,
+        msg TEXT NOT NULL,
+        settled INTEGER NOT NULL DEFAULT 0
+    );
+";
+
+const ORDER_COLUMNS: &str = "order_id, user_address, selected_market, status, created_at, \
+    encrypted_order_value, buy_token, sell_token, trader_address, chain, matched_with";
+
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, MatcherError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self, MatcherError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, MatcherError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Store { conn })
+    }
+
+    pub fn order(&self, order_id: &str) -> Result<Option<Order>, MatcherError> {
+        let order = self
+            .conn
+            .query_row(
+                &format!("SELECT {} FROM orders WHERE order_id = ?1", ORDER_COLUMNS),
+                params![order_id],
+                order_from_row,
+            )
+            .optional()?;
+        Ok(order)
+    }
+
+    /// Orders of `user_address`, or every order when `None`, oldest first
+    pub fn orders(&self, user_address: Option<&str>) -> Result<Vec<Order>, MatcherError> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM orders WHERE ?1 IS NULL OR user_address = ?1 ORDER BY rowid",
+            ORDER_COLUMNS
+        ))?;
+        let orders = statement
+            .query_map(params![user_address], order_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(orders)
+    }
+
+    /// Open orders selling `sell_token` for `buy_token`, oldest first
+    pub fn open_orders(
+        &self,
+        sell_token: &str,
+        buy_token: &str,
+    ) -> Result<Vec<Order>, MatcherError> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM orders
+             WHERE status = ?1 AND sell_token = ?2 AND buy_token = ?3
+             ORDER BY rowid",
+            ORDER_COLUMNS
+        ))?;
+        let orders = statement
+            .query_map(params![STATUS_OPEN, sell_token, buy_token], order_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(orders)
+    }
+
+    /// Amount of `order_id` sold by fills so far, in the sell token's base units
+    pub fn filled(&self, order_id: &str) -> Result<Uint128, MatcherError> {
+        let filled: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT filled FROM orders WHERE order_id = ?1",
+                params![order_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match filled {
+            Some(filled) => Uint128::from_str(&filled).map_err(|_| MatcherError::InvalidOrder {
+                reason: format!("stored fill of {} is corrupt", order_id),
+            }),
+            None => Ok(Uint128::zero()),
+        }
+    }
+
+    /// Stores a new order together with the fills it was matched with, atomically
+    pub fn insert_order(&mut self, order: &Order, fills: &[Fill]) -> Result<(), MatcherError> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            &format!(
+                "INSERT INTO orders ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                ORDER_COLUMNS
+            ),
+            params![
+                order.order_id,
+                order.user_address,
+                order.selected_market,
+                order.status,
+                order.created_at as i64,
+                order.encrypted_order_value,
+                order.buy_token,
+                order.sell_token,
+                order.trader_address,
+                order.chain,
+                order.matched_with
+            ],
+        )?;
+        for fill in fills {
+            let status = if fill.complete {
+                STATUS_MATCHED
+            } else {
+                STATUS_OPEN
+            };
+            tx.execute(
+                "UPDATE orders SET status = ?1, matched_with = ?2, filled = ?3
+                 WHERE order_id = ?4",
+                params![
+                    status,
+                    fill.matched_with,
+                    fill.filled.to_string(),
+                    fill.order_id
+                ],
+            )?;
+            tx.execute(
+                "INSERT INTO executions (order_id, msg) VALUES (?1, ?2)",
+                params![fill.order_id, serde_json::to_string(&fill.msg)?],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn pending_executions(&self) -> Result<Vec<Execution>, MatcherError> {
+        let mut statement = self.conn.prepare(
+            "SELECT id, order_id, msg, settled FROM executions WHERE settled = 0 ORDER BY id",
+        )?;
+        let mut rows = statement.query([])?;
+        let mut executions = vec![];
+        while let Some(row) = rows.next()? {
+            executions.push(execution_from_row(row)?);
+        }
+        Ok(executions)
+    }
+
+    /// Records that the owner submitted the execution. Its order is settled once it is
+    /// filled completely and every execution of it was submitted.
+    pub fn mark_settled(&mut self, id: i64) -> Result<Execution, MatcherError> {
+        let tx = self.conn.transaction()?;
+        let order_id: Option<String> = tx
+            .query_row(
+                "SELECT order_id FROM executions WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let order_id = match order_id {
+            Some(order_id) => order_id,
+            None => return Err(MatcherError::ExecutionNotFound { id }),
+        };
+        tx.execute(
+            "UPDATE executions SET settled = 1 WHERE id = ?1",
+            params![id],
+        )?;
+        tx.execute(
+            "UPDATE orders SET status = ?1
+             WHERE order_id = ?2 AND status = ?3 AND NOT EXISTS (
+                 SELECT 1 FROM executions WHERE order_id = ?2 AND settled = 0
+             )",
+            params![STATUS_SETTLED, order_id, STATUS_MATCHED],
+        )?;
+        let execution = tx.query_row(
+            "SELECT id, order_id, msg, settled FROM executions WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get(3)?,
+                ))
+            },
+        )?;
+        tx.commit()?;
+
+        let (id, order_id, msg, settled): (i64, String, String, bool) = execution;
+        Ok(Execution {
+            id,
+            order_id,
+            msg: serde_json::from_str(&msg)?,
+            settled,
+        })
+    }
+}
+
+fn order_from_row(row: &Row) -> rusqlite::Result<Order> {
+    Ok(Order {
+        order_id: row.get(0)?,
+        user_address: row.get(1)?,
+        selected_market: row.get(2)?,
+        status: row.get(3)?,
+        created_at: row.get::<_, i64>(4)? as u64,
+        encrypted_order_value: row.get(5)?,
+        buy_token: row.get(6)?,
+        sell_token: row.get(7)?,
+        trader_address: row.get(8)?,
+        chain: row.get(9)?,
+        matched_with: row.get(10)?,
+    })
+}
+
+fn execution_from_row(row: &Row) -> Result<Execution, MatcherError> {
+    let msg: String = row.get(2)?;
+    Ok(Execution {
+        id: row.get(0)?,
+        order_id: row.get(1)?,
+        msg: serde_json::from_str(&msg)?,
+        settled: row.get(3)?,
+    })
+}
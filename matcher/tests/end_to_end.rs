@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use cosmwasm_std::{to_json_binary, Addr, Decimal, Uint128, Uint512};
+use cw_multi_test::{App, ContractWrapper, Executor};
+use leverage_contract::contract::{execute, instantiate, query, reply};
+use leverage_contract::msg::{
+    Cw20ReceiveMsg, ExecuteMsg, InstantiateMsg, OrderExecute, QueryMsg, QueryTokenData, TokenData,
+};
+use leverage_matcher::order::{Execution, Order, STATUS_MATCHED, STATUS_OPEN, STATUS_SETTLED};
+use leverage_matcher::paillier::PrivateKey;
+use leverage_matcher::price::FixedPrices;
+use leverage_matcher::server::router;
+use leverage_matcher::store::Store;
+use leverage_matcher::Matcher;
+use num_bigint::BigUint;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+fn private_key() -> PrivateKey {
+    PrivateKey::new(
+        BigUint::from(2_147_483_647u64),
+        BigUint::from(2_305_843_009_213_693_951u64),
+    )
+    .unwrap()
+}
+
+fn matcher() -> Matcher {
+    let tokens = BTreeMap::from([
+        ("USDC".to_string(), Addr::unchecked("usdc_contract")),
+        ("OSMO".to_string(), Addr::unchecked("osmo")),
+    ]);
+    let prices = FixedPrices(BTreeMap::from([
+        (Addr::unchecked("usdc_contract"), Decimal::one()),
+        (
+            Addr::unchecked("osmo"),
+            Decimal::from_atomics(2u128, 0).unwrap(),
+        ),
+    ]));
+    Matcher::new(
+        Store::in_memory().unwrap(),
+        private_key(),
+        tokens,
+        Box::new(prices),
+    )
+}
+
+struct Chain {
+    app: App,
+    contract: Addr,
+}
+
+impl Chain {
+    fn execute(&mut self, sender: &str, msg: &ExecuteMsg) {
+        self.app
+            .execute_contract(Addr::unchecked(sender), self.contract.clone(), msg, &[])
+            .unwrap();
+    }
+
+    fn position(&self, user: &str, token: &str) -> Uint128 {
+        self.app
+            .wrap()
+            .query_wasm_smart(
+                self.contract.clone(),
+                &QueryMsg::UserPositionBalance(QueryTokenData {
+                    token_address: Addr::unchecked(token),
+                    user_address: Addr::unchecked(user),
+                }),
+            )
+            .unwrap()
+    }
+}
+
+/// `user_one` holds a 500 usdc position, `user_two` 250 osmo, both behind the Paillier key
+fn market() -> Chain {
+    let mut app = App::default();
+    let code_id = app.store_code(Box::new(
+        ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+    ));
+    let contract = app
+        .instantiate_contract(
+            code_id,
+            Addr::unchecked("creator"),
+            &InstantiateMsg {
+                token_contract_address: String::from("usdc_contract"),
+            },
+            &[],
+            "leverage_contract",
+            None,
+        )
+        .unwrap();
+    let mut chain = Chain { app, contract };
+    for (asset, price) in [("usdc_contract", "1"), ("osmo", "2")] {
+        chain.execute(
+            "creator",
+            &ExecuteMsg::UpdateAssetPrice {
+                asset: Addr::unchecked(asset),
+                price: price.parse().unwrap(),
+            },
+        );
+    }
+
+    for (user, borrow) in [("user_one", 500u128), ("user_two", 200u128)] {
+        chain.execute(
+            "usdc_contract",
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: String::from(user),
+                amount: Uint128::from(100u128),
+                msg: to_json_binary(&{}).unwrap(),
+            }),
+        );
+        chain.execute(
+            user,
+            &ExecuteMsg::Borrow(TokenData {
+                token_address: Addr::unchecked("usdc_contract"),
+                token_amount: Uint128::from(borrow),
+                on_behalf_of: None,
+                sub_account: None,
+            }),
+        );
+    }
+    chain.execute(
+        "creator",
+        &ExecuteMsg::ExecuteOrder(OrderExecute {
+            user_address: Addr::unchecked("user_two"),
+            token_in: Addr::unchecked("usdc_contract"),
+            token_out: Addr::unchecked("osmo"),
+            amount_in: Uint128::from(200u128),
+            amount_out: Uint128::from(250u128),
+            encrypted_order: None,
+        }),
+    );
+    let n = private_key().public_key().unwrap().n;
+    chain.execute("creator", &ExecuteMsg::UpdatePaillierPublicKey { n });
+    chain
+}
+
+/// Encrypts `amount` and commits it on-chain, returning the ciphertext for the order
+fn commit(chain: &mut Chain, user: &str, order_id: &str, amount: u128, randomness: u128) -> String {
+    let ciphertext = private_key()
+        .public_key()
+        .unwrap()
+        .encrypt_with(Uint512::from(amount), Uint512::from(randomness))
+        .unwrap();
+    chain.execute(
+        user,
+        &ExecuteMsg::CommitEncryptedOrder {
+            order_id: order_id.to_string(),
+            encrypted_order_value: ciphertext,
+            on_behalf_of: None,
+            sub_account: None,
+        },
+    );
+    ciphertext.to_string()
+}
+
+/// Body `Swap.jsx` posts to `/add_order`
+fn order(order_id: &str, user: &str, sell: &str, buy: &str, ciphertext: &str) -> Value {
+    json!({
+        "order_id": order_id,
+        "user_address": user,
+        "selectedMarket": "OSMO/USDC",
+        "status": 1,
+        "createdAt": 1_700_000_000_000u64,
+        "encrypted_order_value": ciphertext,
+        "buyToken": buy,
+        "sellToken": sell,
+        "trader_address": user,
+        "chain": "osmosis-1",
+    })
+}
+
+async fn send<T: DeserializeOwned>(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, T) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+    let response = app
+        .clone()
+        .oneshot(request.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn partially_matched_orders_settle_on_chain() {
+    let mut chain = market();
+    let app = router(matcher());
+
+    let ciphertext = commit(&mut chain, "user_one", "order-1", 100, 987_654_321);
+    let (status, first): (_, Order) = send(
+        &app,
+        "POST",
+        "/add_order",
+        Some(order("order-1", "user_one", "USDC", "OSMO", &ciphertext)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(first.status, STATUS_OPEN);
+    let (_, executions): (_, Vec<Execution>) = send(&app, "GET", "/executions", None).await;
+    assert!(executions.is_empty());
+
+    let ciphertext = commit(&mut chain, "user_two", "order-2", 40, 123_456_789);
+    let (status, second): (_, Order) = send(
+        &app,
+        "POST",
+        "/add_order",
+        Some(order("order-2", "user_two", "OSMO", "USDC", &ciphertext)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(second.status, STATUS_MATCHED);
+    assert_eq!(second.matched_with.as_deref(), Some("order-1"));
+
+    // 40 osmo fill 80 of the 100 usdc, the rest of order-1 fills 10 of 15 osmo
+    let ciphertext = commit(&mut chain, "user_two", "order-3", 15, 192_837_465);
+    let (status, third): (_, Order) = send(
+        &app,
+        "POST",
+        "/add_order",
+        Some(order("order-3", "user_two", "OSMO", "USDC", &ciphertext)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(third.status, STATUS_OPEN);
+    assert_eq!(third.matched_with.as_deref(), Some("order-1"));
+
+    let (_, executions): (_, Vec<Execution>) = send(&app, "GET", "/executions", None).await;
+    assert_eq!(executions.len(), 4);
+    for execution in &executions {
+        chain.execute("creator", &execution.msg);
+        let (status, settled): (_, Execution) = send(
+            &app,
+            "POST",
+            &format!("/executions/{}/settled", execution.id),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(settled.settled);
+    }
+
+    // 100 usdc for 50 osmo at the oracle price of 2, both sides add up
+    assert_eq!(
+        chain.position("user_one", "usdc_contract"),
+        Uint128::from(400u128)
+    );
+    assert_eq!(chain.position("user_one", "osmo"), Uint128::from(50u128));
+    assert_eq!(chain.position("user_two", "osmo"), Uint128::from(200u128));
+    assert_eq!(
+        chain.position("user_two", "usdc_contract"),
+        Uint128::from(100u128)
+    );
+
+    let (_, executions): (_, Vec<Execution>) = send(&app, "GET", "/executions", None).await;
+    assert!(executions.is_empty());
+    let (_, orders): (_, Vec<Order>) = send(&app, "GET", "/orders", None).await;
+    let statuses: Vec<_> = orders.iter().map(|order| order.status).collect();
+    assert_eq!(statuses, [STATUS_SETTLED, STATUS_SETTLED, STATUS_OPEN]);
+}
+
+#[tokio::test]
+async fn rejects_duplicate_and_malformed_orders() {
+    let app = router(matcher());
+    let ciphertext = private_key()
+        .public_key()
+        .unwrap()
+        .encrypt_with(Uint512::from(100u128), Uint512::from(987_654_321u128))
+        .unwrap()
+        .to_string();
+
+    let body = order("order-1", "user_one", "USDC", "OSMO", &ciphertext);
+    let (status, _): (_, Order) = send(&app, "POST", "/add_order", Some(body.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, error): (_, Value) = send(&app, "POST", "/add_order", Some(body)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error["error"], "Order order-1 already exists");
+
+    let body = order("order-2", "user_one", "USDC", "OSMO", "not a number");
+    let (status, _): (_, Value) = send(&app, "POST", "/add_order", Some(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let body = order("order-3", "user_one", "USDC", "ATOM", &ciphertext);
+    let (status, error): (_, Value) = send(&app, "POST", "/add_order", Some(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"], "Token ATOM is not listed");
+
+    let (status, _): (_, Value) = send(&app, "POST", "/executions/7/settled", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
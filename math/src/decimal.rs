@@ -0,0 +1,112 @@
+use crate::{mul_div, Rounding};
+
+const FRACTIONAL: u128 = 1_000_000_000_000_000_000;
+
+/// Unsigned fixed-point number with 18 decimal places
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Decimal(u128);
+
+impl Decimal {
+    pub const DECIMAL_PLACES: u32 = 18;
+
+    pub const fn zero() -> Self {
+        Decimal(0)
+    }
+
+    pub const fn one() -> Self {
+        Decimal(FRACTIONAL)
+    }
+
+    /// From the raw value in units of `10^-18`
+    pub const fn raw(atomics: u128) -> Self {
+        Decimal(atomics)
+    }
+
+    pub const fn atomics(self) -> u128 {
+        self.0
+    }
+
+    pub const fn percent(percent: u64) -> Self {
+        Decimal(percent as u128 * (FRACTIONAL / 100))
+    }
+
+    pub const fn bps(bps: u64) -> Self {
+        Decimal(bps as u128 * (FRACTIONAL / 10_000))
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whole `amount`
+    pub fn from_amount(amount: u128) -> Option<Self> {
+        amount.checked_mul(FRACTIONAL).map(Decimal)
+    }
+
+    /// `numerator / denominator`, rounded down
+    pub fn from_ratio(numerator: u128, denominator: u128) -> Option<Self> {
+        mul_div(numerator, FRACTIONAL, denominator, Rounding::Down).map(Decimal)
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Decimal)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Decimal)
+    }
+
+    pub fn checked_mul(self, other: Self, rounding: Rounding) -> Option<Self> {
+        mul_div(self.0, other.0, FRACTIONAL, rounding).map(Decimal)
+    }
+
+    pub fn checked_div(self, other: Self, rounding: Rounding) -> Option<Self> {
+        mul_div(self.0, FRACTIONAL, other.0, rounding).map(Decimal)
+    }
+
+    /// `amount * self` in base units
+    pub fn mul_amount(self, amount: u128, rounding: Rounding) -> Option<u128> {
+        mul_div(amount, self.0, FRACTIONAL, rounding)
+    }
+
+    /// `amount / self` in base units
+    pub fn div_amount(self, amount: u128, rounding: Rounding) -> Option<u128> {
+        mul_div(amount, FRACTIONAL, self.0, rounding)
+    }
+}
+
+#[cfg(feature = "cosmwasm")]
+impl From<Decimal> for cosmwasm_std::Decimal {
+    fn from(value: Decimal) -> Self {
+        cosmwasm_std::Decimal::raw(value.0)
+    }
+}
+
+#[cfg(feature = "cosmwasm")]
+impl From<cosmwasm_std::Decimal> for Decimal {
+    fn from(value: cosmwasm_std::Decimal) -> Self {
+        Decimal(value.atomics().u128())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounding_is_explicit() {
+        let third = Decimal::from_ratio(1, 3).unwrap();
+        assert_eq!(third.atomics(), 333_333_333_333_333_333);
+        assert_eq!(third.mul_amount(10, Rounding::Down), Some(3));
+        assert_eq!(third.mul_amount(10, Rounding::Up), Some(4));
+        assert_eq!(
+            Decimal::one().checked_div(Decimal::from_amount(3).unwrap(), Rounding::Up),
+            Some(Decimal::raw(333_333_333_333_333_334))
+        );
+        assert_eq!(Decimal::percent(150), Decimal::bps(15_000));
+        assert_eq!(
+            Decimal::one().checked_div(Decimal::zero(), Rounding::Down),
+            None
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Account valuation. Values are in the oracle's price unit.
+
+use crate::{Decimal, Rounding};
+
+/// Value of `amount` base units at `price`, exact
+pub fn asset_value(amount: u128, price: Decimal) -> Option<Decimal> {
+    Decimal::from_amount(amount)?.checked_mul(price, Rounding::Down)
+}
+
+/// `(collateral_value + position_value) / debt_value` rounded down, `None` without debt
+pub fn health_factor(
+    collateral_value: Decimal,
+    position_value: Decimal,
+    debt_value: Decimal,
+) -> Option<Decimal> {
+    if debt_value.is_zero() {
+        return None;
+    }
+    collateral_value
+        .checked_add(position_value)?
+        .checked_div(debt_value, Rounding::Down)
+}
+
+/// Accounts without debt are never liquidatable
+pub fn is_liquidatable(health_factor: Option<Decimal>, liquidation_threshold: Decimal) -> bool {
+    match health_factor {
+        Some(health_factor) => health_factor < liquidation_threshold,
+        None => false,
+    }
+}
+
+/// Exposure over equity, `(collateral + positions) / (collateral + positions - debt)`,
+/// rounded down. One without debt, `None` once the equity is gone.
+pub fn leverage(
+    collateral_value: Decimal,
+    position_value: Decimal,
+    debt_value: Decimal,
+) -> Option<Decimal> {
+    let exposure = collateral_value.checked_add(position_value)?;
+    let equity = exposure.checked_sub(debt_value)?;
+    if equity.is_zero() {
+        return None;
+    }
+    exposure.checked_div(equity, Rounding::Down)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leverage_of_a_levered_position() {
+        // 100 of collateral borrowing 400 into a 400 position
+        let collateral = Decimal::from_amount(100).unwrap();
+        let position = Decimal::from_amount(400).unwrap();
+        let debt = Decimal::from_amount(400).unwrap();
+        assert_eq!(
+            leverage(collateral, position, debt),
+            Some(Decimal::from_amount(5).unwrap())
+        );
+        assert_eq!(
+            health_factor(collateral, position, debt),
+            Some(Decimal::percent(125))
+        );
+        assert_eq!(
+            leverage(collateral, Decimal::zero(), Decimal::zero()),
+            Some(Decimal::one())
+        );
+        assert_eq!(leverage(collateral, Decimal::zero(), collateral), None);
+    }
+}
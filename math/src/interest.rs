@@ -0,0 +1,68 @@
+//! Interest indices. Balances are stored scaled by the index at the time they were
+//! recorded, and grow with the index from then on.
+
+use crate::{mul_div, Decimal, Rounding};
+
+pub const SECONDS_PER_YEAR: u64 = 31_536_000;
+
+/// Index after `elapsed_seconds` at the simple annual `rate`, rounded up
+pub fn accrue(index: Decimal, rate: Decimal, elapsed_seconds: u64) -> Option<Decimal> {
+    let growth = mul_div(
+        rate.atomics(),
+        elapsed_seconds as u128,
+        SECONDS_PER_YEAR as u128,
+        Rounding::Up,
+    )?;
+    index.checked_mul(
+        Decimal::one().checked_add(Decimal::raw(growth))?,
+        Rounding::Up,
+    )
+}
+
+/// Scaled balance recording `amount` at `index`
+pub fn to_scaled(amount: u128, index: Decimal, rounding: Rounding) -> Option<u128> {
+    index.div_amount(amount, rounding)
+}
+
+/// Balance of `scaled` at `index`
+pub fn from_scaled(scaled: u128, index: Decimal, rounding: Rounding) -> Option<u128> {
+    index.mul_amount(scaled, rounding)
+}
+
+/// Scaled debt for a new borrow, rounded up
+pub fn scaled_debt(amount: u128, index: Decimal) -> Option<u128> {
+    to_scaled(amount, index, Rounding::Up)
+}
+
+/// Debt owed for `scaled` at `index`, rounded up
+pub fn debt(scaled: u128, index: Decimal) -> Option<u128> {
+    from_scaled(scaled, index, Rounding::Up)
+}
+
+/// Scaled balance for a new deposit, rounded down
+pub fn scaled_deposit(amount: u128, index: Decimal) -> Option<u128> {
+    to_scaled(amount, index, Rounding::Down)
+}
+
+/// Deposit balance for `scaled` at `index`, rounded down
+pub fn deposit(scaled: u128, index: Decimal) -> Option<u128> {
+    from_scaled(scaled, index, Rounding::Down)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debt_grows_with_the_index() {
+        let index = accrue(Decimal::one(), Decimal::percent(10), SECONDS_PER_YEAR / 2).unwrap();
+        assert_eq!(index, Decimal::percent(105));
+
+        let scaled = scaled_debt(1_000, Decimal::one()).unwrap();
+        assert_eq!(debt(scaled, index), Some(1_050));
+        // 1000 / 1.05 = 952.38, the borrower is charged for the fraction
+        assert_eq!(scaled_debt(1_000, index), Some(953));
+        assert_eq!(scaled_deposit(1_000, index), Some(952));
+        assert_eq!(accrue(index, Decimal::percent(10), 0), Some(index));
+    }
+}
@@ -0,0 +1,112 @@
+//! Fixed-point math shared by the leverage contract, the keeper and off-chain tools.
+//!
+//! The crate is `no_std` and works on plain `u128` amounts and an 18-decimal
+//! [`Decimal`] with the same representation as `cosmwasm_std::Decimal`. The
+//! `cosmwasm` feature adds conversions from and to the `cosmwasm_std` types.
+//!
+//! Every operation is checked and returns `None` on overflow or division by zero.
+//!
+//! # Rounding
+//!
+//! | quantity                                    | rounding                      |
+//! |---------------------------------------------|-------------------------------|
+//! | collateral released for vTokens             | down                          |
+//! | asset value, health factor, leverage        | down                          |
+//! | interest index growth                       | up                            |
+//! | scaled debt and debt owed                   | up                            |
+//! | scaled deposits and deposit balances        | down                          |
+//! | liquidation penalty and its bps shares      | down, remainder to liquidator |
+//!
+//! Amounts the protocol pays out round down and amounts owed to it round up, so no
+//! rounding ever creates value. Risk figures round down, which errs towards
+//! liquidating.
+
+#![no_std]
+
+mod decimal;
+pub mod health;
+pub mod interest;
+pub mod liquidation;
+pub mod vtoken;
+
+pub use crate::decimal::Decimal;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+/// `a * b / denominator` with a 256-bit intermediate product
+pub fn mul_div(a: u128, b: u128, denominator: u128, rounding: Rounding) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    let (high, low) = widening_mul(a, b);
+    // The quotient would not fit in 128 bits
+    if high >= denominator {
+        return None;
+    }
+    let (quotient, remainder) = div_rem_wide(high, low, denominator);
+    match rounding {
+        Rounding::Up if remainder != 0 => quotient.checked_add(1),
+        _ => Some(quotient),
+    }
+}
+
+/// `(high, low)` words of the full product
+fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_high, a_low) = (a >> 64, a & MASK);
+    let (b_high, b_low) = (b >> 64, b & MASK);
+
+    let low_low = a_low * b_low;
+    let low_high = a_low * b_high;
+    let high_low = a_high * b_low;
+    let high_high = a_high * b_high;
+
+    let middle = (low_low >> 64) + (low_high & MASK) + (high_low & MASK);
+    let low = (low_low & MASK) | (middle << 64);
+    let high = high_high + (low_high >> 64) + (high_low >> 64) + (middle >> 64);
+    (high, low)
+}
+
+/// Long division of `high * 2^128 + low` by `divisor`, requires `high < divisor`
+fn div_rem_wide(high: u128, low: u128, divisor: u128) -> (u128, u128) {
+    let mut remainder = high;
+    let mut quotient = 0u128;
+    for bit in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+        // With the carry the true remainder is above 2^128 and thus above the divisor
+        if carry == 1 || remainder >= divisor {
+            remainder = remainder.wrapping_sub(divisor);
+            quotient |= 1;
+        }
+    }
+    (quotient, remainder)
+}
+
+/// `bps` basis points of `amount`, rounded down
+pub fn bps_of(amount: u128, bps: u64) -> Option<u128> {
+    mul_div(amount, bps as u128, 10_000, Rounding::Down)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_uses_the_full_product() {
+        assert_eq!(
+            mul_div(u128::MAX, u128::MAX, u128::MAX, Rounding::Down),
+            Some(u128::MAX)
+        );
+        assert_eq!(mul_div(u128::MAX, 2, 2, Rounding::Up), Some(u128::MAX));
+        assert_eq!(mul_div(u128::MAX, 2, 1, Rounding::Down), None);
+        assert_eq!(mul_div(7, 3, 2, Rounding::Down), Some(10));
+        assert_eq!(mul_div(7, 3, 2, Rounding::Up), Some(11));
+        assert_eq!(mul_div(7, 3, 0, Rounding::Down), None);
+    }
+}
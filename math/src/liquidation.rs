@@ -0,0 +1,52 @@
+use crate::bps_of;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LiquidationSplit {
+    pub repaid: u128,
+    /// Debt the seized value could not cover, bad debt
+    pub shortfall: u128,
+    pub penalty: u128,
+    pub insurance_share: u128,
+    pub protocol_share: u128,
+    pub liquidator_reward: u128,
+    /// Credited back to the liquidated account as collateral
+    pub returned_collateral: u128,
+}
+
+/**
+ * @dev How a liquidation splits the seized value, all amounts in the debt token.
+ *
+ * `seized` positions and `collateral` repay the debt first. The penalty on the debt is
+ * taken from what is left, the insurance fund gets `insurance_share_bps` of it, the
+ * treasury `liquidation_fee_bps` of the rest and the liquidator keeps the remainder.
+ * Whatever is left after the penalty goes back to the account.
+ */
+pub fn liquidation_split(
+    seized: u128,
+    collateral: u128,
+    debt: u128,
+    liquidation_penalty_bps: u64,
+    insurance_share_bps: u64,
+    liquidation_fee_bps: u64,
+) -> Option<LiquidationSplit> {
+    let available = seized.checked_add(collateral)?;
+
+    let repaid = available.min(debt);
+    let shortfall = debt - repaid;
+    let surplus = available - repaid;
+
+    let penalty = surplus.min(bps_of(debt, liquidation_penalty_bps)?);
+    let insurance_share = bps_of(penalty, insurance_share_bps)?.min(penalty);
+    let protocol_share =
+        bps_of(penalty - insurance_share, liquidation_fee_bps)?.min(penalty - insurance_share);
+
+    Some(LiquidationSplit {
+        repaid,
+        shortfall,
+        penalty,
+        insurance_share,
+        protocol_share,
+        liquidator_reward: penalty - insurance_share - protocol_share,
+        returned_collateral: surplus - penalty,
+    })
+}
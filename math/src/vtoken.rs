@@ -0,0 +1,14 @@
+//! Conversion between deposited collateral and the unminted vToken it backs.
+
+/// vTokens credited per unit of deposited collateral
+pub const V_TOKENS_PER_COLLATERAL: u128 = 10;
+
+/// vTokens backed by `collateral`, exact
+pub fn to_v_tokens(collateral: u128) -> Option<u128> {
+    collateral.checked_mul(V_TOKENS_PER_COLLATERAL)
+}
+
+/// Collateral released for `v_tokens`, rounded down
+pub fn to_collateral(v_tokens: u128) -> u128 {
+    v_tokens / V_TOKENS_PER_COLLATERAL
+}
@@ -0,0 +1,146 @@
+use leverage_math::health::{asset_value, health_factor, leverage};
+use leverage_math::interest::{accrue, debt, deposit, scaled_debt, scaled_deposit};
+use leverage_math::liquidation::liquidation_split;
+use leverage_math::vtoken::{to_collateral, to_v_tokens};
+use leverage_math::{mul_div, Decimal, Rounding};
+use proptest::prelude::*;
+
+fn decimal() -> impl Strategy<Value = Decimal> {
+    // Up to 10^12 with full precision
+    (0u128..=1_000_000_000_000_000_000_000_000_000_000).prop_map(Decimal::raw)
+}
+
+fn debt_value() -> impl Strategy<Value = Decimal> {
+    // From 10^-6, so health factors fit
+    (1_000_000_000_000u128..=1_000_000_000_000_000_000_000_000_000_000).prop_map(Decimal::raw)
+}
+
+fn price() -> impl Strategy<Value = Decimal> {
+    (0u128..=5_000_000_000_000_000_000).prop_map(Decimal::raw)
+}
+
+fn index() -> impl Strategy<Value = Decimal> {
+    // Between 1 and 100
+    (1_000_000_000_000_000_000u128..=100_000_000_000_000_000_000).prop_map(Decimal::raw)
+}
+
+proptest! {
+    #[test]
+    fn mul_div_matches_native_arithmetic(a in any::<u64>(), b in any::<u64>(), d in 1u128..) {
+        let product = a as u128 * b as u128;
+        prop_assert_eq!(mul_div(a as u128, b as u128, d, Rounding::Down), Some(product / d));
+        let up = product / d + u128::from(!product.is_multiple_of(d));
+        prop_assert_eq!(mul_div(a as u128, b as u128, d, Rounding::Up), Some(up));
+    }
+
+    #[test]
+    fn mul_div_is_exact_when_it_cancels(a in any::<u128>(), b in 1u128..) {
+        prop_assert_eq!(mul_div(a, b, b, Rounding::Down), Some(a));
+        prop_assert_eq!(mul_div(a, b, b, Rounding::Up), Some(a));
+    }
+
+    #[test]
+    fn rounding_up_exceeds_rounding_down_by_at_most_one(
+        a in any::<u128>(),
+        b in any::<u128>(),
+        d in 1u128..,
+    ) {
+        if let (Some(down), Some(up)) = (
+            mul_div(a, b, d, Rounding::Down),
+            mul_div(a, b, d, Rounding::Up),
+        ) {
+            prop_assert!(up == down || up == down + 1);
+        }
+    }
+
+    #[test]
+    fn v_tokens_round_trip(collateral in 0u128..u128::MAX / 10, dust in 0u128..10) {
+        let v_tokens = to_v_tokens(collateral).unwrap();
+        prop_assert_eq!(to_collateral(v_tokens), collateral);
+        // Burning less than a full unit never releases collateral for it
+        prop_assert_eq!(to_collateral(v_tokens + dust), collateral);
+        prop_assert_eq!(to_v_tokens(u128::MAX / 10 + 1), None);
+    }
+
+    #[test]
+    fn health_factor_is_monotonic(
+        collateral in decimal(),
+        position in decimal(),
+        debt in debt_value(),
+        extra in decimal(),
+    ) {
+        let health = health_factor(collateral, position, debt).unwrap();
+        let richer = health_factor(collateral.checked_add(extra).unwrap(), position, debt).unwrap();
+        prop_assert!(richer >= health);
+        let poorer = health_factor(collateral, position, debt.checked_add(extra).unwrap()).unwrap();
+        prop_assert!(poorer <= health);
+    }
+
+    #[test]
+    fn leverage_is_at_least_one(collateral in decimal(), position in decimal(), debt in decimal()) {
+        if let Some(leverage) = leverage(collateral, position, debt) {
+            prop_assert!(leverage >= Decimal::one());
+        }
+    }
+
+    #[test]
+    fn asset_value_is_additive(a in any::<u64>(), b in any::<u64>(), price in price()) {
+        let sum = asset_value(a as u128 + b as u128, price).unwrap();
+        let parts = asset_value(a as u128, price)
+            .unwrap()
+            .checked_add(asset_value(b as u128, price).unwrap())
+            .unwrap();
+        // Whole amounts are valued exactly
+        prop_assert_eq!(sum, parts);
+    }
+
+    #[test]
+    fn index_never_decreases(
+        index in index(),
+        rate in 0u128..=10_000_000_000_000_000_000,
+        elapsed in 0u64..=10 * 31_536_000,
+    ) {
+        let accrued = accrue(index, Decimal::raw(rate), elapsed).unwrap();
+        prop_assert!(accrued >= index);
+    }
+
+    #[test]
+    fn scaled_balances_favour_the_protocol(amount in any::<u64>(), index in index()) {
+        let amount = amount as u128;
+        // A borrower never owes less than they borrowed
+        prop_assert!(debt(scaled_debt(amount, index).unwrap(), index).unwrap() >= amount);
+        // A depositor never gets back more than they deposited
+        prop_assert!(deposit(scaled_deposit(amount, index).unwrap(), index).unwrap() <= amount);
+    }
+
+    #[test]
+    fn liquidation_split_conserves_value(
+        seized in any::<u64>(),
+        collateral in any::<u64>(),
+        debt in any::<u64>(),
+        penalty_bps in 0u64..=10_000,
+        insurance_bps in 0u64..=10_000,
+        fee_bps in 0u64..=10_000,
+    ) {
+        let split = liquidation_split(
+            seized as u128,
+            collateral as u128,
+            debt as u128,
+            penalty_bps,
+            insurance_bps,
+            fee_bps,
+        )
+        .unwrap();
+
+        prop_assert_eq!(split.repaid + split.shortfall, debt as u128);
+        prop_assert_eq!(
+            split.repaid + split.penalty + split.returned_collateral,
+            seized as u128 + collateral as u128
+        );
+        prop_assert_eq!(
+            split.insurance_share + split.protocol_share + split.liquidator_reward,
+            split.penalty
+        );
+        prop_assert!(split.shortfall == 0 || split.penalty == 0);
+    }
+}
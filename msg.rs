@@ -1,10 +1,17 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Binary, Uint128};
-use cw20::{Cw20Coin, Logo, MinterResponse};
+use cosmwasm_std::{Addr, Binary, Decimal, Timestamp, Uint128, Uint512};
+use cw20::{Cw20Coin, Expiration, Logo, MinterResponse};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::paillier::PublicKey;
+use crate::state::{
+    BadDebtStats, ConditionalOrder, ConditionalOrderKind, FeeConfig, InsuranceConfig, MarginMode,
+    OperatorScope, OrderCommitment, PriceFeedConfig, PriceSource, PriceSources, RevealedOrder,
+    RiskConfig, SwapConfig, TokenCaps, TwapConfig,
+};
+
 #[cw_serde]
 pub struct InstantiateMsg {
     pub token_contract_address: String,
@@ -32,16 +39,140 @@ pub struct Cw20Instantiate {
 pub enum ExecuteMsg {
     ListTokenOnLeverage {
         token_address: String,
+        /// Bank denom a native token is deposited in, `None` lists a CW20
+        native_denom: Option<String>,
     },
     Receive(Cw20ReceiveMsg),
     DepositNative {
         token_address: String,
+        sub_account: Option<u32>,
     },
     Borrow(TokenData),
     ExecuteOrder(OrderExecute),
     Repay(TokenData),
     Burn(TokenData),
     WithdrawToken(WithdrawData),
+    UpdateAssetPrice {
+        asset: Addr,
+        price: Decimal,
+    },
+    PlaceConditionalOrder(ConditionalOrderData),
+    CancelConditionalOrder {
+        order_id: u64,
+        on_behalf_of: Option<Addr>,
+        sub_account: Option<u32>,
+    },
+    ExecuteConditionalOrder {
+        owner: Addr,
+        order_id: u64,
+    },
+    UpdatePaillierPublicKey {
+        n: Uint512,
+    },
+    CommitEncryptedOrder {
+        order_id: String,
+        encrypted_order_value: Uint512,
+        on_behalf_of: Option<Addr>,
+        sub_account: Option<u32>,
+    },
+    CommitOrder {
+        commitment: Binary,
+        margin_token: Addr,
+        margin_amount: Uint128,
+        on_behalf_of: Option<Addr>,
+        sub_account: Option<u32>,
+    },
+    RevealOrder {
+        commitment_id: u64,
+        order: RevealedOrderData,
+        on_behalf_of: Option<Addr>,
+        sub_account: Option<u32>,
+    },
+    ExpireOrderCommitment {
+        commitment_id: u64,
+    },
+    SettleOrderBatch {
+        height: u64,
+    },
+    UpdateRevealWindow {
+        blocks: u64,
+    },
+    UpdateSwapConfig {
+        router: Addr,
+        max_slippage_bps: u64,
+    },
+    UpdateFeeConfig(FeeConfig),
+    ClaimFees {
+        asset: Addr,
+        amount: Option<Uint128>,
+    },
+    UpdateRiskConfig(RiskConfig),
+    UpdateInsuranceConfig(InsuranceConfig),
+    UpdateTokenCaps {
+        token_address: Addr,
+        caps: TokenCaps,
+    },
+    Liquidate {
+        user_address: Addr,
+        debt_token: Addr,
+    },
+    GrantOperator {
+        operator: Addr,
+        scope: OperatorScope,
+        expires: Option<Expiration>,
+    },
+    RevokeOperator {
+        operator: Addr,
+    },
+    OpenSubAccount {
+        margin_mode: MarginMode,
+    },
+    SetMarginMode {
+        sub_account: u32,
+        margin_mode: MarginMode,
+    },
+    TransferCollateral {
+        token_address: Addr,
+        amount: Uint128,
+        from_sub_account: u32,
+        to_sub_account: u32,
+    },
+    OpenLeveragedPosition {
+        /// Listed name of the native collateral attached as funds
+        collateral: String,
+        /// Amount borrowed as a multiple of the deposit
+        target_leverage: Decimal,
+        asset_to_buy: Addr,
+        min_out: Uint128,
+        sub_account: Option<u32>,
+    },
+    ClosePosition(ClosePositionData),
+    FlashLoan {
+        asset: Addr,
+        amount: Uint128,
+        callback: Binary,
+    },
+    UpdatePriceFeedConfig(PriceFeedConfig),
+    /// Prices pushed by a feeder, each observed at its `publish_time`
+    FeedPrices {
+        prices: Vec<PriceUpdate>,
+    },
+    UpdateTwapConfig(TwapConfig),
+    UpdatePriceSources {
+        asset: Addr,
+        price_sources: PriceSources,
+    },
+    /// Pulls the latest price of `asset` from each of its oracle sources
+    SyncOraclePrices {
+        asset: Addr,
+    },
+}
+
+#[cw_serde]
+pub struct PriceUpdate {
+    pub asset: Addr,
+    pub price: Decimal,
+    pub publish_time: Timestamp,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -49,6 +180,10 @@ pub enum ExecuteMsg {
 pub struct TokenData {
     pub token_address: Addr,
     pub token_amount: Uint128,
+    /// Account an operator acts on, the sender's own account when `None`
+    pub on_behalf_of: Option<Addr>,
+    /// Sub-account of that account, the main account when `None`
+    pub sub_account: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -57,7 +192,10 @@ pub struct WithdrawData {
     pub token_address: Addr,
     pub token_amount: Uint128,
     pub withdraw_type: String,
-    pub native: Option<String>
+    pub native: Option<String>,
+    /// Account an operator withdraws from, the tokens always go to that account
+    pub on_behalf_of: Option<Addr>,
+    pub sub_account: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -75,11 +213,40 @@ impl fmt::Display for Cw20ReceiveMsg {
             "sender:{} amount:{} msg:{}",
             self.sender,
             self.amount,
-            self.msg.to_string()
+            self.msg
         )
     }
 }
 
+/// Optional CW20 `Send` hook of a deposit
+#[cw_serde]
+pub struct DepositReceiveMsg {
+    pub sub_account: Option<u32>,
+    /// Borrows against the deposit and swaps the loan, like `OpenLeveragedPosition`
+    pub open_position: Option<LeveragedPositionData>,
+}
+
+#[cw_serde]
+pub struct ClosePositionData {
+    /// Asset the position is held in
+    pub asset: Addr,
+    /// Borrowed token the position is sold into and repaid in
+    pub debt_token: Addr,
+    /// Share of the position sold and of the debt repaid, 1 closes it completely
+    pub fraction: Decimal,
+    pub min_out: Uint128,
+    /// Withdraws all collateral in `debt_token` once the debt is repaid, "fungible" or "native"
+    pub withdraw_type: Option<String>,
+    pub sub_account: Option<u32>,
+}
+
+#[cw_serde]
+pub struct LeveragedPositionData {
+    pub target_leverage: Decimal,
+    pub asset_to_buy: Addr,
+    pub min_out: Uint128,
+}
+
 #[cw_serde]
 pub struct DepositCollateralReceive {
     pub message: String,
@@ -94,7 +261,80 @@ pub struct OrderExecute {
     pub token_in: Addr,
     pub token_out: Addr,
     pub amount_in: Uint128,
+    /// Settled amount, or the minimum accepted output when a swap router is configured
     pub amount_out: Uint128,
+    pub encrypted_order: Option<EncryptedOrderReveal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct EncryptedOrderReveal {
+    pub order_id: String,
+    /// Paillier randomness of the committed ciphertext, proving it decrypts to `order_amount`
+    pub randomness: Uint512,
+    /// Committed amount when `amount_in` fills it partially, `amount_in` when `None`
+    #[serde(default)]
+    pub order_amount: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct ConditionalOrderData {
+    pub token_in: Addr,
+    pub token_out: Addr,
+    pub amount_in: Option<Uint128>,
+    pub kind: ConditionalOrderKind,
+    pub bounty: Uint128,
+    pub on_behalf_of: Option<Addr>,
+    pub sub_account: Option<u32>,
+}
+
+/// Preimage of an order commitment: `sha256(sender ++ to_json_vec(&RevealedOrderData))`
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct RevealedOrderData {
+    pub token_in: Addr,
+    pub token_out: Addr,
+    pub amount_in: Uint128,
+    pub limit_price: Decimal,
+    pub salt: String,
+}
+
+/// Callback a flash loan borrower must implement. Principal plus `fee` has to be back
+/// in the leverage contract when it returns.
+#[cw_serde]
+pub enum FlashLoanReceiverExecuteMsg {
+    FlashLoanCallback {
+        /// CW20 address or native denom of the loaned asset
+        asset: String,
+        amount: Uint128,
+        fee: Uint128,
+        msg: Binary,
+    },
+}
+
+/// Message understood by the configured swap router. CW20 input is sent to the router
+/// with this message as the `Send` hook, native input is attached as funds.
+#[cw_serde]
+pub enum SwapRouterExecuteMsg {
+    Swap {
+        output_asset: String,
+        min_output: Uint128,
+    },
+}
+
+/// Query understood by an oracle source. `asset` is the CW20 address or native denom.
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum PriceOracleQueryMsg {
+    #[returns(PriceOracleResponse)]
+    Price { asset: String },
+}
+
+#[cw_serde]
+pub struct PriceOracleResponse {
+    pub price: Decimal,
+    pub publish_time: Timestamp,
 }
 
 #[cw_serde]
@@ -113,7 +353,138 @@ pub enum QueryMsg {
     UserBorrowTokenBalance(QueryTokenData),
 
     #[returns(Uint128)]
-    UserVTokenBalance(QueryTokenData)
+    UserVTokenBalance(QueryTokenData),
+
+    #[returns(Vec<String>)]
+    ListedTokens {},
+
+    #[returns(Uint128)]
+    UserPositionBalance(QueryTokenData),
+
+    #[returns(Decimal)]
+    AssetPrice { asset: Addr },
+
+    /// Price `Liquidate` checks health at: the TWAP over the liquidation window when one
+    /// is configured, the asset price otherwise
+    #[returns(Decimal)]
+    LiquidationPrice { asset: Addr },
+
+    #[returns(Vec<ConditionalOrder>)]
+    UserConditionalOrders { user_address: Addr },
+
+    #[returns(Option<PublicKey>)]
+    PaillierPublicKey {},
+
+    #[returns(Option<Uint512>)]
+    EncryptedOrder {
+        user_address: Addr,
+        order_id: String,
+    },
+
+    #[returns(Option<OrderCommitment>)]
+    OrderCommitment { commitment_id: u64 },
+
+    #[returns(Vec<RevealedOrder>)]
+    RevealedOrders { height: u64 },
+
+    #[returns(Option<SwapConfig>)]
+    SwapConfig {},
+
+    #[returns(FeeConfig)]
+    FeeConfig {},
+
+    #[returns(Vec<AssetAmount>)]
+    AccumulatedFees {},
+
+    #[returns(RiskConfig)]
+    RiskConfig {},
+
+    #[returns(AccountHealth)]
+    AccountHealth { user_address: Addr },
+
+    #[returns(InsuranceFundResponse)]
+    InsuranceFund { asset: Addr },
+
+    #[returns(TokenCapacityResponse)]
+    TokenCapacity {
+        token_address: Addr,
+        user_address: Option<Addr>,
+    },
+
+    #[returns(Vec<OperatorGrantResponse>)]
+    OperatorGrants { owner: Addr },
+
+    #[returns(Vec<SubAccountResponse>)]
+    SubAccounts { owner: Addr },
+
+    /// Outstanding borrows, paginated by (token, account)
+    #[returns(Vec<BorrowResponse>)]
+    Borrows {
+        start_after: Option<(Addr, Addr)>,
+        limit: Option<u32>,
+    },
+
+    #[returns(AccountLedgersResponse)]
+    AccountLedgers { user_address: Addr },
+
+    #[returns(InsuranceConfig)]
+    InsuranceConfig {},
+
+    #[returns(PriceFeedConfig)]
+    PriceFeedConfig {},
+
+    /// Last price of `asset` with its age, `None` when it was never priced
+    #[returns(Option<PriceInfoResponse>)]
+    PriceInfo { asset: Addr },
+
+    #[returns(TwapConfig)]
+    TwapConfig {},
+
+    /// Settlement price of `asset` averaged over the last `window` seconds, `None` when the
+    /// observations do not cover the window
+    #[returns(Option<Decimal>)]
+    Twap { asset: Addr, window: u64 },
+
+    /// Every price source of `asset` with its last price and the aggregate
+    #[returns(PriceSourcesResponse)]
+    PriceSources { asset: Addr },
+}
+
+#[cw_serde]
+pub struct AccountHealth {
+    pub collateral_value: Decimal,
+    pub position_value: Decimal,
+    pub debt_value: Decimal,
+    /// `(collateral_value + position_value) / debt_value`, `None` without debt
+    pub health_factor: Option<Decimal>,
+}
+
+#[cw_serde]
+pub struct InsuranceFundResponse {
+    pub balance: Uint128,
+    pub bad_debt: BadDebtStats,
+    pub socialized_loss_index: Decimal,
+    pub total_deposits: Uint128,
+}
+
+#[cw_serde]
+pub struct BorrowResponse {
+    pub token_address: Addr,
+    pub user_address: Addr,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct AccountLedgersResponse {
+    pub collateral: Vec<AssetAmount>,
+    pub debt: Vec<AssetAmount>,
+    pub positions: Vec<AssetAmount>,
+}
+
+#[cw_serde]
+pub struct AssetAmount {
+    pub asset: Addr,
+    pub amount: Uint128,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -150,3 +521,60 @@ pub struct GetListedTokenResponse {
 
 #[cw_serde]
 pub struct UserLeverageData {}
+
+/// Remaining capacities are `None` when uncapped, user capacities only with a user and
+/// counted over every sub-account of its owner
+#[cw_serde]
+pub struct TokenCapacityResponse {
+    pub caps: TokenCaps,
+    pub total_supplied: Uint128,
+    pub total_borrowed: Uint128,
+    pub remaining_supply: Option<Uint128>,
+    pub remaining_borrow: Option<Uint128>,
+    pub remaining_user_supply: Option<Uint128>,
+    pub remaining_user_borrow: Option<Uint128>,
+}
+
+#[cw_serde]
+pub struct OperatorGrantResponse {
+    pub operator: Addr,
+    pub scope: OperatorScope,
+    pub expires: Expiration,
+}
+
+#[cw_serde]
+pub struct SubAccountResponse {
+    pub sub_account: u32,
+    /// Address the sub-account is keyed under in every ledger and per-user query
+    pub account: Addr,
+    pub margin_mode: MarginMode,
+}
+
+#[cw_serde]
+pub struct PriceSourceState {
+    pub source: PriceSource,
+    /// `None` when the source has no price yet, or for a TWAP not covering its window
+    pub price: Option<Decimal>,
+    /// `None` for TWAPs and for feed prices set before timestamps were recorded
+    pub publish_time: Option<Timestamp>,
+    pub fresh: bool,
+}
+
+#[cw_serde]
+pub struct PriceSourcesResponse {
+    pub sources: Vec<PriceSourceState>,
+    pub quorum: u32,
+    /// Median of the fresh sources, or the fallback below the quorum
+    pub price: Option<Decimal>,
+    /// Whether risk-increasing actions can be priced
+    pub quorum_met: bool,
+}
+
+#[cw_serde]
+pub struct PriceInfoResponse {
+    pub price: Decimal,
+    /// `None` for prices set before timestamps were recorded
+    pub publish_time: Option<Timestamp>,
+    /// Whether actions would reject the price as too old
+    pub stale: bool,
+}
@@ -0,0 +1,83 @@
+//! Aggregation of an asset's price sources, see `PriceSources` for how they are configured.
+
+use cosmwasm_std::{Decimal, Timestamp};
+
+/// How an action reads prices
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pricing {
+    /// Block time sources must be fresh at, `None` reports the last prices without checks
+    pub at: Option<Timestamp>,
+    /// Settlement TWAP window that replaces the aggregate, refused where no observation
+    /// covers it
+    pub twap_window: Option<u64>,
+    /// Refuses to fall back when fewer fresh sources than the quorum are left
+    pub risk_increasing: bool,
+}
+
+impl Pricing {
+    pub fn at(time: Timestamp) -> Self {
+        Pricing {
+            at: Some(time),
+            ..Pricing::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Aggregate {
+    /// Median of at least a quorum of fresh prices
+    Median(Decimal),
+    /// First fresh price in fallback order, fewer fresh prices than the quorum
+    Fallback(Decimal),
+    /// No fresh price
+    Unavailable,
+}
+
+/// Aggregates source prices given in fallback order, `None` for sources that are not fresh
+pub fn aggregate(prices: &[Option<Decimal>], quorum: u32) -> Aggregate {
+    let fresh: Vec<Decimal> = prices.iter().flatten().copied().collect();
+    if fresh.len() >= quorum as usize {
+        if let Some(price) = median(fresh.clone()) {
+            return Aggregate::Median(price);
+        }
+    }
+    match fresh.first() {
+        Some(price) => Aggregate::Fallback(*price),
+        None => Aggregate::Unavailable,
+    }
+}
+
+/// Middle price, the mean of the two middle prices for an even count, `None` when empty
+pub fn median(mut prices: Vec<Decimal>) -> Option<Decimal> {
+    prices.sort();
+    let mid = prices.len() / 2;
+    match prices.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(prices[mid]),
+        _ => Some(prices[mid - 1] + (prices[mid] - prices[mid - 1]) / Decimal::percent(200)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_of_fresh_prices_needs_quorum() {
+        let price = |price: &str| Some(price.parse::<Decimal>().unwrap());
+
+        assert_eq!(
+            aggregate(&[price("2"), None, price("1.8"), price("2.1")], 2),
+            Aggregate::Median(Decimal::percent(200))
+        );
+        assert_eq!(
+            aggregate(&[price("2"), price("1.8")], 2),
+            Aggregate::Median(Decimal::percent(190))
+        );
+        assert_eq!(
+            aggregate(&[None, price("1.8"), price("2.1")], 3),
+            Aggregate::Fallback(Decimal::percent(180))
+        );
+        assert_eq!(aggregate(&[None, None], 1), Aggregate::Unavailable);
+    }
+}
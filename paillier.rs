@@ -0,0 +1,193 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::Uint512;
+
+use crate::error::ContractError;
+
+/// Paillier public key with generator `g = n + 1`, matching the key the frontend
+/// encrypts order values with (`paillier-bigint`).
+///
+/// `n` must stay below 2^255 so that every intermediate value modulo `n^2` fits into
+/// a `Uint512` without overflowing.
+#[cw_serde]
+pub struct PublicKey {
+    pub n: Uint512,
+}
+
+impl PublicKey {
+    pub fn new(n: Uint512) -> Result<Self, ContractError> {
+        let max_n = Uint512::one() << 255;
+        if n <= Uint512::from(2u128) || n >= max_n || is_even(n) {
+            return Err(ContractError::InvalidPaillierKey {});
+        }
+        Ok(PublicKey { n })
+    }
+
+    pub fn n_squared(&self) -> Uint512 {
+        self.n * self.n
+    }
+
+    /**
+     * @dev Encrypts `plaintext` with the given randomness: `c = g^m * r^n mod n^2`.
+     *
+     * Since `g = n + 1`, `g^m mod n^2` reduces to `1 + m * n`.
+     */
+    pub fn encrypt_with(
+        &self,
+        plaintext: Uint512,
+        randomness: Uint512,
+    ) -> Result<Uint512, ContractError> {
+        if plaintext >= self.n || !self.is_valid_randomness(randomness) {
+            return Err(ContractError::InvalidCiphertext {});
+        }
+        let n_squared = self.n_squared();
+        let g_m = (Uint512::one() + mul_mod(plaintext, self.n, n_squared)) % n_squared;
+        let r_n = pow_mod(randomness, self.n, n_squared);
+        Ok(mul_mod(g_m, r_n, n_squared))
+    }
+
+    /// Homomorphic addition: the result decrypts to the sum of both plaintexts
+    pub fn add(&self, lhs: Uint512, rhs: Uint512) -> Result<Uint512, ContractError> {
+        self.ensure_ciphertext(lhs)?;
+        self.ensure_ciphertext(rhs)?;
+        Ok(mul_mod(lhs, rhs, self.n_squared()))
+    }
+
+    /**
+     * @dev Verifies a decryption proof for `ciphertext`.
+     *
+     * The proof is the encryption randomness `r`, which the holder of the private key
+     * can always recover (`r = c^(n^-1 mod lambda) mod n`). The ciphertext decrypts to
+     * `plaintext` exactly when re-encrypting `plaintext` with `r` gives it back.
+     */
+    pub fn verify_decryption(
+        &self,
+        ciphertext: Uint512,
+        plaintext: Uint512,
+        randomness: Uint512,
+    ) -> Result<bool, ContractError> {
+        self.ensure_ciphertext(ciphertext)?;
+        match self.encrypt_with(plaintext, randomness) {
+            Ok(expected) => Ok(expected == ciphertext),
+            Err(_) => Ok(false),
+        }
+    }
+
+    pub fn ensure_ciphertext(&self, ciphertext: Uint512) -> Result<(), ContractError> {
+        if ciphertext.is_zero() || ciphertext >= self.n_squared() || gcd(ciphertext, self.n) != Uint512::one() {
+            return Err(ContractError::InvalidCiphertext {});
+        }
+        Ok(())
+    }
+
+    fn is_valid_randomness(&self, randomness: Uint512) -> bool {
+        !randomness.is_zero() && randomness < self.n && gcd(randomness, self.n) == Uint512::one()
+    }
+}
+
+fn is_even(value: Uint512) -> bool {
+    value.to_be_bytes()[63] & 1 == 0
+}
+
+fn gcd(mut a: Uint512, mut b: Uint512) -> Uint512 {
+    while !b.is_zero() {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+/// `a * b mod modulus` by double-and-add, valid while `2 * modulus` fits in 512 bits
+fn mul_mod(a: Uint512, b: Uint512, modulus: Uint512) -> Uint512 {
+    let a = a % modulus;
+    let mut result = Uint512::zero();
+    for byte in b.to_be_bytes().iter().skip_while(|byte| **byte == 0) {
+        for bit in (0..8).rev() {
+            result = add_mod(result, result, modulus);
+            if (byte >> bit) & 1 == 1 {
+                result = add_mod(result, a, modulus);
+            }
+        }
+    }
+    result
+}
+
+fn add_mod(a: Uint512, b: Uint512, modulus: Uint512) -> Uint512 {
+    let sum = a + b;
+    if sum >= modulus {
+        sum - modulus
+    } else {
+        sum
+    }
+}
+
+fn pow_mod(base: Uint512, exponent: Uint512, modulus: Uint512) -> Uint512 {
+    let base = base % modulus;
+    let mut result = Uint512::one() % modulus;
+    for byte in exponent.to_be_bytes().iter().skip_while(|byte| **byte == 0) {
+        for bit in (0..8).rev() {
+            result = mul_mod(result, result, modulus);
+            if (byte >> bit) & 1 == 1 {
+                result = mul_mod(result, base, modulus);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // Public key hard-coded in constant/constant.js
+    fn frontend_key() -> PublicKey {
+        PublicKey::new(Uint512::from_str("2110635290356708079658926219106600858277").unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn encrypt_matches_reference_ciphertext() {
+        let key = frontend_key();
+        let ciphertext = key
+            .encrypt_with(Uint512::from(250u128), Uint512::from(123456789u128))
+            .unwrap();
+        assert_eq!(
+            ciphertext,
+            Uint512::from_str(
+                "978255687296028892500921178445080651556975604145982087130567304742788996324464"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn add_and_verify_decryption() {
+        let key = frontend_key();
+        let c1 = key
+            .encrypt_with(Uint512::from(250u128), Uint512::from(123456789u128))
+            .unwrap();
+        let c2 = key
+            .encrypt_with(Uint512::from(750u128), Uint512::from(987654321u128))
+            .unwrap();
+
+        let sum = key.add(c1, c2).unwrap();
+        let sum_randomness = Uint512::from(121932631112635269u128);
+
+        assert!(key
+            .verify_decryption(sum, Uint512::from(1000u128), sum_randomness)
+            .unwrap());
+        assert!(!key
+            .verify_decryption(sum, Uint512::from(999u128), sum_randomness)
+            .unwrap());
+        assert!(!key
+            .verify_decryption(c1, Uint512::from(250u128), Uint512::from(7u128))
+            .unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_key() {
+        assert!(PublicKey::new(Uint512::from(36u128)).is_err());
+        assert!(PublicKey::new(Uint512::one() << 255).is_err());
+    }
+}
@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+
+use crate::Quote;
+
+/// Quotes by symbol, each served for `ttl` seconds after it was fetched
+pub struct QuoteCache {
+    ttl: u64,
+    quotes: HashMap<String, (u64, Quote)>,
+}
+
+impl QuoteCache {
+    pub fn new(ttl: u64) -> Self {
+        QuoteCache {
+            ttl,
+            quotes: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, symbol: &str, now: u64) -> Option<Quote> {
+        match self.quotes.get(symbol) {
+            Some((fetched_at, quote)) if now < fetched_at.saturating_add(self.ttl) => {
+                Some(quote.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, symbol: &str, quote: Quote, now: u64) {
+        self.quotes.insert(symbol.to_string(), (now, quote));
+    }
+}
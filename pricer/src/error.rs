@@ -0,0 +1,33 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PricerError {
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Provider error: {error}")]
+    Provider { error: String },
+
+    #[error("No quote for {symbol}: {reason}")]
+    QuoteNotAvailable { symbol: String, reason: String },
+
+    #[error("Relaying prices failed: {error}")]
+    Relay { error: String },
+}
+
+impl IntoResponse for PricerError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            PricerError::QuoteNotAvailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
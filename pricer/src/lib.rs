@@ -0,0 +1,124 @@
+//! Price service replacing the frontend's CoinMarketCap `price-conversion` route.
+//!
+//! Every symbol is quoted in USD, the contract's price unit, by the first provider in the
+//! configured order that has a quote for it. Quotes are cached for `ttl` seconds so the
+//! frontend's conversions do not reach the providers on every keystroke. A conversion is
+//! the sell token's quote over the buy token's, served in the shape the CoinMarketCap
+//! route returned.
+//!
+//! With a relay configured the service also pushes its quotes into the contract's price
+//! feed with `FeedPrices`, signed by a whitelisted feeder key, so the contract sees the
+//! same prices as the frontend.
+
+pub mod cache;
+mod error;
+pub mod provider;
+pub mod relay;
+pub mod server;
+
+pub use crate::error::PricerError;
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cosmwasm_std::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::QuoteCache;
+use crate::provider::PriceProvider;
+
+/// USD price of a symbol with the time, in unix seconds, the provider last updated it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Quote {
+    pub price: Decimal,
+    pub last_updated: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConversionQuote {
+    /// A number rather than a decimal string, as CoinMarketCap served it to the frontend
+    pub price: f64,
+    pub last_updated: u64,
+}
+
+/// One unit of `symbol` in each symbol of `quote`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Conversion {
+    pub symbol: String,
+    pub amount: u64,
+    pub last_updated: u64,
+    pub quote: BTreeMap<String, ConversionQuote>,
+}
+
+pub struct Pricer {
+    providers: Vec<Box<dyn PriceProvider + Send>>,
+    cache: QuoteCache,
+}
+
+impl Pricer {
+    /// `providers` are asked in order, `ttl` is how long a quote is served from the cache
+    pub fn new(providers: Vec<Box<dyn PriceProvider + Send>>, ttl: u64) -> Self {
+        Pricer {
+            providers,
+            cache: QuoteCache::new(ttl),
+        }
+    }
+
+    /// Quote of `symbol` at `now`, from the cache while it is fresh
+    pub fn quote(&mut self, symbol: &str, now: u64) -> Result<Quote, PricerError> {
+        let symbol = symbol.to_uppercase();
+        if let Some(quote) = self.cache.get(&symbol, now) {
+            return Ok(quote);
+        }
+
+        let mut errors = vec![];
+        for provider in self.providers.iter() {
+            match provider.quote(&symbol) {
+                Ok(quote) => {
+                    self.cache.insert(&symbol, quote.clone(), now);
+                    return Ok(quote);
+                }
+                Err(error) => errors.push(format!("{}: {}", provider.name(), error)),
+            }
+        }
+        if errors.is_empty() {
+            errors.push("no providers".to_string());
+        }
+        Err(PricerError::QuoteNotAvailable {
+            symbol,
+            reason: errors.join("; "),
+        })
+    }
+
+    /// Price of one `sell` token in `buy` tokens
+    pub fn convert(&mut self, sell: &str, buy: &str, now: u64) -> Result<Conversion, PricerError> {
+        let sell_quote = self.quote(sell, now)?;
+        let buy_quote = self.quote(buy, now)?;
+        let price = sell_quote.price.checked_div(buy_quote.price).map_err(|_| {
+            PricerError::QuoteNotAvailable {
+                symbol: buy.to_uppercase(),
+                reason: "price is zero".to_string(),
+            }
+        })?;
+        let last_updated = sell_quote.last_updated.min(buy_quote.last_updated);
+
+        Ok(Conversion {
+            symbol: sell.to_uppercase(),
+            amount: 1,
+            last_updated,
+            quote: BTreeMap::from([(
+                buy.to_uppercase(),
+                ConversionQuote {
+                    price: price.to_string().parse().unwrap_or_default(),
+                    last_updated,
+                },
+            )]),
+        })
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
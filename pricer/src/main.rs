@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use cosmwasm_std::Addr;
+use leverage_pricer::provider::{CoinMarketCap, FileProvider, PriceProvider};
+use leverage_pricer::relay::{CliRelay, Relayer};
+use leverage_pricer::server::router;
+use leverage_pricer::{unix_now, Pricer};
+
+/// Providers are asked in the order given. With `--contract` the quotes of every `--token`
+/// are relayed into the contract's price feed, signed by the feeder key `--from`.
+const USAGE: &str = "usage: [CMC_API_KEY=<key>] leverage-pricer \
+    (--prices-file <path> | --coinmarketcap)... [--ttl 60] [--listen 0.0.0.0:5001] \
+    [--contract <address> --from <key> --chain-id <id> --token <SYMBOL=address>... \
+    [--binary osmosisd] [--node http://localhost:26657] [--gas-prices 0.025uosmo] \
+    [--relay-interval 30]]";
+
+struct Args {
+    providers: Vec<Box<dyn PriceProvider + Send>>,
+    ttl: u64,
+    listen: String,
+    relay: Option<CliRelay>,
+    tokens: BTreeMap<String, Addr>,
+    relay_interval: u64,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut providers: Vec<Box<dyn PriceProvider + Send>> = vec![];
+    let mut ttl = 60;
+    let mut listen = "0.0.0.0:5001".to_string();
+    let mut relay = CliRelay {
+        binary: "osmosisd".to_string(),
+        node: "http://localhost:26657".to_string(),
+        chain_id: String::new(),
+        contract: String::new(),
+        from: String::new(),
+        gas_prices: "0.025uosmo".to_string(),
+    };
+    let mut tokens = BTreeMap::new();
+    let mut relay_interval = 30;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        if flag == "--coinmarketcap" {
+            let api_key = std::env::var("CMC_API_KEY").ok()?;
+            providers.push(Box::new(CoinMarketCap::new(api_key)));
+            continue;
+        }
+        let value = args.next()?;
+        match flag.as_str() {
+            "--prices-file" => providers.push(Box::new(FileProvider {
+                path: PathBuf::from(value),
+            })),
+            "--ttl" => ttl = value.parse().ok()?,
+            "--listen" => listen = value,
+            "--binary" => relay.binary = value,
+            "--node" => relay.node = value,
+            "--chain-id" => relay.chain_id = value,
+            "--contract" => relay.contract = value,
+            "--from" => relay.from = value,
+            "--gas-prices" => relay.gas_prices = value,
+            "--token" => {
+                let (symbol, address) = value.split_once('=')?;
+                tokens.insert(symbol.to_uppercase(), Addr::unchecked(address));
+            }
+            "--relay-interval" => relay_interval = value.parse().ok()?,
+            _ => return None,
+        }
+    }
+    if providers.is_empty() {
+        return None;
+    }
+    let relay = if relay.contract.is_empty() {
+        None
+    } else if relay.from.is_empty() || relay.chain_id.is_empty() || tokens.is_empty() {
+        return None;
+    } else {
+        Some(relay)
+    };
+
+    Some(Args {
+        providers,
+        ttl,
+        listen,
+        relay,
+        tokens,
+        relay_interval,
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+    let pricer = Arc::new(Mutex::new(Pricer::new(args.providers, args.ttl)));
+
+    if let Some(relay) = args.relay {
+        let pricer = pricer.clone();
+        let mut relayer = Relayer::new(relay, args.tokens);
+        spawn(move || loop {
+            let round = {
+                let mut pricer = pricer
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                relayer.relay_once(&mut pricer, unix_now())
+            };
+            for (symbol, txhash) in round.relayed {
+                println!("relayed {}: {}", symbol, txhash);
+            }
+            for (symbol, error) in round.skipped {
+                eprintln!("could not relay {}: {}", symbol, error);
+            }
+            sleep(Duration::from_secs(args.relay_interval));
+        });
+    }
+
+    let listener = match tokio::net::TcpListener::bind(&args.listen).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1);
+        }
+    };
+    println!("listening on {}", args.listen);
+    if let Err(error) = axum::serve(listener, router(pricer)).await {
+        eprintln!("{}", error);
+        exit(1);
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use cosmwasm_std::Decimal;
+use serde_json::Value;
+
+use crate::{PricerError, Quote};
+
+/// Source of USD quotes
+pub trait PriceProvider {
+    fn name(&self) -> &str;
+
+    /// Quote of an upper-case symbol
+    fn quote(&self, symbol: &str) -> Result<Quote, PricerError>;
+}
+
+/**
+ * @dev Quotes read from a JSON file mapping symbols to quotes, e.g.
+ * `{ "OSMO": { "price": "0.45", "last_updated": 1700000000 } }`. The file is read on every
+ * call so it can be rewritten while the service runs. Used by tests and local runs.
+ */
+pub struct FileProvider {
+    pub path: PathBuf,
+}
+
+impl PriceProvider for FileProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn quote(&self, symbol: &str) -> Result<Quote, PricerError> {
+        let quotes: BTreeMap<String, Quote> =
+            serde_json::from_reader(BufReader::new(File::open(&self.path)?))?;
+        quotes
+            .get(symbol)
+            .cloned()
+            .ok_or_else(|| PricerError::Provider {
+                error: format!("{} is not in {}", symbol, self.path.display()),
+            })
+    }
+}
+
+/// CoinMarketCap's latest quotes in USD. The API key is passed in at startup, it never
+/// lives in the source.
+pub struct CoinMarketCap {
+    pub api_key: String,
+    pub base_url: String,
+}
+
+impl CoinMarketCap {
+    pub fn new(api_key: String) -> Self {
+        CoinMarketCap {
+            api_key,
+            base_url: "https://pro-api.coinmarketcap.com".to_string(),
+        }
+    }
+}
+
+impl PriceProvider for CoinMarketCap {
+    fn name(&self) -> &str {
+        "coinmarketcap"
+    }
+
+    fn quote(&self, symbol: &str) -> Result<Quote, PricerError> {
+        let failed = |error: String| PricerError::Provider { error };
+        let url = format!(
+            "{}/v1/cryptocurrency/quotes/latest?symbol={}&convert=USD",
+            self.base_url.trim_end_matches('/'),
+            symbol
+        );
+        let response: Value = ureq::get(&url)
+            .set("X-CMC_PRO_API_KEY", &self.api_key)
+            .call()
+            .map_err(|error| failed(error.to_string()))?
+            .into_json()?;
+
+        let usd = &response["data"][symbol]["quote"]["USD"];
+        let price = match usd["price"].as_f64() {
+            // Decimal takes at most 18 fractional digits
+            Some(price) => Decimal::from_str(&format!("{:.18}", price))
+                .map_err(|error| failed(error.to_string()))?,
+            None => return Err(failed(format!("no USD price for {}", symbol))),
+        };
+        let last_updated = usd["last_updated"]
+            .as_str()
+            .and_then(parse_timestamp)
+            .ok_or_else(|| failed(format!("no update time for {}", symbol)))?;
+
+        Ok(Quote {
+            price,
+            last_updated,
+        })
+    }
+}
+
+/// Unix seconds of a UTC timestamp like `2024-05-01T12:30:00.000Z`
+pub fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.trim_end_matches('Z').split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':');
+    let hours: i64 = time.next()?.parse().ok()?;
+    let minutes: i64 = time.next()?.parse().ok()?;
+    let seconds: f64 = time.next()?.parse().ok()?;
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hours * 3_600 + minutes * 60 + seconds as i64;
+    u64::try_from(seconds).ok()
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use cosmwasm_std::{Addr, Timestamp};
+use leverage_contract::msg::{ExecuteMsg, PriceUpdate};
+use serde_json::Value;
+
+use crate::{Pricer, PricerError};
+
+/// Submits `FeedPrices` signed by a feeder key whitelisted in the contract's
+/// `PriceFeedConfig`
+pub trait FeedRelay {
+    /// Feeds the price of one asset in its own transaction and returns the transaction hash
+    fn feed(&mut self, price: PriceUpdate) -> Result<String, PricerError>;
+}
+
+/**
+ * @dev Talks to a node through its CLI binary (`osmosisd`, `wasmd`, ...), which does the
+ * signing with the feeder's key from its keyring.
+ */
+pub struct CliRelay {
+    pub binary: String,
+    pub node: String,
+    pub chain_id: String,
+    pub contract: String,
+    /// Keyring name of the feeder's key
+    pub from: String,
+    pub gas_prices: String,
+}
+
+impl CliRelay {
+    fn run(&self, args: &[&str]) -> Result<Value, String> {
+        let output = Command::new(&self.binary)
+            .args(args)
+            .args(["--node", &self.node, "--output", "json"])
+            .output()
+            .map_err(|error| error.to_string())?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        serde_json::from_slice(&output.stdout).map_err(|error| error.to_string())
+    }
+}
+
+impl FeedRelay for CliRelay {
+    fn feed(&mut self, price: PriceUpdate) -> Result<String, PricerError> {
+        let msg = serde_json::to_string(&ExecuteMsg::FeedPrices {
+            prices: vec![price],
+        })?;
+        let failed = |error: String| PricerError::Relay { error };
+
+        let response = self
+            .run(&[
+                "tx",
+                "wasm",
+                "execute",
+                &self.contract,
+                &msg,
+                "--from",
+                &self.from,
+                "--chain-id",
+                &self.chain_id,
+                "--gas",
+                "auto",
+                "--gas-adjustment",
+                "1.3",
+                "--gas-prices",
+                &self.gas_prices,
+                "--yes",
+            ])
+            .map_err(failed)?;
+
+        // CheckTx failures come back with a non-zero code and a zero exit status
+        if response["code"].as_u64().unwrap_or(0) != 0 {
+            return Err(failed(
+                response["raw_log"].as_str().unwrap_or_default().to_string(),
+            ));
+        }
+        match response["txhash"].as_str() {
+            Some(txhash) => Ok(txhash.to_string()),
+            None => Err(failed("response without txhash".to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RelayRound {
+    /// Symbols fed this round with the hash of the transaction that fed each
+    pub relayed: Vec<(String, String)>,
+    /// Symbols without a quote this round or whose feed the chain rejected
+    pub skipped: Vec<(String, PricerError)>,
+}
+
+/// Pushes the quotes of the mapped symbols into the contract, one transaction per symbol
+/// so a quote the contract rejects does not hold back the others. Quotes not newer than
+/// the last one relayed are left out, the contract would reject them.
+pub struct Relayer<R: FeedRelay> {
+    pub relay: R,
+    /// Symbol to the asset address the contract prices it under
+    pub tokens: BTreeMap<String, Addr>,
+    last_relayed: BTreeMap<String, u64>,
+}
+
+impl<R: FeedRelay> Relayer<R> {
+    pub fn new(relay: R, tokens: BTreeMap<String, Addr>) -> Self {
+        Relayer {
+            relay,
+            tokens,
+            last_relayed: BTreeMap::new(),
+        }
+    }
+
+    pub fn relay_once(&mut self, pricer: &mut Pricer, now: u64) -> RelayRound {
+        let mut round = RelayRound::default();
+        for (symbol, asset) in self.tokens.iter() {
+            let quote = match pricer.quote(symbol, now) {
+                Ok(quote) => quote,
+                Err(error) => {
+                    round.skipped.push((symbol.clone(), error));
+                    continue;
+                }
+            };
+            if self
+                .last_relayed
+                .get(symbol)
+                .is_some_and(|last_relayed| quote.last_updated <= *last_relayed)
+            {
+                continue;
+            }
+            let price = PriceUpdate {
+                asset: asset.clone(),
+                price: quote.price,
+                publish_time: Timestamp::from_seconds(quote.last_updated),
+            };
+            // A rejected quote is not recorded and is retried next round
+            match self.relay.feed(price) {
+                Ok(txhash) => {
+                    self.last_relayed.insert(symbol.clone(), quote.last_updated);
+                    round.relayed.push((symbol.clone(), txhash));
+                }
+                Err(error) => round.skipped.push((symbol.clone(), error)),
+            }
+        }
+        round
+    }
+}
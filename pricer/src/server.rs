@@ -0,0 +1,52 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tower_http::cors::CorsLayer;
+
+use crate::{unix_now, Conversion, Pricer, PricerError};
+
+#[derive(Clone)]
+pub struct AppState(pub Arc<Mutex<Pricer>>);
+
+impl AppState {
+    fn pricer(&self) -> MutexGuard<'_, Pricer> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Request body of the frontend's `/api/price-conversion`, both fields are token symbols
+#[derive(Deserialize)]
+pub struct ConversionRequest {
+    pub sell_token_price: String,
+    pub buy_token_price: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConversionResponse {
+    pub data: Conversion,
+}
+
+/// Routes served to the frontend. The pricer is shared with the relay loop, if any.
+pub fn router(pricer: Arc<Mutex<Pricer>>) -> Router {
+    Router::new()
+        .route("/api/price-conversion", post(price_conversion))
+        .layer(CorsLayer::permissive())
+        .with_state(AppState(pricer))
+}
+
+async fn price_conversion(
+    State(state): State<AppState>,
+    Json(request): Json<ConversionRequest>,
+) -> Result<Json<ConversionResponse>, PricerError> {
+    let data = state.pricer().convert(
+        &request.sell_token_price,
+        &request.buy_token_price,
+        unix_now(),
+    )?;
+    Ok(Json(ConversionResponse { data }))
+}
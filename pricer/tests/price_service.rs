@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use cosmwasm_std::{Addr, Decimal, Timestamp};
+use cw_multi_test::{App, ContractWrapper, Executor};
+use leverage_contract::contract::{execute, instantiate, query, reply};
+use leverage_contract::msg::{
+    ExecuteMsg, InstantiateMsg, PriceInfoResponse, PriceUpdate, QueryMsg,
+};
+use leverage_contract::state::PriceFeedConfig;
+use leverage_pricer::provider::{parse_timestamp, FileProvider, PriceProvider};
+use leverage_pricer::relay::{FeedRelay, RelayRound, Relayer};
+use leverage_pricer::server::router;
+use leverage_pricer::{Pricer, PricerError};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+/// Quoted at 1571797000, before the multi-test block time
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/prices.json");
+
+/// Copy of the fixture the test can rewrite
+fn prices_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "leverage-pricer-{}-{}.json",
+        name,
+        std::process::id()
+    ));
+    fs::copy(FIXTURE, &path).unwrap();
+    path
+}
+
+fn set_quote(path: &PathBuf, symbol: &str, price: &str, last_updated: u64) {
+    let mut quotes: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    quotes[symbol] = json!({ "price": price, "last_updated": last_updated });
+    fs::write(path, quotes.to_string()).unwrap();
+}
+
+fn file_provider(path: PathBuf) -> Box<dyn PriceProvider + Send> {
+    Box::new(FileProvider { path })
+}
+
+#[test]
+fn conversions_fall_back_across_providers_and_are_cached() {
+    let path = prices_file("cache");
+    let missing = std::env::temp_dir().join("leverage-pricer-missing.json");
+    let mut pricer = Pricer::new(
+        vec![file_provider(missing), file_provider(path.clone())],
+        60,
+    );
+
+    let conversion = pricer.convert("osmo", "usdc", 1_000).unwrap();
+    assert_eq!(conversion.symbol, "OSMO");
+    assert_eq!(conversion.quote["USDC"].price, 2.0);
+    assert_eq!(conversion.last_updated, 1_571_797_000);
+
+    // Served from the cache until the ttl runs out
+    set_quote(&path, "OSMO", "2.5", 1_571_797_100);
+    assert_eq!(
+        pricer.convert("OSMO", "USDC", 1_059).unwrap().quote["USDC"].price,
+        2.0
+    );
+    assert_eq!(
+        pricer.convert("OSMO", "USDC", 1_060).unwrap().quote["USDC"].price,
+        2.5
+    );
+    assert_eq!(
+        pricer.convert("ATOM", "OSMO", 1_060).unwrap().quote["OSMO"].price,
+        3.4
+    );
+
+    let err = pricer.convert("OSMO", "JUNO", 1_060).unwrap_err();
+    assert!(matches!(err, PricerError::QuoteNotAvailable { ref symbol, .. } if symbol == "JUNO"));
+    assert!(err.to_string().contains("JUNO is not in"));
+
+    assert_eq!(parse_timestamp("1970-01-02T00:00:00.000Z"), Some(86_400));
+    assert_eq!(
+        parse_timestamp("2024-05-01T12:30:00.000Z"),
+        Some(1_714_566_600)
+    );
+    assert_eq!(parse_timestamp("yesterday"), None);
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn serves_the_frontend_conversion_api() {
+    let path = prices_file("server");
+    let app = router(Arc::new(Mutex::new(Pricer::new(
+        vec![file_provider(path.clone())],
+        60,
+    ))));
+
+    let send = |body: Value| {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/price-conversion")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<Value>(&bytes).unwrap())
+        }
+    };
+
+    // The shape `Swap.jsx` reads: `data.quote[buyToken].price`
+    let (status, body) =
+        send(json!({ "sell_token_price": "OSMO", "buy_token_price": "ATOM" })).await;
+    assert_eq!(status, StatusCode::OK);
+    let price = body["data"]["quote"]["ATOM"]["price"].as_f64().unwrap();
+    assert!((price - 2.0 / 8.5).abs() < 1e-12);
+
+    let (status, body) =
+        send(json!({ "sell_token_price": "JUNO", "buy_token_price": "OSMO" })).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("No quote for JUNO"));
+    fs::remove_file(path).unwrap();
+}
+
+/// Relay submitting `FeedPrices` as the feeder in `cw-multi-test`
+struct MultiTestRelay {
+    app: App,
+    contract: Addr,
+    /// Transactions sent so far
+    sent: u64,
+}
+
+impl FeedRelay for MultiTestRelay {
+    fn feed(&mut self, price: PriceUpdate) -> Result<String, PricerError> {
+        self.app
+            .execute_contract(
+                Addr::unchecked("feeder"),
+                self.contract.clone(),
+                &ExecuteMsg::FeedPrices {
+                    prices: vec![price],
+                },
+                &[],
+            )
+            .map_err(|error| PricerError::Relay {
+                error: error.root_cause().to_string(),
+            })?;
+        self.sent += 1;
+        Ok(format!(
+            "block {} tx {}",
+            self.app.block_info().height,
+            self.sent
+        ))
+    }
+}
+
+impl MultiTestRelay {
+    fn price_info(&self, asset: &str) -> Option<PriceInfoResponse> {
+        self.app
+            .wrap()
+            .query_wasm_smart(
+                self.contract.clone(),
+                &QueryMsg::PriceInfo {
+                    asset: Addr::unchecked(asset),
+                },
+            )
+            .unwrap()
+    }
+}
+
+#[test]
+fn relays_new_quotes_into_the_price_feed() {
+    let mut app = App::default();
+    let code_id = app.store_code(Box::new(
+        ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+    ));
+    let contract = app
+        .instantiate_contract(
+            code_id,
+            Addr::unchecked("creator"),
+            &InstantiateMsg {
+                token_contract_address: String::from("usdc_contract"),
+            },
+            &[],
+            "leverage_contract",
+            None,
+        )
+        .unwrap();
+    app.execute_contract(
+        Addr::unchecked("creator"),
+        contract.clone(),
+        &ExecuteMsg::UpdatePriceFeedConfig(PriceFeedConfig {
+            feeders: vec![Addr::unchecked("feeder")],
+            max_price_age: None,
+            max_deviation_bps: Some(1_000),
+        }),
+        &[],
+    )
+    .unwrap();
+
+    let path = prices_file("relay");
+    let mut pricer = Pricer::new(vec![file_provider(path.clone())], 0);
+    let tokens = BTreeMap::from([
+        ("JUNO".to_string(), Addr::unchecked("juno")),
+        ("OSMO".to_string(), Addr::unchecked("osmo")),
+        ("USDC".to_string(), Addr::unchecked("usdc_contract")),
+    ]);
+    let mut relayer = Relayer::new(
+        MultiTestRelay {
+            app,
+            contract,
+            sent: 0,
+        },
+        tokens,
+    );
+
+    let relayed = |round: &RelayRound| -> Vec<String> {
+        round
+            .relayed
+            .iter()
+            .map(|(symbol, _)| symbol.clone())
+            .collect()
+    };
+    let round = relayer.relay_once(&mut pricer, 1_000);
+    assert_eq!(relayed(&round), vec!["OSMO", "USDC"]);
+    assert_ne!(round.relayed[0].1, round.relayed[1].1);
+    assert_eq!(round.skipped[0].0, "JUNO");
+    assert_eq!(
+        relayer.relay.price_info("osmo"),
+        Some(PriceInfoResponse {
+            price: Decimal::percent(200),
+            publish_time: Some(Timestamp::from_seconds(1_571_797_000)),
+            stale: false,
+        })
+    );
+
+    // Unchanged quotes are not sent again
+    let round = relayer.relay_once(&mut pricer, 1_010);
+    assert!(round.relayed.is_empty());
+
+    set_quote(&path, "OSMO", "2.1", 1_571_797_300);
+    let round = relayer.relay_once(&mut pricer, 1_020);
+    assert_eq!(relayed(&round), vec!["OSMO"]);
+    assert_eq!(
+        relayer.relay.price_info("osmo").unwrap().price,
+        Decimal::percent(210)
+    );
+
+    // A jump past the feed's deviation bound is rejected by the contract without holding
+    // back the other quotes, and is retried next round
+    set_quote(&path, "OSMO", "3", 1_571_797_400);
+    set_quote(&path, "USDC", "1.01", 1_571_797_400);
+    let round = relayer.relay_once(&mut pricer, 1_030);
+    assert_eq!(relayed(&round), vec!["USDC"]);
+    let (symbol, error) = round.skipped.last().unwrap();
+    assert_eq!(symbol, "OSMO");
+    assert!(error.to_string().contains("Price of osmo moves"));
+    assert_eq!(
+        relayer.relay.price_info("usdc_contract").unwrap().price,
+        Decimal::percent(101)
+    );
+    let round = relayer.relay_once(&mut pricer, 1_040);
+    assert_eq!(round.skipped.last().unwrap().0, "OSMO");
+    fs::remove_file(path).unwrap();
+}
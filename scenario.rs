@@ -0,0 +1,633 @@
+//! Scenario harness for the contract in `cw-multi-test`.
+//!
+//! The harness instantiates a mock CW20 for usdc, funds native `uosmo` and lists it as
+//! `osmo`, and uses the contract's own price feed as the oracle. A scenario is a list
+//! of steps, each followed by the exact balances and events it must produce. Token
+//! arguments and attribute values of `"usdc"` resolve to the mock CW20's address.
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{
+    coins, to_json_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError,
+    StdResult, Storage, Uint128,
+};
+use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg, Cw20ReceiveMsg};
+use cw_multi_test::{App, AppBuilder, AppResponse, ContractWrapper, Executor};
+use cw_storage_plus::Map;
+
+use crate::contract::{execute, instantiate, query, reply};
+use crate::msg::{
+    ExecuteMsg, InstantiateMsg, OrderExecute, QueryMsg, QueryTokenData, TokenData, WithdrawData,
+};
+
+const BALANCES: Map<&Addr, Uint128> = Map::new("balances");
+
+#[cw_serde]
+struct MockCw20InstantiateMsg {
+    balances: Vec<(String, Uint128)>,
+}
+
+fn mock_cw20_instantiate(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: MockCw20InstantiateMsg,
+) -> StdResult<Response> {
+    for (address, amount) in msg.balances {
+        BALANCES.save(deps.storage, &Addr::unchecked(address), &amount)?;
+    }
+    Ok(Response::new())
+}
+
+/// CW20 supporting `Transfer` and `Send`, enough for deposits and withdrawals
+fn mock_cw20_execute(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: Cw20ExecuteMsg,
+) -> StdResult<Response> {
+    match msg {
+        Cw20ExecuteMsg::Transfer { recipient, amount } => {
+            move_balance(deps.storage, &info.sender, &Addr::unchecked(recipient), amount)?;
+            Ok(Response::new())
+        }
+        Cw20ExecuteMsg::Send {
+            contract,
+            amount,
+            msg,
+        } => {
+            move_balance(deps.storage, &info.sender, &Addr::unchecked(&contract), amount)?;
+            let receive = Cw20ReceiveMsg {
+                sender: info.sender.to_string(),
+                amount,
+                msg,
+            };
+            Ok(Response::new().add_message(receive.into_cosmos_msg(contract)?))
+        }
+        _ => Err(StdError::generic_err("unsupported by the mock CW20")),
+    }
+}
+
+fn move_balance(
+    storage: &mut dyn Storage,
+    from: &Addr,
+    to: &Addr,
+    amount: Uint128,
+) -> StdResult<()> {
+    let balance = BALANCES.may_load(storage, from)?.unwrap_or_default();
+    BALANCES.save(storage, from, &balance.checked_sub(amount)?)?;
+    let balance = BALANCES.may_load(storage, to)?.unwrap_or_default();
+    BALANCES.save(storage, to, &(balance + amount))
+}
+
+fn mock_cw20_query(deps: Deps, _env: Env, msg: Cw20QueryMsg) -> StdResult<Binary> {
+    match msg {
+        Cw20QueryMsg::Balance { address } => to_json_binary(&BalanceResponse {
+            balance: BALANCES
+                .may_load(deps.storage, &Addr::unchecked(address))?
+                .unwrap_or_default(),
+        }),
+        _ => Err(StdError::generic_err("unsupported by the mock CW20")),
+    }
+}
+
+/// Token arguments are aliases, see the module docs
+pub(crate) enum Step {
+    /// CW20 `Send` of usdc into the contract: user, amount
+    DepositCw20(&'static str, u128),
+    /// user, token, amount, denom
+    DepositNative(&'static str, &'static str, u128, &'static str),
+    /// asset, price
+    SetPrice(&'static str, &'static str),
+    /// user, token, amount
+    Borrow(&'static str, &'static str, u128),
+    /// Owner-settled order
+    Trade {
+        user: &'static str,
+        token_in: &'static str,
+        token_out: &'static str,
+        amount_in: u128,
+        amount_out: u128,
+    },
+    /// liquidator, user, debt token
+    Liquidate(&'static str, &'static str, &'static str),
+    /// user, token, amount
+    Repay(&'static str, &'static str, u128),
+    /// user, token, amount, withdraw type ("fungible" or "native")
+    Withdraw(&'static str, &'static str, u128, &'static str),
+}
+
+/// Ledgers are given as user, token, amount. Address "contract" is the leverage contract.
+enum Expect {
+    Collateral(&'static str, &'static str, u128),
+    Unminted(&'static str, &'static str, u128),
+    Borrowed(&'static str, &'static str, u128),
+    Position(&'static str, &'static str, u128),
+    /// address, amount
+    Cw20Balance(&'static str, u128),
+    /// address, denom, amount
+    NativeBalance(&'static str, &'static str, u128),
+    /// A `wasm-{ty}` event with at least these attributes
+    Event(&'static str, &'static [(&'static str, &'static str)]),
+    /// The step fails with an error containing this message
+    Error(&'static str),
+}
+
+pub(crate) struct Harness {
+    pub(crate) app: App,
+    pub(crate) contract: Addr,
+    pub(crate) usdc: Addr,
+}
+
+impl Harness {
+    fn new() -> Self {
+        Self::with_users(&["user_one", "user_two"], 1_000)
+    }
+
+    /// Each of `users` starts with `funds` uosmo and `funds` mock usdc
+    pub(crate) fn with_users(users: &[&str], funds: u128) -> Self {
+        let mut app = AppBuilder::new().build(|router, _, storage| {
+            for user in users {
+                router
+                    .bank
+                    .init_balance(storage, &Addr::unchecked(*user), coins(funds, "uosmo"))
+                    .unwrap();
+            }
+        });
+
+        let cw20_code_id = app.store_code(Box::new(ContractWrapper::new(
+            mock_cw20_execute,
+            mock_cw20_instantiate,
+            mock_cw20_query,
+        )));
+        let usdc = app
+            .instantiate_contract(
+                cw20_code_id,
+                Addr::unchecked("creator"),
+                &MockCw20InstantiateMsg {
+                    balances: users
+                        .iter()
+                        .map(|user| (user.to_string(), Uint128::from(funds)))
+                        .collect(),
+                },
+                &[],
+                "usdc",
+                None,
+            )
+            .unwrap();
+
+        let code_id = app.store_code(Box::new(
+            ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+        ));
+        let contract = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    token_contract_address: usdc.to_string(),
+                },
+                &[],
+                "leverage_contract",
+                None,
+            )
+            .unwrap();
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            contract.clone(),
+            &ExecuteMsg::ListTokenOnLeverage {
+                token_address: String::from("osmo"),
+                native_denom: Some(String::from("uosmo")),
+            },
+            &[],
+        )
+        .unwrap();
+
+        Harness {
+            app,
+            contract,
+            usdc,
+        }
+    }
+
+    pub(crate) fn token(&self, alias: &str) -> Addr {
+        match alias {
+            "usdc" => self.usdc.clone(),
+            _ => Addr::unchecked(alias),
+        }
+    }
+
+    fn execute(&mut self, sender: &str, msg: &ExecuteMsg) -> Result<AppResponse, String> {
+        self.app
+            .execute_contract(Addr::unchecked(sender), self.contract.clone(), msg, &[])
+            .map_err(|error| error.root_cause().to_string())
+    }
+
+    pub(crate) fn run(&mut self, step: &Step) -> Result<AppResponse, String> {
+        match *step {
+            Step::DepositCw20(user, amount) => self
+                .app
+                .execute_contract(
+                    Addr::unchecked(user),
+                    self.usdc.clone(),
+                    &Cw20ExecuteMsg::Send {
+                        contract: self.contract.to_string(),
+                        amount: Uint128::from(amount),
+                        msg: to_json_binary(&{}).unwrap(),
+                    },
+                    &[],
+                )
+                .map_err(|error| error.root_cause().to_string()),
+            Step::DepositNative(user, token, amount, denom) => self
+                .app
+                .execute_contract(
+                    Addr::unchecked(user),
+                    self.contract.clone(),
+                    &ExecuteMsg::DepositNative {
+                        token_address: token.to_string(),
+                        sub_account: None,
+                    },
+                    &coins(amount, denom),
+                )
+                .map_err(|error| error.root_cause().to_string()),
+            Step::SetPrice(asset, price) => {
+                let msg = ExecuteMsg::UpdateAssetPrice {
+                    asset: self.token(asset),
+                    price: price.parse().unwrap(),
+                };
+                self.execute("creator", &msg)
+            }
+            Step::Borrow(user, token, amount) => {
+                let msg = ExecuteMsg::Borrow(TokenData {
+                    token_address: self.token(token),
+                    token_amount: Uint128::from(amount),
+                    on_behalf_of: None,
+                    sub_account: None,
+                });
+                self.execute(user, &msg)
+            }
+            Step::Trade {
+                user,
+                token_in,
+                token_out,
+                amount_in,
+                amount_out,
+            } => {
+                let msg = ExecuteMsg::ExecuteOrder(OrderExecute {
+                    user_address: Addr::unchecked(user),
+                    token_in: self.token(token_in),
+                    token_out: self.token(token_out),
+                    amount_in: Uint128::from(amount_in),
+                    amount_out: Uint128::from(amount_out),
+                    encrypted_order: None,
+                });
+                self.execute("creator", &msg)
+            }
+            Step::Liquidate(liquidator, user, debt_token) => {
+                let msg = ExecuteMsg::Liquidate {
+                    user_address: Addr::unchecked(user),
+                    debt_token: self.token(debt_token),
+                };
+                self.execute(liquidator, &msg)
+            }
+            Step::Repay(user, token, amount) => {
+                let msg = ExecuteMsg::Repay(TokenData {
+                    token_address: self.token(token),
+                    token_amount: Uint128::from(amount),
+                    on_behalf_of: None,
+                    sub_account: None,
+                });
+                self.execute(user, &msg)
+            }
+            Step::Withdraw(user, token, amount, withdraw_type) => {
+                let msg = ExecuteMsg::WithdrawToken(WithdrawData {
+                    token_address: self.token(token),
+                    token_amount: Uint128::from(amount),
+                    withdraw_type: withdraw_type.to_string(),
+                    native: None,
+                    on_behalf_of: None,
+                    sub_account: None,
+                });
+                self.execute(user, &msg)
+            }
+        }
+    }
+
+    pub(crate) fn ledger(
+        &self,
+        query: fn(QueryTokenData) -> QueryMsg,
+        user: &str,
+        token: &str,
+    ) -> u128 {
+        let balance: Uint128 = self
+            .app
+            .wrap()
+            .query_wasm_smart(
+                self.contract.clone(),
+                &query(QueryTokenData {
+                    token_address: self.token(token),
+                    user_address: Addr::unchecked(user),
+                }),
+            )
+            .unwrap();
+        balance.u128()
+    }
+
+    pub(crate) fn cw20_balance(&self, address: &str) -> u128 {
+        let response: BalanceResponse = self
+            .app
+            .wrap()
+            .query_wasm_smart(
+                self.usdc.clone(),
+                &Cw20QueryMsg::Balance {
+                    address: address.to_string(),
+                },
+            )
+            .unwrap();
+        response.balance.u128()
+    }
+
+    /// Checks `expect` against the state and the step's `result`, `Err` describes the mismatch
+    fn check(
+        &self,
+        expect: &Expect,
+        result: &Result<AppResponse, String>,
+    ) -> Result<(), String> {
+        let (what, actual, expected) = match *expect {
+            Expect::Collateral(user, token, amount) => (
+                format!("collateral of {} in {}", user, token),
+                self.ledger(QueryMsg::UserCollateralTokenBalance, user, token),
+                amount,
+            ),
+            Expect::Unminted(user, token, amount) => (
+                format!("unminted vToken of {} in {}", user, token),
+                self.ledger(QueryMsg::UserWrappedTokenBalance, user, token),
+                amount,
+            ),
+            Expect::Borrowed(user, token, amount) => (
+                format!("debt of {} in {}", user, token),
+                self.ledger(QueryMsg::UserBorrowTokenBalance, user, token),
+                amount,
+            ),
+            Expect::Position(user, token, amount) => (
+                format!("position of {} in {}", user, token),
+                self.ledger(QueryMsg::UserPositionBalance, user, token),
+                amount,
+            ),
+            Expect::Cw20Balance(address, amount) => {
+                let address = match address {
+                    "contract" => self.contract.to_string(),
+                    _ => address.to_string(),
+                };
+                (format!("usdc balance of {}", address), self.cw20_balance(&address), amount)
+            }
+            Expect::NativeBalance(address, denom, amount) => {
+                let address = match address {
+                    "contract" => self.contract.clone(),
+                    _ => Addr::unchecked(address),
+                };
+                let balance = self.app.wrap().query_balance(&address, denom).unwrap();
+                (format!("{} balance of {}", denom, address), balance.amount.u128(), amount)
+            }
+            Expect::Event(ty, attributes) => {
+                let response = result
+                    .as_ref()
+                    .map_err(|error| format!("step failed: {}", error))?;
+                let ty = format!("wasm-{}", ty);
+                let event = response
+                    .events
+                    .iter()
+                    .find(|event| event.ty == ty)
+                    .ok_or_else(|| format!("no {} event", ty))?;
+                for (key, value) in attributes {
+                    let value = match *value {
+                        "usdc" => self.usdc.to_string(),
+                        _ => value.to_string(),
+                    };
+                    let actual = event
+                        .attributes
+                        .iter()
+                        .find(|attribute| attribute.key == *key)
+                        .map(|attribute| attribute.value.clone());
+                    if actual.as_deref() != Some(value.as_str()) {
+                        return Err(format!("{}.{} is {:?}, expected {}", ty, key, actual, value));
+                    }
+                }
+                return Ok(());
+            }
+            Expect::Error(message) => {
+                return match result {
+                    Err(error) if error.contains(message) => Ok(()),
+                    Err(error) => Err(format!("failed with {}, expected {}", error, message)),
+                    Ok(_) => Err(format!("succeeded, expected {}", message)),
+                };
+            }
+        };
+
+        if actual != expected {
+            return Err(format!("{} is {}, expected {}", what, actual, expected));
+        }
+        Ok(())
+    }
+}
+
+/// Runs the steps on a fresh harness, panicking at the first unmet expectation
+fn run_scenario(name: &str, steps: &[(Step, &[Expect])]) {
+    let mut harness = Harness::new();
+    for (index, (step, expectations)) in steps.iter().enumerate() {
+        let result = harness.run(step);
+        let expects_error = expectations
+            .iter()
+            .any(|expect| matches!(expect, Expect::Error(_)));
+        if let (Err(error), false) = (&result, expects_error) {
+            panic!("{}: step {} failed: {}", name, index + 1, error);
+        }
+        for expect in expectations.iter() {
+            if let Err(mismatch) = harness.check(expect, &result) {
+                panic!("{}: step {}: {}", name, index + 1, mismatch);
+            }
+        }
+    }
+}
+
+#[test]
+fn deposit_borrow_repay_withdraw() {
+    run_scenario(
+        "deposit_borrow_repay_withdraw",
+        &[
+            (Step::SetPrice("usdc", "1"), &[]),
+            (
+                Step::DepositCw20("user_one", 1_000),
+                &[
+                    Expect::Collateral("user_one", "usdc", 1_000),
+                    Expect::Unminted("user_one", "usdc", 10_000),
+                    Expect::Cw20Balance("user_one", 0),
+                    Expect::Cw20Balance("contract", 1_000),
+                    Expect::Event(
+                        "deposit",
+                        &[
+                            ("user", "user_one"),
+                            ("asset", "usdc"),
+                            ("amount", "1000"),
+                            ("collateral_balance", "1000"),
+                            ("unminted_balance", "10000"),
+                        ],
+                    ),
+                ],
+            ),
+            (
+                Step::Borrow("user_one", "usdc", 500),
+                &[
+                    Expect::Borrowed("user_one", "usdc", 500),
+                    Expect::Position("user_one", "usdc", 500),
+                    Expect::Unminted("user_one", "usdc", 9_500),
+                    Expect::Event(
+                        "borrow",
+                        &[("amount", "500"), ("fee", "0"), ("borrow_balance", "500")],
+                    ),
+                ],
+            ),
+            (
+                Step::Withdraw("user_one", "usdc", 100, "fungible"),
+                &[
+                    Expect::Error("Borrow amount is not zero"),
+                    Expect::Collateral("user_one", "usdc", 1_000),
+                ],
+            ),
+            (
+                Step::Repay("user_one", "usdc", 500),
+                &[
+                    Expect::Borrowed("user_one", "usdc", 0),
+                    Expect::Position("user_one", "usdc", 0),
+                    Expect::Unminted("user_one", "usdc", 10_000),
+                    Expect::Event("repay", &[("amount", "500"), ("borrow_balance", "0")]),
+                ],
+            ),
+            (
+                Step::Withdraw("user_one", "usdc", 1_000, "fungible"),
+                &[
+                    Expect::Collateral("user_one", "usdc", 0),
+                    Expect::Unminted("user_one", "usdc", 0),
+                    Expect::Cw20Balance("user_one", 1_000),
+                    Expect::Cw20Balance("contract", 0),
+                    Expect::Event(
+                        "withdraw",
+                        &[("amount", "1000"), ("fee", "0"), ("recipient", "user_one")],
+                    ),
+                ],
+            ),
+        ],
+    );
+}
+
+#[test]
+fn price_move_liquidates_leveraged_trade() {
+    run_scenario(
+        "price_move_liquidates_leveraged_trade",
+        &[
+            (Step::SetPrice("usdc", "1"), &[]),
+            (Step::SetPrice("osmo", "2"), &[]),
+            (
+                Step::DepositCw20("user_one", 100),
+                &[Expect::Unminted("user_one", "usdc", 1_000)],
+            ),
+            (
+                Step::Borrow("user_one", "usdc", 1_001),
+                &[Expect::Error("Insufficient unminted token")],
+            ),
+            (
+                Step::Borrow("user_one", "usdc", 1_000),
+                &[Expect::Borrowed("user_one", "usdc", 1_000)],
+            ),
+            (
+                Step::Trade {
+                    user: "user_one",
+                    token_in: "usdc",
+                    token_out: "osmo",
+                    amount_in: 1_000,
+                    amount_out: 500,
+                },
+                &[
+                    Expect::Position("user_one", "usdc", 0),
+                    Expect::Position("user_one", "osmo", 500),
+                    Expect::Event(
+                        "order_settled",
+                        &[
+                            ("token_in", "usdc"),
+                            ("token_out", "osmo"),
+                            ("amount_in", "1000"),
+                            ("amount_out", "500"),
+                            ("position_balance", "500"),
+                        ],
+                    ),
+                ],
+            ),
+            (
+                Step::Liquidate("keeper", "user_one", "usdc"),
+                &[Expect::Error("Account is not liquidatable")],
+            ),
+            // 100 + 500 * 1.85 = 1025 against 1000 of debt, below the 1.05 threshold
+            (Step::SetPrice("osmo", "1.85"), &[]),
+            (
+                Step::Liquidate("keeper", "user_one", "usdc"),
+                &[
+                    Expect::Borrowed("user_one", "usdc", 0),
+                    Expect::Collateral("user_one", "usdc", 0),
+                    Expect::Position("user_one", "osmo", 0),
+                    Expect::Collateral("keeper", "usdc", 25),
+                    Expect::Event(
+                        "liquidation",
+                        &[
+                            ("user", "user_one"),
+                            ("liquidator", "keeper"),
+                            ("debt", "1000"),
+                            ("seized_positions", "925"),
+                            ("seized_collateral", "100"),
+                            ("penalty", "25"),
+                            ("liquidator_reward", "25"),
+                            ("returned_collateral", "0"),
+                            ("shortfall", "0"),
+                        ],
+                    ),
+                ],
+            ),
+            (
+                Step::Withdraw("keeper", "usdc", 25, "fungible"),
+                &[
+                    Expect::Cw20Balance("keeper", 25),
+                    Expect::Cw20Balance("contract", 75),
+                ],
+            ),
+        ],
+    );
+}
+
+#[test]
+fn native_deposit_and_withdraw() {
+    run_scenario(
+        "native_deposit_and_withdraw",
+        &[
+            (Step::SetPrice("osmo", "2"), &[]),
+            (
+                Step::DepositNative("user_two", "osmo", 600, "uosmo"),
+                &[
+                    Expect::Collateral("user_two", "osmo", 600),
+                    Expect::NativeBalance("user_two", "uosmo", 400),
+                    Expect::NativeBalance("contract", "uosmo", 600),
+                    Expect::Event("deposit", &[("asset", "osmo"), ("amount", "600")]),
+                ],
+            ),
+            (
+                Step::Withdraw("user_two", "osmo", 700, "native"),
+                &[Expect::Error("Insufficient balance")],
+            ),
+            (
+                Step::Withdraw("user_two", "osmo", 250, "native"),
+                &[
+                    Expect::Collateral("user_two", "osmo", 350),
+                    Expect::Unminted("user_two", "osmo", 3_500),
+                    Expect::NativeBalance("user_two", "uosmo", 650),
+                    Expect::NativeBalance("contract", "uosmo", 350),
+                ],
+            ),
+        ],
+    );
+}
@@ -0,0 +1,386 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, Binary, Decimal, Decimal256, Timestamp, Uint128, Uint512};
+use cw20::Expiration;
+use cw_storage_plus::{Item, Map};
+
+use crate::paillier::PublicKey;
+
+pub const LEVERAGE_CONTRACT_OWNER: Item<Addr> = Item::new("leverage_contract_owner");
+
+pub const LISTED_TOKEN: Item<Vec<String>> = Item::new("listed_token");
+
+/// Native denom recorded for a native listed token (e.g. "osmo" -> "uosmo")
+pub const NATIVE_TOKEN_DENOM: Map<&Addr, String> = Map::new("native_token_denom");
+
+/// (token_address, user_address) -> deposited collateral
+pub const USER_TOKEN_BALANCE: Map<(&Addr, &Addr), Uint128> = Map::new("user_token_balance");
+
+/// (token_address, user_address) -> unminted (borrowable) vToken
+pub const USER_UNMINTED_TOKEN: Map<(&Addr, &Addr), Uint128> = Map::new("user_unminted_token");
+
+/// (token_address, user_address) -> borrowed vToken
+pub const USER_BORROW_BALANCE: Map<(&Addr, &Addr), Uint128> = Map::new("user_borrow_balance");
+
+/// (token_address, user_address) -> realized profit vToken
+pub const USER_PROFIT_TOKEN: Map<(&Addr, &Addr), Uint128> = Map::new("user_profit_token");
+
+/// (user_address, asset) -> position held with borrowed vToken
+pub const USER_POSITION_BALANCE: Map<(&Addr, &Addr), Uint128> =
+    Map::new("user_position_balance");
+
+/// asset -> price in the common quote unit
+pub const ASSET_PRICE: Map<&Addr, Decimal> = Map::new("asset_price");
+
+/// asset -> time `ASSET_PRICE` was observed at, missing for prices set before feeds
+pub const ASSET_PRICE_TIME: Map<&Addr, Timestamp> = Map::new("asset_price_time");
+
+pub const PRICE_FEED_CONFIG: Item<PriceFeedConfig> = Item::new("price_feed_config");
+
+/// (asset, observed_at seconds) -> price a settlement implied for the asset
+pub const PRICE_OBSERVATIONS: Map<(&Addr, u64), PriceObservation> =
+    Map::new("price_observations");
+
+pub const TWAP_CONFIG: Item<TwapConfig> = Item::new("twap_config");
+
+/// asset -> sources its price is aggregated from, the pushed feed alone when unset
+pub const PRICE_SOURCES: Map<&Addr, PriceSources> = Map::new("price_sources");
+
+/// (asset, oracle contract) -> last price pulled from the oracle by `SyncOraclePrices`
+pub const ORACLE_PRICES: Map<(&Addr, &Addr), OraclePrice> = Map::new("oracle_prices");
+
+/// Paillier key the matching service decrypts order values with
+pub const PAILLIER_PUBLIC_KEY: Item<PublicKey> = Item::new("paillier_public_key");
+
+/// (user_address, order_id) -> encrypted order value committed before matching
+pub const ENCRYPTED_ORDERS: Map<(&Addr, &str), Uint512> = Map::new("encrypted_orders");
+
+/// (user_address, order_id) -> amount of an encrypted order settled by partial fills so far
+pub const ENCRYPTED_ORDER_FILLED: Map<(&Addr, &str), Uint128> =
+    Map::new("encrypted_order_filled");
+
+/// Number of blocks after a commitment during which the order can be revealed
+pub const ORDER_REVEAL_WINDOW: Item<u64> = Item::new("order_reveal_window");
+
+pub const ORDER_COMMITMENT_SEQ: Item<u64> = Item::new("order_commitment_seq");
+
+/// commitment_id -> committed order hash with its escrowed margin
+pub const ORDER_COMMITMENTS: Map<u64, OrderCommitment> = Map::new("order_commitments");
+
+/// (reveal_height, commitment_id) -> revealed order waiting for its batch to settle
+pub const REVEALED_ORDERS: Map<(u64, u64), RevealedOrder> = Map::new("revealed_orders");
+
+/// Router `ExecuteOrder` swaps through, when configured
+pub const SWAP_CONFIG: Item<SwapConfig> = Item::new("swap_config");
+
+/// Swap dispatched by `ExecuteOrder`, settled in `reply`
+pub const PENDING_SWAP: Item<PendingSwap> = Item::new("pending_swap");
+
+/// Flash loan dispatched by `FlashLoan`, checked for repayment in `reply`
+pub const PENDING_FLASH_LOAN: Item<PendingFlashLoan> = Item::new("pending_flash_loan");
+
+pub const FEE_CONFIG: Item<FeeConfig> = Item::new("fee_config");
+
+/// asset -> protocol fees accrued and not yet claimed
+pub const TREASURY: Map<&Addr, Uint128> = Map::new("treasury");
+
+pub const RISK_CONFIG: Item<RiskConfig> = Item::new("risk_config");
+
+pub const INSURANCE_CONFIG: Item<InsuranceConfig> = Item::new("insurance_config");
+
+/// asset -> insurance fund backing bad debt in that asset
+pub const INSURANCE_FUND: Map<&Addr, Uint128> = Map::new("insurance_fund");
+
+/// asset -> bad debt absorbed by the insurance fund and socialized across depositors
+pub const BAD_DEBT: Map<&Addr, BadDebtStats> = Map::new("bad_debt");
+
+/// asset -> sum of all users' deposited collateral
+pub const TOTAL_DEPOSITS: Map<&Addr, Uint128> = Map::new("total_deposits");
+
+/// asset -> share of deposited collateral socialized losses have left, see `LossIndex`
+pub const SOCIALIZED_LOSS_INDEX: Map<&Addr, LossIndex> = Map::new("socialized_loss_index");
+
+/// (token_address, user_address) -> loss index the user's collateral was last settled at
+pub const USER_LOSS_CHECKPOINT: Map<(&Addr, &Addr), LossIndex> =
+    Map::new("user_loss_checkpoint");
+
+/// asset -> supply and borrow caps of a listed token
+pub const TOKEN_CAPS: Map<&Addr, TokenCaps> = Map::new("token_caps");
+
+/// asset -> sum of all users' borrow balances
+pub const TOTAL_BORROWS: Map<&Addr, Uint128> = Map::new("total_borrows");
+
+/// (owner, operator) -> permissions the owner granted the operator
+pub const OPERATOR_GRANTS: Map<(&Addr, &Addr), OperatorGrant> = Map::new("operator_grants");
+
+/// (owner, sub_account) -> margin mode of a numbered sub-account
+///
+/// Sub-account `n > 0` of `owner` is keyed in every ledger under the account address
+/// `"{owner}/{n}"`, sub-account 0 is the owner's own address.
+pub const SUB_ACCOUNTS: Map<(&Addr, u32), MarginMode> = Map::new("sub_accounts");
+
+/// owner -> number of the last opened sub-account
+pub const SUB_ACCOUNT_SEQ: Map<&Addr, u32> = Map::new("sub_account_seq");
+
+pub const CONDITIONAL_ORDER_SEQ: Item<u64> = Item::new("conditional_order_seq");
+
+/// (owner, order_id) -> conditional order
+pub const CONDITIONAL_ORDERS: Map<(&Addr, u64), ConditionalOrder> =
+    Map::new("conditional_orders");
+
+#[cw_serde]
+pub enum ConditionalOrderKind {
+    /// Executable once the price of `token_in` falls to or below `trigger_price`
+    StopLoss { trigger_price: Decimal },
+    /// Executable once the price of `token_in` rises to or above `trigger_price`
+    TakeProfit { trigger_price: Decimal },
+    /// Executable once the price falls `trail_bps` below the highest price seen
+    TrailingStop { trail_bps: u64 },
+}
+
+#[cw_serde]
+pub struct ConditionalOrder {
+    pub order_id: u64,
+    pub owner: Addr,
+    pub token_in: Addr,
+    pub token_out: Addr,
+    /// `None` sells the whole position at execution time
+    pub amount_in: Option<Uint128>,
+    pub kind: ConditionalOrderKind,
+    /// Paid to the executing keeper out of the `token_out` proceeds
+    pub bounty: Uint128,
+    /// Price at placement, raised to the high-water mark for trailing stops
+    pub reference_price: Decimal,
+}
+
+#[cw_serde]
+pub struct OrderCommitment {
+    pub commitment_id: u64,
+    pub owner: Addr,
+    /// sha256(owner ++ JSON of the revealed order)
+    pub commitment: Binary,
+    pub margin_token: Addr,
+    pub margin_amount: Uint128,
+    pub committed_at: u64,
+}
+
+#[cw_serde]
+pub struct RevealedOrder {
+    pub commitment_id: u64,
+    pub owner: Addr,
+    pub token_in: Addr,
+    pub token_out: Addr,
+    pub amount_in: Uint128,
+    /// Minimum price of `token_in` in `token_out`
+    pub limit_price: Decimal,
+}
+
+#[cw_serde]
+pub struct SwapConfig {
+    pub router: Addr,
+    /// Maximum shortfall against the asset price registry, in basis points
+    pub max_slippage_bps: u64,
+}
+
+#[cw_serde]
+pub struct PendingSwap {
+    pub user_address: Addr,
+    pub token_in: Addr,
+    pub token_out: Addr,
+    pub amount_in: Uint128,
+    pub min_out: Uint128,
+    /// Contract balance of `token_out` before the swap
+    pub balance_before: Uint128,
+    /// Lowest health factor the account may be left with after the swap
+    #[serde(default)]
+    pub min_health_factor: Option<Decimal>,
+    /// Set when the swap sells a position that `ClosePosition` is closing
+    #[serde(default)]
+    pub close: Option<PendingClose>,
+    /// Set when the swap executes a conditional order, paid out of the proceeds
+    #[serde(default)]
+    pub bounty: Option<KeeperBounty>,
+}
+
+#[cw_serde]
+pub struct KeeperBounty {
+    pub keeper: Addr,
+    /// Conditional order the bounty is paid for
+    pub order_id: u64,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct PendingClose {
+    /// Fraction of the debt repaid out of the proceeds
+    pub fraction: Decimal,
+    pub withdraw_type: Option<String>,
+}
+
+#[cw_serde]
+pub struct PendingFlashLoan {
+    pub borrower: Addr,
+    pub asset: Addr,
+    pub amount: Uint128,
+    pub fee: Uint128,
+    /// Contract balance of `asset` before the loan was sent
+    pub balance_before: Uint128,
+}
+
+/// Protocol fees in basis points
+#[cw_serde]
+pub struct FeeConfig {
+    /// Origination fee taken from the borrowed amount
+    pub borrow_fee_bps: u64,
+    /// Fee on the output of orders settled against the matching service, router or keepers
+    pub taker_fee_bps: u64,
+    /// Fee on the output of orders filled in a commit-reveal batch
+    pub maker_fee_bps: u64,
+    /// Share of the liquidation penalty kept by the protocol
+    pub liquidation_fee_bps: u64,
+    /// Fee taken from withdrawn collateral
+    pub withdraw_fee_bps: u64,
+    /// Fee on flash loaned amounts, paid back on top of the principal
+    #[serde(default)]
+    pub flash_loan_fee_bps: u64,
+    /// Only address allowed to claim the treasury
+    pub fee_collector: Addr,
+}
+
+#[cw_serde]
+pub struct RiskConfig {
+    /// Accounts whose health factor falls below this can be liquidated
+    pub liquidation_threshold: Decimal,
+    /// Penalty on the liquidated debt, split between liquidator, treasury and insurance
+    pub liquidation_penalty_bps: u64,
+}
+
+/// Price feeder role and the guards on pushed prices
+#[cw_serde]
+#[derive(Default)]
+pub struct PriceFeedConfig {
+    /// Addresses allowed to push prices besides the owner
+    pub feeders: Vec<Addr>,
+    /// Prices older than this many seconds are stale, `None` never expires them
+    pub max_price_age: Option<u64>,
+    /// Largest move from the last price a feeder may push, `None` leaves it unbounded
+    pub max_deviation_bps: Option<u64>,
+}
+
+#[cw_serde]
+pub struct PriceObservation {
+    /// Price in the common quote unit, held until the next observation
+    pub price: Decimal,
+    /// Amount of the asset traded by the fills averaged into `price` within the block
+    #[serde(default)]
+    pub weight: Uint128,
+    /// Sum of each earlier observation's price times the seconds it was held
+    pub cumulative_price: Decimal256,
+}
+
+#[cw_serde]
+pub struct TwapConfig {
+    /// Longest window in seconds, older observations are pruned
+    pub max_window: u64,
+    /// Window liquidation checks average prices over, `None` checks against spot prices
+    pub liquidation_window: Option<u64>,
+}
+
+#[cw_serde]
+pub enum PriceSource {
+    /// Price pushed by the owner or a feeder
+    Feed,
+    /// Settlement TWAP over `window` seconds
+    Twap { window: u64 },
+    /// Oracle contract answering `PriceOracleQueryMsg::Price`
+    Oracle { contract: Addr },
+}
+
+#[cw_serde]
+pub struct PriceSources {
+    /// Sources in fallback order
+    pub sources: Vec<PriceSource>,
+    /// Fresh sources the median needs. Below it risk-increasing actions are refused and
+    /// everything else falls back to the first fresh source.
+    pub quorum: u32,
+}
+
+#[cw_serde]
+pub struct OraclePrice {
+    pub price: Decimal,
+    pub publish_time: Timestamp,
+}
+
+#[cw_serde]
+pub struct InsuranceConfig {
+    /// Share of every protocol fee paid into the insurance fund
+    pub fee_share_bps: u64,
+    /// Share of every liquidation penalty paid into the insurance fund
+    pub penalty_share_bps: u64,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct BadDebtStats {
+    pub covered_by_insurance: Uint128,
+    pub socialized: Uint128,
+    /// Shortfall that could not be socialized because nobody had deposits left
+    pub unrecovered: Uint128,
+}
+
+/// Share of collateral deposited at the start of `epoch` that socialized losses have left.
+/// Every loss multiplies `index` by the share of deposits it leaves, a loss of all
+/// deposits starts a new epoch instead and wipes out collateral settled in an older one.
+#[cw_serde]
+pub struct LossIndex {
+    pub epoch: u64,
+    pub index: Decimal,
+}
+
+impl Default for LossIndex {
+    fn default() -> Self {
+        LossIndex {
+            epoch: 0,
+            index: Decimal::one(),
+        }
+    }
+}
+
+/// Caps on a listed token, `None` leaves that dimension uncapped
+#[cw_serde]
+#[derive(Default)]
+pub struct TokenCaps {
+    pub supply_cap: Option<Uint128>,
+    pub borrow_cap: Option<Uint128>,
+    pub user_supply_cap: Option<Uint128>,
+    pub user_borrow_cap: Option<Uint128>,
+}
+
+#[cw_serde]
+pub enum OperatorScope {
+    /// Place, cancel, commit and reveal orders
+    Trade,
+    /// Repay borrowed tokens
+    Repay,
+    /// Every handler that accepts `on_behalf_of`, including borrow and withdraw
+    Full,
+}
+
+impl OperatorScope {
+    pub fn allows(&self, required: &OperatorScope) -> bool {
+        *self == OperatorScope::Full || self == required
+    }
+}
+
+#[cw_serde]
+pub struct OperatorGrant {
+    pub scope: OperatorScope,
+    pub expires: Expiration,
+}
+
+#[cw_serde]
+pub enum MarginMode {
+    /// Shares margin with the owner's other cross-margin sub-accounts
+    Cross,
+    /// Checked and liquidated on its own collateral only
+    Isolated,
+}